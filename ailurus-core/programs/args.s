@@ -0,0 +1,26 @@
+# Test program of the ELF loader: stores `argc` and the first byte of `argv[0]` in `result`,
+# through a pointer which needs a relocation when linked as a static PIE, then exits with
+# `argc | argv[0][0] << 8` read back from `result`.
+
+.intel_syntax noprefix
+.globl _start
+
+.text
+_start:
+    mov rbx, [rip + pointer]
+    mov rax, [rsp]                  # argc
+    mov [rbx], rax
+    mov rax, [rsp + 8]              # argv[0]
+    movzx eax, byte ptr [rax]
+    mov [rbx + 8], rax
+    mov rdi, [rbx + 8]
+    shl rdi, 8
+    or rdi, [rbx]
+    mov eax, 2                      # exit(status)
+    syscall
+
+.data
+result:
+    .quad 0, 0
+pointer:
+    .quad result
@@ -0,0 +1,161 @@
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+#[repr(transparent)]
+pub struct PhysAddr(u64);
+
+impl PhysAddr {
+    pub fn new(address: u64) -> Self {
+        assert_eq!(address & 0xfff0_0000_0000_0000, 0,
+                   "Invalid physical address: 0x{:x}", address);
+        PhysAddr(address)
+    }
+
+    /// # Safety
+    ///
+    /// `address` must fit in 52 bits.
+    pub const unsafe fn new_unchecked(address: u64) -> Self {
+        PhysAddr(address)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn align_down<U>(&self, align: U) -> Self
+        where U: Into<u64> {
+        PhysAddr::new(align_down(self.0, align.into()))
+    }
+
+    pub fn is_aligned<U>(&self, align: U) -> bool
+        where U: Into<u64> {
+        self.align_down(align) == *self
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+#[repr(transparent)]
+pub struct VirtAddr(u64);
+
+impl VirtAddr {
+    pub fn new(address: u64) -> Self {
+        assert!(!(0x0000_8000_0000_0000..0xffff_8000_0000_0000).contains(&address),
+                "Invalid virtual address: 0x{:x}", address);
+        VirtAddr(address)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn align_down<U>(&self, align: U) -> Self
+        where U: Into<u64> {
+        VirtAddr::new(align_down(self.0, align.into()))
+    }
+
+    pub fn is_aligned<U>(&self, align: U) -> bool
+        where U: Into<u64> {
+        self.align_down(align) == *self
+    }
+
+    pub fn p4_index(&self) -> usize {
+        ((self.0 >> 39) & 0o777) as usize
+    }
+
+    pub fn p3_index(&self) -> usize {
+        ((self.0 >> 30) & 0o777) as usize
+    }
+    pub fn p2_index(&self) -> usize {
+        ((self.0 >> 21) & 0o777) as usize
+    }
+    pub fn p1_index(&self) -> usize {
+        ((self.0 >> 12) & 0o777) as usize
+    }
+
+    pub fn page_offset(&self) -> usize {
+        (self.0 & 0o7777) as usize
+    }
+}
+
+/// Round `address` down to a multiple of `align`, which must be a power of two.
+pub fn align_down(address: u64, align: u64) -> u64 {
+    address & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn align_down_clears_low_bits() {
+        assert_eq!(align_down(0x1fff, 0x1000), 0x1000);
+        assert_eq!(align_down(0x2000, 0x1000), 0x2000);
+        assert!(PhysAddr::new(0x20_0000).is_aligned(0x20_0000u64));
+        assert!(!VirtAddr::new(0x1234).is_aligned(4096u64));
+    }
+
+    #[test]
+    fn virtual_address_indices() {
+        let address = VirtAddr::new(0xffff_ff00_0010_1234);
+        assert_eq!(address.p4_index(), 510);
+        assert_eq!(address.p3_index(), 0);
+        assert_eq!(address.p2_index(), 0);
+        assert_eq!(address.p1_index(), 0x101);
+        assert_eq!(address.page_offset(), 0x234);
+    }
+
+    #[test]
+    fn physical_addresses_have_52_bits() {
+        assert_eq!(PhysAddr::new((1 << 52) - 1).as_u64(), 0x000f_ffff_ffff_ffff);
+        assert!(PhysAddr::new(1 << 51).is_aligned(0x1000u64));
+    }
+
+    #[test]
+    #[should_panic]
+    fn non_canonical_virtual_address() {
+        VirtAddr::new(0x0000_8000_0000_0000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_physical_address() {
+        PhysAddr::new(1 << 52);
+    }
+
+    fn canonical_address() -> impl Strategy<Value = u64> {
+        prop_oneof![0..0x0000_8000_0000_0000u64, 0xffff_8000_0000_0000..=u64::MAX]
+    }
+
+    proptest! {
+        #[test]
+        fn align_down_is_aligned_and_close(address in any::<u64>(), shift in 0..64u32) {
+            let align = 1u64 << shift;
+            let aligned = align_down(address, align);
+            prop_assert!(aligned <= address);
+            prop_assert_eq!(aligned % align, 0);
+            prop_assert!(address - aligned < align);
+        }
+
+        #[test]
+        fn physical_align_down(address in 0..(1u64 << 52), shift in 0..52u32) {
+            let aligned = PhysAddr::new(address).align_down(1u64 << shift);
+            prop_assert!(aligned.is_aligned(1u64 << shift));
+            prop_assert_eq!(aligned.as_u64(), align_down(address, 1u64 << shift));
+        }
+
+        #[test]
+        fn indices_rebuild_address(address in canonical_address()) {
+            let address = VirtAddr::new(address);
+            for &index in [address.p4_index(), address.p3_index(), address.p2_index(),
+                           address.p1_index()].iter() {
+                prop_assert!(index < 512);
+            }
+            prop_assert!(address.page_offset() < 4096);
+
+            let rebuilt = (address.p4_index() as u64) << 39 | (address.p3_index() as u64) << 30 |
+                (address.p2_index() as u64) << 21 | (address.p1_index() as u64) << 12 |
+                address.page_offset() as u64;
+            let sign_extended = if rebuilt & 1 << 47 != 0 { rebuilt | 0xffff_0000_0000_0000 } else { rebuilt };
+            prop_assert_eq!(sign_extended, address.as_u64());
+        }
+    }
+}
@@ -0,0 +1,596 @@
+//! ANSI/VT100 terminal emulation shared by the text consoles.
+//!
+//! A `Parser` splits the output into printable characters, control characters and escape
+//! sequences. A `Terminal` interprets them, keeping the cursor and the colors, and draws on a
+//! `Display` such as the VGA text buffer or the framebuffer:
+//!
+//! ```ignore
+//! terminal.write_str(&mut display, "\x1b[2J\x1b[1;31mError:\x1b[0m disk not found\n");
+//! ```
+//!
+//! Supported are the SGR colors and attributes (bold, reverse, 30-37, 40-47, 90-97, 100-107 and
+//! the defaults), cursor movement (CUP, CUU, CUD, CUF, CUB), erasing (ED, EL) and saving and
+//! restoring the cursor with `ESC 7`/`ESC 8` or `CSI s`/`CSI u`. Anything else is ignored.
+
+use crate::framebuffer::Rgb;
+
+const ESCAPE: char = '\x1b';
+pub const MAX_PARAMS: usize = 16;
+const TAB_WIDTH: usize = 8;
+
+/// One of the 16 colors of the ANSI palette, in its order: black, red, green, yellow, blue,
+/// magenta, cyan and white, followed by their bright variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnsiColor(u8);
+
+impl AnsiColor {
+    pub const BLACK: AnsiColor = AnsiColor(0);
+    pub const RED: AnsiColor = AnsiColor(1);
+    pub const GREEN: AnsiColor = AnsiColor(2);
+    pub const YELLOW: AnsiColor = AnsiColor(3);
+    pub const BLUE: AnsiColor = AnsiColor(4);
+    pub const MAGENTA: AnsiColor = AnsiColor(5);
+    pub const CYAN: AnsiColor = AnsiColor(6);
+    pub const WHITE: AnsiColor = AnsiColor(7);
+
+    /// The color with `index` modulo 16.
+    pub const fn new(index: u8) -> Self {
+        AnsiColor(index & 0xf)
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_bright(self) -> bool {
+        self.0 >= 8
+    }
+
+    pub fn bright(self) -> Self {
+        AnsiColor(self.0 | 8)
+    }
+
+    /// The color as shown by the VGA text mode.
+    pub fn rgb(self) -> Rgb {
+        const PALETTE: [Rgb; 16] = [
+            Rgb::new(0x00, 0x00, 0x00), Rgb::new(0xaa, 0x00, 0x00),
+            Rgb::new(0x00, 0xaa, 0x00), Rgb::new(0xaa, 0x55, 0x00),
+            Rgb::new(0x00, 0x00, 0xaa), Rgb::new(0xaa, 0x00, 0xaa),
+            Rgb::new(0x00, 0xaa, 0xaa), Rgb::new(0xaa, 0xaa, 0xaa),
+            Rgb::new(0x55, 0x55, 0x55), Rgb::new(0xff, 0x55, 0x55),
+            Rgb::new(0x55, 0xff, 0x55), Rgb::new(0xff, 0xff, 0x55),
+            Rgb::new(0x55, 0x55, 0xff), Rgb::new(0xff, 0x55, 0xff),
+            Rgb::new(0x55, 0xff, 0xff), Rgb::new(0xff, 0xff, 0xff),
+        ];
+        PALETTE[self.0 as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub foreground: AnsiColor,
+    pub background: AnsiColor,
+    /// Shown as the bright variant of the foreground.
+    pub bold: bool,
+    /// Swaps the foreground and the background.
+    pub reverse: bool,
+}
+
+impl Attributes {
+    pub const DEFAULT: Attributes = Attributes {
+        foreground: AnsiColor::WHITE,
+        background: AnsiColor::BLACK,
+        bold: false,
+        reverse: false,
+    };
+
+    /// The foreground and background colors to show.
+    pub fn colors(&self) -> (AnsiColor, AnsiColor) {
+        let foreground = if self.bold { self.foreground.bright() } else { self.foreground };
+        if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        }
+    }
+}
+
+/// A character with its colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: AnsiColor,
+    pub background: AnsiColor,
+}
+
+impl Cell {
+    pub fn new(character: char, attributes: &Attributes) -> Self {
+        let (foreground, background) = attributes.colors();
+        Cell { character, foreground, background }
+    }
+}
+
+/// The parameters of a control sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Params {
+    pub fn as_slice(&self) -> &[u16] {
+        &self.values[..self.len]
+    }
+
+    /// Parameter `index`, or `default` if it is missing or zero.
+    pub fn get(&self, index: usize, default: u16) -> u16 {
+        match self.as_slice().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Print(char),
+    /// A C0 control character such as `\n`.
+    Execute(char),
+    /// `ESC` followed by `final_char`.
+    Escape(char),
+    /// A control sequence, `ESC [` followed by the parameters and `final_char`. `private` ones
+    /// start with one of `<=>?`.
+    Csi { params: Params, private: bool, final_char: char },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Skipping the rest of a malformed control sequence.
+    CsiIgnore,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parser {
+    state: State,
+    params: Params,
+    private: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            params: Params { values: [0; MAX_PARAMS], len: 0 },
+            private: false,
+        }
+    }
+
+    /// Feed a character, returning the action it completes.
+    pub fn advance(&mut self, character: char) -> Option<Action> {
+        if character == ESCAPE {
+            // Escape aborts any sequence in progress.
+            self.state = State::Escape;
+            return None;
+        }
+        if character < ' ' || character == '\x7f' {
+            // Controls are executed even in the middle of a sequence.
+            return if character == '\x7f' { None } else { Some(Action::Execute(character)) };
+        }
+        match self.state {
+            State::Ground => Some(Action::Print(character)),
+            State::Escape if character == '[' => {
+                self.state = State::Csi;
+                self.params = Params { values: [0; MAX_PARAMS], len: 0 };
+                self.private = false;
+                None
+            }
+            State::Escape => {
+                self.state = State::Ground;
+                Some(Action::Escape(character))
+            }
+            State::Csi => self.csi(character),
+            State::CsiIgnore => {
+                if is_final(character) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+        }
+    }
+
+    fn csi(&mut self, character: char) -> Option<Action> {
+        let params = &mut self.params;
+        match character {
+            '0'..='9' => {
+                if params.len == 0 {
+                    params.len = 1;
+                }
+                let value = &mut params.values[params.len - 1];
+                *value = value.saturating_mul(10).saturating_add(character as u16 - '0' as u16);
+            }
+            ';' => {
+                if params.len == 0 {
+                    params.len = 1;
+                }
+                if params.len == MAX_PARAMS {
+                    self.state = State::CsiIgnore;
+                } else {
+                    params.len += 1;
+                }
+            }
+            '<'..='?' if params.len == 0 && !self.private => self.private = true,
+            _ if is_final(character) => {
+                self.state = State::Ground;
+                return Some(Action::Csi { params: self.params, private: self.private,
+                                          final_char: character });
+            }
+            _ => self.state = State::CsiIgnore,
+        }
+        None
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+fn is_final(character: char) -> bool {
+    ('@'..='~').contains(&character)
+}
+
+/// A grid of character cells a `Terminal` draws on.
+pub trait Display {
+    /// The number of columns and rows.
+    fn size(&self) -> (usize, usize);
+    fn put(&mut self, column: usize, row: usize, cell: Cell);
+    /// Move all rows up by one, discarding the first and filling the last with `blank`.
+    fn scroll_up(&mut self, blank: Cell);
+    fn move_cursor(&mut self, column: usize, row: usize);
+    /// Hide the cursor until the next `move_cursor`.
+    fn hide_cursor(&mut self);
+}
+
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    parser: Parser,
+    column: usize,
+    row: usize,
+    attributes: Attributes,
+    saved: (usize, usize, Attributes),
+}
+
+impl Terminal {
+    pub const fn new() -> Self {
+        Terminal {
+            parser: Parser::new(),
+            column: 0,
+            row: 0,
+            attributes: Attributes::DEFAULT,
+            saved: (0, 0, Attributes::DEFAULT),
+        }
+    }
+
+    /// The column and row of the cursor.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
+
+    pub fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+    }
+
+    pub fn write_str<D: Display + ?Sized>(&mut self, display: &mut D, s: &str) {
+        for character in s.chars() {
+            self.perform(display, character);
+        }
+        display.move_cursor(self.column, self.row);
+    }
+
+    pub fn write_char<D: Display + ?Sized>(&mut self, display: &mut D, character: char) {
+        self.perform(display, character);
+        display.move_cursor(self.column, self.row);
+    }
+
+    /// Erase the display and move the cursor to the top left corner.
+    pub fn clear<D: Display + ?Sized>(&mut self, display: &mut D) {
+        let rows = display.size().1;
+        self.erase(display, 0, 0, 0, rows);
+        self.column = 0;
+        self.row = 0;
+        display.move_cursor(0, 0);
+    }
+
+    fn perform<D: Display + ?Sized>(&mut self, display: &mut D, character: char) {
+        match self.parser.advance(character) {
+            Some(Action::Print(character)) => self.print(display, character),
+            Some(Action::Execute(control)) => self.execute(display, control),
+            Some(Action::Escape('7')) => self.save_cursor(),
+            Some(Action::Escape('8')) => self.restore_cursor(display),
+            Some(Action::Csi { params, private: false, final_char }) => {
+                self.control_sequence(display, &params, final_char)
+            }
+            _ => {}
+        }
+    }
+
+    fn print<D: Display + ?Sized>(&mut self, display: &mut D, character: char) {
+        display.put(self.column, self.row, Cell::new(character, &self.attributes));
+        self.column += 1;
+        if self.column >= display.size().0 {
+            self.new_line(display);
+        }
+    }
+
+    fn execute<D: Display + ?Sized>(&mut self, display: &mut D, control: char) {
+        match control {
+            '\n' => self.new_line(display),
+            '\r' => self.column = 0,
+            '\x08' => self.backspace(display),
+            '\t' => {
+                // Up to the next tab stop, but not beyond the end of the line.
+                let columns = display.size().0;
+                let spaces = (TAB_WIDTH - self.column % TAB_WIDTH).min(columns - self.column);
+                for _ in 0..spaces {
+                    self.print(display, ' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn new_line<D: Display + ?Sized>(&mut self, display: &mut D) {
+        self.column = 0;
+        if self.row + 1 < display.size().1 {
+            self.row += 1;
+        } else {
+            display.scroll_up(Cell::new(' ', &self.attributes));
+        }
+    }
+
+    /// Move back one column, wrapping to the end of the previous line, and erase the character.
+    fn backspace<D: Display + ?Sized>(&mut self, display: &mut D) {
+        if self.column > 0 {
+            self.column -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.column = display.size().0 - 1;
+        } else {
+            return;
+        }
+        display.put(self.column, self.row, Cell::new(' ', &self.attributes));
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.column, self.row, self.attributes);
+    }
+
+    fn restore_cursor<D: Display + ?Sized>(&mut self, display: &mut D) {
+        let (columns, rows) = display.size();
+        let (column, row, attributes) = self.saved;
+        self.column = column.min(columns - 1);
+        self.row = row.min(rows - 1);
+        self.attributes = attributes;
+    }
+
+    fn control_sequence<D: Display + ?Sized>(&mut self, display: &mut D, params: &Params,
+                                             final_char: char) {
+        let (columns, rows) = display.size();
+        let count = usize::from(params.get(0, 1));
+        match final_char {
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.row = (self.row + count).min(rows - 1),
+            'C' => self.column = (self.column + count).min(columns - 1),
+            'D' => self.column = self.column.saturating_sub(count),
+            'H' | 'f' => {
+                self.row = (usize::from(params.get(0, 1)) - 1).min(rows - 1);
+                self.column = (usize::from(params.get(1, 1)) - 1).min(columns - 1);
+            }
+            'J' => {
+                let (column, row) = (self.column, self.row);
+                match params.as_slice().first().cloned().unwrap_or(0) {
+                    0 => self.erase(display, column, row, 0, rows),
+                    1 => self.erase(display, 0, 0, column + 1, row),
+                    _ => self.erase(display, 0, 0, 0, rows),
+                }
+            }
+            'K' => {
+                let (column, row) = (self.column, self.row);
+                match params.as_slice().first().cloned().unwrap_or(0) {
+                    0 => self.erase(display, column, row, columns, row),
+                    1 => self.erase(display, 0, row, column + 1, row),
+                    _ => self.erase(display, 0, row, columns, row),
+                }
+            }
+            'm' => self.select_graphic_rendition(params),
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(display),
+            _ => {}
+        }
+    }
+
+    /// Erase from `(column, row)` up to, but excluding, `(end_column, end_row)`, in reading
+    /// order.
+    fn erase<D: Display + ?Sized>(&self, display: &mut D, column: usize, row: usize,
+                                  end_column: usize, end_row: usize) {
+        let columns = display.size().0;
+        let blank = Cell::new(' ', &self.attributes);
+        let (start, end) = (row * columns + column, end_row * columns + end_column);
+        for index in start..end.max(start) {
+            display.put(index % columns, index / columns, blank);
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        let attributes = &mut self.attributes;
+        if params.as_slice().is_empty() {
+            *attributes = Attributes::DEFAULT;
+        }
+        for &param in params.as_slice() {
+            match param {
+                0 => *attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                7 => attributes.reverse = true,
+                22 => attributes.bold = false,
+                27 => attributes.reverse = false,
+                30..=37 => attributes.foreground = AnsiColor::new((param - 30) as u8),
+                39 => attributes.foreground = Attributes::DEFAULT.foreground,
+                40..=47 => attributes.background = AnsiColor::new((param - 40) as u8),
+                49 => attributes.background = Attributes::DEFAULT.background,
+                90..=97 => attributes.foreground = AnsiColor::new((param - 90) as u8).bright(),
+                100..=107 => {
+                    attributes.background = AnsiColor::new((param - 100) as u8).bright()
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Terminal::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    struct TestDisplay {
+        cells: Vec<Vec<Cell>>,
+        cursor: Option<(usize, usize)>,
+    }
+
+    impl TestDisplay {
+        fn new(columns: usize, rows: usize) -> Self {
+            let blank = Cell::new(' ', &Attributes::DEFAULT);
+            TestDisplay { cells: vec![vec![blank; columns]; rows], cursor: None }
+        }
+
+        fn text(&self) -> Vec<String> {
+            self.cells.iter().map(|row| row.iter().map(|cell| cell.character).collect())
+                .collect()
+        }
+    }
+
+    impl Display for TestDisplay {
+        fn size(&self) -> (usize, usize) {
+            (self.cells[0].len(), self.cells.len())
+        }
+
+        fn put(&mut self, column: usize, row: usize, cell: Cell) {
+            self.cells[row][column] = cell;
+        }
+
+        fn scroll_up(&mut self, blank: Cell) {
+            self.cells.remove(0);
+            let columns = self.size().0;
+            self.cells.push(vec![blank; columns]);
+        }
+
+        fn move_cursor(&mut self, column: usize, row: usize) {
+            self.cursor = Some((column, row));
+        }
+
+        fn hide_cursor(&mut self) {
+            self.cursor = None;
+        }
+    }
+
+    fn run(input: &str) -> (Terminal, TestDisplay) {
+        let mut terminal = Terminal::new();
+        let mut display = TestDisplay::new(6, 3);
+        terminal.write_str(&mut display, input);
+        (terminal, display)
+    }
+
+    fn actions(input: &str) -> Vec<Action> {
+        let mut parser = Parser::new();
+        input.chars().filter_map(|character| parser.advance(character)).collect()
+    }
+
+    #[test]
+    fn parses_sequences() {
+        let csi = |values: &[u16], private, final_char| {
+            let mut params = Params { values: [0; MAX_PARAMS], len: values.len() };
+            params.values[..values.len()].copy_from_slice(values);
+            Action::Csi { params, private, final_char }
+        };
+        assert_eq!(actions("a\x1b[1;31mb"), [Action::Print('a'), csi(&[1, 31], false, 'm'),
+                                             Action::Print('b')]);
+        assert_eq!(actions("\x1b[H\x1b[;5H\x1b[?25l"), [csi(&[], false, 'H'),
+                                                        csi(&[0, 5], false, 'H'),
+                                                        csi(&[25], true, 'l')]);
+        assert_eq!(actions("\x1b7\x1b[1\n2J"), [Action::Escape('7'), Action::Execute('\n'),
+                                                csi(&[12], false, 'J')]);
+        // Malformed and interrupted sequences are dropped.
+        assert_eq!(actions("\x1b[1!?mx\x1b[3\x1b[2K"), [Action::Print('x'),
+                                                       csi(&[2], false, 'K')]);
+    }
+
+    #[test]
+    fn prints_wraps_and_scrolls() {
+        let (terminal, display) = run("abcdefg\nhi\tj\r\nk\x08l");
+        assert_eq!(display.text(), ["hi    ", "j     ", "l     "]);
+        assert_eq!(terminal.cursor(), (1, 2));
+        assert_eq!(display.cursor, Some((1, 2)));
+    }
+
+    #[test]
+    fn moves_cursor() {
+        let input = "\x1b[2;3Ha\x1b[Ab\x1b[9C\x1b[3Dc\x1b[5Bd\x1b[99;99H\x1b[4De";
+        let (terminal, display) = run(input);
+        assert_eq!(display.text(), ["  cb  ", "  a   ", " e d  "]);
+        assert_eq!(terminal.cursor(), (2, 2));
+        let (_, display) = run("ab\x1b[sc\x1b[3;1Hd\x1b[ue\x1b7\x1b[Hf\x1b8g");
+        assert_eq!(display.text(), ["fbeg  ", "      ", "d     "]);
+    }
+
+    #[test]
+    fn erases() {
+        let full = "abcdefghijklmnopq";
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[J", full));
+        assert_eq!(display.text(), ["abcdef", "gh    ", "      "]);
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[1J", full));
+        assert_eq!(display.text(), ["      ", "   jkl", "mnopq "]);
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[2J", full));
+        assert_eq!(display.text(), ["      ", "      ", "      "]);
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[K", full));
+        assert_eq!(display.text(), ["abcdef", "gh    ", "mnopq "]);
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[1K", full));
+        assert_eq!(display.text(), ["abcdef", "   jkl", "mnopq "]);
+        let (_, display) = run(&format!("{}\x1b[2;3H\x1b[2K", full));
+        assert_eq!(display.text(), ["abcdef", "      ", "mnopq "]);
+    }
+
+    #[test]
+    fn selects_colors() {
+        let (terminal, display) = run("a\x1b[31;44mb\x1b[1mc\x1b[7md\x1b[0;92;101me\x1b[me");
+        let colors: Vec<_> = display.cells[0][..6].iter()
+            .map(|cell| (cell.foreground.index(), cell.background.index()))
+            .collect();
+        assert_eq!(colors, [(7, 0), (1, 4), (9, 4), (4, 9), (10, 9), (7, 0)]);
+        assert_eq!(terminal.attributes(), Attributes::DEFAULT);
+        assert_eq!(AnsiColor::YELLOW.bright().rgb(), Rgb::new(0xff, 0xff, 0x55));
+    }
+
+    proptest! {
+        #[test]
+        fn cursor_stays_on_display(input in "[a-c\n\r\t\x08\x1b\\[0-9;HJKABCDfmsu78]{0,200}") {
+            let (terminal, display) = run(&input);
+            let (column, row) = terminal.cursor();
+            prop_assert!(column < 6 && row < 3);
+            prop_assert_eq!(display.cursor, Some((column, row)));
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Ring buffer delivering every value to each of its subscribers.
+//!
+//! The values are stored once and every subscriber has its own read position. The producer never
+//! waits: a subscriber which falls more than `BROADCAST_CAPACITY` values behind loses the oldest
+//! ones, and `take_dropped` tells it how many.
+
+pub const BROADCAST_CAPACITY: usize = 256;
+pub const MAX_SUBSCRIBERS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    /// Number of values published before the next one to read, wrapping.
+    position: usize,
+    dropped: usize,
+}
+
+pub struct Broadcast<T> {
+    slots: [T; BROADCAST_CAPACITY],
+    /// Number of values published so far, wrapping.
+    head: usize,
+    cursors: [Option<Cursor>; MAX_SUBSCRIBERS],
+}
+
+impl<T: Copy> Broadcast<T> {
+    /// Create a buffer without subscribers. `slots` only provides the storage; its values are
+    /// never returned.
+    pub const fn new(slots: [T; BROADCAST_CAPACITY]) -> Self {
+        Broadcast { slots, head: 0, cursors: [None; MAX_SUBSCRIBERS] }
+    }
+
+    /// Register a subscriber, which receives the values published from now on. Returns `None`
+    /// if there are already `MAX_SUBSCRIBERS`.
+    pub fn subscribe(&mut self) -> Option<usize> {
+        let index = self.cursors.iter().position(|cursor| cursor.is_none())?;
+        self.cursors[index] = Some(Cursor { position: self.head, dropped: 0 });
+        Some(index)
+    }
+
+    pub fn unsubscribe(&mut self, subscriber: usize) {
+        self.cursors[subscriber] = None;
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.cursors.iter().filter(|cursor| cursor.is_some()).count()
+    }
+
+    pub fn publish(&mut self, value: T) {
+        self.slots[self.head % BROADCAST_CAPACITY] = value;
+        self.head = self.head.wrapping_add(1);
+    }
+
+    /// Take the oldest value `subscriber` has not seen yet.
+    pub fn pop(&mut self, subscriber: usize) -> Option<T> {
+        let cursor = Self::cursor(&mut self.cursors, subscriber, self.head);
+        if cursor.position == self.head {
+            return None;
+        }
+        let value = self.slots[cursor.position % BROADCAST_CAPACITY];
+        cursor.position = cursor.position.wrapping_add(1);
+        Some(value)
+    }
+
+    /// Number of values `subscriber` has lost since the last call.
+    pub fn take_dropped(&mut self, subscriber: usize) -> usize {
+        let cursor = Self::cursor(&mut self.cursors, subscriber, self.head);
+        let dropped = cursor.dropped;
+        cursor.dropped = 0;
+        dropped
+    }
+
+    /// The cursor of `subscriber`, moved past the values which have been overwritten.
+    fn cursor(cursors: &mut [Option<Cursor>; MAX_SUBSCRIBERS], subscriber: usize, head: usize)
+        -> &mut Cursor {
+        let cursor = cursors[subscriber].as_mut().expect("not subscribed");
+        let behind = head.wrapping_sub(cursor.position);
+        if behind > BROADCAST_CAPACITY {
+            cursor.dropped += behind - BROADCAST_CAPACITY;
+            cursor.position = head.wrapping_sub(BROADCAST_CAPACITY);
+        }
+        cursor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::VecDeque;
+
+    fn broadcast() -> Broadcast<u32> {
+        Broadcast::new([0; BROADCAST_CAPACITY])
+    }
+
+    #[test]
+    fn every_subscriber_sees_every_value() {
+        let mut broadcast = broadcast();
+        broadcast.publish(0);
+        let first = broadcast.subscribe().unwrap();
+        broadcast.publish(1);
+        let second = broadcast.subscribe().unwrap();
+        broadcast.publish(2);
+
+        assert_eq!(broadcast.pop(first), Some(1));
+        assert_eq!(broadcast.pop(first), Some(2));
+        assert_eq!(broadcast.pop(first), None);
+        assert_eq!(broadcast.pop(second), Some(2));
+        assert_eq!(broadcast.pop(second), None);
+    }
+
+    #[test]
+    fn subscribers_are_limited() {
+        let mut broadcast = broadcast();
+        for _ in 0..MAX_SUBSCRIBERS {
+            broadcast.subscribe().unwrap();
+        }
+        assert_eq!(broadcast.subscribe(), None);
+        broadcast.unsubscribe(3);
+        assert_eq!(broadcast.subscribers(), MAX_SUBSCRIBERS - 1);
+        assert_eq!(broadcast.subscribe(), Some(3));
+    }
+
+    #[test]
+    fn slow_subscribers_lose_oldest_values() {
+        let mut broadcast = broadcast();
+        let subscriber = broadcast.subscribe().unwrap();
+        for value in 0..BROADCAST_CAPACITY as u32 + 10 {
+            broadcast.publish(value);
+        }
+        assert_eq!(broadcast.take_dropped(subscriber), 10);
+        assert_eq!(broadcast.take_dropped(subscriber), 0);
+        assert_eq!(broadcast.pop(subscriber), Some(10));
+    }
+
+    proptest! {
+        #[test]
+        fn behaves_like_deques(operations in prop::collection::vec(any::<Option<bool>>(), 0..2000)) {
+            let mut broadcast = broadcast();
+            let subscribers = [broadcast.subscribe().unwrap(), broadcast.subscribe().unwrap()];
+            let mut models = [VecDeque::new(), VecDeque::new()];
+            let mut dropped = [0, 0];
+            for (value, operation) in operations.into_iter().enumerate() {
+                match operation {
+                    None => {
+                        broadcast.publish(value as u32);
+                        for (model, dropped) in models.iter_mut().zip(dropped.iter_mut()) {
+                            model.push_back(value as u32);
+                            if model.len() > BROADCAST_CAPACITY {
+                                model.pop_front();
+                                *dropped += 1;
+                            }
+                        }
+                    }
+                    Some(second) => {
+                        let index = second as usize;
+                        prop_assert_eq!(broadcast.pop(subscribers[index]),
+                                        models[index].pop_front());
+                    }
+                }
+            }
+            for index in 0..2 {
+                prop_assert_eq!(broadcast.take_dropped(subscribers[index]), dropped[index]);
+            }
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! Double-buffered 2D drawing on a framebuffer.
+//!
+//! A `Canvas` draws into a back buffer in ordinary memory, where reading pixels for alpha
+//! blending or scrolling is fast, and remembers which rectangles changed. `present` then copies
+//! only those to the framebuffer:
+//!
+//! ```ignore
+//! canvas.fill_rect(Rect::new(10, 10, 200, 100), Rgb::new(0, 0, 0x80));
+//! canvas.draw_line(10, 110, 210, 10, Rgb::WHITE);
+//! canvas.blend_rect(Rect::new(60, 40, 100, 40), Rgba::new(0xff, 0, 0, 0x80));
+//! canvas.present();
+//! ```
+//!
+//! Coordinates are signed so that shapes may lie partly outside of the canvas; everything is
+//! clipped to its bounds.
+
+use crate::framebuffer::{Rgb, Surface};
+use crate::psf::Glyph;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    /// 0 is transparent, 0xff opaque.
+    pub alpha: u8,
+}
+
+impl Rgba {
+    pub const fn new(red: u8, green: u8, blue: u8, alpha: u8) -> Self {
+        Rgba { red, green, blue, alpha }
+    }
+
+    /// `self` drawn over `under`.
+    pub fn blend(self, under: Rgb) -> Rgb {
+        let alpha = u32::from(self.alpha);
+        let mix = |over: u8, under: u8| {
+            ((u32::from(over) * alpha + u32::from(under) * (0xff - alpha) + 0x7f) / 0xff) as u8
+        };
+        Rgb::new(mix(self.red, under.red), mix(self.green, under.green),
+                 mix(self.blue, under.blue))
+    }
+}
+
+impl From<Rgb> for Rgba {
+    fn from(color: Rgb) -> Self {
+        Rgba::new(color.red, color.green, color.blue, 0xff)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    /// A negative size is taken as zero.
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect { x, y, width: width.max(0), height: height.max(0) }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn area(&self) -> i64 {
+        i64::from(self.width) * i64::from(self.height)
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y && other.right() <= self.right() &&
+            other.bottom() <= self.bottom()
+    }
+
+    /// The overlap of both rectangles, if any.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let rect = Rect::new(x, y, self.right().min(other.right()) - x,
+                             self.bottom().min(other.bottom()) - y);
+        if rect.is_empty() { None } else { Some(rect) }
+    }
+
+    /// The smallest rectangle containing both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// Whether the rectangles overlap or share an edge, so that their union covers nothing else.
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() && other.x <= self.right() && self.y <= other.bottom() &&
+            other.y <= self.bottom()
+    }
+}
+
+/// An image in memory for `Canvas::blit`, in rows from the top.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [Rgba],
+}
+
+impl<'a> Image<'a> {
+    /// Panics if `pixels` does not hold `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: &'a [Rgba]) -> Self {
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        Image { width, height, pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgba {
+        self.pixels[y * self.width + x]
+    }
+}
+
+pub const MAX_DIRTY_RECTS: usize = 16;
+
+/// The regions changed since the last `Canvas::present`. Overlapping and adjacent rectangles
+/// are merged, and when there are too many, the two whose union adds the least area.
+#[derive(Debug, Clone)]
+pub struct DirtyRects {
+    rects: [Rect; MAX_DIRTY_RECTS],
+    len: usize,
+}
+
+impl DirtyRects {
+    pub const fn new() -> Self {
+        DirtyRects { rects: [Rect { x: 0, y: 0, width: 0, height: 0 }; MAX_DIRTY_RECTS], len: 0 }
+    }
+
+    pub fn as_slice(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() || self.as_slice().iter().any(|dirty| dirty.contains(&rect)) {
+            return;
+        }
+        let mut rect = rect;
+        loop {
+            // Merging may make the rectangle touch others it did not before.
+            while let Some(index) = self.as_slice().iter().position(|dirty| dirty.touches(&rect)) {
+                rect = rect.union(&self.remove(index));
+            }
+            if self.len < MAX_DIRTY_RECTS {
+                break;
+            }
+            let growth = |dirty: &Rect| dirty.union(&rect).area() - dirty.area() - rect.area();
+            let index = (0..self.len).min_by_key(|&index| growth(&self.rects[index])).unwrap();
+            rect = rect.union(&self.remove(index));
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) -> Rect {
+        let rect = self.rects[index];
+        self.len -= 1;
+        self.rects[index] = self.rects[self.len];
+        rect
+    }
+}
+
+impl Default for DirtyRects {
+    fn default() -> Self {
+        DirtyRects::new()
+    }
+}
+
+pub struct Canvas<'a> {
+    back: Surface<'a>,
+    front: Surface<'a>,
+    dirty: DirtyRects,
+}
+
+impl<'a> Canvas<'a> {
+    /// Draw into `back` and present on `front`. Panics if their sizes or formats differ.
+    pub fn new(back: Surface<'a>, front: Surface<'a>) -> Self {
+        assert_eq!(back.info(), front.info(), "back and front buffer differ");
+        Canvas { back, front, dirty: DirtyRects::new() }
+    }
+
+    pub fn width(&self) -> usize {
+        self.back.info().width
+    }
+
+    pub fn height(&self) -> usize {
+        self.back.info().height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width() as i32, self.height() as i32)
+    }
+
+    /// The regions which `present` will copy to the framebuffer.
+    pub fn dirty_rects(&self) -> &[Rect] {
+        self.dirty.as_slice()
+    }
+
+    /// Clip `rect` to the canvas and mark the visible part dirty.
+    fn touch(&mut self, rect: Rect) -> Option<Rect> {
+        let visible = rect.intersection(&self.bounds())?;
+        self.dirty.add(visible);
+        Some(visible)
+    }
+
+    /// The pixel at `(x, y)` of the back buffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb {
+        self.back.pixel(x, y)
+    }
+
+    pub fn put_pixel(&mut self, x: i32, y: i32, color: Rgb) {
+        if self.touch(Rect::new(x, y, 1, 1)).is_some() {
+            self.back.put_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Rgba) {
+        if self.touch(Rect::new(x, y, 1, 1)).is_some() {
+            let (x, y) = (x as usize, y as usize);
+            let under = self.back.pixel(x, y);
+            self.back.put_pixel(x, y, color.blend(under));
+        }
+    }
+
+    /// Draw a line from `(x0, y0)` to `(x1, y1)`, both ends included.
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Rgb) {
+        let rect = Rect::new(x0.min(x1), y0.min(y1), (x1 - x0).abs() + 1, (y1 - y0).abs() + 1);
+        if self.touch(rect).is_none() {
+            return;
+        }
+        // Bresenham's algorithm, for all octants.
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (step_x, step_y) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            if x >= 0 && y >= 0 {
+                self.back.put_pixel(x as usize, y as usize, color);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Rgb) {
+        if let Some(rect) = self.touch(rect) {
+            self.back.fill_rect(rect.x as usize, rect.y as usize, rect.width as usize,
+                                rect.height as usize, color);
+        }
+    }
+
+    /// Draw the outline of `rect`, one pixel wide.
+    pub fn draw_rect(&mut self, rect: Rect, color: Rgb) {
+        if rect.is_empty() {
+            return;
+        }
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+        self.draw_line(rect.x, rect.y, right, rect.y, color);
+        self.draw_line(rect.x, bottom, right, bottom, color);
+        self.draw_line(rect.x, rect.y, rect.x, bottom, color);
+        self.draw_line(right, rect.y, right, bottom, color);
+    }
+
+    /// Fill `rect` with a translucent color.
+    pub fn blend_rect(&mut self, rect: Rect, color: Rgba) {
+        if let Some(rect) = self.touch(rect) {
+            for y in rect.y..rect.bottom() {
+                for x in rect.x..rect.right() {
+                    let (x, y) = (x as usize, y as usize);
+                    let under = self.back.pixel(x, y);
+                    self.back.put_pixel(x, y, color.blend(under));
+                }
+            }
+        }
+    }
+
+    /// Draw `image` with its top left corner at `(x, y)`, blending translucent pixels.
+    pub fn blit(&mut self, x: i32, y: i32, image: &Image) {
+        let rect = Rect::new(x, y, image.width() as i32, image.height() as i32);
+        let visible = match self.touch(rect) {
+            Some(visible) => visible,
+            None => return,
+        };
+        for row in visible.y..visible.bottom() {
+            for column in visible.x..visible.right() {
+                let color = image.pixel((column - x) as usize, (row - y) as usize);
+                let (column, row) = (column as usize, row as usize);
+                match color.alpha {
+                    0 => {}
+                    0xff => self.back.put_pixel(column, row, Rgb::new(color.red, color.green,
+                                                                      color.blue)),
+                    _ => {
+                        let under = self.back.pixel(column, row);
+                        self.back.put_pixel(column, row, color.blend(under));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw `glyph` with its top left corner at `(x, y)`, in `foreground` on `background`.
+    pub fn draw_glyph(&mut self, x: i32, y: i32, glyph: &Glyph, foreground: Rgb,
+                      background: Rgb) {
+        let rect = Rect::new(x, y, glyph.width() as i32, glyph.height() as i32);
+        if self.bounds().contains(&rect) {
+            self.touch(rect);
+            self.back.draw_glyph(x as usize, y as usize, glyph, foreground, background);
+        }
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        let bounds = self.bounds();
+        self.fill_rect(bounds, color);
+    }
+
+    /// Move the whole content up by `lines` pixels, filling the lines at the bottom with `color`.
+    pub fn scroll_up(&mut self, lines: usize, color: Rgb) {
+        self.back.scroll_up(lines, color);
+        let bounds = self.bounds();
+        self.touch(bounds);
+    }
+
+    /// Copy the changed regions to the framebuffer.
+    pub fn present(&mut self) {
+        for rect in self.dirty.as_slice() {
+            self.front.copy_from(&self.back, rect.x as usize, rect.y as usize,
+                                 rect.width as usize, rect.height as usize);
+        }
+        self.dirty.clear();
+    }
+
+    /// Copy the whole back buffer to the framebuffer, e.g. after something else drew on it.
+    pub fn present_all(&mut self) {
+        let bounds = self.bounds();
+        self.dirty.add(bounds);
+        self.present();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{FramebufferInfo, PixelFormat};
+    use proptest::prelude::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 8;
+    const RED: Rgb = Rgb::new(0xff, 0, 0);
+
+    fn info() -> FramebufferInfo {
+        FramebufferInfo { width: WIDTH, height: HEIGHT, pitch: WIDTH * 4,
+                          format: PixelFormat::XRGB8888 }
+    }
+
+    fn buffers() -> (Vec<u8>, Vec<u8>) {
+        (vec![0; info().size()], vec![0; info().size()])
+    }
+
+    fn canvas<'a>(back: &'a mut [u8], front: &'a mut [u8]) -> Canvas<'a> {
+        Canvas::new(Surface::new(back, info()), Surface::new(front, info()))
+    }
+
+    /// The back buffer as text, `#` for white, `.` for black and `-` for anything else.
+    fn render(canvas: &Canvas) -> Vec<String> {
+        (0..HEIGHT).map(|y| (0..WIDTH).map(|x| match canvas.pixel(x, y) {
+            Rgb::WHITE => '#',
+            Rgb::BLACK => '.',
+            _ => '-',
+        }).collect()).collect()
+    }
+
+    #[test]
+    fn blends_colors() {
+        assert_eq!(Rgba::new(0xff, 0, 0, 0x80).blend(Rgb::new(0, 0, 0xff)),
+                   Rgb::new(0x80, 0, 0x7f));
+        assert_eq!(Rgba::new(1, 2, 3, 0).blend(RED), RED);
+        assert_eq!(Rgba::from(RED).blend(Rgb::WHITE), RED);
+    }
+
+    #[test]
+    fn rectangles() {
+        let a = Rect::new(0, 0, 4, 4);
+        let b = Rect::new(2, 3, 4, 4);
+        assert_eq!(a.intersection(&b), Some(Rect::new(2, 3, 2, 1)));
+        assert_eq!(a.union(&b), Rect::new(0, 0, 6, 7));
+        assert_eq!(a.intersection(&Rect::new(4, 0, 1, 1)), None);
+        assert!(a.contains(&Rect::new(1, 1, 3, 3)));
+        assert!(!a.contains(&b));
+        assert!(Rect::new(3, 3, -1, 5).is_empty());
+    }
+
+    #[test]
+    fn draws_lines_and_rectangles() {
+        let (mut back, mut front) = buffers();
+        let mut canvas = canvas(&mut back, &mut front);
+        canvas.draw_line(0, 0, 6, 3, Rgb::WHITE);
+        canvas.draw_rect(Rect::new(8, 1, 5, 4), Rgb::WHITE);
+        canvas.fill_rect(Rect::new(14, 5, 10, 10), Rgb::WHITE);
+        canvas.draw_line(-2, 7, 2, 7, Rgb::WHITE);
+        assert_eq!(render(&canvas), [
+            "#...............",
+            ".##.....#####...",
+            "...##...#...#...",
+            ".....##.#...#...",
+            "........#####...",
+            "..............##",
+            "..............##",
+            "###...........##",
+        ]);
+    }
+
+    #[test]
+    fn blits_and_blends() {
+        let (mut back, mut front) = buffers();
+        let mut canvas = canvas(&mut back, &mut front);
+        let clear = Rgba::new(0, 0, 0, 0);
+        let white = Rgba::from(Rgb::WHITE);
+        let pixels = [white, clear, white, clear, white, Rgba::new(0xff, 0xff, 0xff, 0x80)];
+        canvas.blit(14, 6, &Image::new(3, 2, &pixels));
+        canvas.blend_rect(Rect::new(0, 0, 2, 1), Rgba::new(0xff, 0xff, 0xff, 0x40));
+        canvas.blend_pixel(2, 0, Rgba::from(Rgb::WHITE));
+        let rows = render(&canvas);
+        assert_eq!(rows[0], "--#.............");
+        assert_eq!(rows[6], "..............#.");
+        assert_eq!(rows[7], "...............#");
+        assert_eq!(canvas.pixel(0, 0), Rgb::new(0x40, 0x40, 0x40));
+    }
+
+    #[test]
+    fn presents_dirty_regions() {
+        let (mut back, mut front) = buffers();
+        {
+            let mut canvas = canvas(&mut back, &mut front);
+            canvas.fill_rect(Rect::new(1, 1, 2, 2), Rgb::WHITE);
+            canvas.put_pixel(3, 2, Rgb::WHITE);
+            canvas.put_pixel(10, 5, RED);
+            canvas.put_pixel(-1, 5, RED);
+            assert_eq!(canvas.dirty_rects(), [Rect::new(1, 1, 3, 2), Rect::new(10, 5, 1, 1)]);
+            canvas.present();
+            assert!(canvas.dirty_rects().is_empty());
+            // Only presented regions reach the framebuffer.
+            canvas.back.put_pixel(0, 0, Rgb::WHITE);
+            canvas.present();
+        }
+        let front = Surface::new(&mut front, info());
+        assert_eq!(front.pixel(2, 2), Rgb::WHITE);
+        assert_eq!(front.pixel(10, 5), RED);
+        assert_eq!(front.pixel(0, 0), Rgb::BLACK);
+    }
+
+    proptest! {
+        #[test]
+        fn dirty_rects_cover_changes(rects in prop::collection::vec((0..40i32, 0..40i32, 1..8i32,
+                                                                      1..8i32), 0..64)) {
+            let mut dirty = DirtyRects::new();
+            for &(x, y, width, height) in rects.iter() {
+                dirty.add(Rect::new(x, y, width, height));
+            }
+            let dirty = dirty.as_slice();
+            prop_assert!(dirty.len() <= MAX_DIRTY_RECTS);
+            for &(x, y, width, height) in rects.iter() {
+                for pixel in (x..x + width).flat_map(|px| (y..y + height).map(move |py| (px, py))) {
+                    let pixel = Rect::new(pixel.0, pixel.1, 1, 1);
+                    prop_assert!(dirty.iter().any(|rect| rect.contains(&pixel)));
+                }
+            }
+            for (index, a) in dirty.iter().enumerate() {
+                for b in &dirty[index + 1..] {
+                    prop_assert!(a.intersection(b).is_none());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Kernel command line parsing.
+//!
+//! The command line is a whitespace-separated list of `key=value` options and `key` flags, e.g.
+//! `keyboard.layout=uk keyboard.scancodes=2`. Later options override earlier ones.
+
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLine<'a> {
+    source: &'a str,
+}
+
+impl<'a> CommandLine<'a> {
+    pub const fn new(source: &'a str) -> Self {
+        CommandLine { source }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.source
+    }
+
+    /// The options in order, as keys with optional values.
+    pub fn options(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.source.split_whitespace().map(|option| {
+            match option.find('=') {
+                Some(index) => (&option[..index], Some(&option[index + 1..])),
+                None => (option, None),
+            }
+        })
+    }
+
+    /// The value of the last `key=value` option.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.options().filter(|&(name, _)| name == key).filter_map(|(_, value)| value).last()
+    }
+
+    /// Whether `key` is given, with or without a value.
+    pub fn contains(&self, key: &str) -> bool {
+        self.options().any(|(name, _)| name == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options() {
+        let command_line = CommandLine::new("  keyboard.layout=uk quiet\tkeyboard.layout=jis a=\n");
+        assert_eq!(command_line.options().collect::<Vec<_>>(),
+                   vec![("keyboard.layout", Some("uk")), ("quiet", None),
+                        ("keyboard.layout", Some("jis")), ("a", Some(""))]);
+        assert_eq!(command_line.get("keyboard.layout"), Some("jis"));
+        assert_eq!(command_line.get("quiet"), None);
+        assert!(command_line.contains("quiet"));
+        assert!(!command_line.contains("keyboard"));
+        assert_eq!(command_line.get("a"), Some(""));
+    }
+}
@@ -0,0 +1,91 @@
+//! Code page 437, the character set of the VGA text mode's built-in font.
+//!
+//! Besides ASCII it has accented Latin letters, some Greek and mathematical symbols, box drawing
+//! characters, and symbols such as `☺` and `♪` in the place of the control characters.
+
+/// Shown for characters without a glyph: `■`.
+pub const REPLACEMENT: u8 = 0xfe;
+
+/// The characters of the bytes 0x00 to 0x1f.
+const LOW: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const DELETE: char = '⌂';
+
+/// The characters of the bytes 0x80 to 0xff.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Characters that look the same as one in the code page.
+const ALIASES: [(char, u8); 7] = [
+    ('β', 0xe1), // Greek beta for the German sharp s.
+    ('μ', 0xe6), // Greek mu for the micro sign.
+    ('Ω', 0xea), // Ohm sign for Greek omega.
+    ('∑', 0xe4), // N-ary summation for Greek sigma.
+    ('∅', 0xed), // Empty set for Greek phi.
+    ('∈', 0xee), // Element of for Greek epsilon.
+    ('ϕ', 0xed), // Greek phi symbol.
+];
+
+/// The byte showing `character`, if the code page has a glyph for it.
+pub fn encode(character: char) -> Option<u8> {
+    if (' '..='~').contains(&character) {
+        return Some(character as u8);
+    }
+    if character == DELETE {
+        return Some(0x7f);
+    }
+    if let Some(index) = HIGH.iter().position(|&glyph| glyph == character) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW[1..].iter().position(|&glyph| glyph == character) {
+        return Some(1 + index as u8);
+    }
+    ALIASES.iter().find(|&&(alias, _)| alias == character).map(|&(_, byte)| byte)
+}
+
+/// The character shown for `byte`.
+pub fn decode(byte: u8) -> char {
+    match byte {
+        0x00..=0x1f => LOW[byte as usize],
+        0x7f => DELETE,
+        0x80..=0xff => HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn encodes_characters() {
+        assert_eq!(encode('A'), Some(b'A'));
+        assert_eq!(encode('é'), Some(0x82));
+        assert_eq!(encode('╬'), Some(0xce));
+        assert_eq!(encode('☺'), Some(0x01));
+        assert_eq!(encode('β'), Some(0xe1));
+        assert_eq!(encode('€'), None);
+        assert_eq!(encode('\n'), None);
+        assert_eq!(encode('\0'), None);
+        assert_eq!(decode(REPLACEMENT), '■');
+    }
+
+    proptest! {
+        #[test]
+        fn decoding_reverses_encoding(byte in 1u8..) {
+            prop_assert_eq!(encode(decode(byte)), Some(byte));
+        }
+    }
+}
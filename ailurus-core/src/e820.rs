@@ -0,0 +1,260 @@
+//! Memory map reported by the BIOS through `int 0x15, eax=0xE820`.
+//!
+//! The firmware map may be unsorted and contain overlapping or empty entries, so it is
+//! sanitised before use.
+
+use crate::address::PhysAddr;
+
+/// Maximum number of firmware entries `sanitize` accepts.
+pub const MAX_ENTRIES: usize = 128;
+
+/// Physical addresses are limited to 52 bits.
+const MAX_PHYSICAL_ADDRESS: u64 = 1 << 52;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E820Type {
+    None,
+    Free,
+    Reserved,
+    Unknown,
+}
+
+impl E820Type {
+    pub fn from_raw(mem_type: u32) -> Self {
+        match mem_type {
+            0 => E820Type::None,
+            1 => E820Type::Free,
+            2 => E820Type::Reserved,
+            _ => E820Type::Unknown
+        }
+    }
+
+    /// When areas overlap, the type with the higher priority wins, so memory is only
+    /// considered free if no entry says otherwise.
+    fn priority(self) -> u8 {
+        match self {
+            E820Type::None => 0,
+            E820Type::Free => 1,
+            E820Type::Unknown => 2,
+            E820Type::Reserved => 3,
+        }
+    }
+}
+
+/// An entry as written by the bootloader.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct RawE820Tag {
+    pub base_address: u64,
+    pub size: u64,
+    pub mem_type: u32,
+    pub _reversed: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct E820Tag {
+    pub base_address: PhysAddr,
+    pub size: usize,
+    pub mem_type: E820Type,
+}
+
+impl E820Tag {
+    pub const fn missing() -> Self {
+        E820Tag {
+            base_address: unsafe{ PhysAddr::new_unchecked(0) },
+            size: 0,
+            mem_type: E820Type::None
+        }
+    }
+
+    /// Convert a raw entry, clipping it to the physical address space. Returns `None` if
+    /// nothing of it is left.
+    pub fn from_raw(raw: &RawE820Tag) -> Option<Self> {
+        if raw.base_address >= MAX_PHYSICAL_ADDRESS {
+            return None;
+        }
+        let end = raw.base_address.saturating_add(raw.size).min(MAX_PHYSICAL_ADDRESS);
+        Some(E820Tag {
+            base_address: PhysAddr::new(raw.base_address),
+            size: (end - raw.base_address) as usize,
+            mem_type: E820Type::from_raw(raw.mem_type),
+        })
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.mem_type == E820Type::Free
+    }
+
+    pub fn start(&self) -> u64 {
+        self.base_address.as_u64()
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start() + self.size as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E820Error {
+    /// The input or the sanitised map does not fit.
+    TooManyEntries,
+}
+
+/// Write a sorted map without empty, overlapping or mergeable entries of `input` to `output`
+/// and return its length. Overlaps are resolved in favour of the type with the higher priority.
+pub fn sanitize(input: &[E820Tag], output: &mut [E820Tag]) -> Result<usize, E820Error> {
+    if input.len() > MAX_ENTRIES {
+        return Err(E820Error::TooManyEntries);
+    }
+    let valid = |tag: &&E820Tag| tag.size > 0 && tag.mem_type != E820Type::None;
+
+    let mut boundaries = [0u64; 2 * MAX_ENTRIES];
+    let mut count = 0;
+    for tag in input.iter().filter(valid) {
+        boundaries[count] = tag.start();
+        boundaries[count + 1] = tag.end();
+        count += 2;
+    }
+    let boundaries = &mut boundaries[..count];
+    boundaries.sort_unstable();
+
+    // Every piece between two neighbouring boundaries is covered by the same set of entries.
+    let mut len = 0;
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        if start == end {
+            continue;
+        }
+        let mem_type = input.iter().filter(valid)
+            .filter(|tag| tag.start() <= start && end <= tag.end())
+            .map(|tag| tag.mem_type)
+            .max_by_key(|mem_type| mem_type.priority());
+        let mem_type = match mem_type {
+            Some(mem_type) => mem_type,
+            None => continue,
+        };
+
+        if len > 0 && output[len - 1].mem_type == mem_type && output[len - 1].end() == start {
+            output[len - 1].size += (end - start) as usize;
+        } else {
+            if len == output.len() {
+                return Err(E820Error::TooManyEntries);
+            }
+            output[len] = E820Tag {
+                base_address: PhysAddr::new(start),
+                size: (end - start) as usize,
+                mem_type,
+            };
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn tag(base: u64, size: usize, mem_type: E820Type) -> E820Tag {
+        E820Tag { base_address: PhysAddr::new(base), size, mem_type }
+    }
+
+    fn sanitized(input: &[E820Tag]) -> Vec<E820Tag> {
+        let mut output = [E820Tag::missing(); 2 * MAX_ENTRIES];
+        let len = sanitize(input, &mut output).unwrap();
+        output[..len].to_vec()
+    }
+
+    #[test]
+    fn typical_bios_map() {
+        let input = [
+            tag(0, 0x9fc00, E820Type::Free),
+            tag(0x9fc00, 0x400, E820Type::Reserved),
+            tag(0xf0000, 0x10000, E820Type::Reserved),
+            tag(0x10_0000, 0x7ee_0000, E820Type::Free),
+            tag(0xfffc_0000, 0x4_0000, E820Type::Reserved),
+        ];
+        assert_eq!(sanitized(&input), vec![
+            tag(0, 0x9fc00, E820Type::Free),
+            tag(0x9fc00, 0x400, E820Type::Reserved),
+            tag(0xf0000, 0x10000, E820Type::Reserved),
+            tag(0x10_0000, 0x7ee_0000, E820Type::Free),
+            tag(0xfffc_0000, 0x4_0000, E820Type::Reserved),
+        ]);
+    }
+
+    #[test]
+    fn reserved_splits_free_area() {
+        let input = [
+            tag(0x10_0000, 0x10_0000, E820Type::Reserved),
+            tag(0, 0x100_0000, E820Type::Free),
+            tag(0x200_0000, 0, E820Type::Reserved),
+        ];
+        assert_eq!(sanitized(&input), vec![
+            tag(0, 0x10_0000, E820Type::Free),
+            tag(0x10_0000, 0x10_0000, E820Type::Reserved),
+            tag(0x20_0000, 0xe0_0000, E820Type::Free),
+        ]);
+    }
+
+    #[test]
+    fn adjacent_areas_are_merged() {
+        let input = [tag(0x1000, 0x1000, E820Type::Free), tag(0, 0x1000, E820Type::Free)];
+        assert_eq!(sanitized(&input), vec![tag(0, 0x2000, E820Type::Free)]);
+    }
+
+    #[test]
+    fn output_overflow() {
+        let input = [tag(0, 0x1000, E820Type::Free), tag(0x2000, 0x1000, E820Type::Free)];
+        let mut output = [E820Tag::missing(); 1];
+        assert_eq!(sanitize(&input, &mut output), Err(E820Error::TooManyEntries));
+    }
+
+    #[test]
+    fn raw_entries_are_clipped() {
+        let raw = |base_address, size| RawE820Tag { base_address, size, mem_type: 1, _reversed: 0 };
+        assert_eq!(E820Tag::from_raw(&raw(1 << 52, 0x1000)), None);
+        assert_eq!(E820Tag::from_raw(&raw((1 << 52) - 0x1000, u64::MAX)),
+                   Some(tag((1 << 52) - 0x1000, 0x1000, E820Type::Free)));
+        assert_eq!(E820Tag::from_raw(&raw(0, 0x1000)).unwrap().mem_type, E820Type::Free);
+    }
+
+    fn mem_type() -> impl Strategy<Value = E820Type> {
+        prop_oneof![Just(E820Type::Free), Just(E820Type::Reserved), Just(E820Type::Unknown)]
+    }
+
+    fn area() -> impl Strategy<Value = E820Tag> {
+        (0..64u64, 0..16usize, mem_type()).prop_map(|(base, size, mem_type)| {
+            tag(base * 0x1000, size * 0x1000, mem_type)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn sanitized_map_is_ordered_and_disjoint(input in prop::collection::vec(area(), 0..20)) {
+            let output = sanitized(&input);
+            for tag in output.iter() {
+                prop_assert!(tag.size > 0);
+            }
+            for pair in output.windows(2) {
+                prop_assert!(pair[0].end() <= pair[1].start());
+                prop_assert!(pair[0].end() < pair[1].start() || pair[0].mem_type != pair[1].mem_type);
+            }
+        }
+
+        #[test]
+        fn sanitized_map_keeps_strongest_type(input in prop::collection::vec(area(), 0..20),
+                                              page in 0..80u64) {
+            let address = page * 0x1000;
+            let expected = input.iter()
+                .filter(|tag| tag.start() <= address && address < tag.end())
+                .map(|tag| tag.mem_type)
+                .max_by_key(|mem_type| mem_type.priority());
+            let actual = sanitized(&input).iter()
+                .find(|tag| tag.start() <= address && address < tag.end())
+                .map(|tag| tag.mem_type);
+            prop_assert_eq!(actual, expected);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! ELF64 executables of user programs for x86_64: static ones, linked at an address in the user
+//! part of the address space, and static PIE ones, linked at 0 and relocated to wherever the
+//! kernel loads them.
+//!
+//! `Elf::parse` checks the headers, the loadable segments and the relocations, so that loading
+//! cannot fail halfway because of the file. Executables which need a dynamic linker are refused.
+//! `stack` lays out the initial stack of the program.
+//!
+//! `programs/` holds test executables, built from `args.s` with `make programs`.
+
+pub mod stack;
+
+pub const PAGE_SIZE: u64 = 4096;
+/// The size of a program header, for `AT_PHENT`.
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const HEADER_SIZE: usize = 64;
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const CURRENT_VERSION: u8 = 1;
+
+const TYPE_EXECUTABLE: u16 = 2;
+const TYPE_SHARED: u16 = 3;
+const MACHINE_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DYNAMIC_ENTRY_SIZE: usize = 16;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const DT_JMPREL: u64 = 23;
+const DT_RELR: u64 = 36;
+
+const RELA_SIZE: usize = 24;
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    InvalidMagic,
+    /// Not a 64-bit little-endian ELF file of the current version, or with headers of another
+    /// size.
+    UnsupportedFormat,
+    UnsupportedMachine(u16),
+    /// Neither an executable nor a position-independent one.
+    UnsupportedType(u16),
+    /// A header, segment or table extends beyond the end of the file.
+    Truncated,
+    /// A loadable segment with more bytes in the file than in memory, beyond the end of the
+    /// address space, or overlapping another one.
+    InvalidSegment,
+    NoLoadableSegment,
+    /// The entry point is not in an executable segment.
+    InvalidEntry,
+    /// `PT_INTERP` or `DT_NEEDED`.
+    NeedsDynamicLinker,
+    /// Relocations of another type than `R_X86_64_RELATIVE`, or in another table than
+    /// `DT_RELA`, whose tag or type this is.
+    UnsupportedRelocation(u64),
+    /// A relocation outside the loadable segments.
+    InvalidRelocation,
+}
+
+bitflags! {
+    pub struct SegmentFlags: u32 {
+        const EXECUTE = 1;
+        const WRITE = 2;
+        const READ = 4;
+    }
+}
+
+/// A loadable segment, which is not empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// Relative to the load base for position-independent executables.
+    pub address: u64,
+    pub memory_size: u64,
+    pub offset: u64,
+    /// Copied from the file, the rest being zeroed.
+    pub file_size: u64,
+    pub flags: SegmentFlags,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.address + self.memory_size
+    }
+
+    /// The first page of the segment, and the end of its last one.
+    pub fn pages(&self) -> (u64, u64) {
+        (self.address & !(PAGE_SIZE - 1), page_align(self.end()))
+    }
+
+    fn contains(&self, address: u64, size: u64) -> bool {
+        address >= self.address && address <= self.end() && size <= self.end() - address
+    }
+}
+
+/// Write the load base plus `addend` at the load base plus `offset`, as a 64-bit value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub addend: u64,
+}
+
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    position_independent: bool,
+    entry: u64,
+    program_headers: usize,
+    program_header_count: usize,
+    /// The `Elf64_Rela` entries.
+    relocations: &'a [u8],
+}
+
+fn read(data: &[u8], offset: usize, size: usize) -> Result<u64, ElfError> {
+    let end = offset.checked_add(size).ok_or(ElfError::Truncated)?;
+    let bytes = data.get(offset..end).ok_or(ElfError::Truncated)?;
+    Ok(bytes.iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+}
+
+/// `address` rounded up to a page boundary.
+fn page_align(address: u64) -> u64 {
+    address.wrapping_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if !data.starts_with(&MAGIC) {
+            return Err(ElfError::InvalidMagic);
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[4] != CLASS_64 || data[5] != LITTLE_ENDIAN || data[6] != CURRENT_VERSION {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        let machine = read(data, 18, 2)? as u16;
+        if machine != MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+        let position_independent = match read(data, 16, 2)? as u16 {
+            TYPE_EXECUTABLE => false,
+            TYPE_SHARED => true,
+            kind => return Err(ElfError::UnsupportedType(kind)),
+        };
+        if read(data, 54, 2)? as usize != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        let program_headers = read(data, 32, 8)? as usize;
+        let program_header_count = read(data, 56, 2)? as usize;
+        let table_end = program_headers.checked_add(program_header_count * PROGRAM_HEADER_SIZE);
+        if table_end.filter(|&end| end <= data.len()).is_none() {
+            return Err(ElfError::Truncated);
+        }
+
+        let mut elf = Elf {
+            data,
+            position_independent,
+            entry: read(data, 24, 8)?,
+            program_headers,
+            program_header_count,
+            relocations: &[],
+        };
+        elf.check_segments()?;
+        elf.relocations = elf.relocation_table()?;
+        Ok(elf)
+    }
+
+    /// Whether the executable is a static PIE one, loaded at any page-aligned base.
+    pub fn is_position_independent(&self) -> bool {
+        self.position_independent
+    }
+
+    /// The entry point, relative to the load base for position-independent executables.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.program_header_count
+    }
+
+    /// Where the program headers are in memory once loaded, for `AT_PHDR`, if a segment loads
+    /// them. Relative to the load base for position-independent executables.
+    pub fn program_headers_address(&self) -> Option<u64> {
+        let (start, size) = (self.program_headers as u64,
+                             (self.program_header_count * PROGRAM_HEADER_SIZE) as u64);
+        self.segments().find(|segment| {
+            start >= segment.offset && start + size <= segment.offset + segment.file_size
+        }).map(|segment| segment.address + (start - segment.offset))
+    }
+
+    fn program_header(&self, index: usize) -> ProgramHeader {
+        let header = self.program_headers + index * PROGRAM_HEADER_SIZE;
+        // Within the file, as `parse` checked.
+        let field = |offset: usize, size: usize| read(self.data, header + offset, size).unwrap();
+        ProgramHeader {
+            kind: field(0, 4) as u32,
+            flags: field(4, 4) as u32,
+            offset: field(8, 8),
+            address: field(16, 8),
+            file_size: field(32, 8),
+            memory_size: field(40, 8),
+        }
+    }
+
+    fn program_header_iter(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let elf = *self;
+        (0..self.program_header_count).map(move |index| elf.program_header(index))
+    }
+
+    /// The loadable segments which are not empty.
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + 'a {
+        self.program_header_iter()
+            .filter(|header| header.kind == PT_LOAD && header.memory_size != 0)
+            .map(|header| Segment {
+                address: header.address,
+                memory_size: header.memory_size,
+                offset: header.offset,
+                file_size: header.file_size,
+                flags: SegmentFlags::from_bits_truncate(header.flags),
+            })
+    }
+
+    /// The first page of the segments, and the end of the last one. Relative to the load base
+    /// for position-independent executables.
+    pub fn extent(&self) -> (u64, u64) {
+        let start = self.segments().map(|segment| segment.pages().0).min().unwrap();
+        let end = self.segments().map(|segment| segment.pages().1).max().unwrap();
+        (start, end)
+    }
+
+    /// The bytes of `segment` in the file.
+    pub fn file_bytes(&self, segment: &Segment) -> &'a [u8] {
+        let start = segment.offset as usize;
+        &self.data[start..start + segment.file_size as usize]
+    }
+
+    fn check_segments(&self) -> Result<(), ElfError> {
+        if self.program_header_iter().any(|header| header.kind == PT_INTERP) {
+            return Err(ElfError::NeedsDynamicLinker);
+        }
+        for segment in self.segments() {
+            if segment.file_size > segment.memory_size {
+                return Err(ElfError::InvalidSegment);
+            }
+            let file_end = segment.offset.checked_add(segment.file_size);
+            if file_end.filter(|&end| end <= self.data.len() as u64).is_none() {
+                return Err(ElfError::Truncated);
+            }
+            // The last page has to fit too.
+            let end = segment.address.checked_add(segment.memory_size);
+            if end.filter(|&end| end <= 0u64.wrapping_sub(PAGE_SIZE)).is_none() {
+                return Err(ElfError::InvalidSegment);
+            }
+        }
+        for (index, segment) in self.segments().enumerate() {
+            let overlaps = self.segments().skip(index + 1).any(|other| {
+                segment.address < other.end() && other.address < segment.end()
+            });
+            if overlaps {
+                return Err(ElfError::InvalidSegment);
+            }
+        }
+        if self.segments().next().is_none() {
+            return Err(ElfError::NoLoadableSegment);
+        }
+        let executable = self.segments().any(|segment| {
+            segment.contains(self.entry, 1) && segment.flags.contains(SegmentFlags::EXECUTE)
+        });
+        if !executable {
+            return Err(ElfError::InvalidEntry);
+        }
+        Ok(())
+    }
+
+    /// The bytes loaded from the file at `address`, if a segment holds all `size` of them.
+    fn loaded_bytes(&self, address: u64, size: u64) -> Option<&'a [u8]> {
+        let segment = self.segments().find(|segment| {
+            address >= segment.address && address - segment.address <= segment.file_size
+                && size <= segment.file_size - (address - segment.address)
+        })?;
+        let start = (segment.offset + (address - segment.address)) as usize;
+        Some(&self.data[start..start + size as usize])
+    }
+
+    /// Find and check the relocations of the dynamic segment.
+    fn relocation_table(&self) -> Result<&'a [u8], ElfError> {
+        let dynamic = match self.program_header_iter().find(|header| header.kind == PT_DYNAMIC) {
+            Some(dynamic) => dynamic,
+            None => return Ok(&[]),
+        };
+        let start = dynamic.offset as usize;
+        let entries = start.checked_add(dynamic.file_size as usize)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or(ElfError::Truncated)?;
+        let (mut table, mut table_size, mut entry_size) = (None, 0, RELA_SIZE as u64);
+        for entry in entries.chunks(DYNAMIC_ENTRY_SIZE) {
+            let (tag, value) = (read(entry, 0, 8)?, read(entry, 8, 8)?);
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => return Err(ElfError::NeedsDynamicLinker),
+                DT_RELA => table = Some(value),
+                DT_RELASZ => table_size = value,
+                DT_RELAENT => entry_size = value,
+                DT_REL | DT_JMPREL | DT_RELR => return Err(ElfError::UnsupportedRelocation(tag)),
+                _ => {}
+            }
+        }
+        if entry_size != RELA_SIZE as u64 {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        let table = match table {
+            Some(address) => self.loaded_bytes(address, table_size).ok_or(ElfError::Truncated)?,
+            None => return Ok(&[]),
+        };
+        for relocation in table.chunks(RELA_SIZE) {
+            let (offset, info) = (read(relocation, 0, 8)?, read(relocation, 8, 8)?);
+            match info as u32 {
+                R_X86_64_NONE => {}
+                R_X86_64_RELATIVE => {
+                    if !self.segments().any(|segment| segment.contains(offset, 8)) {
+                        return Err(ElfError::InvalidRelocation);
+                    }
+                }
+                kind => return Err(ElfError::UnsupportedRelocation(u64::from(kind))),
+            }
+        }
+        Ok(table)
+    }
+
+    /// The relocations to apply once the segments are loaded, before making them read-only.
+    pub fn relocations(&self) -> impl Iterator<Item = Relocation> + 'a {
+        self.relocations.chunks(RELA_SIZE)
+            .filter(|relocation| read(relocation, 8, 8).unwrap() as u32 == R_X86_64_RELATIVE)
+            .map(|relocation| Relocation {
+                offset: read(relocation, 0, 8).unwrap(),
+                addend: read(relocation, 16, 8).unwrap(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const STATIC: &[u8] = include_bytes!("../../programs/args-static");
+    const PIE: &[u8] = include_bytes!("../../programs/args-pie");
+
+    fn patched(data: &[u8], offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    #[test]
+    fn static_executables_have_fixed_addresses() {
+        let elf = Elf::parse(STATIC).unwrap();
+        assert!(!elf.is_position_independent());
+        assert_eq!(elf.entry(), 0x80_0100_1000);
+        assert_eq!(elf.extent(), (0x80_0100_0000, 0x80_0100_3000));
+        assert_eq!(elf.program_headers_address(), Some(0x80_0100_0040));
+        assert_eq!(elf.relocations().count(), 0);
+        let flags: Vec<_> = elf.segments().map(|segment| segment.flags).collect();
+        assert_eq!(flags, [SegmentFlags::READ, SegmentFlags::READ | SegmentFlags::EXECUTE,
+                           SegmentFlags::READ | SegmentFlags::WRITE]);
+        let text = elf.segments().nth(1).unwrap();
+        assert_eq!(elf.file_bytes(&text).len() as u64, text.file_size);
+    }
+
+    #[test]
+    fn pie_executables_are_relocated() {
+        let elf = Elf::parse(PIE).unwrap();
+        assert!(elf.is_position_independent());
+        assert_eq!(elf.entry(), 0x1000);
+        assert_eq!(elf.extent().0, 0);
+        // The empty segment is skipped, the data one shares a page with the previous segment.
+        assert_eq!(elf.segments().count(), 3);
+        let relocations: Vec<_> = elf.relocations().collect();
+        assert_eq!(relocations.len(), 1);
+        let data = elf.segments().last().unwrap();
+        assert!(data.flags.contains(SegmentFlags::WRITE));
+        assert!(data.contains(relocations[0].offset, 8));
+        assert!(data.contains(relocations[0].addend, 16));
+    }
+
+    #[test]
+    fn bad_headers_are_refused() {
+        assert_eq!(Elf::parse(b"MZ").err(), Some(ElfError::InvalidMagic));
+        assert_eq!(Elf::parse(&STATIC[..40]).err(), Some(ElfError::Truncated));
+        assert_eq!(Elf::parse(&patched(STATIC, 4, &[1])).err(),
+                   Some(ElfError::UnsupportedFormat));
+        assert_eq!(Elf::parse(&patched(STATIC, 18, &[40, 0])).err(),
+                   Some(ElfError::UnsupportedMachine(40)));
+        assert_eq!(Elf::parse(&patched(STATIC, 16, &[1, 0])).err(),
+                   Some(ElfError::UnsupportedType(1)));
+        assert_eq!(Elf::parse(&STATIC[..0x100]).err(), Some(ElfError::Truncated));
+        // The entry point in the read-only first segment.
+        assert_eq!(Elf::parse(&patched(STATIC, 25, &[0])).err(), Some(ElfError::InvalidEntry));
+    }
+
+    #[test]
+    fn interpreters_are_refused() {
+        // The first program header becomes PT_INTERP.
+        assert_eq!(Elf::parse(&patched(STATIC, 64, &[3])).err(),
+                   Some(ElfError::NeedsDynamicLinker));
+    }
+
+    #[test]
+    fn bad_segments_are_refused() {
+        // The text segment's file size grows beyond its memory size.
+        let text = 64 + PROGRAM_HEADER_SIZE;
+        assert_eq!(Elf::parse(&patched(STATIC, text + 33, &[1])).err(),
+                   Some(ElfError::InvalidSegment));
+        // Its memory size grows over the data segment.
+        assert_eq!(Elf::parse(&patched(STATIC, text + 41, &[0x20])).err(),
+                   Some(ElfError::InvalidSegment));
+        // Its file size grows beyond the end of the file.
+        let data = patched(&patched(STATIC, text + 33, &[0x20]), text + 41, &[0x20]);
+        assert_eq!(Elf::parse(&data).err(), Some(ElfError::Truncated));
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_garbage_does_not_panic(offset in 0..0x200usize, bytes in any::<[u8; 8]>()) {
+            for data in [STATIC, PIE].iter() {
+                if let Ok(elf) = Elf::parse(&patched(data, offset, &bytes)) {
+                    let _ = (elf.extent(), elf.program_headers_address());
+                    let _ = elf.relocations().count();
+                }
+            }
+        }
+    }
+}
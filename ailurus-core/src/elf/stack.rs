@@ -0,0 +1,186 @@
+//! The initial stack of a program, as the System V ABI for x86_64 lays it out. From the stack
+//! pointer up:
+//!
+//! ```text
+//! argc
+//! argv[0] .. argv[argc - 1], 0
+//! envp[0] .. envp[n - 1], 0
+//! auxv: (type, value) pairs, ending with (AT_NULL, 0)
+//! padding
+//! the 16 bytes AT_RANDOM points to, then the argument and environment strings
+//! ```
+//!
+//! The stack pointer is 16-byte aligned.
+
+pub const AT_NULL: u64 = 0;
+/// The address of the program headers.
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+/// The load base of the dynamic linker, none for static executables.
+pub const AT_BASE: u64 = 7;
+pub const AT_ENTRY: u64 = 9;
+/// The address of 16 random bytes, e.g. for stack protector canaries.
+pub const AT_RANDOM: u64 = 25;
+
+const WORD_SIZE: usize = 8;
+const ALIGNMENT: usize = 16;
+const RANDOM_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackTooSmall;
+
+/// The number of bytes `build` uses at most for these arguments, environment and `auxv`.
+pub fn size(argv: &[&[u8]], envp: &[&[u8]], auxv: &[(u64, u64)]) -> usize {
+    let strings: usize = argv.iter().chain(envp.iter()).map(|string| string.len() + 1).sum();
+    // `AT_RANDOM` and `AT_NULL` come with `auxv`.
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 2);
+    // With the padding which aligns the stack pointer.
+    RANDOM_SIZE + strings + words * WORD_SIZE + (ALIGNMENT - 1)
+}
+
+/// Lay out the initial stack in `stack`, the memory right below `top` in the address space of
+/// the program, and return the stack pointer. Strings get their terminating null byte, and
+/// `auxv` its `AT_RANDOM` entry pointing to `random`, and `AT_NULL`.
+pub fn build(stack: &mut [u8], top: u64, argv: &[&[u8]], envp: &[&[u8]], auxv: &[(u64, u64)],
+             random: [u8; 16]) -> Result<u64, StackTooSmall> {
+    if size(argv, envp, auxv) > stack.len() {
+        return Err(StackTooSmall);
+    }
+    let bottom = top - stack.len() as u64;
+    let address = |offset: usize| bottom + offset as u64;
+
+    let random_offset = stack.len() - RANDOM_SIZE;
+    stack[random_offset..].copy_from_slice(&random);
+    let strings: usize = argv.iter().chain(envp.iter()).map(|string| string.len() + 1).sum();
+    let strings_offset = random_offset - strings;
+
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 2);
+    let stack_pointer = (address(strings_offset) - (words * WORD_SIZE) as u64)
+        & !(ALIGNMENT as u64 - 1);
+    let mut word_offset = (stack_pointer - bottom) as usize;
+    let mut push = |stack: &mut [u8], value: u64| {
+        stack[word_offset..word_offset + WORD_SIZE].copy_from_slice(&value.to_le_bytes());
+        word_offset += WORD_SIZE;
+    };
+
+    push(stack, argv.len() as u64);
+    let mut string_offset = strings_offset;
+    for strings in [argv, envp].iter() {
+        for string in strings.iter() {
+            push(stack, address(string_offset));
+            stack[string_offset..string_offset + string.len()].copy_from_slice(string);
+            stack[string_offset + string.len()] = 0;
+            string_offset += string.len() + 1;
+        }
+        push(stack, 0);
+    }
+    for &(kind, value) in auxv.iter() {
+        push(stack, kind);
+        push(stack, value);
+    }
+    push(stack, AT_RANDOM);
+    push(stack, address(random_offset));
+    push(stack, AT_NULL);
+    push(stack, 0);
+    Ok(stack_pointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TOP: u64 = 0x7fff_ffff_f000;
+
+    /// A reader of the stack built in `stack`.
+    struct Reader<'a> {
+        stack: &'a [u8],
+        offset: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn new(stack: &'a [u8], stack_pointer: u64) -> Self {
+            Reader { stack, offset: Reader::offset_of(stack, stack_pointer) }
+        }
+
+        fn offset_of(stack: &[u8], address: u64) -> usize {
+            (address - (TOP - stack.len() as u64)) as usize
+        }
+
+        fn word(&mut self) -> u64 {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&self.stack[self.offset..self.offset + 8]);
+            self.offset += 8;
+            u64::from_le_bytes(bytes)
+        }
+
+        fn string(&self, address: u64) -> &'a [u8] {
+            let start = Reader::offset_of(self.stack, address);
+            let len = self.stack[start..].iter().position(|&byte| byte == 0).unwrap();
+            &self.stack[start..start + len]
+        }
+
+        fn strings(&mut self) -> Vec<&'a [u8]> {
+            let mut strings = Vec::new();
+            loop {
+                match self.word() {
+                    0 => return strings,
+                    address => strings.push(self.string(address)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stack_follows_the_abi() {
+        let mut stack = vec![0; 512];
+        let argv: [&[u8]; 2] = [b"init", b"-v"];
+        let envp: [&[u8]; 1] = [b"TERM=ailurus"];
+        let auxv = [(AT_PAGESZ, 4096), (AT_ENTRY, 0x40_1000)];
+        let random = [7; 16];
+        let stack_pointer = build(&mut stack, TOP, &argv, &envp, &auxv, random).unwrap();
+        assert_eq!(stack_pointer % 16, 0);
+
+        let mut reader = Reader::new(&stack, stack_pointer);
+        assert_eq!(reader.word(), 2);
+        assert_eq!(reader.strings(), argv);
+        assert_eq!(reader.strings(), envp);
+        assert_eq!((reader.word(), reader.word()), auxv[0]);
+        assert_eq!((reader.word(), reader.word()), auxv[1]);
+        assert_eq!(reader.word(), AT_RANDOM);
+        let random_offset = Reader::offset_of(&stack, reader.word());
+        assert_eq!(stack[random_offset..random_offset + 16], random);
+        assert_eq!((reader.word(), reader.word()), (AT_NULL, 0));
+    }
+
+    #[test]
+    fn small_stacks_are_refused() {
+        let mut stack = vec![0; 64];
+        let argv: [&[u8]; 1] = [b"init"];
+        assert_eq!(build(&mut stack, TOP, &argv, &[], &[], [0; 16]), Err(StackTooSmall));
+    }
+
+    proptest! {
+        #[test]
+        fn stack_pointer_is_aligned_and_in_bounds(
+            argv in prop::collection::vec(prop::collection::vec(1..=255u8, 0..20), 0..5),
+            envp in prop::collection::vec(prop::collection::vec(1..=255u8, 0..20), 0..5),
+            auxv_len in 0..4usize) {
+            let argv: Vec<&[u8]> = argv.iter().map(Vec::as_slice).collect();
+            let envp: Vec<&[u8]> = envp.iter().map(Vec::as_slice).collect();
+            let auxv = vec![(AT_PAGESZ, 4096); auxv_len];
+            let needed = size(&argv, &envp, &auxv);
+            let mut stack = vec![0; needed];
+            let stack_pointer = build(&mut stack, TOP, &argv, &envp, &auxv, [0; 16]).unwrap();
+            prop_assert_eq!(stack_pointer % 16, 0);
+            prop_assert!(stack_pointer >= TOP - needed as u64);
+
+            let mut reader = Reader::new(&stack, stack_pointer);
+            prop_assert_eq!(reader.word(), argv.len() as u64);
+            prop_assert_eq!(reader.strings(), argv);
+            prop_assert_eq!(reader.strings(), envp);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Linear framebuffers: pixel formats and drawing into the framebuffer memory.
+//!
+//! A `Surface` draws into any byte slice laid out like the framebuffer, so the same code renders
+//! to the video memory, to a back buffer (see `canvas`) and, in the tests, to a `Vec`.
+
+use crate::psf::Glyph;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Rgb { red, green, blue }
+    }
+}
+
+/// The bits of one color channel within a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8,
+}
+
+impl ColorField {
+    fn encode(self, value: u8) -> u32 {
+        if self.size == 0 {
+            return 0;
+        }
+        let size = self.size.min(8);
+        u32::from(value >> (8 - size)) << self.position
+    }
+
+    fn decode(self, pixel: u32) -> u8 {
+        if self.size == 0 {
+            return 0;
+        }
+        let size = self.size.min(8);
+        let max = (1 << size) - 1;
+        let value = (pixel >> self.position) & max;
+        // Scale so that the maximum value of the field becomes 0xff.
+        (value * 0xff / max) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u8,
+    pub red: ColorField,
+    pub green: ColorField,
+    pub blue: ColorField,
+}
+
+impl PixelFormat {
+    /// 32 bits per pixel with blue in the lowest byte, the usual format of VBE modes.
+    pub const XRGB8888: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        red: ColorField { position: 16, size: 8 },
+        green: ColorField { position: 8, size: 8 },
+        blue: ColorField { position: 0, size: 8 },
+    };
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        crate::bytes_for_bits(self.bits_per_pixel as usize)
+    }
+
+    pub fn encode(&self, color: Rgb) -> u32 {
+        self.red.encode(color.red) | self.green.encode(color.green) |
+            self.blue.encode(color.blue)
+    }
+
+    pub fn decode(&self, pixel: u32) -> Rgb {
+        Rgb::new(self.red.decode(pixel), self.green.decode(pixel), self.blue.decode(pixel))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Bytes from the start of one line to the start of the next.
+    pub pitch: usize,
+    pub format: PixelFormat,
+}
+
+impl FramebufferInfo {
+    /// The size of the framebuffer memory in bytes.
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+}
+
+pub struct Surface<'a> {
+    buffer: &'a mut [u8],
+    info: FramebufferInfo,
+}
+
+impl<'a> Surface<'a> {
+    /// Panics if `buffer` is smaller than `info.size()`.
+    pub fn new(buffer: &'a mut [u8], info: FramebufferInfo) -> Self {
+        assert!(buffer.len() >= info.size(), "framebuffer is too small");
+        Surface { buffer, info }
+    }
+
+    pub fn info(&self) -> &FramebufferInfo {
+        &self.info
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        y * self.info.pitch + x * self.info.format.bytes_per_pixel()
+    }
+
+    /// Set the pixel at `(x, y)`, ignoring coordinates outside of the surface.
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+        let bytes = self.info.format.bytes_per_pixel();
+        let offset = self.offset(x, y);
+        let pixel = self.info.format.encode(color).to_le_bytes();
+        self.buffer[offset..offset + bytes].copy_from_slice(&pixel[..bytes]);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb {
+        let bytes = self.info.format.bytes_per_pixel();
+        let offset = self.offset(x, y);
+        let mut pixel = [0; 4];
+        pixel[..bytes].copy_from_slice(&self.buffer[offset..offset + bytes]);
+        self.info.format.decode(u32::from_le_bytes(pixel))
+    }
+
+    /// Fill the rectangle with its top left corner at `(x, y)`, clipped to the surface.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let right = x.saturating_add(width).min(self.info.width);
+        let bottom = y.saturating_add(height).min(self.info.height);
+        for row in y..bottom {
+            for column in x..right {
+                self.put_pixel(column, row, color);
+            }
+        }
+    }
+
+    /// Draw `glyph` with its top left corner at `(x, y)`, in `foreground` on `background`.
+    pub fn draw_glyph(&mut self, x: usize, y: usize, glyph: &Glyph, foreground: Rgb,
+                      background: Rgb) {
+        for row in 0..glyph.height() {
+            for column in 0..glyph.width() {
+                let color = if glyph.is_set(column, row) { foreground } else { background };
+                self.put_pixel(x + column, y + row, color);
+            }
+        }
+    }
+
+    /// Copy a rectangle from `source`, which must have the same format, to the same position.
+    pub fn copy_from(&mut self, source: &Surface, x: usize, y: usize, width: usize,
+                     height: usize) {
+        assert_eq!(self.info.format, source.info.format, "pixel formats differ");
+        let right = x.saturating_add(width).min(self.info.width).min(source.info.width);
+        let bottom = y.saturating_add(height).min(self.info.height).min(source.info.height);
+        if right <= x {
+            return;
+        }
+        let len = (right - x) * self.info.format.bytes_per_pixel();
+        for row in y..bottom {
+            let (to, from) = (self.offset(x, row), source.offset(x, row));
+            self.buffer[to..to + len].copy_from_slice(&source.buffer[from..from + len]);
+        }
+    }
+
+    /// Move the content up by `lines` pixels, filling the lines at the bottom with `color`.
+    pub fn scroll_up(&mut self, lines: usize, color: Rgb) {
+        let lines = lines.min(self.info.height);
+        let pitch = self.info.pitch;
+        self.buffer.copy_within(lines * pitch..self.info.size(), 0);
+        let width = self.info.width;
+        self.fill_rect(0, self.info.height - lines, width, lines, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psf::Font;
+
+    const RGB565: PixelFormat = PixelFormat {
+        bits_per_pixel: 16,
+        red: ColorField { position: 11, size: 5 },
+        green: ColorField { position: 5, size: 6 },
+        blue: ColorField { position: 0, size: 5 },
+    };
+
+    fn info(width: usize, height: usize, format: PixelFormat) -> FramebufferInfo {
+        // Some padding at the end of each line, as real framebuffers often have.
+        FramebufferInfo { width, height, pitch: width * format.bytes_per_pixel() + 4, format }
+    }
+
+    #[test]
+    fn encodes_pixel_formats() {
+        let color = Rgb::new(0x12, 0x34, 0x56);
+        assert_eq!(PixelFormat::XRGB8888.encode(color), 0x12_3456);
+        assert_eq!(PixelFormat::XRGB8888.decode(0x12_3456), color);
+        assert_eq!(RGB565.encode(Rgb::WHITE), 0xffff);
+        assert_eq!(RGB565.decode(0xffff), Rgb::WHITE);
+        assert_eq!(RGB565.encode(Rgb::new(0xff, 0, 0)), 0xf800);
+        assert_eq!(RGB565.decode(0x0010), Rgb::new(0, 0, 0x83));
+    }
+
+    #[test]
+    fn draws_pixels_and_rectangles() {
+        for &format in [PixelFormat::XRGB8888, RGB565].iter() {
+            let info = info(6, 4, format);
+            let mut buffer = vec![0; info.size()];
+            let mut surface = Surface::new(&mut buffer, info);
+            surface.fill_rect(4, 2, 10, 10, Rgb::WHITE);
+            surface.put_pixel(0, 0, Rgb::WHITE);
+            surface.put_pixel(6, 0, Rgb::WHITE);
+            for y in 0..4 {
+                for x in 0..6 {
+                    let white = (x == 0 && y == 0) || (x >= 4 && y >= 2);
+                    let expected = if white { Rgb::WHITE } else { Rgb::BLACK };
+                    assert_eq!(surface.pixel(x, y), expected, "{:?} at {}, {}", format, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_glyphs_and_scrolls() {
+        let mut data = vec![0x72, 0xb5, 0x4a, 0x86, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0];
+        for &field in [1u32, 2, 2, 8].iter() {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[0x81, 0x18]);
+        let font = Font::parse(&data).unwrap();
+
+        let info = info(8, 3, PixelFormat::XRGB8888);
+        let mut buffer = vec![0; info.size()];
+        let mut surface = Surface::new(&mut buffer, info);
+        surface.draw_glyph(0, 1, &font.glyph_at(0), Rgb::WHITE, Rgb::new(0, 0, 0xff));
+        let row = |surface: &Surface, y| -> Vec<u8> {
+            (0..8).map(|x| match surface.pixel(x, y) {
+                Rgb::WHITE => b'#',
+                Rgb::BLACK => b'.',
+                _ => b'-',
+            }).collect()
+        };
+        assert_eq!(row(&surface, 1), b"#------#");
+        assert_eq!(row(&surface, 2), b"---##---");
+
+        surface.scroll_up(1, Rgb::BLACK);
+        assert_eq!(row(&surface, 0), b"#------#");
+        assert_eq!(row(&surface, 1), b"---##---");
+        assert_eq!(row(&surface, 2), b"........");
+    }
+}
@@ -0,0 +1,229 @@
+//! Packet format and command parsing of the GDB remote serial protocol.
+//!
+//! A packet is sent as `$<data>#<checksum>`, where the checksum is the sum of the data bytes
+//! modulo 256 in two hex digits. The receiver acknowledges with `+` or asks for a retransmission
+//! with `-`. Only the commands needed to debug a single-threaded target are understood.
+
+/// Kind of a breakpoint or watchpoint as encoded in `Z`/`z` packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointKind {
+    Software,
+    Hardware,
+    Write,
+    Read,
+    Access,
+}
+
+impl BreakpointKind {
+    fn from_raw(kind: u64) -> Option<Self> {
+        match kind {
+            0 => Some(BreakpointKind::Software),
+            1 => Some(BreakpointKind::Hardware),
+            2 => Some(BreakpointKind::Write),
+            3 => Some(BreakpointKind::Read),
+            4 => Some(BreakpointKind::Access),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `?`
+    StopReason,
+    /// `g`
+    ReadRegisters,
+    /// `G<hex>`
+    WriteRegisters(&'a [u8]),
+    /// `p<n>`
+    ReadRegister(usize),
+    /// `P<n>=<hex>`
+    WriteRegister(usize, &'a [u8]),
+    /// `m<address>,<length>`
+    ReadMemory { address: u64, length: usize },
+    /// `M<address>,<length>:<hex>`
+    WriteMemory { address: u64, data: &'a [u8] },
+    /// `c[<address>]`
+    Continue(Option<u64>),
+    /// `s[<address>]`
+    Step(Option<u64>),
+    /// `Z<kind>,<address>,<length>`
+    InsertBreakpoint { kind: BreakpointKind, address: u64, length: usize },
+    /// `z<kind>,<address>,<length>`
+    RemoveBreakpoint { kind: BreakpointKind, address: u64, length: usize },
+    /// `q<name>...`, with the name and arguments
+    Query(&'a [u8]),
+    /// `H<op><thread>`; there is only one thread.
+    SetThread,
+    /// `D`
+    Detach,
+    /// `k`
+    Kill,
+    /// Anything else, answered with an empty packet.
+    Unsupported,
+}
+
+/// The sum of `data` modulo 256.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// The lowercase hex digit for the low four bits of `value`.
+pub fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xf) as usize]
+}
+
+pub fn parse_hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big-endian hex number of at most 16 digits.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, &digit| Some(value << 4 | parse_hex_digit(digit)? as u64))
+}
+
+/// Decode pairs of hex digits into `output`, returning the number of bytes written.
+pub fn decode_hex_bytes(hex: &[u8], output: &mut [u8]) -> Option<usize> {
+    if hex.len() & 1 != 0 || hex.len() / 2 > output.len() {
+        return None;
+    }
+    for (byte, pair) in output.iter_mut().zip(hex.chunks(2)) {
+        *byte = parse_hex_digit(pair[0])? << 4 | parse_hex_digit(pair[1])?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// Parse the data of a packet. Returns `None` if a known command is malformed.
+pub fn parse_command<'a>(packet: &'a [u8]) -> Option<Command<'a>> {
+    let (&command, arguments) = match packet.split_first() {
+        Some(split) => split,
+        None => return Some(Command::Unsupported),
+    };
+    let optional_address = |arguments: &[u8]| {
+        if arguments.is_empty() {
+            Some(None)
+        } else {
+            parse_hex(arguments).map(Some)
+        }
+    };
+    let command = match command {
+        b'?' => Command::StopReason,
+        b'g' => Command::ReadRegisters,
+        b'G' => Command::WriteRegisters(arguments),
+        b'p' => Command::ReadRegister(parse_hex(arguments)? as usize),
+        b'P' => {
+            let (index, value) = split_at_byte(arguments, b'=')?;
+            Command::WriteRegister(parse_hex(index)? as usize, value)
+        }
+        b'm' => {
+            let (address, length) = split_at_byte(arguments, b',')?;
+            Command::ReadMemory { address: parse_hex(address)?, length: parse_hex(length)? as usize }
+        }
+        b'M' => {
+            let (address, rest) = split_at_byte(arguments, b',')?;
+            let (length, data) = split_at_byte(rest, b':')?;
+            if parse_hex(length)? as usize * 2 != data.len() {
+                return None;
+            }
+            Command::WriteMemory { address: parse_hex(address)?, data }
+        }
+        b'c' => Command::Continue(optional_address(arguments)?),
+        b's' => Command::Step(optional_address(arguments)?),
+        b'Z' | b'z' => {
+            let (kind, rest) = split_at_byte(arguments, b',')?;
+            let (address, length) = split_at_byte(rest, b',')?;
+            // Conditions and commands after a `;` are not supported.
+            let length = length.split(|&byte| byte == b';').next()?;
+            let kind = BreakpointKind::from_raw(parse_hex(kind)?)?;
+            let (address, length) = (parse_hex(address)?, parse_hex(length)? as usize);
+            if command == b'Z' {
+                Command::InsertBreakpoint { kind, address, length }
+            } else {
+                Command::RemoveBreakpoint { kind, address, length }
+            }
+        }
+        b'q' => Command::Query(arguments),
+        b'H' => Command::SetThread,
+        b'D' => Command::Detach,
+        b'k' => Command::Kill,
+        _ => Command::Unsupported,
+    };
+    Some(command)
+}
+
+fn split_at_byte(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|&byte| byte == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command(b"?"), Some(Command::StopReason));
+        assert_eq!(parse_command(b"g"), Some(Command::ReadRegisters));
+        assert_eq!(parse_command(b"p10"), Some(Command::ReadRegister(16)));
+        assert_eq!(parse_command(b"P10=0010000000000000"),
+                   Some(Command::WriteRegister(16, b"0010000000000000")));
+        assert_eq!(parse_command(b"mffffffff80001000,40"),
+                   Some(Command::ReadMemory { address: 0xffff_ffff_8000_1000, length: 0x40 }));
+        assert_eq!(parse_command(b"M1000,2:cc90"),
+                   Some(Command::WriteMemory { address: 0x1000, data: b"cc90" }));
+        assert_eq!(parse_command(b"c"), Some(Command::Continue(None)));
+        assert_eq!(parse_command(b"s1000"), Some(Command::Step(Some(0x1000))));
+        assert_eq!(parse_command(b"Z0,1000,1"), Some(Command::InsertBreakpoint {
+            kind: BreakpointKind::Software, address: 0x1000, length: 1,
+        }));
+        assert_eq!(parse_command(b"z2,2000,8;X1,0"), Some(Command::RemoveBreakpoint {
+            kind: BreakpointKind::Write, address: 0x2000, length: 8,
+        }));
+        assert_eq!(parse_command(b"qSupported:multiprocess+"),
+                   Some(Command::Query(b"Supported:multiprocess+")));
+        assert_eq!(parse_command(b"Hg0"), Some(Command::SetThread));
+        assert_eq!(parse_command(b"vMustReplyEmpty"), Some(Command::Unsupported));
+        assert_eq!(parse_command(b""), Some(Command::Unsupported));
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(parse_command(b"m1000"), None);
+        assert_eq!(parse_command(b"mxyz,1"), None);
+        assert_eq!(parse_command(b"M1000,2:cc"), None);
+        assert_eq!(parse_command(b"Z9,1000,1"), None);
+        assert_eq!(parse_command(b"c12345678901234567"), None);
+    }
+
+    #[test]
+    fn checksum_of_known_packet() {
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b""), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn hex_round_trip(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let hex: Vec<u8> = bytes.iter().flat_map(|&b| vec![hex_digit(b >> 4), hex_digit(b)])
+                .collect();
+            let mut output = [0u8; 64];
+            let len = decode_hex_bytes(&hex, &mut output).unwrap();
+            prop_assert_eq!(&output[..len], &bytes[..]);
+        }
+
+        #[test]
+        fn parse_hex_matches_format(value in any::<u64>()) {
+            prop_assert_eq!(parse_hex(format!("{:x}", value).as_bytes()), Some(value));
+            prop_assert_eq!(parse_hex(format!("{:X}", value).as_bytes()), Some(value));
+        }
+    }
+}
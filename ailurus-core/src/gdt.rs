@@ -0,0 +1,152 @@
+//! Segment descriptors of the global descriptor table, and the task state segment.
+//!
+//! Segments only select a privilege level in long mode: code and data segments span the whole
+//! address space. The TSS holds the stack pointer the CPU switches to on an interrupt from user
+//! mode.
+
+use core::mem::size_of;
+
+const ACCESSED: u64 = 1 << 40;
+/// Writable for data segments, readable for code segments.
+const WRITABLE: u64 = 1 << 41;
+const EXECUTABLE: u64 = 1 << 43;
+/// Set for code and data segments, clear for system segments such as the TSS.
+const USER_SEGMENT: u64 = 1 << 44;
+const RING_3: u64 = 3 << 45;
+const PRESENT: u64 = 1 << 47;
+const LONG_MODE: u64 = 1 << 53;
+const AVAILABLE_TSS: u64 = 0x9 << 40;
+
+/// An entry of the GDT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Descriptor(u64);
+
+impl Descriptor {
+    pub const NULL: Descriptor = Descriptor(0);
+    pub const KERNEL_CODE: Descriptor =
+        Descriptor(PRESENT | USER_SEGMENT | EXECUTABLE | WRITABLE | ACCESSED | LONG_MODE);
+    pub const KERNEL_DATA: Descriptor = Descriptor(PRESENT | USER_SEGMENT | WRITABLE | ACCESSED);
+    pub const USER_CODE: Descriptor = Descriptor(Self::KERNEL_CODE.0 | RING_3);
+    pub const USER_DATA: Descriptor = Descriptor(Self::KERNEL_DATA.0 | RING_3);
+
+    /// The two entries describing an available 64-bit TSS at `base`.
+    pub fn tss(base: u64) -> [Descriptor; 2] {
+        let limit = size_of::<TaskStateSegment>() as u64 - 1;
+        let low = PRESENT | AVAILABLE_TSS | limit | (base & 0xff_ffff) << 16 |
+            (base >> 24 & 0xff) << 56;
+        [Descriptor(low), Descriptor(base >> 32)]
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_present(self) -> bool {
+        self.0 & PRESENT != 0
+    }
+
+    /// The privilege level (0-3) of the segment.
+    pub fn privilege_level(self) -> u16 {
+        (self.0 >> 45 & 0b11) as u16
+    }
+}
+
+/// The 64-bit task state segment.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    /// The stack pointers loaded on an interrupt from a less privileged ring into rings 0 to 2.
+    privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    /// The stacks selected by the stack index of IDT entries, 1 to 7.
+    interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    /// The offset of the I/O permission bitmap, the size of the TSS for none: user mode may not
+    /// access any port.
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    pub const fn new() -> Self {
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+
+    /// The stack pointer loaded on an interrupt from user mode, `RSP0`.
+    pub fn kernel_stack(&self) -> u64 {
+        self.privilege_stack_table[0]
+    }
+
+    pub fn set_kernel_stack(&mut self, stack_top: u64) {
+        let mut stacks = self.privilege_stack_table;
+        stacks[0] = stack_top;
+        self.privilege_stack_table = stacks;
+    }
+
+    /// The stack of interrupt stack table entry `index` (1-7).
+    pub fn interrupt_stack(&self, index: usize) -> u64 {
+        assert!(index != 0 && index < 8, "Invalid interrupt stack index {}", index);
+        self.interrupt_stack_table[index - 1]
+    }
+
+    pub fn set_interrupt_stack(&mut self, index: usize, stack_top: u64) {
+        assert!(index != 0 && index < 8, "Invalid interrupt stack index {}", index);
+        let mut stacks = self.interrupt_stack_table;
+        stacks[index - 1] = stack_top;
+        self.interrupt_stack_table = stacks;
+    }
+}
+
+impl Default for TaskStateSegment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_match_their_usual_encodings() {
+        assert_eq!(Descriptor::KERNEL_CODE.bits(), 0x0020_9b00_0000_0000);
+        assert_eq!(Descriptor::KERNEL_DATA.bits(), 0x0000_9300_0000_0000);
+        assert_eq!(Descriptor::USER_CODE.bits(), 0x0020_fb00_0000_0000);
+        assert_eq!(Descriptor::USER_DATA.bits(), 0x0000_f300_0000_0000);
+        assert_eq!(Descriptor::USER_CODE.privilege_level(), 3);
+        assert_eq!(Descriptor::KERNEL_DATA.privilege_level(), 0);
+        assert!(!Descriptor::NULL.is_present());
+    }
+
+    #[test]
+    fn tss_descriptor_splits_its_base() {
+        assert_eq!(size_of::<TaskStateSegment>(), 104);
+        let [low, high] = Descriptor::tss(0xffff_8000_1234_5678);
+        assert_eq!(low.bits(), 0x1200_8934_5678_0067);
+        assert_eq!(high.bits(), 0xffff_8000);
+        assert!(low.is_present());
+    }
+
+    #[test]
+    fn tss_keeps_its_stacks() {
+        let mut tss = TaskStateSegment::new();
+        tss.set_kernel_stack(0xffff_fb00_0001_1000);
+        tss.set_interrupt_stack(1, 0x1000);
+        tss.set_interrupt_stack(7, 0x7000);
+        assert_eq!(tss.kernel_stack(), 0xffff_fb00_0001_1000);
+        assert_eq!(tss.interrupt_stack(1), 0x1000);
+        assert_eq!(tss.interrupt_stack(7), 0x7000);
+        let iomap_base = tss.iomap_base;
+        assert_eq!(iomap_base, 104);
+    }
+}
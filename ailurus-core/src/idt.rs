@@ -0,0 +1,102 @@
+/// Type and attribute bits of an IDT entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdtEntryOption(u16);
+
+impl IdtEntryOption {
+    /// A not present 64-bit interrupt gate.
+    pub const fn minimal() -> Self {
+        IdtEntryOption(1 << 9 | 1 << 10 | 1 << 11)
+    }
+
+    pub fn new() -> Self {
+        let mut options = Self::minimal();
+        options.set_present(true).enable_interrupt(false);
+        options
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.0 & 1 << 15 != 0
+    }
+
+    pub fn set_present(&mut self, present: bool) -> &mut Self {
+        if present {
+            self.0 |= 1 << 15;
+        } else {
+            self.0 &= 0x7fff;
+        }
+        self
+    }
+
+    /// Keep interrupts enabled in the handler by using a trap gate instead of an interrupt gate.
+    pub fn enable_interrupt(&mut self, enable: bool) -> &mut Self {
+        if enable {
+            self.0 |= 1 << 8;
+        } else {
+            self.0 &= 0xfeff;
+        }
+        self
+    }
+
+    /// Set the lowest privilege level (0-3) allowed to invoke the handler with `int`.
+    pub fn set_privilege_level(&mut self, dpl: u16) -> &mut Self {
+        assert!(dpl < 4, "Invalid privilege level {}", dpl);
+        self.0 = (self.0 & 0x9fff) | dpl << 13;
+        self
+    }
+
+    /// Set the interrupt stack table index (1-7) of the handler, 0 keeps the current stack.
+    pub fn set_stack_index(&mut self, stack_index: u16) -> &mut Self {
+        assert!(stack_index < 8, "Invalid interrupt stack index {}", stack_index);
+        self.0 = (self.0 & 0xfff8) | stack_index;
+        self
+    }
+}
+
+impl Default for IdtEntryOption {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn minimal_is_not_present() {
+        assert!(!IdtEntryOption::minimal().is_present());
+        assert_eq!(IdtEntryOption::new().bits(), 0x8e00);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_privilege_level() {
+        IdtEntryOption::new().set_privilege_level(4);
+    }
+
+    proptest! {
+        #[test]
+        fn setters_only_touch_their_bits(present in any::<bool>(), interrupt in any::<bool>(),
+                                         dpl in 0..4u16, stack_index in 0..8u16) {
+            let mut options = IdtEntryOption::minimal();
+            options.set_present(present)
+                .enable_interrupt(interrupt)
+                .set_privilege_level(dpl)
+                .set_stack_index(stack_index);
+
+            let bits = options.bits();
+            prop_assert_eq!(options.is_present(), present);
+            prop_assert_eq!(bits & 1 << 8 != 0, interrupt);
+            prop_assert_eq!(bits >> 13 & 0b11, dpl);
+            prop_assert_eq!(bits & 0b111, stack_index);
+            // The type bits of a 64-bit gate are never changed.
+            prop_assert_eq!(bits & 0b1110_0000_0000, 0b1110_0000_0000);
+            prop_assert_eq!(bits & 0b1111_1000, 0);
+        }
+    }
+}
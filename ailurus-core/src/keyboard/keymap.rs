@@ -0,0 +1,251 @@
+//! Keymaps: the characters each key types without modifiers, with Shift and with AltGr.
+//!
+//! Keymaps are plain text so that custom ones can be loaded from a file. Every line maps a key to
+//! its characters:
+//!
+//! ```text
+//! # <key> <normal> [<shift> [<altgr>]]
+//! Key2 2 " @
+//! E e E U+20AC
+//! ```
+//!
+//! Keys are named like `KeyCode`s. A character is written as itself or as `U+XXXX`, and `-` stands
+//! for none. Without a Shift character, the key types the same with and without Shift. A keymap
+//! only lists the keys which differ from the US layout, which is applied first.
+
+use super::{KeyCode, Modifiers, KEY_COUNT};
+
+const NORMAL: usize = 0;
+const SHIFT: usize = 1;
+const ALTGR: usize = 2;
+const LEVELS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeymapErrorKind {
+    UnknownKey,
+    /// A character which is neither a single character, `U+XXXX` nor `-`.
+    InvalidCharacter,
+    MissingCharacter,
+    TooManyCharacters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeymapError {
+    /// The line of the error, starting at 1.
+    pub line: usize,
+    pub kind: KeymapErrorKind,
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    characters: [[Option<char>; LEVELS]; KEY_COUNT],
+}
+
+impl Keymap {
+    pub fn empty() -> Self {
+        Keymap { characters: [[None; LEVELS]; KEY_COUNT] }
+    }
+
+    /// Parse a keymap, applied on top of the US layout.
+    pub fn parse(source: &str) -> Result<Self, KeymapError> {
+        let mut keymap = Layout::Us.keymap();
+        keymap.apply(source)?;
+        Ok(keymap)
+    }
+
+    /// Replace the characters of the keys listed in `source`. On error, the lines before the
+    /// faulty one have been applied.
+    pub fn apply(&mut self, source: &str) -> Result<(), KeymapError> {
+        for (index, line) in source.lines().enumerate() {
+            let error = |kind| KeymapError { line: index + 1, kind };
+            let mut tokens = line.split_whitespace();
+            let key = match tokens.next() {
+                Some(token) if token.starts_with('#') => continue,
+                Some(token) => KeyCode::from_name(token).ok_or(error(KeymapErrorKind::UnknownKey))?,
+                None => continue,
+            };
+
+            let mut characters = [None; LEVELS];
+            let mut count = 0;
+            for token in tokens {
+                if count == LEVELS {
+                    return Err(error(KeymapErrorKind::TooManyCharacters));
+                }
+                characters[count] = parse_character(token)
+                    .ok_or(error(KeymapErrorKind::InvalidCharacter))?;
+                count += 1;
+            }
+            match count {
+                0 => return Err(error(KeymapErrorKind::MissingCharacter)),
+                1 => characters[SHIFT] = characters[NORMAL],
+                _ => {}
+            }
+            self.characters[key as usize] = characters;
+        }
+        Ok(())
+    }
+
+    /// The characters of `key` without modifiers, with Shift and with AltGr.
+    pub fn characters(&self, key: KeyCode) -> [Option<char>; LEVELS] {
+        self.characters[key as usize]
+    }
+
+    pub fn set_characters(&mut self, key: KeyCode, characters: [Option<char>; LEVELS]) {
+        self.characters[key as usize] = characters;
+    }
+
+    /// The character typed by pressing `key` with `modifiers`.
+    pub fn character(&self, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+        let characters = self.characters(key);
+        if is_numpad_navigation(key) && !modifiers.contains(Modifiers::NUM_LOCK) {
+            return None;
+        }
+        if modifiers.contains(Modifiers::RIGHT_ALT) {
+            return characters[ALTGR];
+        }
+
+        let mut shift = modifiers.shift();
+        let letter = characters[NORMAL].filter(|character| character.is_lowercase());
+        if letter.is_some() && modifiers.contains(Modifiers::CAPS_LOCK) {
+            shift = !shift;
+        }
+        let character = characters[if shift { SHIFT } else { NORMAL }]?;
+        if modifiers.ctrl() && character.is_ascii_alphabetic() {
+            // Ctrl+A is 0x01 and so on.
+            return Some((character.to_ascii_uppercase() as u8 & 0x1f) as char);
+        }
+        Some(character)
+    }
+}
+
+/// Numpad keys which move the cursor instead of typing when Num Lock is off.
+fn is_numpad_navigation(key: KeyCode) -> bool {
+    (KeyCode::Numpad0 <= key && key <= KeyCode::Numpad9) || key == KeyCode::NumpadPeriod
+}
+
+fn parse_character(token: &str) -> Option<Option<char>> {
+    if token == "-" {
+        return Some(None);
+    }
+    if token.len() > 2 && (token.starts_with("U+") || token.starts_with("u+")) {
+        let code = u32::from_str_radix(&token[2..], 16).ok()?;
+        return core::char::from_u32(code).map(Some);
+    }
+    let mut chars = token.chars();
+    match (chars.next(), chars.next()) {
+        (Some(character), None) => Some(Some(character)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    Dvorak,
+    Azerty,
+    Jis,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [Layout::Us, Layout::Uk, Layout::Dvorak, Layout::Azerty,
+                                  Layout::Jis];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::Dvorak => "dvorak",
+            Layout::Azerty => "azerty",
+            Layout::Jis => "jis",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.iter().cloned().find(|layout| layout.name() == name)
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Layout::Us => include_str!("../../keymaps/us.keymap"),
+            Layout::Uk => include_str!("../../keymaps/uk.keymap"),
+            Layout::Dvorak => include_str!("../../keymaps/dvorak.keymap"),
+            Layout::Azerty => include_str!("../../keymaps/azerty.keymap"),
+            Layout::Jis => include_str!("../../keymaps/jis.keymap"),
+        }
+    }
+
+    pub fn keymap(self) -> Keymap {
+        let mut keymap = Keymap::empty();
+        keymap.apply(Layout::Us.source()).expect("invalid built-in keymap");
+        if self != Layout::Us {
+            keymap.apply(self.source()).expect("invalid built-in keymap");
+        }
+        keymap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(keymap: &Keymap, keys: &[KeyCode], modifiers: Modifiers) -> String {
+        keys.iter().filter_map(|&key| keymap.character(key, modifiers)).collect()
+    }
+
+    #[test]
+    fn built_in_layouts() {
+        let keys = [KeyCode::Q, KeyCode::W, KeyCode::A, KeyCode::Z, KeyCode::Key2,
+                    KeyCode::Quote, KeyCode::BackSlash];
+        let expected = [
+            (Layout::Us, "qwaz2'\\", "QWAZ@\"|"),
+            (Layout::Uk, "qwaz2'#", "QWAZ\"@~"),
+            (Layout::Dvorak, "',a;2-\\", "\"<A:@_|"),
+            (Layout::Azerty, "azqwé\u{f9}*", "AZQW2%\u{b5}"),
+            (Layout::Jis, "qwaz2:]", "QWAZ\"*}"),
+        ];
+        for &(layout, normal, shifted) in expected.iter() {
+            let keymap = layout.keymap();
+            assert_eq!(typed(&keymap, &keys, Modifiers::empty()), normal, "{:?}", layout);
+            assert_eq!(typed(&keymap, &keys, Modifiers::LEFT_SHIFT), shifted, "{:?}", layout);
+            assert_eq!(Layout::from_name(layout.name()), Some(layout));
+        }
+        assert_eq!(Layout::Uk.keymap().character(KeyCode::Key4, Modifiers::RIGHT_ALT),
+                   Some('€'));
+        assert_eq!(Layout::Jis.keymap().character(KeyCode::Yen, Modifiers::empty()), Some('¥'));
+    }
+
+    #[test]
+    fn modifiers_change_characters() {
+        let keymap = Layout::Us.keymap();
+        assert_eq!(keymap.character(KeyCode::A, Modifiers::CAPS_LOCK), Some('A'));
+        assert_eq!(keymap.character(KeyCode::A, Modifiers::CAPS_LOCK | Modifiers::LEFT_SHIFT),
+                   Some('a'));
+        assert_eq!(keymap.character(KeyCode::Key1, Modifiers::CAPS_LOCK), Some('1'));
+        assert_eq!(keymap.character(KeyCode::C, Modifiers::LEFT_CTRL), Some('\x03'));
+        assert_eq!(keymap.character(KeyCode::Numpad7, Modifiers::empty()), None);
+        assert_eq!(keymap.character(KeyCode::Numpad7, Modifiers::NUM_LOCK), Some('7'));
+        assert_eq!(keymap.character(KeyCode::Enter, Modifiers::LEFT_SHIFT), Some('\n'));
+        assert_eq!(keymap.character(KeyCode::ArrowUp, Modifiers::empty()), None);
+    }
+
+    #[test]
+    fn parses_custom_keymaps() {
+        let keymap = Keymap::parse("# Swap Y and Z\n\nY z Z\nZ y Y\nE e E U+20AC\nKey1 -\n")
+            .unwrap();
+        assert_eq!(keymap.characters(KeyCode::Y), [Some('z'), Some('Z'), None]);
+        assert_eq!(keymap.characters(KeyCode::E), [Some('e'), Some('E'), Some('€')]);
+        assert_eq!(keymap.characters(KeyCode::Key1), [None, None, None]);
+        assert_eq!(keymap.characters(KeyCode::Q), [Some('q'), Some('Q'), None]);
+    }
+
+    #[test]
+    fn rejects_invalid_keymaps() {
+        let error = |source| Keymap::parse(source).err().map(|error| (error.line, error.kind));
+        assert_eq!(error("A a\nNoSuchKey x"), Some((2, KeymapErrorKind::UnknownKey)));
+        assert_eq!(error("A"), Some((1, KeymapErrorKind::MissingCharacter)));
+        assert_eq!(error("A ab"), Some((1, KeymapErrorKind::InvalidCharacter)));
+        assert_eq!(error("A U+D800"), Some((1, KeymapErrorKind::InvalidCharacter)));
+        assert_eq!(error("A a A b c"), Some((1, KeymapErrorKind::TooManyCharacters)));
+    }
+}
@@ -0,0 +1,157 @@
+//! Keyboard decoding: scancodes to key events, and key events to characters.
+
+pub mod keymap;
+pub mod scancode;
+
+macro_rules! key_codes {
+    ($($name:ident),* $(,)*) => {
+        /// A physical key, named after its meaning on a US keyboard.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        pub enum KeyCode {
+            $($name),*
+        }
+
+        /// Number of `KeyCode`s.
+        pub const KEY_COUNT: usize = 0 $(+ 1 + 0 * (KeyCode::$name as usize))*;
+
+        impl KeyCode {
+            pub const ALL: [KeyCode; KEY_COUNT] = [$(KeyCode::$name),*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(KeyCode::$name => stringify!($name)),*
+                }
+            }
+        }
+    };
+}
+
+key_codes! {
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, PrintScreen, ScrollLock, PauseBreak,
+    BackTick, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, Minus, Equals, Backspace,
+    Tab, Q, W, E, R, T, Y, U, I, O, P, BracketSquareLeft, BracketSquareRight, BackSlash,
+    CapsLock, A, S, D, F, G, H, J, K, L, SemiColon, Quote, Enter,
+    ShiftLeft, NonUsBackslash, Z, X, C, V, B, N, M, Comma, Fullstop, Slash, ShiftRight,
+    ControlLeft, WindowsLeft, AltLeft, Spacebar, AltRight, WindowsRight, Menus, ControlRight,
+    Insert, Home, PageUp, Delete, End, PageDown, ArrowUp, ArrowLeft, ArrowDown, ArrowRight,
+    NumpadLock, NumpadSlash, NumpadStar, NumpadMinus, NumpadPlus, NumpadEnter, NumpadPeriod,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    // Additional keys of Japanese keyboards
+    Katakana, Henkan, Muhenkan, Ro, Yen,
+}
+
+impl KeyCode {
+    pub fn from_name(name: &str) -> Option<KeyCode> {
+        KeyCode::ALL.iter().cloned().find(|key| key.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Down,
+    Up,
+}
+
+bitflags! {
+    /// Modifier keys held down and lock keys switched on.
+    pub struct Modifiers: u16 {
+        const NONE =        0;
+        const LEFT_SHIFT =  1 << 0;
+        const RIGHT_SHIFT = 1 << 1;
+        const LEFT_CTRL =   1 << 2;
+        const RIGHT_CTRL =  1 << 3;
+        const LEFT_ALT =    1 << 4;
+        /// AltGr on international layouts.
+        const RIGHT_ALT =   1 << 5;
+        const CAPS_LOCK =   1 << 6;
+        const NUM_LOCK =    1 << 7;
+        const SCROLL_LOCK = 1 << 8;
+    }
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.intersects(Modifiers::LEFT_SHIFT | Modifiers::RIGHT_SHIFT)
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.intersects(Modifiers::LEFT_CTRL | Modifiers::RIGHT_CTRL)
+    }
+
+    pub fn alt(&self) -> bool {
+        self.intersects(Modifiers::LEFT_ALT | Modifiers::RIGHT_ALT)
+    }
+
+    /// The argument of the PS/2 keyboard's "set LEDs" command for the lock keys.
+    pub fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.contains(Modifiers::SCROLL_LOCK) {
+            leds |= 1 << 0;
+        }
+        if self.contains(Modifiers::NUM_LOCK) {
+            leds |= 1 << 1;
+        }
+        if self.contains(Modifiers::CAPS_LOCK) {
+            leds |= 1 << 2;
+        }
+        leds
+    }
+
+    /// Update the state for a key event, returning false if `code` is not a modifier key.
+    pub fn update(&mut self, code: KeyCode, state: KeyState) -> bool {
+        let (modifier, lock) = match code {
+            KeyCode::ShiftLeft => (Modifiers::LEFT_SHIFT, false),
+            KeyCode::ShiftRight => (Modifiers::RIGHT_SHIFT, false),
+            KeyCode::ControlLeft => (Modifiers::LEFT_CTRL, false),
+            KeyCode::ControlRight => (Modifiers::RIGHT_CTRL, false),
+            KeyCode::AltLeft => (Modifiers::LEFT_ALT, false),
+            KeyCode::AltRight => (Modifiers::RIGHT_ALT, false),
+            KeyCode::CapsLock => (Modifiers::CAPS_LOCK, true),
+            KeyCode::NumpadLock => (Modifiers::NUM_LOCK, true),
+            KeyCode::ScrollLock => (Modifiers::SCROLL_LOCK, true),
+            _ => return false,
+        };
+        match (lock, state) {
+            (true, KeyState::Down) => self.toggle(modifier),
+            (true, KeyState::Up) => {}
+            (false, KeyState::Down) => self.insert(modifier),
+            (false, KeyState::Up) => self.remove(modifier),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names_round_trip() {
+        assert_eq!(KEY_COUNT, KeyCode::ALL.len());
+        for (index, &key) in KeyCode::ALL.iter().enumerate() {
+            assert_eq!(key as usize, index);
+            assert_eq!(KeyCode::from_name(key.name()), Some(key));
+        }
+        assert_eq!(KeyCode::from_name("NoSuchKey"), None);
+    }
+
+    #[test]
+    fn modifiers_follow_key_events() {
+        let mut modifiers = Modifiers::empty();
+        assert!(modifiers.update(KeyCode::ShiftLeft, KeyState::Down));
+        assert!(modifiers.shift());
+        modifiers.update(KeyCode::ShiftLeft, KeyState::Up);
+        assert!(!modifiers.shift());
+
+        modifiers.update(KeyCode::CapsLock, KeyState::Down);
+        modifiers.update(KeyCode::CapsLock, KeyState::Up);
+        assert!(modifiers.contains(Modifiers::CAPS_LOCK));
+        modifiers.update(KeyCode::CapsLock, KeyState::Down);
+        assert!(!modifiers.contains(Modifiers::CAPS_LOCK));
+        assert!(!modifiers.update(KeyCode::A, KeyState::Down));
+
+        modifiers.update(KeyCode::NumpadLock, KeyState::Down);
+        modifiers.update(KeyCode::ScrollLock, KeyState::Down);
+        assert_eq!(modifiers.leds(), 0b011);
+    }
+}
@@ -0,0 +1,432 @@
+//! Decoding of PS/2 scancode sets 1 and 2.
+//!
+//! Set 1 sends a make code per key press and the same code with bit 7 set on release. Set 2
+//! announces a release with an `F0` prefix instead. In both sets, `E0` precedes the codes of
+//! keys added after the original XT/AT keyboards, and Pause sends an `E1` sequence.
+
+use core::mem;
+use super::{KeyCode, KeyState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+impl ScancodeSet {
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            1 => Some(ScancodeSet::Set1),
+            2 => Some(ScancodeSet::Set2),
+            _ => None,
+        }
+    }
+}
+
+const EXTENDED: u8 = 0xe0;
+const PAUSE: u8 = 0xe1;
+const SET2_RELEASE: u8 = 0xf0;
+const SET1_RELEASE_BIT: u8 = 0x80;
+
+pub struct ScancodeDecoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool,
+    /// Bytes of a Pause sequence still to come.
+    pause_remaining: u8,
+}
+
+impl ScancodeDecoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        ScancodeDecoder { set, extended: false, release: false, pause_remaining: 0 }
+    }
+
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// Feed a byte from the keyboard, returning the event once a sequence is complete.
+    pub fn add_byte(&mut self, byte: u8) -> Option<(KeyCode, KeyState)> {
+        if self.pause_remaining > 0 {
+            return self.add_pause_byte(byte);
+        }
+        match (self.set, byte) {
+            (_, EXTENDED) => {
+                self.extended = true;
+                None
+            }
+            (_, PAUSE) => {
+                self.pause_remaining = 2;
+                None
+            }
+            (ScancodeSet::Set1, _) => {
+                let extended = mem::replace(&mut self.extended, false);
+                let state = if byte & SET1_RELEASE_BIT != 0 { KeyState::Up } else { KeyState::Down };
+                let code = byte & !SET1_RELEASE_BIT;
+                let key = if extended { set1_extended_key(code) } else { set1_key(code) };
+                key.map(|key| (key, state))
+            }
+            (ScancodeSet::Set2, SET2_RELEASE) => {
+                self.release = true;
+                None
+            }
+            (ScancodeSet::Set2, _) => {
+                let extended = mem::replace(&mut self.extended, false);
+                let release = mem::replace(&mut self.release, false);
+                let state = if release { KeyState::Up } else { KeyState::Down };
+                let key = if extended { set2_extended_key(byte) } else { set2_key(byte) };
+                key.map(|key| (key, state))
+            }
+        }
+    }
+
+    /// Pause has no release code but sends two halves on a press, `E1 1D 45 E1 9D C5` in set 1
+    /// and `E1 14 77 E1 F0 14 F0 77` in set 2. They are reported as press and release.
+    fn add_pause_byte(&mut self, byte: u8) -> Option<(KeyCode, KeyState)> {
+        if self.set == ScancodeSet::Set2 && byte == SET2_RELEASE {
+            self.release = true;
+            return None;
+        }
+        if self.set == ScancodeSet::Set1 && byte & SET1_RELEASE_BIT != 0 {
+            self.release = true;
+        }
+        self.pause_remaining -= 1;
+        if self.pause_remaining > 0 {
+            return None;
+        }
+        let release = mem::replace(&mut self.release, false);
+        Some((KeyCode::PauseBreak, if release { KeyState::Up } else { KeyState::Down }))
+    }
+}
+
+fn set1_key(code: u8) -> Option<KeyCode> {
+    use super::KeyCode::*;
+    let key = match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0a => Key9,
+        0x0b => Key0,
+        0x0c => Minus,
+        0x0d => Equals,
+        0x0e => Backspace,
+        0x0f => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1a => BracketSquareLeft,
+        0x1b => BracketSquareRight,
+        0x1c => Enter,
+        0x1d => ControlLeft,
+        0x1e => A,
+        0x1f => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => SemiColon,
+        0x28 => Quote,
+        0x29 => BackTick,
+        0x2a => ShiftLeft,
+        0x2b => BackSlash,
+        0x2c => Z,
+        0x2d => X,
+        0x2e => C,
+        0x2f => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Fullstop,
+        0x35 => Slash,
+        0x36 => ShiftRight,
+        0x37 => NumpadStar,
+        0x38 => AltLeft,
+        0x39 => Spacebar,
+        0x3a => CapsLock,
+        0x3b => F1,
+        0x3c => F2,
+        0x3d => F3,
+        0x3e => F4,
+        0x3f => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumpadLock,
+        0x46 => ScrollLock,
+        0x47 => Numpad7,
+        0x48 => Numpad8,
+        0x49 => Numpad9,
+        0x4a => NumpadMinus,
+        0x4b => Numpad4,
+        0x4c => Numpad5,
+        0x4d => Numpad6,
+        0x4e => NumpadPlus,
+        0x4f => Numpad1,
+        0x50 => Numpad2,
+        0x51 => Numpad3,
+        0x52 => Numpad0,
+        0x53 => NumpadPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        0x70 => Katakana,
+        0x73 => Ro,
+        0x79 => Henkan,
+        0x7b => Muhenkan,
+        0x7d => Yen,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Keys after an `E0` prefix. The fake shifts sent around Print Screen and the navigation
+/// keys are ignored.
+fn set1_extended_key(code: u8) -> Option<KeyCode> {
+    use super::KeyCode::*;
+    let key = match code {
+        0x1c => NumpadEnter,
+        0x1d => ControlRight,
+        0x35 => NumpadSlash,
+        0x37 => PrintScreen,
+        0x38 => AltRight,
+        0x47 => Home,
+        0x48 => ArrowUp,
+        0x49 => PageUp,
+        0x4b => ArrowLeft,
+        0x4d => ArrowRight,
+        0x4f => End,
+        0x50 => ArrowDown,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5b => WindowsLeft,
+        0x5c => WindowsRight,
+        0x5d => Menus,
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn set2_key(code: u8) -> Option<KeyCode> {
+    use super::KeyCode::*;
+    let key = match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0a => F8,
+        0x0b => F6,
+        0x0c => F4,
+        0x0d => Tab,
+        0x0e => BackTick,
+        0x11 => AltLeft,
+        0x12 => ShiftLeft,
+        0x13 => Katakana,
+        0x14 => ControlLeft,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1a => Z,
+        0x1b => S,
+        0x1c => A,
+        0x1d => W,
+        0x1e => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Spacebar,
+        0x2a => V,
+        0x2b => F,
+        0x2c => T,
+        0x2d => R,
+        0x2e => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3a => M,
+        0x3b => J,
+        0x3c => U,
+        0x3d => Key7,
+        0x3e => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Fullstop,
+        0x4a => Slash,
+        0x4b => L,
+        0x4c => SemiColon,
+        0x4d => P,
+        0x4e => Minus,
+        0x51 => Ro,
+        0x52 => Quote,
+        0x54 => BracketSquareLeft,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => ShiftRight,
+        0x5a => Enter,
+        0x5b => BracketSquareRight,
+        0x5d => BackSlash,
+        0x61 => NonUsBackslash,
+        0x64 => Henkan,
+        0x66 => Backspace,
+        0x67 => Muhenkan,
+        0x69 => Numpad1,
+        0x6a => Yen,
+        0x6b => Numpad4,
+        0x6c => Numpad7,
+        0x70 => Numpad0,
+        0x71 => NumpadPeriod,
+        0x72 => Numpad2,
+        0x73 => Numpad5,
+        0x74 => Numpad6,
+        0x75 => Numpad8,
+        0x76 => Escape,
+        0x77 => NumpadLock,
+        0x78 => F11,
+        0x79 => NumpadPlus,
+        0x7a => Numpad3,
+        0x7b => NumpadMinus,
+        0x7c => NumpadStar,
+        0x7d => Numpad9,
+        0x7e => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn set2_extended_key(code: u8) -> Option<KeyCode> {
+    use super::KeyCode::*;
+    let key = match code {
+        0x11 => AltRight,
+        0x14 => ControlRight,
+        0x1f => WindowsLeft,
+        0x27 => WindowsRight,
+        0x2f => Menus,
+        0x4a => NumpadSlash,
+        0x5a => NumpadEnter,
+        0x69 => End,
+        0x6b => ArrowLeft,
+        0x6c => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => ArrowDown,
+        0x74 => ArrowRight,
+        0x75 => ArrowUp,
+        0x7a => PageDown,
+        0x7c => PrintScreen,
+        0x7d => PageUp,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode(set: ScancodeSet, bytes: &[u8]) -> Vec<(KeyCode, KeyState)> {
+        let mut decoder = ScancodeDecoder::new(set);
+        bytes.iter().filter_map(|&byte| decoder.add_byte(byte)).collect()
+    }
+
+    #[test]
+    fn set1_sequences() {
+        assert_eq!(decode(ScancodeSet::Set1, &[0x1e, 0x9e]),
+                   vec![(KeyCode::A, KeyState::Down), (KeyCode::A, KeyState::Up)]);
+        assert_eq!(decode(ScancodeSet::Set1, &[0xe0, 0x48, 0xe0, 0xc8]),
+                   vec![(KeyCode::ArrowUp, KeyState::Down), (KeyCode::ArrowUp, KeyState::Up)]);
+        // Print Screen with its fake left shift.
+        assert_eq!(decode(ScancodeSet::Set1, &[0xe0, 0x2a, 0xe0, 0x37]),
+                   vec![(KeyCode::PrintScreen, KeyState::Down)]);
+        assert_eq!(decode(ScancodeSet::Set1, &[0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5, 0x1e]),
+                   vec![(KeyCode::PauseBreak, KeyState::Down), (KeyCode::PauseBreak, KeyState::Up),
+                        (KeyCode::A, KeyState::Down)]);
+    }
+
+    #[test]
+    fn set2_sequences() {
+        assert_eq!(decode(ScancodeSet::Set2, &[0x1c, 0xf0, 0x1c]),
+                   vec![(KeyCode::A, KeyState::Down), (KeyCode::A, KeyState::Up)]);
+        assert_eq!(decode(ScancodeSet::Set2, &[0xe0, 0x75, 0xe0, 0xf0, 0x75]),
+                   vec![(KeyCode::ArrowUp, KeyState::Down), (KeyCode::ArrowUp, KeyState::Up)]);
+        assert_eq!(decode(ScancodeSet::Set2, &[0x83, 0xf0, 0x83]),
+                   vec![(KeyCode::F7, KeyState::Down), (KeyCode::F7, KeyState::Up)]);
+        assert_eq!(decode(ScancodeSet::Set2,
+                          &[0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77, 0x1c]),
+                   vec![(KeyCode::PauseBreak, KeyState::Down), (KeyCode::PauseBreak, KeyState::Up),
+                        (KeyCode::A, KeyState::Down)]);
+        // Acknowledgements and errors are not keys.
+        assert_eq!(decode(ScancodeSet::Set2, &[0xfa, 0xaa, 0x00]), vec![]);
+    }
+
+    /// Find the make code of `key` by trying every plain and extended byte.
+    fn make_code(set: ScancodeSet, key: KeyCode) -> Option<Vec<u8>> {
+        (0..=255u8).map(|byte| vec![byte]).chain((0..=255u8).map(|byte| vec![EXTENDED, byte]))
+            .find(|bytes| decode(set, bytes) == vec![(key, KeyState::Down)])
+    }
+
+    #[test]
+    fn every_key_has_a_scancode() {
+        for &key in KeyCode::ALL.iter().filter(|&&key| key != KeyCode::PauseBreak) {
+            assert!(make_code(ScancodeSet::Set1, key).is_some(), "{:?} in set 1", key);
+            assert!(make_code(ScancodeSet::Set2, key).is_some(), "{:?} in set 2", key);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn release_follows_press(index in 0..KeyCode::ALL.len()) {
+            let key = KeyCode::ALL[index];
+            prop_assume!(key != KeyCode::PauseBreak);
+
+            let mut set1 = make_code(ScancodeSet::Set1, key).unwrap();
+            let last = set1.len() - 1;
+            set1.push(set1[last] | SET1_RELEASE_BIT);
+            if last == 1 {
+                set1.insert(2, EXTENDED);
+            }
+            prop_assert_eq!(decode(ScancodeSet::Set1, &set1),
+                            vec![(key, KeyState::Down), (key, KeyState::Up)]);
+
+            let mut set2 = make_code(ScancodeSet::Set2, key).unwrap();
+            let make = set2.clone();
+            set2.extend_from_slice(&make[..make.len() - 1]);
+            set2.push(SET2_RELEASE);
+            set2.push(make[make.len() - 1]);
+            prop_assert_eq!(decode(ScancodeSet::Set2, &set2),
+                            vec![(key, KeyState::Down), (key, KeyState::Up)]);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Architecture-independent parts of the Ailurus kernel.
+//!
+//! Nothing in here touches the hardware, so the crate also builds for the host and `cargo test`
+//! can exercise it directly.
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate bitflags;
+
+pub mod address;
+pub mod ansi;
+pub mod broadcast;
+pub mod canvas;
+pub mod cmdline;
+pub mod cp437;
+pub mod e820;
+pub mod elf;
+pub mod framebuffer;
+pub mod gdb;
+pub mod gdt;
+pub mod idt;
+pub mod keyboard;
+pub mod lockdep;
+pub mod page_table;
+pub mod ps2;
+pub mod psf;
+pub mod ring_queue;
+pub mod sched;
+pub mod syscall;
+pub mod utf8;
+pub mod vt;
+
+/// The number of bytes holding `bits` bits. `usize::div_ceil` is too recent for the kernel's
+/// toolchain.
+#[allow(clippy::manual_div_ceil)]
+pub(crate) fn bytes_for_bits(bits: usize) -> usize {
+    (bits + 7) / 8
+}
@@ -0,0 +1,418 @@
+//! Lock dependency validation, after Linux's lockdep.
+//!
+//! Every lock belongs to a class, and every context (a task, or the interrupt handlers of a CPU)
+//! has a `Chain` of the classes it holds, in the order it took them. The `Validator` records
+//! which classes were taken while which others were held, and reports a `Violation` as soon as
+//! a deadlock becomes possible, even if it did not happen:
+//!
+//! - taking a class already held, which deadlocks right away with spinlocks;
+//! - taking a class while holding another one taken after it elsewhere, directly or through
+//!   other classes, which deadlocks if both contexts run at the same time;
+//! - taking a class both in interrupt context and with interrupts enabled, which deadlocks if
+//!   the interrupt arrives while it is held.
+//!
+//! The validator stops after the first violation, or when it runs out of room.
+
+use core::fmt;
+
+/// The number of lock classes the validator can follow.
+pub const MAX_CLASSES: usize = 64;
+/// The number of locks a context can hold at once.
+pub const MAX_HELD: usize = 16;
+
+/// The index of a lock class, given by `Validator::register`.
+pub type ClassId = usize;
+
+/// Lock classes in the order they were taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chain {
+    classes: [u8; MAX_HELD],
+    len: usize,
+}
+
+impl Chain {
+    pub const EMPTY: Chain = Chain { classes: [0; MAX_HELD], len: 0 };
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, class: ClassId) -> bool {
+        self.iter().any(|held| held == class)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = ClassId> + 'a {
+        self.classes[..self.len].iter().map(|&class| class as ClassId)
+    }
+
+    /// Append `class`, returning false if the chain is full.
+    pub fn push(&mut self, class: ClassId) -> bool {
+        if self.len == MAX_HELD {
+            return false;
+        }
+        self.classes[self.len] = class as u8;
+        self.len += 1;
+        true
+    }
+
+    /// Take out the last `class`, returning false if there is none. Locks need not be released
+    /// in the reverse order of taking them.
+    pub fn remove(&mut self, class: ClassId) -> bool {
+        let classes = &self.classes[..self.len];
+        let position = match classes.iter().rposition(|&held| held as ClassId == class) {
+            Some(position) => position,
+            None => return false,
+        };
+        for index in position..self.len - 1 {
+            self.classes[index] = self.classes[index + 1];
+        }
+        // Unused entries stay zero for the comparison of chains.
+        self.classes[self.len - 1] = 0;
+        self.len -= 1;
+        true
+    }
+
+    /// This chain followed by `class`, if there is room.
+    fn with(mut self, class: ClassId) -> Chain {
+        self.push(class);
+        self
+    }
+
+    fn mask(&self) -> u64 {
+        self.iter().fold(0, |mask, class| mask | 1 << class)
+    }
+}
+
+/// The context a lock is taken in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Interrupt,
+    InterruptsEnabled,
+    InterruptsDisabled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// `class` was taken while already in `held`.
+    Recursion { class: ClassId, held: Chain },
+    /// `class` was taken while holding `held`, but `dependency` starts with `class` and ends
+    /// with a class of `held`, each of its classes having been taken while the previous one
+    /// was held.
+    Inversion { class: ClassId, held: Chain, dependency: Chain },
+    /// `class` was taken in interrupt context, ending `interrupt`, and with interrupts enabled,
+    /// ending `interrupts_enabled`.
+    IrqUnsafe { class: ClassId, interrupt: Chain, interrupts_enabled: Chain },
+}
+
+pub struct Validator {
+    names: [&'static str; MAX_CLASSES],
+    len: usize,
+    /// Bit `b` of `after[a]` is set once class `b` was taken while class `a` was held.
+    after: [u64; MAX_CLASSES],
+    /// The chain of the first time each class was taken in interrupt context.
+    interrupt: [Option<Chain>; MAX_CLASSES],
+    /// The chain of the first time each class was taken with interrupts enabled.
+    interrupts_enabled: [Option<Chain>; MAX_CLASSES],
+    enabled: bool,
+}
+
+impl Validator {
+    pub const fn new() -> Self {
+        Validator {
+            names: [""; MAX_CLASSES],
+            len: 0,
+            after: [0; MAX_CLASSES],
+            interrupt: [None; MAX_CLASSES],
+            interrupts_enabled: [None; MAX_CLASSES],
+            enabled: true,
+        }
+    }
+
+    /// Whether the validator still follows the locks: it stops after the first violation, or
+    /// when it runs out of room.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Add a lock class, returning `None` once there are `MAX_CLASSES`.
+    pub fn register(&mut self, name: &'static str) -> Option<ClassId> {
+        if self.len == MAX_CLASSES {
+            self.enabled = false;
+            return None;
+        }
+        self.names[self.len] = name;
+        self.len += 1;
+        Some(self.len - 1)
+    }
+
+    pub fn name(&self, class: ClassId) -> &'static str {
+        self.names[class]
+    }
+
+    /// Record that the context holding `held` takes `class` for `usage`, and add it to `held`.
+    /// A successful `try_lock` cannot deadlock, so `try_lock` skips the checks of the order.
+    pub fn acquire(&mut self, held: &mut Chain, class: ClassId, usage: Usage, try_lock: bool)
+                   -> Result<(), Violation> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if !try_lock {
+            if held.contains(class) {
+                return Err(self.fail(Violation::Recursion { class, held: *held }));
+            }
+            if let Some(dependency) = self.dependency(class, held.mask()) {
+                return Err(self.fail(Violation::Inversion { class, held: *held, dependency }));
+            }
+            for previous in held.iter() {
+                self.after[previous] |= 1 << class;
+            }
+        }
+        let chain = held.with(class);
+        match usage {
+            Usage::Interrupt => {
+                self.interrupt[class].get_or_insert(chain);
+            }
+            Usage::InterruptsEnabled => {
+                self.interrupts_enabled[class].get_or_insert(chain);
+            }
+            Usage::InterruptsDisabled => {}
+        }
+        if let (Some(interrupt), Some(interrupts_enabled)) =
+            (self.interrupt[class], self.interrupts_enabled[class]) {
+            if usage != Usage::InterruptsDisabled {
+                let violation = Violation::IrqUnsafe { class, interrupt, interrupts_enabled };
+                return Err(self.fail(violation));
+            }
+        }
+        if !held.push(class) {
+            // Too deep to follow.
+            self.enabled = false;
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, violation: Violation) -> Violation {
+        self.enabled = false;
+        violation
+    }
+
+    /// The shortest chain of dependencies from `from` to one of the classes of `targets`.
+    fn dependency(&self, from: ClassId, targets: u64) -> Option<Chain> {
+        let mut previous = [from; MAX_CLASSES];
+        let mut queue = [from; MAX_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        let mut seen = 1u64 << from;
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+            if targets & 1 << class != 0 {
+                let mut path = [class; MAX_CLASSES];
+                let mut len = 1;
+                while path[len - 1] != from {
+                    path[len] = previous[path[len - 1]];
+                    len += 1;
+                }
+                let mut chain = Chain::EMPTY;
+                for &class in path[..len].iter().rev() {
+                    chain.push(class);
+                }
+                return Some(chain);
+            }
+            let mut next = self.after[class] & !seen;
+            seen |= next;
+            while next != 0 {
+                let following = next.trailing_zeros() as ClassId;
+                next &= next - 1;
+                previous[following] = class;
+                queue[tail] = following;
+                tail += 1;
+            }
+        }
+        None
+    }
+
+    /// The description of `violation`, with the names of the classes.
+    pub fn report(&self, violation: Violation) -> Report {
+        Report { names: self.names, violation }
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A violation ready to be printed, which needs no access to the validator any more.
+pub struct Report {
+    names: [&'static str; MAX_CLASSES],
+    violation: Violation,
+}
+
+impl Report {
+    fn chain(&self, chain: Chain) -> ChainNames<'_> {
+        ChainNames { names: &self.names, chain }
+    }
+}
+
+struct ChainNames<'a> {
+    names: &'a [&'static str; MAX_CLASSES],
+    chain: Chain,
+}
+
+impl<'a> fmt::Display for ChainNames<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, class) in self.chain.iter().enumerate() {
+            if index > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", self.names[class])?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.violation {
+            Violation::Recursion { class, held } => {
+                writeln!(f, "lockdep: {} taken again while held", self.names[class])?;
+                write!(f, "  held: {}", self.chain(held.with(class)))
+            }
+            Violation::Inversion { class, held, dependency } => {
+                writeln!(f, "lockdep: lock order inversion taking {}", self.names[class])?;
+                writeln!(f, "  now:     {}", self.chain(held.with(class)))?;
+                write!(f, "  earlier: {}", self.chain(dependency))
+            }
+            Violation::IrqUnsafe { class, interrupt, interrupts_enabled } => {
+                writeln!(f, "lockdep: {} taken both in interrupt context and with interrupts \
+                             enabled", self.names[class])?;
+                writeln!(f, "  in interrupt:       {}", self.chain(interrupt))?;
+                write!(f, "  interrupts enabled: {}", self.chain(interrupts_enabled))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(names: &[&'static str]) -> Validator {
+        let mut validator = Validator::new();
+        for (class, name) in names.iter().enumerate() {
+            assert_eq!(validator.register(name), Some(class));
+        }
+        validator
+    }
+
+    fn chain(classes: &[ClassId]) -> Chain {
+        let mut chain = Chain::EMPTY;
+        for &class in classes {
+            assert!(chain.push(class));
+        }
+        chain
+    }
+
+    const A: ClassId = 0;
+    const B: ClassId = 1;
+    const C: ClassId = 2;
+
+    #[test]
+    fn chains_release_out_of_order() {
+        let mut held = chain(&[A, B, A, C]);
+        assert!(held.remove(A));
+        assert_eq!(held, chain(&[A, B, C]));
+        assert!(!held.remove(3));
+        assert!(held.remove(A) && held.remove(C) && held.remove(B));
+        assert!(held.is_empty());
+    }
+
+    #[test]
+    fn consistent_orders_pass() {
+        let mut validator = validator(&["A", "B", "C"]);
+        let mut held = Chain::EMPTY;
+        for _ in 0..2 {
+            validator.acquire(&mut held, A, Usage::InterruptsEnabled, false).unwrap();
+            validator.acquire(&mut held, B, Usage::InterruptsEnabled, false).unwrap();
+            held.remove(A);
+            validator.acquire(&mut held, C, Usage::InterruptsEnabled, false).unwrap();
+            assert_eq!(held, chain(&[B, C]));
+            held = Chain::EMPTY;
+        }
+        assert!(validator.is_enabled());
+    }
+
+    #[test]
+    fn inversions_are_reported_with_both_chains() {
+        let mut validator = validator(&["A", "B", "C"]);
+        let mut held = Chain::EMPTY;
+        validator.acquire(&mut held, A, Usage::InterruptsDisabled, false).unwrap();
+        validator.acquire(&mut held, B, Usage::InterruptsDisabled, false).unwrap();
+        held = Chain::EMPTY;
+        validator.acquire(&mut held, B, Usage::InterruptsDisabled, false).unwrap();
+        validator.acquire(&mut held, C, Usage::InterruptsDisabled, false).unwrap();
+        held = Chain::EMPTY;
+
+        validator.acquire(&mut held, C, Usage::InterruptsDisabled, false).unwrap();
+        // Only a successful try_lock, which cannot deadlock.
+        validator.acquire(&mut held, A, Usage::InterruptsDisabled, true).unwrap();
+        held.remove(A);
+        let violation = validator.acquire(&mut held, A, Usage::InterruptsDisabled, false);
+        let (mut held, dependency) = (chain(&[C]), chain(&[A, B, C]));
+        assert_eq!(violation, Err(Violation::Inversion { class: A, held, dependency }));
+        assert_eq!(validator.report(violation.unwrap_err()).to_string(),
+                   "lockdep: lock order inversion taking A\n  now:     C -> A\n  \
+                    earlier: A -> B -> C");
+
+        // Only the first violation is reported.
+        assert!(!validator.is_enabled());
+        assert_eq!(validator.acquire(&mut held, C, Usage::InterruptsDisabled, false), Ok(()));
+    }
+
+    #[test]
+    fn recursion_is_reported() {
+        let mut validator = validator(&["A", "B"]);
+        let mut held = chain(&[A, B]);
+        let violation = validator.acquire(&mut held, A, Usage::InterruptsDisabled, false);
+        assert_eq!(violation, Err(Violation::Recursion { class: A, held: chain(&[A, B]) }));
+        assert_eq!(validator.report(violation.unwrap_err()).to_string(),
+                   "lockdep: A taken again while held\n  held: A -> B -> A");
+    }
+
+    #[test]
+    fn locks_taken_in_interrupts_need_them_disabled() {
+        let mut validator = validator(&["A", "B"]);
+        let mut held = Chain::EMPTY;
+        validator.acquire(&mut held, B, Usage::Interrupt, false).unwrap();
+        held = Chain::EMPTY;
+        validator.acquire(&mut held, A, Usage::InterruptsEnabled, false).unwrap();
+        validator.acquire(&mut held, B, Usage::InterruptsDisabled, false).unwrap();
+        held = chain(&[A]);
+        let violation = validator.acquire(&mut held, B, Usage::InterruptsEnabled, false);
+        let expected = Violation::IrqUnsafe {
+            class: B,
+            interrupt: chain(&[B]),
+            interrupts_enabled: chain(&[A, B]),
+        };
+        assert_eq!(violation, Err(expected));
+        assert_eq!(validator.report(expected).to_string(),
+                   "lockdep: B taken both in interrupt context and with interrupts enabled\n  \
+                    in interrupt:       B\n  interrupts enabled: A -> B");
+    }
+
+    #[test]
+    fn validator_stops_when_full() {
+        let mut validator = Validator::new();
+        for _ in 0..MAX_CLASSES {
+            assert!(validator.register("lock").is_some());
+        }
+        assert!(validator.is_enabled());
+        assert_eq!(validator.register("lock"), None);
+        assert!(!validator.is_enabled());
+    }
+}
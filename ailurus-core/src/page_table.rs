@@ -1,6 +1,7 @@
-use super::{PhysAddr, PhysFrame};
+use crate::address::PhysAddr;
 use core::ops::{IndexMut, Index};
-use super::{Size4KiB, PageSize};
+
+const PAGE_SIZE: u64 = 4096;
 
 
 pub const ENTRY_COUNT: usize = 512;
@@ -101,7 +102,7 @@ impl PageTableEntry {
     }
 
     pub fn set_address(&mut self, address: PhysAddr, flags: PageTableFlags) {
-        assert!(address.is_aligned(Size4KiB::SIZE));
+        assert!(address.is_aligned(PAGE_SIZE));
         self.entry = (address.as_u64()) | flags.bits();
     }
 
@@ -130,6 +131,12 @@ impl PageTable {
     }
 }
 
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Index<usize> for PageTable {
     type Output = PageTableEntry;
 
@@ -142,4 +149,42 @@ impl IndexMut<usize> for PageTable {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.entries[index]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn new_entry_is_unused() {
+        let mut table = PageTable::new();
+        assert!(table[0].is_unused());
+        table[0].set_address(PhysAddr::new(0x1000), PageTableFlags::PRESENT);
+        assert!(!table[0].is_unused());
+        table.zero();
+        assert!(table[0].is_unused());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unaligned_address() {
+        PageTable::new()[0].set_address(PhysAddr::new(0x1234), PageTableFlags::PRESENT);
+    }
+
+    proptest! {
+        #[test]
+        fn entry_round_trip(frame in 0..(1u64 << 40), flags in any::<u64>()) {
+            let address = PhysAddr::new(frame << 12);
+            let flags = PageTableFlags::from_bits_truncate(flags);
+            let mut entry = PageTableEntry::new();
+            entry.set_address(address, flags);
+            prop_assert_eq!(entry.address(), address);
+            prop_assert_eq!(entry.flags(), flags);
+
+            entry.set_flags(PageTableFlags::PRESENT);
+            prop_assert_eq!(entry.address(), address);
+            prop_assert_eq!(entry.flags(), PageTableFlags::PRESENT);
+        }
+    }
+}
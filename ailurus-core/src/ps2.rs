@@ -0,0 +1,138 @@
+//! PS/2 device protocols: keyboard typematic settings and mouse packets.
+
+bitflags! {
+    pub struct MouseButtons: u8 {
+        const NONE =   0;
+        const LEFT =   1 << 0;
+        const RIGHT =  1 << 1;
+        const MIDDLE = 1 << 2;
+    }
+}
+
+/// Movement and button state reported by the mouse. `dy` grows downwards, like screen
+/// coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MousePacket {
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: MouseButtons,
+}
+
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+const PACKET_SIZE: usize = 3;
+
+/// Assembles the three-byte packets of a standard PS/2 mouse.
+pub struct MouseDecoder {
+    bytes: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl MouseDecoder {
+    pub const fn new() -> Self {
+        MouseDecoder { bytes: [0; PACKET_SIZE], len: 0 }
+    }
+
+    /// Feed a byte from the mouse, returning the packet once it is complete.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MousePacket> {
+        // The first byte always has bit 3 set. Dropping bytes until one does resynchronises
+        // after a lost byte, at least most of the time.
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < PACKET_SIZE {
+            return None;
+        }
+        self.len = 0;
+
+        let flags = self.bytes[0];
+        let movement = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+        Some(MousePacket {
+            dx: movement(self.bytes[1], X_SIGN, X_OVERFLOW),
+            dy: -movement(self.bytes[2], Y_SIGN, Y_OVERFLOW),
+            buttons: MouseButtons::from_bits_truncate(flags),
+        })
+    }
+}
+
+impl Default for MouseDecoder {
+    fn default() -> Self {
+        MouseDecoder::new()
+    }
+}
+
+/// The argument of the keyboard's "set typematic rate and delay" command closest to repeating
+/// keys `rate` times per second after holding them for `delay_ms`.
+pub fn typematic_byte(delay_ms: u32, rate: u32) -> u8 {
+    // 250 to 1000 ms in steps of 250 ms.
+    let delay = (delay_ms.saturating_sub(125) / 250).min(3);
+    // The repeat period is (8 + A) * 2^B * 4.17 ms, with A in bits 0-2 and B in bits 3-4.
+    let millirate = |byte: u32| 100_000_000 / ((8 + (byte & 7)) * (1 << (byte >> 3)) * 417);
+    let target = i64::from(rate) * 1000;
+    let closest = (0..32).min_by_key(|&byte| (i64::from(millirate(byte)) - target).abs()).unwrap();
+    (delay << 5 | closest) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode(bytes: &[u8]) -> Vec<MousePacket> {
+        let mut decoder = MouseDecoder::new();
+        bytes.iter().filter_map(|&byte| decoder.add_byte(byte)).collect()
+    }
+
+    #[test]
+    fn decodes_packets() {
+        assert_eq!(decode(&[0x09, 5, 3]),
+                   vec![MousePacket { dx: 5, dy: -3, buttons: MouseButtons::LEFT }]);
+        assert_eq!(decode(&[0x38, 0xfb, 0xfd]),
+                   vec![MousePacket { dx: -5, dy: 3, buttons: MouseButtons::empty() }]);
+        assert_eq!(decode(&[0x4e, 0xff, 7]),
+                   vec![MousePacket { dx: 0, dy: -7,
+                                      buttons: MouseButtons::RIGHT | MouseButtons::MIDDLE }]);
+    }
+
+    #[test]
+    fn resynchronises_on_first_byte() {
+        assert_eq!(decode(&[0x00, 0x08, 1, 1]),
+                   vec![MousePacket { dx: 1, dy: -1, buttons: MouseButtons::empty() }]);
+    }
+
+    #[test]
+    fn typematic_settings() {
+        assert_eq!(typematic_byte(250, 30), 0x00);
+        assert_eq!(typematic_byte(1000, 2), 0x7f);
+        assert_eq!(typematic_byte(500, 10), 0x2c);
+        assert_eq!(typematic_byte(0, 1000), 0x00);
+    }
+
+    proptest! {
+        #[test]
+        fn movement_round_trips(dx in -255i16..256, dy in -255i16..256, buttons in 0u8..8) {
+            let mut flags = ALWAYS_ONE | buttons;
+            if dx < 0 {
+                flags |= X_SIGN;
+            }
+            if -dy < 0 {
+                flags |= Y_SIGN;
+            }
+            let packet = MousePacket { dx, dy, buttons: MouseButtons::from_bits_truncate(buttons) };
+            prop_assert_eq!(decode(&[flags, dx as u8, (-dy) as u8]), vec![packet]);
+        }
+    }
+}
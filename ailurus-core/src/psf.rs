@@ -0,0 +1,321 @@
+//! PC Screen Fonts, the bitmap font format of the Linux console.
+//!
+//! Both versions are supported. A PSF1 font has 256 or 512 glyphs 8 pixels wide, a PSF2 font any
+//! number of glyphs of any size. Either may come with a table mapping Unicode characters to
+//! glyphs; without one, character `n` is glyph `n`.
+//!
+//! `DEFAULT_FONT` is an 8x16 PSF2 font covering ASCII, the accented lowercase Latin letters,
+//! a few currency signs, single box drawing lines and block elements.
+
+pub const DEFAULT_FONT: &[u8] = include_bytes!("../fonts/default8x16.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQUENCE: u8 = 0xfe;
+
+const ASCII_COUNT: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// Neither a PSF1 nor a PSF2 magic number.
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    /// The header or the glyphs extend beyond the end of the data.
+    Truncated,
+    /// A glyph size of zero, or a PSF2 glyph size not matching its width and height.
+    InvalidGlyphSize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UnicodeTable<'a> {
+    None,
+    Psf1(&'a [u8]),
+    Psf2(&'a [u8]),
+}
+
+#[derive(Clone)]
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    glyph_count: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+    table: UnicodeTable<'a>,
+    /// Glyph indices of the ASCII characters, looked up once since they are the most common.
+    ascii: [Option<u16>; ASCII_COUNT],
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, FontError> {
+        let mut font = if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)?
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)?
+        } else {
+            return Err(FontError::InvalidMagic);
+        };
+        for code in 0..ASCII_COUNT {
+            font.ascii[code] = font.find_glyph(code as u8 as char).map(|index| index as u16);
+        }
+        Ok(font)
+    }
+
+    fn parse_psf1(data: &'a [u8]) -> Result<Self, FontError> {
+        if data.len() < PSF1_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        let mode = data[2];
+        let glyph_size = data[3] as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs = Self::glyphs(data, PSF1_HEADER_SIZE, glyph_count, glyph_size)?;
+        let rest = &data[PSF1_HEADER_SIZE + glyphs.len()..];
+        let table = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+            UnicodeTable::Psf1(rest)
+        } else {
+            UnicodeTable::None
+        };
+        Ok(Font { glyphs, glyph_count, glyph_size, width: 8, height: glyph_size, table,
+                  ascii: [None; ASCII_COUNT] })
+    }
+
+    fn parse_psf2(data: &'a [u8]) -> Result<Self, FontError> {
+        if data.len() < PSF2_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        let field = |index: usize| {
+            let bytes = &data[index * 4..index * 4 + 4];
+            u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16 |
+                u32::from(bytes[3]) << 24
+        };
+        let (version, header_size, flags) = (field(1), field(2) as usize, field(3));
+        let (glyph_count, glyph_size) = (field(4) as usize, field(5) as usize);
+        let (height, width) = (field(6) as usize, field(7) as usize);
+        if version != 0 {
+            return Err(FontError::UnsupportedVersion(version));
+        }
+        if glyph_size != height * crate::bytes_for_bits(width) {
+            return Err(FontError::InvalidGlyphSize);
+        }
+        let glyphs = Self::glyphs(data, header_size, glyph_count, glyph_size)?;
+        let table = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            UnicodeTable::Psf2(&data[header_size + glyphs.len()..])
+        } else {
+            UnicodeTable::None
+        };
+        Ok(Font { glyphs, glyph_count, glyph_size, width, height, table,
+                  ascii: [None; ASCII_COUNT] })
+    }
+
+    fn glyphs(data: &'a [u8], offset: usize, count: usize, size: usize)
+        -> Result<&'a [u8], FontError> {
+        if size == 0 {
+            return Err(FontError::InvalidGlyphSize);
+        }
+        let end = count.checked_mul(size).and_then(|len| len.checked_add(offset))
+            .ok_or(FontError::Truncated)?;
+        data.get(offset..end).ok_or(FontError::Truncated)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    /// The index of the glyph showing `character`, if the font has one.
+    pub fn glyph_index(&self, character: char) -> Option<usize> {
+        match self.ascii.get(character as usize) {
+            Some(&index) => index.map(usize::from),
+            None => self.find_glyph(character),
+        }
+    }
+
+    fn find_glyph(&self, character: char) -> Option<usize> {
+        let index = match self.table {
+            UnicodeTable::None => Some(character as usize),
+            UnicodeTable::Psf1(table) => psf1_position(table, character),
+            UnicodeTable::Psf2(table) => {
+                table.split(|&byte| byte == PSF2_SEPARATOR).position(|entry| {
+                    let single = entry.split(|&byte| byte == PSF2_START_SEQUENCE).next()
+                        .unwrap_or(&[]);
+                    core::str::from_utf8(single).ok()
+                        .filter(|characters| characters.contains(character)).is_some()
+                })
+            }
+        };
+        index.filter(|&index| index < self.glyph_count)
+    }
+
+    /// The glyph showing `character`, or the one for the replacement character U+FFFD, `?` or
+    /// the first glyph if the font lacks it.
+    pub fn glyph(&self, character: char) -> Glyph<'a> {
+        let index = self.glyph_index(character)
+            .or_else(|| self.glyph_index('\u{fffd}'))
+            .or_else(|| self.glyph_index('?'))
+            .unwrap_or(0);
+        self.glyph_at(index)
+    }
+
+    pub fn glyph_at(&self, index: usize) -> Glyph<'a> {
+        let start = index * self.glyph_size;
+        Glyph {
+            bitmap: &self.glyphs[start..start + self.glyph_size],
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// The index of the PSF1 table entry listing `character`, ignoring sequences.
+fn psf1_position(table: &[u8], character: char) -> Option<usize> {
+    let mut index = 0;
+    let mut in_sequence = false;
+    for bytes in table.chunks_exact(2) {
+        match u16::from(bytes[0]) | u16::from(bytes[1]) << 8 {
+            PSF1_SEPARATOR => {
+                index += 1;
+                in_sequence = false;
+            }
+            PSF1_START_SEQUENCE => in_sequence = true,
+            code if !in_sequence && u32::from(code) == character as u32 => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The bitmap of a glyph: rows of `(width + 7) / 8` bytes, most significant bit leftmost.
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph<'a> {
+    bitmap: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Glyph<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the pixel at column `x` of row `y` is part of the character.
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        let stride = crate::bytes_for_bits(self.width);
+        self.bitmap[y * stride + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// The built-in font.
+pub fn default_font() -> Font<'static> {
+    Font::parse(DEFAULT_FONT).expect("invalid built-in font")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PSF1 font with 256 glyphs 8x2, where glyph `n` has rows `n` and `!n`, mapping `a`
+    /// and `ä` to glyph 1.
+    fn psf1_font() -> Vec<u8> {
+        let mut data = vec![0x36, 0x04, PSF1_MODE_HAS_TABLE, 2];
+        for glyph in 0..=255u8 {
+            data.extend_from_slice(&[glyph, !glyph]);
+        }
+        let mut entry = |codes: &[u16]| {
+            for &code in codes.iter().chain(&[PSF1_SEPARATOR]) {
+                data.extend_from_slice(&code.to_le_bytes());
+            }
+        };
+        entry(&[]);
+        entry(&['a' as u16, PSF1_START_SEQUENCE, 'b' as u16, 0x308, 'ä' as u16]);
+        entry(&['b' as u16]);
+        data
+    }
+
+    fn psf2_header(count: u32, width: u32, height: u32, flags: u32) -> Vec<u8> {
+        let size = height * crate::bytes_for_bits(width as usize) as u32;
+        [0x864a_b572, 0, 32, flags, count, size, height, width].iter()
+            .flat_map(|field: &u32| field.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn parses_psf1_fonts() {
+        let data = psf1_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (8, 2, 256));
+        assert_eq!(font.glyph_index('a'), Some(1));
+        assert_eq!(font.glyph_index('b'), Some(2));
+        assert_eq!(font.glyph_index('ä'), None);
+        assert_eq!(font.glyph_index('c'), None);
+
+        let glyph = font.glyph('b');
+        assert!(!glyph.is_set(5, 0) && glyph.is_set(6, 0));
+        assert!(glyph.is_set(5, 1) && !glyph.is_set(6, 1));
+    }
+
+    #[test]
+    fn parses_psf2_fonts_without_table() {
+        let mut data = psf2_header(3, 10, 1, 0);
+        data.extend_from_slice(&[0x00, 0x00, 0x80, 0x40, 0xff, 0xc0]);
+        let font = Font::parse(&data).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (10, 1, 3));
+        assert_eq!(font.glyph_index('\u{1}'), Some(1));
+        assert_eq!(font.glyph_index('\u{3}'), None);
+
+        let glyph = font.glyph_at(1);
+        let pixels: Vec<bool> = (0..10).map(|x| glyph.is_set(x, 0)).collect();
+        assert_eq!(pixels, [true, false, false, false, false, false, false, false, false, true]);
+        // Glyph 0 stands in for missing characters without U+FFFD or `?` glyphs.
+        assert!((0..10).all(|x| !font.glyph('x').is_set(x, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_fonts() {
+        assert_eq!(Font::parse(b"BM").err(), Some(FontError::InvalidMagic));
+        assert_eq!(Font::parse(&psf1_font()[..300]).err(), Some(FontError::Truncated));
+        assert_eq!(Font::parse(&psf2_header(1, 8, 16, 0)).err(), Some(FontError::Truncated));
+
+        let mut data = psf2_header(0, 8, 16, 0);
+        data[4] = 1;
+        assert_eq!(Font::parse(&data).err(), Some(FontError::UnsupportedVersion(1)));
+        let mut data = psf2_header(0, 8, 16, 0);
+        data[20] = 17;
+        assert_eq!(Font::parse(&data).err(), Some(FontError::InvalidGlyphSize));
+    }
+
+    #[test]
+    fn default_font_covers_ascii() {
+        let font = default_font();
+        assert_eq!((font.width(), font.height()), (8, 16));
+        for character in (0x20..0x7f).map(|code| code as u8 as char) {
+            assert!(font.glyph_index(character).is_some(), "{:?}", character);
+        }
+        for &character in ['é', 'ü', 'ç', '€', '─', '┼', '█', '▒'].iter() {
+            assert!(font.glyph_index(character).is_some(), "{:?}", character);
+        }
+        // Missing characters show the replacement glyph.
+        let replacement = font.glyph_index('\u{fffd}').unwrap();
+        assert_eq!(font.glyph('\u{4e00}').bitmap, font.glyph_at(replacement).bitmap);
+        assert!(font.glyph(' ').bitmap.iter().all(|&row| row == 0));
+    }
+}
@@ -0,0 +1,177 @@
+//! Lock-free queue with a single producer, e.g. an interrupt handler, and any number of
+//! consumers.
+//!
+//! The producer never waits, so it is safe to push from interrupt context while a consumer on
+//! the same CPU is in the middle of a `pop`.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const RING_QUEUE_CAPACITY: usize = 256;
+
+pub struct RingQueue<T> {
+    slots: UnsafeCell<[T; RING_QUEUE_CAPACITY]>,
+    /// Number of values pushed so far, wrapping.
+    head: AtomicUsize,
+    /// Number of values popped so far, wrapping.
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send> Sync for RingQueue<T> {}
+
+impl<T> RingQueue<T> {
+    /// Create an empty queue. `slots` only provides the storage; its values are never returned.
+    pub const fn new(slots: [T; RING_QUEUE_CAPACITY]) -> Self {
+        RingQueue {
+            slots: UnsafeCell::new(slots),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        self.head.load(Ordering::Acquire).wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Copy> RingQueue<T> {
+    /// Append `value`, handing it back if the queue is full.
+    ///
+    /// # Safety
+    ///
+    /// There must be only one producer: `push` must not run concurrently with itself.
+    pub unsafe fn push(&self, value: T) -> Result<(), T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) >= RING_QUEUE_CAPACITY {
+            return Err(value);
+        }
+        ptr::write_volatile(&mut (*self.slots.get())[head % RING_QUEUE_CAPACITY], value);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove the oldest value.
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            if tail == self.head.load(Ordering::Acquire) {
+                return None;
+            }
+            // If another consumer takes this slot first, the producer may already be reusing it,
+            // but then the exchange below fails and the value is discarded.
+            let value = unsafe {
+                ptr::read_volatile(&(*self.slots.get())[tail % RING_QUEUE_CAPACITY])
+            };
+            if self.tail.compare_exchange(tail, tail.wrapping_add(1), Ordering::AcqRel,
+                                          Ordering::Relaxed).is_ok() {
+                return Some(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::VecDeque;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn queue() -> RingQueue<u32> {
+        RingQueue::new([0; RING_QUEUE_CAPACITY])
+    }
+
+    #[test]
+    fn values_come_out_in_order() {
+        let queue = queue();
+        unsafe {
+            queue.push(1).unwrap();
+            queue.push(2).unwrap();
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn full_queue_rejects_values() {
+        let queue = queue();
+        for value in 0..RING_QUEUE_CAPACITY as u32 {
+            unsafe { queue.push(value).unwrap(); }
+        }
+        assert_eq!(unsafe { queue.push(1000) }, Err(1000));
+        assert_eq!(queue.pop(), Some(0));
+        assert_eq!(unsafe { queue.push(1000) }, Ok(()));
+    }
+
+    #[test]
+    fn concurrent_consumers_see_every_value_once() {
+        const COUNT: u32 = 100_000;
+        let queue = Arc::new(queue());
+        let done = Arc::new(AtomicBool::new(false));
+        let consumers: Vec<_> = (0..3).map(|_| {
+            let (queue, done) = (queue.clone(), done.clone());
+            thread::spawn(move || {
+                let mut values = Vec::new();
+                loop {
+                    // Check before popping, so that nothing pushed before `done` is missed.
+                    let finished = done.load(Ordering::SeqCst);
+                    match queue.pop() {
+                        Some(value) => values.push(value),
+                        None if finished => return values,
+                        None => thread::yield_now(),
+                    }
+                }
+            })
+        }).collect();
+
+        for value in 0..COUNT {
+            while unsafe { queue.push(value) }.is_err() {
+                thread::yield_now();
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+
+        let mut seen = vec![false; COUNT as usize];
+        for consumer in consumers {
+            let values = consumer.join().unwrap();
+            assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+            for value in values {
+                assert!(!seen[value as usize]);
+                seen[value as usize] = true;
+            }
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    proptest! {
+        #[test]
+        fn behaves_like_a_deque(operations in prop::collection::vec(any::<Option<u32>>(), 0..1000)) {
+            let queue = queue();
+            let mut model = VecDeque::new();
+            for operation in operations {
+                match operation {
+                    Some(value) => {
+                        let pushed = unsafe { queue.push(value) }.is_ok();
+                        prop_assert_eq!(pushed, model.len() < RING_QUEUE_CAPACITY);
+                        if pushed {
+                            model.push_back(value);
+                        }
+                    }
+                    None => prop_assert_eq!(queue.pop(), model.pop_front()),
+                }
+                prop_assert_eq!(queue.len(), model.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! The fair scheduling class, after Linux's CFS.
+//!
+//! Each task accumulates virtual runtime while it runs, more slowly the higher its weight, and
+//! the task with the least virtual runtime runs next. The queued tasks are kept sorted by it in a
+//! red-black tree. A task which slept gets at most `SLEEPER_CREDIT` of virtual runtime less than
+//! the others when it wakes up, so that interactive tasks respond quickly without starving the
+//! ones which never sleep.
+
+use super::rbtree::RbTree;
+use super::{Enqueue, Entity, Policy, SchedClass, MIN_NICE};
+
+/// The weight of the nice values from `MIN_NICE` to `MAX_NICE`, as in Linux: a task gets about
+/// 10% more CPU time than one with a nice value one higher.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906, 3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423, 335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+pub const NICE_0_WEIGHT: u64 = 1024;
+
+/// The virtual runtime of a tick at nice 0.
+pub const TICK_VRUNTIME: u64 = 1 << 16;
+/// How much less virtual runtime than the others a woken task may have.
+pub const SLEEPER_CREDIT: u64 = 3 * TICK_VRUNTIME;
+/// How much less virtual runtime a woken task must have to preempt the running one.
+pub const WAKEUP_GRANULARITY: u64 = TICK_VRUNTIME;
+
+pub fn weight(policy: Policy) -> u64 {
+    match policy {
+        Policy::Fair { nice } => {
+            let index = (nice as i32 - MIN_NICE as i32).max(0) as usize;
+            WEIGHTS[index.min(WEIGHTS.len() - 1)]
+        }
+        _ => NICE_0_WEIGHT,
+    }
+}
+
+pub struct FairClass {
+    /// Sorted by virtual runtime, then by the order of arrival.
+    tree: RbTree,
+    /// The sum of the weights of the queued tasks.
+    load: u64,
+    /// Never decreases: the smallest virtual runtime of the queued and the running tasks, as
+    /// far as it grew.
+    min_vruntime: u64,
+    arrivals: u64,
+}
+
+impl FairClass {
+    pub const fn new() -> Self {
+        FairClass { tree: RbTree::new(), load: 0, min_vruntime: 0, arrivals: 0 }
+    }
+
+    pub fn min_vruntime(&self) -> u64 {
+        self.min_vruntime
+    }
+
+    fn first_vruntime(&self) -> Option<u64> {
+        self.tree.first().and_then(|task| self.tree.key(task)).map(|(vruntime, _)| vruntime)
+    }
+
+    fn update_min_vruntime(&mut self, current: u64) {
+        let smallest = self.first_vruntime().map_or(current, |first| first.min(current));
+        self.min_vruntime = self.min_vruntime.max(smallest);
+    }
+}
+
+impl Default for FairClass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedClass for FairClass {
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn enqueue(&mut self, task: usize, entities: &mut [Entity], reason: Enqueue) {
+        let entity = &mut entities[task];
+        match reason {
+            Enqueue::Spawned => entity.vruntime = self.min_vruntime,
+            Enqueue::Woken => {
+                let credited = self.min_vruntime.saturating_sub(SLEEPER_CREDIT);
+                entity.vruntime = entity.vruntime.max(credited);
+            }
+            Enqueue::Preempted | Enqueue::Expired => {}
+            // Behind all the others.
+            Enqueue::Yielded => {
+                let last = self.tree.last().and_then(|task| self.tree.key(task));
+                if let Some((vruntime, _)) = last {
+                    entity.vruntime = entity.vruntime.max(vruntime);
+                }
+            }
+        }
+        self.arrivals += 1;
+        if self.tree.insert(task, (entity.vruntime, self.arrivals)) {
+            self.load += weight(entity.policy);
+        }
+    }
+
+    fn dequeue(&mut self, task: usize, entities: &mut [Entity]) -> bool {
+        let removed = self.tree.remove(task);
+        if removed {
+            self.load -= weight(entities[task].policy);
+        }
+        removed
+    }
+
+    fn pick_next(&mut self, entities: &mut [Entity]) -> Option<usize> {
+        let task = self.tree.first()?;
+        self.dequeue(task, entities);
+        self.update_min_vruntime(entities[task].vruntime);
+        Some(task)
+    }
+
+    fn tick(&mut self, task: usize, entities: &mut [Entity], quantum: u32) -> bool {
+        let entity = &mut entities[task];
+        let weight = weight(entity.policy);
+        entity.vruntime += TICK_VRUNTIME * NICE_0_WEIGHT / weight;
+        let (vruntime, ran) = (entity.vruntime, entity.ran as u64);
+        self.update_min_vruntime(vruntime);
+        let first = match self.first_vruntime() {
+            Some(first) => first,
+            None => return false,
+        };
+        // The share of the running task of a quantum, shared by all the tasks.
+        let slice = (quantum as u64 * weight / (self.load + weight)).max(1);
+        ran >= slice || vruntime > first + slice * TICK_VRUNTIME
+    }
+
+    fn preempts(&self, woken: &Entity, current: &Entity) -> bool {
+        woken.vruntime + WAKEUP_GRANULARITY < current.vruntime
+    }
+
+    fn migration_candidate(&self, _entities: &[Entity], allowed: &dyn Fn(usize) -> bool)
+                           -> Option<usize> {
+        let mut task = self.tree.last();
+        while let Some(candidate) = task {
+            if allowed(candidate) {
+                return Some(candidate);
+            }
+            task = self.tree.previous(candidate);
+        }
+        None
+    }
+
+    // Virtual runtimes only compare within a queue: a task keeps its lead or lag over the
+    // others when it moves.
+    fn migrate_out(&self, entity: &mut Entity) {
+        entity.vruntime = entity.vruntime.saturating_sub(self.min_vruntime);
+    }
+
+    fn migrate_in(&self, entity: &mut Entity) {
+        entity.vruntime += self.min_vruntime;
+    }
+}
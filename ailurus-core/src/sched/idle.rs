@@ -0,0 +1,51 @@
+//! The idle scheduling class: tasks which only run when no task of another class is ready, in
+//! round-robin order.
+
+use super::{Enqueue, Entity, RunQueue, SchedClass};
+
+pub struct IdleClass {
+    queue: RunQueue,
+}
+
+impl IdleClass {
+    pub const fn new() -> Self {
+        IdleClass { queue: RunQueue::new() }
+    }
+}
+
+impl Default for IdleClass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedClass for IdleClass {
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn enqueue(&mut self, task: usize, _entities: &mut [Entity], _reason: Enqueue) {
+        self.queue.push_back(task);
+    }
+
+    fn dequeue(&mut self, task: usize, _entities: &mut [Entity]) -> bool {
+        self.queue.remove(task)
+    }
+
+    fn pick_next(&mut self, _entities: &mut [Entity]) -> Option<usize> {
+        self.queue.pop_front()
+    }
+
+    fn tick(&mut self, task: usize, entities: &mut [Entity], quantum: u32) -> bool {
+        entities[task].ran >= quantum && !self.queue.is_empty()
+    }
+
+    fn preempts(&self, _woken: &Entity, _current: &Entity) -> bool {
+        false
+    }
+
+    fn migration_candidate(&self, _entities: &[Entity], allowed: &dyn Fn(usize) -> bool)
+                           -> Option<usize> {
+        self.queue.iter().filter(|&task| allowed(task)).last()
+    }
+}
@@ -0,0 +1,637 @@
+//! The scheduling policy of the kernel, apart from the switching itself. Tasks are identified by
+//! their slot in the kernel's task table.
+//!
+//! Every task belongs to one of the scheduling classes, chosen by its `Policy`. `Classes` always
+//! runs a task of the highest class with a ready task:
+//!
+//! 1. `rt`: real-time tasks by fixed priority, first in first out or round-robin among equals.
+//! 2. `fair`: time-shared tasks, each getting CPU time in proportion to its weight.
+//! 3. `idle`: tasks which only run when nothing else is ready.
+//!
+//! Time is counted in timer ticks, and the quantum is both the time slice of the round-robin
+//! policies and the period over which the fair class shares the CPU.
+
+pub mod fair;
+pub mod idle;
+pub mod rbtree;
+pub mod rt;
+pub mod smp;
+
+use self::fair::FairClass;
+use self::idle::IdleClass;
+use self::rt::RtClass;
+
+pub const MAX_TASKS: usize = 64;
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+pub const MIN_RT_PRIORITY: u8 = 1;
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// The tasks ready to run, in round-robin order. Each task is queued at most once.
+pub struct RunQueue {
+    slots: [usize; MAX_TASKS],
+    /// The position of the first task in `slots`.
+    head: usize,
+    len: usize,
+    queued: [bool; MAX_TASKS],
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        RunQueue { slots: [0; MAX_TASKS], head: 0, len: 0, queued: [false; MAX_TASKS] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, task: usize) -> bool {
+        self.queued[task]
+    }
+
+    /// The queued tasks, first to last.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        (0..self.len).map(move |offset| self.slots[(self.head + offset) % MAX_TASKS])
+    }
+
+    /// Queue `task` behind the others, returning false if it already is.
+    pub fn push_back(&mut self, task: usize) -> bool {
+        if self.queued[task] {
+            return false;
+        }
+        self.slots[(self.head + self.len) % MAX_TASKS] = task;
+        self.len += 1;
+        self.queued[task] = true;
+        true
+    }
+
+    /// Take the task which has waited longest.
+    pub fn pop_front(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let task = self.slots[self.head];
+        self.head = (self.head + 1) % MAX_TASKS;
+        self.len -= 1;
+        self.queued[task] = false;
+        Some(task)
+    }
+
+    /// Take `task` out of the queue, returning false if it was not queued.
+    pub fn remove(&mut self, task: usize) -> bool {
+        if !self.queued[task] {
+            return false;
+        }
+        let position = (0..self.len).find(|&offset| {
+            self.slots[(self.head + offset) % MAX_TASKS] == task
+        }).unwrap();
+        for offset in position..self.len - 1 {
+            self.slots[(self.head + offset) % MAX_TASKS] =
+                self.slots[(self.head + offset + 1) % MAX_TASKS];
+        }
+        self.len -= 1;
+        self.queued[task] = false;
+        true
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Share the CPU with the other fair tasks by the weight of `nice`, from `MIN_NICE`, the
+    /// largest share, to `MAX_NICE`.
+    Fair { nice: i8 },
+    /// Run until blocking or yielding, or until a real-time task of a higher `priority` is
+    /// ready. Priorities go from `MIN_RT_PRIORITY` to `MAX_RT_PRIORITY`.
+    Fifo { priority: u8 },
+    /// Like `Fifo`, but take turns every quantum with the tasks of the same priority.
+    RoundRobin { priority: u8 },
+    /// Run only when no task of another policy is ready.
+    Idle,
+}
+
+impl Policy {
+    pub fn is_valid(self) -> bool {
+        match self {
+            Policy::Fair { nice } => (MIN_NICE..=MAX_NICE).contains(&nice),
+            Policy::Fifo { priority } | Policy::RoundRobin { priority } => {
+                (MIN_RT_PRIORITY..=MAX_RT_PRIORITY).contains(&priority)
+            }
+            Policy::Idle => true,
+        }
+    }
+
+    /// The index of the class in `Classes`, 0 being the highest.
+    fn class(self) -> usize {
+        match self {
+            Policy::Fifo { .. } | Policy::RoundRobin { .. } => 0,
+            Policy::Fair { .. } => 1,
+            Policy::Idle => 2,
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Fair { nice: 0 }
+    }
+}
+
+/// Why a task goes into its class's queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueue {
+    /// It was just created.
+    Spawned,
+    /// It was blocked.
+    Woken,
+    /// It was running and another task takes over before it used up its time.
+    Preempted,
+    /// It was running and used up its time. `Classes` tells this from `Preempted` by itself.
+    Expired,
+    /// It was running and gave way.
+    Yielded,
+}
+
+/// The scheduling state of a task.
+#[derive(Debug, Clone, Copy)]
+pub struct Entity {
+    policy: Policy,
+    /// The CPU time used so far, weighted for the fair class.
+    vruntime: u64,
+    /// Ticks since the task was last picked to run.
+    ran: u32,
+}
+
+impl Entity {
+    const fn new() -> Self {
+        Entity { policy: Policy::Fair { nice: 0 }, vruntime: 0, ran: 0 }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    pub fn vruntime(&self) -> u64 {
+        self.vruntime
+    }
+
+    pub fn ran(&self) -> u32 {
+        self.ran
+    }
+}
+
+/// A scheduling class: the queue of the ready tasks with some policies, and the rules to pick
+/// the next one from it. The running task is not in the queue.
+pub trait SchedClass {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the ready `task`.
+    fn enqueue(&mut self, task: usize, entities: &mut [Entity], reason: Enqueue);
+
+    /// Take `task` out of the queue, returning false if it was not in it.
+    fn dequeue(&mut self, task: usize, entities: &mut [Entity]) -> bool;
+
+    /// Take the task to run next out of the queue.
+    fn pick_next(&mut self, entities: &mut [Entity]) -> Option<usize>;
+
+    /// Account a tick to the running `task`, returning whether it should make way for a task in
+    /// the queue.
+    fn tick(&mut self, task: usize, entities: &mut [Entity], quantum: u32) -> bool;
+
+    /// Whether `woken` should preempt the running `current` right away.
+    fn preempts(&self, woken: &Entity, current: &Entity) -> bool;
+
+    /// The queued task to move to another CPU among those for which `allowed` holds: the one
+    /// which would run last.
+    fn migration_candidate(&self, entities: &[Entity], allowed: &dyn Fn(usize) -> bool)
+                           -> Option<usize>;
+
+    /// Make the state of a dequeued task independent of this queue before it moves to another
+    /// CPU.
+    fn migrate_out(&self, _entity: &mut Entity) {}
+
+    /// Undo `migrate_out` for a task coming from another CPU.
+    fn migrate_in(&self, _entity: &mut Entity) {}
+}
+
+const CLASS_COUNT: usize = 3;
+
+/// The scheduling classes, and the state of every task.
+pub struct Classes {
+    entities: [Entity; MAX_TASKS],
+    rt: RtClass,
+    fair: FairClass,
+    idle: IdleClass,
+    quantum: u32,
+}
+
+impl Classes {
+    pub const fn new(quantum: u32) -> Self {
+        Classes {
+            entities: [Entity::new(); MAX_TASKS],
+            rt: RtClass::new(),
+            fair: FairClass::new(),
+            idle: IdleClass::new(),
+            // No `max` in a const fn on the kernel's toolchain.
+            quantum: quantum + (quantum == 0) as u32,
+        }
+    }
+
+    /// The class with index `class`, and the entities it needs.
+    fn split(&mut self, class: usize) -> (&mut dyn SchedClass, &mut [Entity]) {
+        let class: &mut dyn SchedClass = match class {
+            0 => &mut self.rt,
+            1 => &mut self.fair,
+            _ => &mut self.idle,
+        };
+        (class, &mut self.entities)
+    }
+
+    fn class_of(&mut self, task: usize) -> (&mut dyn SchedClass, &mut [Entity]) {
+        let class = self.entities[task].policy.class();
+        self.split(class)
+    }
+
+    /// The time slice in ticks.
+    pub fn quantum(&self) -> u32 {
+        self.quantum
+    }
+
+    pub fn set_quantum(&mut self, ticks: u32) {
+        self.quantum = ticks.max(1);
+    }
+
+    pub fn entity(&self, task: usize) -> &Entity {
+        &self.entities[task]
+    }
+
+    /// The number of queued tasks.
+    pub fn len(&self) -> usize {
+        self.rt.len() + self.fair.len() + self.idle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Start afresh for a new `task`, which is not queued yet.
+    pub fn add(&mut self, task: usize, policy: Policy) {
+        self.entities[task] = Entity { policy, ..Entity::new() };
+    }
+
+    /// Change the policy of `task`, returning false if `policy` is invalid.
+    pub fn set_policy(&mut self, task: usize, policy: Policy) -> bool {
+        if !policy.is_valid() {
+            return false;
+        }
+        let queued = self.dequeue(task);
+        self.entities[task].policy = policy;
+        if queued {
+            self.enqueue(task, Enqueue::Preempted);
+        }
+        true
+    }
+
+    pub fn enqueue(&mut self, task: usize, reason: Enqueue) {
+        let reason = if reason == Enqueue::Preempted && self.entities[task].ran >= self.quantum {
+            Enqueue::Expired
+        } else {
+            reason
+        };
+        let (class, entities) = self.class_of(task);
+        class.enqueue(task, entities, reason);
+    }
+
+    /// Take `task` out of its queue, returning false if it was not in it.
+    pub fn dequeue(&mut self, task: usize) -> bool {
+        let (class, entities) = self.class_of(task);
+        class.dequeue(task, entities)
+    }
+
+    /// Take the task to run next out of its queue.
+    pub fn pick_next(&mut self) -> Option<usize> {
+        for class in 0..CLASS_COUNT {
+            let (class, entities) = self.split(class);
+            if let Some(task) = class.pick_next(entities) {
+                entities[task].ran = 0;
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    /// Account a tick to the running `task`, returning whether it should make way for a queued
+    /// task.
+    pub fn tick(&mut self, task: usize) -> bool {
+        let ran = &mut self.entities[task].ran;
+        *ran = ran.saturating_add(1);
+        let current_class = self.entities[task].policy.class();
+        let quantum = self.quantum;
+        let (class, entities) = self.split(current_class);
+        let expired = class.tick(task, entities, quantum);
+        expired || (0..current_class).any(|class| !self.split(class).0.is_empty())
+    }
+
+    /// Take `task` out of the queues if it is queued, to move it to another CPU. Returns its
+    /// state, for `attach` on the other CPU.
+    pub fn detach(&mut self, task: usize) -> Entity {
+        let (class, entities) = self.class_of(task);
+        class.dequeue(task, entities);
+        let mut entity = entities[task];
+        class.migrate_out(&mut entity);
+        entity
+    }
+
+    /// Queue `task` coming from another CPU for `reason`, with the state `detach` returned
+    /// there.
+    pub fn attach(&mut self, task: usize, entity: Entity, reason: Enqueue) {
+        self.entities[task] = entity;
+        let (class, entities) = self.class_of(task);
+        class.migrate_in(&mut entities[task]);
+        class.enqueue(task, entities, reason);
+    }
+
+    /// Detach the queued task most worth moving to another CPU among those for which `allowed`
+    /// holds.
+    pub fn steal(&mut self, allowed: &dyn Fn(usize) -> bool) -> Option<(usize, Entity)> {
+        let task = (0..CLASS_COUNT).filter_map(|class| {
+            let (class, entities) = self.split(class);
+            class.migration_candidate(entities, allowed)
+        }).next()?;
+        Some((task, self.detach(task)))
+    }
+
+    /// Whether the queued `woken` should preempt the running `current` right away.
+    pub fn preempts(&mut self, woken: usize, current: usize) -> bool {
+        let (woken_class, current_class) =
+            (self.entities[woken].policy.class(), self.entities[current].policy.class());
+        if woken_class != current_class {
+            return woken_class < current_class;
+        }
+        let (class, entities) = self.split(current_class);
+        class.preempts(&entities[woken], &entities[current])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn queues_tasks_once_in_order() {
+        let mut queue = RunQueue::new();
+        assert!(queue.push_back(3));
+        assert!(queue.push_back(1));
+        assert!(!queue.push_back(3));
+        assert!(queue.push_back(2));
+        assert_eq!(queue.len(), 3);
+
+        assert!(queue.remove(1));
+        assert!(!queue.remove(1));
+        assert!(!queue.contains(1));
+        assert_eq!(queue.pop_front(), Some(3));
+        assert!(queue.push_back(3));
+        assert_eq!(queue.pop_front(), Some(2));
+        assert_eq!(queue.pop_front(), Some(3));
+        assert_eq!(queue.pop_front(), None);
+        assert!(queue.is_empty());
+    }
+
+    /// Add `tasks`, then run them without ever blocking for `ticks`, and return the number of
+    /// ticks each task got.
+    fn run(classes: &mut Classes, tasks: &[(usize, Policy)], ticks: u32) -> Vec<u32> {
+        for &(task, policy) in tasks {
+            classes.add(task, policy);
+            classes.enqueue(task, Enqueue::Spawned);
+        }
+        let mut current = classes.pick_next().unwrap();
+        let mut counts = vec![0; MAX_TASKS];
+        for _ in 0..ticks {
+            counts[current] += 1;
+            if classes.tick(current) {
+                classes.enqueue(current, Enqueue::Preempted);
+                current = classes.pick_next().unwrap();
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn fair_tasks_share_by_weight() {
+        let mut classes = Classes::new(10);
+        let nice_0 = Policy::Fair { nice: 0 };
+        let counts = run(&mut classes, &[(1, nice_0), (2, nice_0)], 1000);
+        assert!((counts[1] as i32 - counts[2] as i32).abs() <= 10, "{:?}", &counts[1..3]);
+
+        let mut classes = Classes::new(10);
+        let counts = run(&mut classes, &[(1, nice_0), (2, Policy::Fair { nice: 5 })], 1000);
+        // The weights are 1024 and 335.
+        let ratio = counts[1] as f64 / counts[2] as f64;
+        assert!(2.7 < ratio && ratio < 3.5, "{:?}", &counts[1..3]);
+    }
+
+    #[test]
+    fn woken_fair_tasks_preempt_busy_ones() {
+        let mut classes = Classes::new(10);
+        classes.add(1, Policy::default());
+        classes.add(2, Policy::default());
+        classes.enqueue(1, Enqueue::Spawned);
+        classes.enqueue(2, Enqueue::Spawned);
+        assert_eq!(classes.pick_next(), Some(1));
+        assert!(!classes.tick(1));
+        // Task 1 blocks.
+        assert_eq!(classes.pick_next(), Some(2));
+        for _ in 0..100 {
+            assert!(!classes.tick(2));
+        }
+
+        classes.enqueue(1, Enqueue::Woken);
+        assert!(classes.preempts(1, 2));
+        // The credit for sleeping is limited.
+        let credit = classes.entity(2).vruntime() - classes.entity(1).vruntime();
+        assert!(credit <= fair::SLEEPER_CREDIT + fair::TICK_VRUNTIME);
+        assert!(classes.tick(2));
+        classes.enqueue(2, Enqueue::Preempted);
+        assert_eq!(classes.pick_next(), Some(1));
+    }
+
+    #[test]
+    fn yielding_fair_tasks_go_last() {
+        let mut classes = Classes::new(10);
+        for task in 1..4 {
+            classes.add(task, Policy::default());
+            classes.enqueue(task, Enqueue::Spawned);
+        }
+        assert_eq!(classes.pick_next(), Some(1));
+        classes.enqueue(1, Enqueue::Yielded);
+        assert_eq!(classes.pick_next(), Some(2));
+        assert_eq!(classes.pick_next(), Some(3));
+        assert_eq!(classes.pick_next(), Some(1));
+        assert_eq!(classes.pick_next(), None);
+    }
+
+    #[test]
+    fn higher_classes_run_first() {
+        let mut classes = Classes::new(10);
+        let tasks = [
+            (1, Policy::default()),
+            (2, Policy::Idle),
+            (3, Policy::Fifo { priority: 10 }),
+            (4, Policy::RoundRobin { priority: 20 }),
+            (5, Policy::Fifo { priority: 10 }),
+        ];
+        for &(task, policy) in &tasks {
+            classes.add(task, policy);
+            classes.enqueue(task, Enqueue::Spawned);
+        }
+        assert_eq!(classes.len(), 5);
+        let order: Vec<_> = (0..5).map(|_| classes.pick_next().unwrap()).collect();
+        assert_eq!(order, [4, 3, 5, 1, 2]);
+        assert!(classes.is_empty());
+
+        classes.enqueue(2, Enqueue::Woken);
+        assert!(!classes.preempts(2, 1));
+        assert!(!classes.tick(1));
+        classes.enqueue(3, Enqueue::Woken);
+        assert!(classes.preempts(3, 1));
+        assert!(classes.tick(1));
+        assert!(!classes.preempts(5, 4));
+        assert!(classes.preempts(4, 5));
+    }
+
+    #[test]
+    fn realtime_tasks_take_turns_by_policy() {
+        let fifo = Policy::Fifo { priority: 50 };
+        let counts = run(&mut Classes::new(10), &[(1, fifo), (2, fifo), (3, Policy::default())],
+                         100);
+        assert_eq!(&counts[1..4], [100, 0, 0]);
+
+        let round_robin = Policy::RoundRobin { priority: 50 };
+        let counts = run(&mut Classes::new(10),
+                         &[(1, round_robin), (2, round_robin), (3, Policy::default())], 100);
+        assert_eq!(&counts[1..4], [50, 50, 0]);
+    }
+
+    #[test]
+    fn preempted_realtime_tasks_continue_first() {
+        let mut classes = Classes::new(10);
+        let round_robin = Policy::RoundRobin { priority: 50 };
+        for task in 1..3 {
+            classes.add(task, round_robin);
+            classes.enqueue(task, Enqueue::Spawned);
+        }
+        assert_eq!(classes.pick_next(), Some(1));
+        classes.enqueue(1, Enqueue::Preempted);
+        assert_eq!(classes.pick_next(), Some(1));
+        for _ in 0..10 {
+            classes.tick(1);
+        }
+        // Its quantum is used up.
+        classes.enqueue(1, Enqueue::Preempted);
+        assert_eq!(classes.pick_next(), Some(2));
+    }
+
+    #[test]
+    fn policies_can_be_changed() {
+        let mut classes = Classes::new(10);
+        classes.add(1, Policy::Idle);
+        classes.add(2, Policy::default());
+        classes.enqueue(1, Enqueue::Spawned);
+        classes.enqueue(2, Enqueue::Spawned);
+        assert!(!classes.set_policy(1, Policy::Fifo { priority: 0 }));
+        assert!(!classes.set_policy(1, Policy::Fair { nice: 20 }));
+        assert!(classes.set_policy(1, Policy::Fifo { priority: MAX_RT_PRIORITY }));
+        assert_eq!(classes.entity(1).policy(), Policy::Fifo { priority: MAX_RT_PRIORITY });
+        assert_eq!(classes.pick_next(), Some(1));
+        assert_eq!(classes.pick_next(), Some(2));
+    }
+
+    #[test]
+    fn tasks_migrate_between_cpus() {
+        let mut from = Classes::new(10);
+        let mut to = Classes::new(10);
+        run(&mut from, &[(1, Policy::default()), (2, Policy::default())], 100);
+        run(&mut to, &[(3, Policy::default())], 10);
+        from.add(4, Policy::Idle);
+        from.enqueue(4, Enqueue::Spawned);
+
+        assert!(from.steal(&|task| task == 5).is_none());
+        let (task, entity) = from.steal(&|task| task != 4).unwrap();
+        assert!(task == 1 || task == 2);
+        assert!(entity.vruntime() <= 10 * fair::TICK_VRUNTIME);
+        to.attach(task, entity, Enqueue::Preempted);
+        // Placed among the tasks of its new CPU by its lag there.
+        let lag = to.entity(task).vruntime() - to.fair.min_vruntime();
+        assert_eq!(lag, entity.vruntime());
+        assert_eq!(to.pick_next(), Some(task));
+        assert_eq!(from.steal(&|_| true).map(|(task, _)| task), Some(4));
+        assert!(from.is_empty());
+
+        // The running task moves too.
+        let running = 3 - task;
+        let entity = from.detach(running);
+        to.attach(running, entity, Enqueue::Woken);
+        assert_eq!(to.len(), 1);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Push(usize),
+        Pop,
+        Remove(usize),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            (0..MAX_TASKS).prop_map(Operation::Push),
+            Just(Operation::Pop),
+            (0..MAX_TASKS).prop_map(Operation::Remove),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn behaves_like_a_deque_of_distinct_tasks(
+            operations in prop::collection::vec(operation(), 0..500)
+        ) {
+            let mut queue = RunQueue::new();
+            let mut model = VecDeque::new();
+            for operation in operations {
+                match operation {
+                    Operation::Push(task) => {
+                        let pushed = !model.contains(&task);
+                        prop_assert_eq!(queue.push_back(task), pushed);
+                        if pushed {
+                            model.push_back(task);
+                        }
+                    }
+                    Operation::Pop => prop_assert_eq!(queue.pop_front(), model.pop_front()),
+                    Operation::Remove(task) => {
+                        let position = model.iter().position(|&queued| queued == task);
+                        prop_assert_eq!(queue.remove(task), position.is_some());
+                        if let Some(position) = position {
+                            model.remove(position);
+                        }
+                    }
+                }
+                prop_assert_eq!(queue.len(), model.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Red-black tree of tasks sorted by a key, for the fair scheduling class.
+//!
+//! Every task slot has its own node, so the tree needs no allocation and holds each task at most
+//! once. Equal keys are ordered by task slot.
+
+use super::MAX_TASKS;
+
+/// Compared by the first value, then by the second.
+pub type Key = (u64, u64);
+
+/// The sentinel standing for every missing child, always black. Its parent link is scratch
+/// space for `remove`.
+const NIL: usize = MAX_TASKS;
+
+#[derive(Clone, Copy)]
+struct Node {
+    key: Key,
+    parent: usize,
+    left: usize,
+    right: usize,
+    red: bool,
+    linked: bool,
+}
+
+const UNLINKED: Node =
+    Node { key: (0, 0), parent: NIL, left: NIL, right: NIL, red: false, linked: false };
+
+pub struct RbTree {
+    nodes: [Node; MAX_TASKS + 1],
+    root: usize,
+    len: usize,
+}
+
+impl RbTree {
+    pub const fn new() -> Self {
+        RbTree { nodes: [UNLINKED; MAX_TASKS + 1], root: NIL, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, task: usize) -> bool {
+        self.nodes[task].linked
+    }
+
+    /// The key `task` was inserted with.
+    pub fn key(&self, task: usize) -> Option<Key> {
+        if self.contains(task) { Some(self.nodes[task].key) } else { None }
+    }
+
+    /// The task with the smallest key.
+    pub fn first(&self) -> Option<usize> {
+        let mut node = self.root;
+        if node == NIL {
+            return None;
+        }
+        while self.nodes[node].left != NIL {
+            node = self.nodes[node].left;
+        }
+        Some(node)
+    }
+
+    /// The task with the largest key.
+    pub fn last(&self) -> Option<usize> {
+        let mut node = self.root;
+        if node == NIL {
+            return None;
+        }
+        while self.nodes[node].right != NIL {
+            node = self.nodes[node].right;
+        }
+        Some(node)
+    }
+
+    /// The task before `task`, in order of keys.
+    pub fn previous(&self, task: usize) -> Option<usize> {
+        if !self.contains(task) {
+            return None;
+        }
+        let mut node = self.nodes[task].left;
+        if node != NIL {
+            while self.nodes[node].right != NIL {
+                node = self.nodes[node].right;
+            }
+            return Some(node);
+        }
+        let mut node = task;
+        let mut parent = self.nodes[node].parent;
+        while parent != NIL && self.nodes[parent].left == node {
+            node = parent;
+            parent = self.nodes[node].parent;
+        }
+        if parent == NIL { None } else { Some(parent) }
+    }
+
+    fn less(&self, key: Key, task: usize, node: usize) -> bool {
+        (key, task) < (self.nodes[node].key, node)
+    }
+
+    /// Add `task` with `key`, returning false if it is already in the tree.
+    pub fn insert(&mut self, task: usize, key: Key) -> bool {
+        if self.contains(task) {
+            return false;
+        }
+        let mut parent = NIL;
+        let mut node = self.root;
+        while node != NIL {
+            parent = node;
+            node = if self.less(key, task, node) {
+                self.nodes[node].left
+            } else {
+                self.nodes[node].right
+            };
+        }
+        self.nodes[task] = Node { key, parent, left: NIL, right: NIL, red: true, linked: true };
+        if parent == NIL {
+            self.root = task;
+        } else if self.less(key, task, parent) {
+            self.nodes[parent].left = task;
+        } else {
+            self.nodes[parent].right = task;
+        }
+        self.len += 1;
+        self.insert_fixup(task);
+        true
+    }
+
+    /// Take `task` out of the tree, returning false if it is not in it.
+    pub fn remove(&mut self, task: usize) -> bool {
+        if !self.contains(task) {
+            return false;
+        }
+        let (left, right) = (self.nodes[task].left, self.nodes[task].right);
+        let mut removed_red = self.nodes[task].red;
+        let child;
+        if left == NIL {
+            child = right;
+            self.transplant(task, right);
+        } else if right == NIL {
+            child = left;
+            self.transplant(task, left);
+        } else {
+            // Put the successor in place of `task`.
+            let mut successor = right;
+            while self.nodes[successor].left != NIL {
+                successor = self.nodes[successor].left;
+            }
+            removed_red = self.nodes[successor].red;
+            child = self.nodes[successor].right;
+            if self.nodes[successor].parent == task {
+                self.nodes[child].parent = successor;
+            } else {
+                self.transplant(successor, child);
+                self.nodes[successor].right = right;
+                self.nodes[right].parent = successor;
+            }
+            self.transplant(task, successor);
+            self.nodes[successor].left = left;
+            self.nodes[left].parent = successor;
+            self.nodes[successor].red = self.nodes[task].red;
+        }
+        if !removed_red {
+            self.remove_fixup(child);
+        }
+        self.nodes[task] = UNLINKED;
+        self.nodes[NIL] = UNLINKED;
+        self.len -= 1;
+        true
+    }
+
+    /// Put `replacement` where `node` hangs from its parent.
+    fn transplant(&mut self, node: usize, replacement: usize) {
+        let parent = self.nodes[node].parent;
+        if parent == NIL {
+            self.root = replacement;
+        } else if self.nodes[parent].left == node {
+            self.nodes[parent].left = replacement;
+        } else {
+            self.nodes[parent].right = replacement;
+        }
+        self.nodes[replacement].parent = parent;
+    }
+
+    fn rotate_left(&mut self, node: usize) {
+        let pivot = self.nodes[node].right;
+        let inner = self.nodes[pivot].left;
+        self.nodes[node].right = inner;
+        if inner != NIL {
+            self.nodes[inner].parent = node;
+        }
+        self.transplant(node, pivot);
+        self.nodes[pivot].left = node;
+        self.nodes[node].parent = pivot;
+    }
+
+    fn rotate_right(&mut self, node: usize) {
+        let pivot = self.nodes[node].left;
+        let inner = self.nodes[pivot].right;
+        self.nodes[node].left = inner;
+        if inner != NIL {
+            self.nodes[inner].parent = node;
+        }
+        self.transplant(node, pivot);
+        self.nodes[pivot].right = node;
+        self.nodes[node].parent = pivot;
+    }
+
+    /// Restore the colors after inserting the red `node`.
+    fn insert_fixup(&mut self, mut node: usize) {
+        while self.nodes[self.nodes[node].parent].red {
+            let parent = self.nodes[node].parent;
+            let grandparent = self.nodes[parent].parent;
+            let parent_is_left = self.nodes[grandparent].left == parent;
+            let uncle = if parent_is_left {
+                self.nodes[grandparent].right
+            } else {
+                self.nodes[grandparent].left
+            };
+            if self.nodes[uncle].red {
+                self.nodes[parent].red = false;
+                self.nodes[uncle].red = false;
+                self.nodes[grandparent].red = true;
+                node = grandparent;
+                continue;
+            }
+            let mut parent = parent;
+            if parent_is_left && self.nodes[parent].right == node {
+                self.rotate_left(parent);
+                node = parent;
+                parent = self.nodes[node].parent;
+            } else if !parent_is_left && self.nodes[parent].left == node {
+                self.rotate_right(parent);
+                node = parent;
+                parent = self.nodes[node].parent;
+            }
+            self.nodes[parent].red = false;
+            self.nodes[grandparent].red = true;
+            if parent_is_left {
+                self.rotate_right(grandparent);
+            } else {
+                self.rotate_left(grandparent);
+            }
+        }
+        let root = self.root;
+        self.nodes[root].red = false;
+    }
+
+    /// Restore the colors after removing a black node, `node` having taken its place.
+    fn remove_fixup(&mut self, mut node: usize) {
+        while node != self.root && !self.nodes[node].red {
+            let parent = self.nodes[node].parent;
+            let is_left = self.nodes[parent].left == node;
+            let mut sibling = if is_left {
+                self.nodes[parent].right
+            } else {
+                self.nodes[parent].left
+            };
+            if self.nodes[sibling].red {
+                self.nodes[sibling].red = false;
+                self.nodes[parent].red = true;
+                if is_left {
+                    self.rotate_left(parent);
+                    sibling = self.nodes[parent].right;
+                } else {
+                    self.rotate_right(parent);
+                    sibling = self.nodes[parent].left;
+                }
+            }
+            let (near, far) = if is_left {
+                (self.nodes[sibling].left, self.nodes[sibling].right)
+            } else {
+                (self.nodes[sibling].right, self.nodes[sibling].left)
+            };
+            if !self.nodes[near].red && !self.nodes[far].red {
+                self.nodes[sibling].red = true;
+                node = parent;
+                continue;
+            }
+            if !self.nodes[far].red {
+                self.nodes[near].red = false;
+                self.nodes[sibling].red = true;
+                if is_left {
+                    self.rotate_right(sibling);
+                    sibling = self.nodes[parent].right;
+                } else {
+                    self.rotate_left(sibling);
+                    sibling = self.nodes[parent].left;
+                }
+            }
+            let far = if is_left { self.nodes[sibling].right } else { self.nodes[sibling].left };
+            self.nodes[sibling].red = self.nodes[parent].red;
+            self.nodes[parent].red = false;
+            self.nodes[far].red = false;
+            if is_left {
+                self.rotate_left(parent);
+            } else {
+                self.rotate_right(parent);
+            }
+            node = self.root;
+        }
+        self.nodes[node].red = false;
+    }
+}
+
+impl Default for RbTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    impl RbTree {
+        /// The tasks in order, checking the links, the order and the colors on the way.
+        fn check(&self) -> Vec<usize> {
+            assert!(!self.nodes[self.root].red);
+            let mut tasks = Vec::new();
+            self.check_subtree(self.root, NIL, &mut tasks);
+            assert_eq!(tasks.len(), self.len);
+            tasks
+        }
+
+        /// Returns the black height of the subtree at `node`.
+        fn check_subtree(&self, node: usize, parent: usize, tasks: &mut Vec<usize>) -> usize {
+            if node == NIL {
+                return 1;
+            }
+            let Node { key, left, right, red, linked, .. } = self.nodes[node];
+            assert!(linked);
+            assert_eq!(self.nodes[node].parent, parent);
+            if red {
+                assert!(!self.nodes[left].red && !self.nodes[right].red);
+            }
+            let left_height = self.check_subtree(left, node, tasks);
+            if let Some(&previous) = tasks.last() {
+                assert!((self.nodes[previous].key, previous) < (key, node));
+            }
+            tasks.push(node);
+            let right_height = self.check_subtree(right, node, tasks);
+            assert_eq!(left_height, right_height);
+            left_height + if red { 0 } else { 1 }
+        }
+    }
+
+    #[test]
+    fn keeps_tasks_sorted() {
+        let mut tree = RbTree::new();
+        assert_eq!(tree.first(), None);
+        assert!(tree.insert(5, (30, 0)));
+        assert!(tree.insert(2, (10, 0)));
+        assert!(tree.insert(7, (10, 0)));
+        assert!(!tree.insert(7, (0, 0)));
+        assert!(tree.insert(1, (20, 1)));
+        assert_eq!(tree.check(), [2, 7, 1, 5]);
+        assert_eq!((tree.first(), tree.last()), (Some(2), Some(5)));
+        assert_eq!(tree.key(1), Some((20, 1)));
+
+        assert!(tree.remove(2));
+        assert!(!tree.remove(2));
+        assert_eq!(tree.key(2), None);
+        assert_eq!(tree.check(), [7, 1, 5]);
+        assert_eq!(tree.len(), 3);
+    }
+
+    proptest! {
+        #[test]
+        fn behaves_like_a_sorted_set(
+            operations in prop::collection::vec((0..MAX_TASKS, any::<Option<u8>>()), 0..500)
+        ) {
+            let mut tree = RbTree::new();
+            let mut model = BTreeSet::new();
+            let mut keys = [0; MAX_TASKS];
+            for (task, operation) in operations {
+                match operation {
+                    Some(key) => {
+                        let inserted = tree.key(task).is_none();
+                        prop_assert_eq!(tree.insert(task, (key as u64, 0)), inserted);
+                        if inserted {
+                            keys[task] = key;
+                            model.insert((key, task));
+                        }
+                    }
+                    None => {
+                        let removed = model.remove(&(keys[task], task));
+                        prop_assert_eq!(tree.remove(task), removed);
+                    }
+                }
+                let sorted: Vec<_> = model.iter().map(|&(_, task)| task).collect();
+                prop_assert_eq!(tree.check(), sorted.clone());
+                prop_assert_eq!(tree.first(), sorted.first().cloned());
+                prop_assert_eq!(tree.last(), sorted.last().cloned());
+                let mut backwards = Vec::new();
+                let mut task = tree.last();
+                while let Some(current) = task {
+                    backwards.push(current);
+                    task = tree.previous(current);
+                }
+                backwards.reverse();
+                prop_assert_eq!(backwards, sorted);
+            }
+        }
+    }
+}
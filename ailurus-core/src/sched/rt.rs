@@ -0,0 +1,110 @@
+//! The real-time scheduling class: the ready task with the highest priority runs, and tasks of
+//! equal priority in the order they became ready. Round-robin tasks go behind the others of
+//! their priority after each quantum, first-in-first-out ones only when they block or yield.
+
+use super::{Enqueue, Entity, Policy, SchedClass, MAX_TASKS};
+
+fn priority(entity: &Entity) -> u8 {
+    match entity.policy {
+        Policy::Fifo { priority } | Policy::RoundRobin { priority } => priority,
+        _ => 0,
+    }
+}
+
+pub struct RtClass {
+    /// By decreasing priority, then in the order to run.
+    tasks: [usize; MAX_TASKS],
+    len: usize,
+}
+
+impl RtClass {
+    pub const fn new() -> Self {
+        RtClass { tasks: [0; MAX_TASKS], len: 0 }
+    }
+
+    fn position(&self, task: usize) -> Option<usize> {
+        self.tasks[..self.len].iter().position(|&queued| queued == task)
+    }
+
+    fn remove_at(&mut self, position: usize) -> usize {
+        let task = self.tasks[position];
+        for index in position..self.len - 1 {
+            self.tasks[index] = self.tasks[index + 1];
+        }
+        self.len -= 1;
+        task
+    }
+}
+
+impl Default for RtClass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedClass for RtClass {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn enqueue(&mut self, task: usize, entities: &mut [Entity], reason: Enqueue) {
+        if self.position(task).is_some() {
+            return;
+        }
+        let entity = &entities[task];
+        let own = priority(entity);
+        // A preempted task continues first once the higher priorities are done, and a FIFO one
+        // does not care about quanta.
+        let first_of_priority = reason == Enqueue::Preempted
+            || (reason == Enqueue::Expired && entity.policy == Policy::Fifo { priority: own });
+        let position = self.tasks[..self.len].iter().position(|&queued| {
+            let queued = priority(&entities[queued]);
+            queued < own || (first_of_priority && queued == own)
+        }).unwrap_or(self.len);
+        for index in (position..self.len).rev() {
+            self.tasks[index + 1] = self.tasks[index];
+        }
+        self.tasks[position] = task;
+        self.len += 1;
+    }
+
+    fn dequeue(&mut self, task: usize, _entities: &mut [Entity]) -> bool {
+        match self.position(task) {
+            Some(position) => {
+                self.remove_at(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn pick_next(&mut self, _entities: &mut [Entity]) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.remove_at(0))
+    }
+
+    fn tick(&mut self, task: usize, entities: &mut [Entity], quantum: u32) -> bool {
+        let current = &entities[task];
+        let own = priority(current);
+        let first = match self.tasks[..self.len].first() {
+            Some(&first) => priority(&entities[first]),
+            None => return false,
+        };
+        let expired = match current.policy {
+            Policy::RoundRobin { .. } => current.ran >= quantum && first == own,
+            _ => false,
+        };
+        first > own || expired
+    }
+
+    fn preempts(&self, woken: &Entity, current: &Entity) -> bool {
+        priority(woken) > priority(current)
+    }
+
+    fn migration_candidate(&self, _entities: &[Entity], allowed: &dyn Fn(usize) -> bool)
+                           -> Option<usize> {
+        self.tasks[..self.len].iter().rev().cloned().find(|&task| allowed(task))
+    }
+}
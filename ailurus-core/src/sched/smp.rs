@@ -0,0 +1,140 @@
+//! Scheduling on several CPUs: sets of CPUs for the affinity of tasks, and the choice of CPUs
+//! when placing tasks and balancing the load. The load of a CPU is the number of tasks ready to
+//! run on it, the running one included.
+
+/// The number of CPUs a `CpuSet` can hold.
+pub const MAX_CPUS: usize = 64;
+
+/// A set of CPU numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuSet(u64);
+
+impl CpuSet {
+    pub const EMPTY: CpuSet = CpuSet(0);
+    pub const ALL: CpuSet = CpuSet(!0);
+
+    pub const fn from_bits(bits: u64) -> Self {
+        CpuSet(bits)
+    }
+
+    pub const fn single(cpu: usize) -> Self {
+        CpuSet(1 << cpu)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0 & 1 << cpu != 0
+    }
+
+    pub fn insert(&mut self, cpu: usize) {
+        self.0 |= 1 << cpu;
+    }
+
+    pub fn remove(&mut self, cpu: usize) {
+        self.0 &= !(1 << cpu);
+    }
+
+    pub fn intersection(self, other: CpuSet) -> CpuSet {
+        CpuSet(self.0 & other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..MAX_CPUS).filter(move |&cpu| self.contains(cpu))
+    }
+}
+
+/// The CPU of `cpus` to place a task on: `preferred` unless another one has less load, else the
+/// one with the least load and the lowest number. `None` if `cpus` has no CPU of `loads`.
+pub fn least_loaded(loads: &[usize], cpus: CpuSet, preferred: usize) -> Option<usize> {
+    let least = cpus.iter().take_while(|&cpu| cpu < loads.len())
+        .min_by_key(|&cpu| (loads[cpu], cpu))?;
+    if cpus.contains(preferred) && preferred < loads.len() && loads[preferred] <= loads[least] {
+        Some(preferred)
+    } else {
+        Some(least)
+    }
+}
+
+/// The CPU of `cpus` which `this` CPU should take a task from, if any: the one with the most
+/// load, provided moving a task evens things out.
+pub fn busiest(loads: &[usize], cpus: CpuSet, this: usize) -> Option<usize> {
+    let busiest = cpus.iter().take_while(|&cpu| cpu < loads.len())
+        .filter(|&cpu| cpu != this)
+        .max_by(|&a, &b| loads[a].cmp(&loads[b]).then(b.cmp(&a)))?;
+    // With a difference of one, moving a task only swaps the loads.
+    if loads[busiest] >= loads[this] + 2 { Some(busiest) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn sets_hold_cpus() {
+        let mut set = CpuSet::EMPTY;
+        assert!(set.is_empty());
+        set.insert(3);
+        set.insert(0);
+        set.insert(3);
+        assert_eq!(set.iter().collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(set.len(), 2);
+        set.remove(0);
+        assert_eq!(set, CpuSet::single(3));
+        assert!(!set.contains(MAX_CPUS));
+        assert_eq!(CpuSet::ALL.intersection(CpuSet::from_bits(0b110)).bits(), 0b110);
+    }
+
+    #[test]
+    fn places_tasks_on_idle_cpus() {
+        let loads = [2, 0, 1, 0];
+        assert_eq!(least_loaded(&loads, CpuSet::ALL, 0), Some(1));
+        assert_eq!(least_loaded(&loads, CpuSet::ALL, 3), Some(3));
+        assert_eq!(least_loaded(&loads, CpuSet::from_bits(0b101), 0), Some(2));
+        assert_eq!(least_loaded(&loads, CpuSet::single(0), 1), Some(0));
+        assert_eq!(least_loaded(&loads, CpuSet::single(4), 0), None);
+    }
+
+    #[test]
+    fn balances_uneven_loads() {
+        let loads = [3, 0, 4, 2];
+        assert_eq!(busiest(&loads, CpuSet::ALL, 1), Some(2));
+        assert_eq!(busiest(&loads, CpuSet::from_bits(0b1011), 1), Some(0));
+        assert_eq!(busiest(&loads, CpuSet::ALL, 0), None);
+        assert_eq!(busiest(&loads, CpuSet::ALL, 2), None);
+        assert_eq!(busiest(&[1, 0], CpuSet::ALL, 1), None);
+    }
+
+    proptest! {
+        #[test]
+        fn balancing_evens_out_loads(mut loads in prop::collection::vec(0..10usize, 1..8)) {
+            // Every CPU in turn pulls a task while it can.
+            let total: usize = loads.iter().sum();
+            let mut moved = true;
+            while moved {
+                moved = false;
+                for this in 0..loads.len() {
+                    if let Some(from) = busiest(&loads, CpuSet::ALL, this) {
+                        loads[from] -= 1;
+                        loads[this] += 1;
+                        moved = true;
+                    }
+                }
+            }
+            prop_assert_eq!(loads.iter().sum::<usize>(), total);
+            let (min, max) = (loads.iter().min().unwrap(), loads.iter().max().unwrap());
+            prop_assert!(max - min <= 1);
+        }
+    }
+}
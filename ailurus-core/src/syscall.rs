@@ -0,0 +1,139 @@
+//! The system call ABI, which user programs rely on.
+//!
+//! User code puts the number of the call in `rax` and its arguments in `rdi`, `rsi`, `rdx`,
+//! `r10`, `r8` and `r9`, then executes `syscall` or `int 0x80`. The result comes back in `rax`:
+//! a value, or an `Error` code negated, between -4095 and -1 as on Linux. `syscall` overwrites
+//! `rcx` and `r11`; every other register is preserved.
+//!
+//! | Number | Call                            | Result                          |
+//! |--------|---------------------------------|---------------------------------|
+//! | 0      | `write(fd, buffer, len)`        | the number of bytes written     |
+//! | 1      | `read(fd, buffer, len)`         | the number of bytes read        |
+//! | 2      | `exit(status)`                  | does not return                 |
+//! | 3      | `yield()`                       | 0                               |
+//! | 4      | `sleep(milliseconds)`           | 0                               |
+//! | 5      | `mmap(address, len, protection)`| the address of the mapping      |
+//! | 6      | `getpid()`                      | the id of the task              |
+
+pub const WRITE: u64 = 0;
+pub const READ: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const MMAP: u64 = 5;
+pub const GETPID: u64 = 6;
+/// The number of system calls.
+pub const COUNT: usize = 7;
+
+/// The file descriptors of `read` and `write`: the kernel terminal.
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// The protection bits of `mmap`. Mappings are always readable.
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+/// The largest mapping `mmap` makes at once.
+pub const MAX_MAPPING_SIZE: u64 = 256 << 20;
+
+const PAGE_SIZE: u64 = 4096;
+/// The largest error code.
+const MAX_ERROR: u64 = 4095;
+
+/// The arguments of a call, in order.
+pub type Arguments = [u64; 6];
+
+/// Why a call failed, with the code of the matching Linux error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// `EBADF`: no such file descriptor, or not open in the needed direction.
+    BadFileDescriptor = 9,
+    /// `ENOMEM`: no room left for a mapping.
+    OutOfMemory = 12,
+    /// `EFAULT`: a pointer to memory user mode may not access.
+    Fault = 14,
+    /// `EINVAL`
+    InvalidArgument = 22,
+    /// `ENOSYS`: no call has this number.
+    NoSuchCall = 38,
+}
+
+impl Error {
+    pub fn code(self) -> u64 {
+        self as u64
+    }
+}
+
+/// The value of `rax` for `result`.
+pub fn encode(result: Result<u64, Error>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => error.code().wrapping_neg(),
+    }
+}
+
+/// The error code in `rax`, if it holds one.
+pub fn error_code(rax: u64) -> Option<u64> {
+    if rax.wrapping_neg() <= MAX_ERROR && rax != 0 { Some(rax.wrapping_neg()) } else { None }
+}
+
+/// The size of a mapping of `len` bytes: whole pages, at least one and at most
+/// `MAX_MAPPING_SIZE` bytes.
+pub fn mapping_size(len: u64) -> Result<u64, Error> {
+    if len == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let size = len.checked_add(PAGE_SIZE - 1).ok_or(Error::InvalidArgument)? & !(PAGE_SIZE - 1);
+    if size > MAX_MAPPING_SIZE { Err(Error::OutOfMemory) } else { Ok(size) }
+}
+
+/// Check the protection bits of `mmap`.
+pub fn check_protection(protection: u64) -> Result<(), Error> {
+    if protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        Err(Error::InvalidArgument)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn numbers_are_stable() {
+        assert_eq!([WRITE, READ, EXIT, YIELD, SLEEP, MMAP, GETPID], [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(COUNT, 7);
+    }
+
+    #[test]
+    fn errors_are_negated() {
+        assert_eq!(encode(Ok(42)), 42);
+        assert_eq!(encode(Err(Error::NoSuchCall)) as i64, -38);
+        assert_eq!(error_code(encode(Err(Error::BadFileDescriptor))), Some(9));
+        assert_eq!(error_code(encode(Err(Error::Fault))), Some(14));
+        assert_eq!(error_code(0), None);
+        assert_eq!(error_code(0xffff_8000_0000_0000), None);
+    }
+
+    #[test]
+    fn mappings_take_whole_pages() {
+        assert_eq!(mapping_size(1), Ok(4096));
+        assert_eq!(mapping_size(8192), Ok(8192));
+        assert_eq!(mapping_size(0), Err(Error::InvalidArgument));
+        assert_eq!(mapping_size(u64::MAX), Err(Error::InvalidArgument));
+        assert_eq!(mapping_size(MAX_MAPPING_SIZE), Ok(MAX_MAPPING_SIZE));
+        assert_eq!(mapping_size(MAX_MAPPING_SIZE + 1), Err(Error::OutOfMemory));
+        assert_eq!(check_protection(PROT_READ | PROT_WRITE), Ok(()));
+        assert_eq!(check_protection(8), Err(Error::InvalidArgument));
+    }
+
+    proptest! {
+        #[test]
+        fn values_are_not_errors(value in 0..u64::MAX - MAX_ERROR) {
+            prop_assert_eq!(error_code(encode(Ok(value))), None);
+        }
+    }
+}
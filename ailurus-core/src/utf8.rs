@@ -0,0 +1,107 @@
+//! Incremental UTF-8 decoding of byte streams, e.g. output written a byte at a time.
+
+/// Emitted for malformed sequences.
+pub const REPLACEMENT: char = '\u{fffd}';
+
+#[derive(Debug, Clone, Default)]
+pub struct Utf8Decoder {
+    code_point: u32,
+    /// The continuation bytes still expected.
+    remaining: u8,
+    /// The smallest code point the current sequence may encode, to reject overlong ones.
+    minimum: u32,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Utf8Decoder { code_point: 0, remaining: 0, minimum: 0 }
+    }
+
+    /// Feed `byte`, calling `emit` with each character it completes. A malformed sequence
+    /// becomes a single `REPLACEMENT`, and a byte interrupting one starts anew.
+    pub fn push<F: FnMut(char)>(&mut self, byte: u8, mut emit: F) {
+        if self.remaining > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.code_point = self.code_point << 6 | u32::from(byte & 0x3f);
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    emit(self.finish());
+                }
+                return;
+            }
+            self.remaining = 0;
+            emit(REPLACEMENT);
+        }
+        match byte {
+            0x00..=0x7f => emit(byte as char),
+            0xc2..=0xdf => self.start(byte & 0x1f, 1, 0x80),
+            0xe0..=0xef => self.start(byte & 0x0f, 2, 0x800),
+            0xf0..=0xf4 => self.start(byte & 0x07, 3, 0x1_0000),
+            _ => emit(REPLACEMENT),
+        }
+    }
+
+    /// Whether a sequence has been started but not finished.
+    pub fn is_pending(&self) -> bool {
+        self.remaining > 0
+    }
+
+    fn start(&mut self, bits: u8, remaining: u8, minimum: u32) {
+        self.code_point = u32::from(bits);
+        self.remaining = remaining;
+        self.minimum = minimum;
+    }
+
+    fn finish(&self) -> char {
+        if self.code_point < self.minimum {
+            return REPLACEMENT;
+        }
+        // Rejects surrogates and code points beyond U+10FFFF.
+        core::char::from_u32(self.code_point).unwrap_or(REPLACEMENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode(bytes: &[u8]) -> String {
+        let mut decoder = Utf8Decoder::new();
+        let mut decoded = String::new();
+        for &byte in bytes {
+            decoder.push(byte, |character| decoded.push(character));
+        }
+        decoded
+    }
+
+    #[test]
+    fn replaces_malformed_sequences() {
+        assert_eq!(decode(b"a\xc3\xa9\xe2\x82\xac\xf0\x9f\x90\xbc"), "aé€🐼");
+        // A lone continuation byte, an interrupted sequence and invalid lead bytes.
+        assert_eq!(decode(b"\x80a\xe2\x82b\xc0\xff"), "\u{fffd}a\u{fffd}b\u{fffd}\u{fffd}");
+        // An overlong encoding of '/', a surrogate and a code point beyond U+10FFFF.
+        assert_eq!(decode(b"\xe0\x80\xaf\xed\xa0\x80\xf4\x90\x80\x80"),
+                   "\u{fffd}\u{fffd}\u{fffd}");
+    }
+
+    proptest! {
+        #[test]
+        fn decodes_valid_text(text in any::<String>()) {
+            prop_assert_eq!(decode(text.as_bytes()), text);
+        }
+
+        #[test]
+        fn emits_only_for_complete_sequences(bytes in any::<Vec<u8>>()) {
+            let mut decoder = Utf8Decoder::new();
+            let mut count = 0;
+            for &byte in &bytes {
+                decoder.push(byte, |_| count += 1);
+            }
+            prop_assert!(count <= bytes.len());
+            if std::str::from_utf8(&bytes).is_ok() {
+                prop_assert!(!decoder.is_pending());
+            }
+        }
+    }
+}
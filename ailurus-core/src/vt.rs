@@ -0,0 +1,282 @@
+//! Virtual terminals: several consoles sharing one screen, each with a scrollback buffer.
+//!
+//! A `VirtualTerminal` keeps its own screen content and history in a `TextBuffer`, so output to
+//! a terminal in the background is kept until it is shown again. Output to the terminal in the
+//! foreground is drawn on the real `Display` at the same time.
+
+use crate::ansi::{Attributes, Cell, Display, Terminal};
+
+const BLANK: Cell = Cell {
+    character: ' ',
+    foreground: Attributes::DEFAULT.foreground,
+    background: Attributes::DEFAULT.background,
+};
+
+/// A screen of character cells plus the lines scrolled off its top, kept in a ring of lines.
+pub struct TextBuffer<'a> {
+    cells: &'a mut [Cell],
+    columns: usize,
+    rows: usize,
+    /// The number of lines `cells` holds.
+    capacity: usize,
+    /// The line at the top of the screen.
+    top: usize,
+    /// The number of lines above the screen.
+    history: usize,
+    /// The number of lines the view is scrolled back.
+    offset: usize,
+    cursor: (usize, usize),
+}
+
+impl<'a> TextBuffer<'a> {
+    /// A blank buffer of `columns` by `rows` cells, using the rest of `cells` for the history.
+    /// Panics if `cells` does not even hold the screen.
+    pub fn new(cells: &'a mut [Cell], columns: usize, rows: usize) -> Self {
+        let capacity = cells.len() / columns;
+        assert!(capacity >= rows, "text buffer is too small");
+        for cell in cells.iter_mut() {
+            *cell = BLANK;
+        }
+        TextBuffer { cells, columns, rows, capacity, top: 0, history: 0, offset: 0,
+                     cursor: (0, 0) }
+    }
+
+    /// The number of lines scrolled off the screen and still kept.
+    pub fn history_len(&self) -> usize {
+        self.history
+    }
+
+    /// The number of lines the view is scrolled back.
+    pub fn scroll_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The cell at `column` and `row` of the screen.
+    pub fn cell(&self, column: usize, row: usize) -> Cell {
+        self.line(self.top + row)[column]
+    }
+
+    /// The cell at `column` and `row` of the view, which is scrolled back by `scroll_offset`.
+    pub fn visible_cell(&self, column: usize, row: usize) -> Cell {
+        self.line(self.top + self.capacity - self.offset + row)[column]
+    }
+
+    /// Scroll the view back by `lines`, or forward if negative, returning whether it moved.
+    pub fn scroll_view(&mut self, lines: isize) -> bool {
+        let offset = if lines < 0 {
+            self.offset.saturating_sub(lines.wrapping_neg() as usize)
+        } else {
+            self.offset.saturating_add(lines as usize).min(self.history)
+        };
+        let moved = offset != self.offset;
+        self.offset = offset;
+        moved
+    }
+
+    /// Draw the view on `display`. The cursor is hidden while scrolled back past it.
+    pub fn show<D: Display + ?Sized>(&self, display: &mut D) {
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                display.put(column, row, self.visible_cell(column, row));
+            }
+        }
+        let (column, row) = self.cursor;
+        if row + self.offset < self.rows {
+            display.move_cursor(column, row + self.offset);
+        } else {
+            display.hide_cursor();
+        }
+    }
+
+    /// Line `index` of the ring, modulo its capacity.
+    fn line(&self, index: usize) -> &[Cell] {
+        let start = index % self.capacity * self.columns;
+        &self.cells[start..start + self.columns]
+    }
+
+    fn line_mut(&mut self, index: usize) -> &mut [Cell] {
+        let start = index % self.capacity * self.columns;
+        &mut self.cells[start..start + self.columns]
+    }
+}
+
+impl<'a> Display for TextBuffer<'a> {
+    fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn put(&mut self, column: usize, row: usize, cell: Cell) {
+        let top = self.top;
+        self.line_mut(top + row)[column] = cell;
+    }
+
+    /// Keep the top line in the history, dropping the oldest line if it is full. A view
+    /// scrolled back stays on the same lines.
+    fn scroll_up(&mut self, blank: Cell) {
+        if self.history + self.rows < self.capacity {
+            self.history += 1;
+        }
+        if self.offset > 0 {
+            self.offset = (self.offset + 1).min(self.history);
+        }
+        self.top = (self.top + 1) % self.capacity;
+        let bottom = self.top + self.rows - 1;
+        for cell in self.line_mut(bottom) {
+            *cell = blank;
+        }
+    }
+
+    fn move_cursor(&mut self, column: usize, row: usize) {
+        self.cursor = (column, row);
+    }
+
+    fn hide_cursor(&mut self) {}
+}
+
+/// Draws on a `TextBuffer` and a `Display` showing it at the same time.
+struct Mirror<'b, 'a, D: ?Sized> {
+    buffer: &'b mut TextBuffer<'a>,
+    display: &'b mut D,
+}
+
+impl<'b, 'a, D: Display + ?Sized> Display for Mirror<'b, 'a, D> {
+    fn size(&self) -> (usize, usize) {
+        self.buffer.size()
+    }
+
+    fn put(&mut self, column: usize, row: usize, cell: Cell) {
+        self.buffer.put(column, row, cell);
+        self.display.put(column, row, cell);
+    }
+
+    fn scroll_up(&mut self, blank: Cell) {
+        self.buffer.scroll_up(blank);
+        self.display.scroll_up(blank);
+    }
+
+    fn move_cursor(&mut self, column: usize, row: usize) {
+        self.buffer.move_cursor(column, row);
+        self.display.move_cursor(column, row);
+    }
+
+    fn hide_cursor(&mut self) {
+        self.display.hide_cursor();
+    }
+}
+
+/// A `Terminal` writing into its own `TextBuffer`.
+pub struct VirtualTerminal<'a> {
+    terminal: Terminal,
+    buffer: TextBuffer<'a>,
+}
+
+impl<'a> VirtualTerminal<'a> {
+    pub fn new(cells: &'a mut [Cell], columns: usize, rows: usize) -> Self {
+        VirtualTerminal { terminal: Terminal::new(), buffer: TextBuffer::new(cells, columns, rows) }
+    }
+
+    pub fn terminal(&mut self) -> &mut Terminal {
+        &mut self.terminal
+    }
+
+    pub fn buffer(&self) -> &TextBuffer<'a> {
+        &self.buffer
+    }
+
+    /// Write `s` while in the background.
+    pub fn write_str(&mut self, s: &str) {
+        self.terminal.write_str(&mut self.buffer, s);
+    }
+
+    /// Write `s` while in the foreground, shown on `display`. A view scrolled back jumps to the
+    /// end first.
+    pub fn write_str_shown<D: Display + ?Sized>(&mut self, display: &mut D, s: &str) {
+        self.scroll_view(display, -(self.buffer.offset as isize));
+        self.terminal.write_str(&mut Mirror { buffer: &mut self.buffer, display }, s);
+    }
+
+    /// Draw the terminal on `display`, e.g. after switching to it.
+    pub fn show<D: Display + ?Sized>(&self, display: &mut D) {
+        self.buffer.show(display);
+    }
+
+    /// Scroll the view of the terminal in the foreground back by `lines`, or forward if
+    /// negative.
+    pub fn scroll_view<D: Display + ?Sized>(&mut self, display: &mut D, lines: isize) {
+        if self.buffer.scroll_view(lines) {
+            self.buffer.show(display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn text(buffer: &TextBuffer) -> Vec<String> {
+        (0..buffer.rows).map(|row| {
+            (0..buffer.columns).map(|column| buffer.visible_cell(column, row).character)
+                .collect()
+        }).collect()
+    }
+
+    #[test]
+    fn keeps_history() {
+        let mut cells = [BLANK; 4 * 5];
+        let mut terminal = VirtualTerminal::new(&mut cells, 4, 2);
+        terminal.write_str("a\nb\nc\nd\ne\nf");
+        assert_eq!(terminal.buffer().history_len(), 3);
+        assert_eq!(text(terminal.buffer()), ["e   ", "f   "]);
+
+        let mut screen_cells = [BLANK; 4 * 2];
+        let mut screen = TextBuffer::new(&mut screen_cells, 4, 2);
+        terminal.scroll_view(&mut screen, 2);
+        assert_eq!(text(&screen), ["c   ", "d   "]);
+        terminal.scroll_view(&mut screen, 5);
+        assert_eq!(terminal.buffer().scroll_offset(), 3);
+        assert_eq!(text(&screen), ["b   ", "c   "]);
+
+        // Output while scrolled back is kept but does not move the view, and the oldest line is
+        // dropped.
+        terminal.write_str("\ng");
+        assert_eq!(terminal.buffer().history_len(), 3);
+        assert_eq!(text(terminal.buffer()), ["c   ", "d   "]);
+
+        // Output in the foreground returns to the end.
+        terminal.write_str_shown(&mut screen, "h");
+        assert_eq!(terminal.buffer().scroll_offset(), 0);
+        assert_eq!(text(&screen), ["f   ", "gh  "]);
+        assert_eq!(screen.cursor, (2, 1));
+        terminal.scroll_view(&mut screen, -1);
+        assert_eq!(text(&screen), ["f   ", "gh  "]);
+    }
+
+    #[test]
+    fn works_without_history() {
+        let mut cells = [BLANK; 3 * 2];
+        let mut terminal = VirtualTerminal::new(&mut cells, 3, 2);
+        terminal.write_str("abc\x1b[31mde\n");
+        assert_eq!(terminal.buffer().history_len(), 0);
+        assert!(!terminal.buffer.scroll_view(1));
+        assert_eq!(text(terminal.buffer()), ["de ", "   "]);
+        assert_eq!(terminal.buffer().cell(0, 0).foreground.index(), 1);
+    }
+
+    proptest! {
+        #[test]
+        fn foreground_output_matches_buffer(before in "[ab\n\x08\t]{0,50}", back in 0isize..8,
+                                            after in "[ab\n\x08\t]{0,50}") {
+            let mut cells = [BLANK; 5 * 7];
+            let mut terminal = VirtualTerminal::new(&mut cells, 5, 3);
+            let mut screen_cells = [BLANK; 5 * 3];
+            let mut screen = TextBuffer::new(&mut screen_cells, 5, 3);
+            terminal.write_str(&before);
+            terminal.show(&mut screen);
+            terminal.scroll_view(&mut screen, back);
+            terminal.write_str_shown(&mut screen, &after);
+            prop_assert_eq!(text(&screen), text(terminal.buffer()));
+            prop_assert_eq!(screen.cursor, terminal.buffer().cursor);
+        }
+    }
+}
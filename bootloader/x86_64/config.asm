@@ -4,8 +4,13 @@ USE16
 align 512, db 0
 
 config:
+%ifdef VESA_XRES
+  .xres: dw VESA_XRES
+  .yres: dw VESA_YRES
+%else
   .xres: dw 0
   .yres: dw 0
+%endif
 
 times 512 - ($ - config) db 0
 
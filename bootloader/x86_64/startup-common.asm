@@ -8,6 +8,26 @@ args:
     .stack_size dq 0
     .env_base dq 0
     .env_size dq 0
+    ; the linear framebuffer, filled in by save_framebuffer if a VBE mode was set
+    .fb_base dq 0
+    .fb_pitch dd 0
+    .fb_width dd 0
+    .fb_height dd 0
+    .fb_bits_per_pixel db 0
+    .fb_red_position db 0
+    .fb_red_size db 0
+    .fb_green_position db 0
+    .fb_green_size db 0
+    .fb_blue_position db 0
+    .fb_blue_size db 0
+
+; passed to the kernel as its environment
+kernel_cmdline:
+%ifdef KERNEL_CMDLINE
+    %defstr KERNEL_CMDLINE_STR %[KERNEL_CMDLINE]
+    db KERNEL_CMDLINE_STR
+%endif
+.end:
 
 startup:
     ; enable A20-Line via IO-Port 92, might not work on all motherboards
@@ -29,7 +49,13 @@ startup:
 .loaded_kernel:
     call memory_map
 
-    ; call vesa
+%ifdef VESA_XRES
+    call vesa
+    test eax, eax
+    jnz .no_framebuffer
+    call save_framebuffer
+.no_framebuffer:
+%endif
 
     mov si, init_fpu_msg
     call print
@@ -135,13 +135,8 @@ long_mode:
     add rsp, rcx
 
     ; copy env to stack
-; %ifdef KERNEL
-    mov rsi, 0
-    mov rcx, 0
-; %else
-;     mov rsi, redoxfs.env
-;     mov rcx, redoxfs.env.end - redoxfs.env
-; %endif
+    mov rsi, kernel_cmdline
+    mov rcx, kernel_cmdline.end - kernel_cmdline
     mov [args.env_size], rcx
 .copy_env:
     cmp rcx, 0
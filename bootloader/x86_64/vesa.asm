@@ -126,6 +126,32 @@ vesa:
 
 .goodmode dw 0
 .currentmode dw 0
+
+; pass the framebuffer of the mode set by vesa to the kernel
+save_framebuffer:
+    mov eax, [VBEModeInfo.physbaseptr]
+    mov [args.fb_base], eax
+    movzx eax, word [VBEModeInfo.bytesperscanline]
+    mov [args.fb_pitch], eax
+    movzx eax, word [VBEModeInfo.xresolution]
+    mov [args.fb_width], eax
+    movzx eax, word [VBEModeInfo.yresolution]
+    mov [args.fb_height], eax
+    mov al, [VBEModeInfo.bitsperpixel]
+    mov [args.fb_bits_per_pixel], al
+    mov al, [VBEModeInfo.redfieldposition]
+    mov [args.fb_red_position], al
+    mov al, [VBEModeInfo.redmasksize]
+    mov [args.fb_red_size], al
+    mov al, [VBEModeInfo.greenfieldposition]
+    mov [args.fb_green_position], al
+    mov al, [VBEModeInfo.greenmasksize]
+    mov [args.fb_green_size], al
+    mov al, [VBEModeInfo.bluefieldposition]
+    mov [args.fb_blue_position], al
+    mov al, [VBEModeInfo.bluemasksize]
+    mov [args.fb_blue_size], al
+    ret
 ;useful functions
 
 ; print a number in decimal
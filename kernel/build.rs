@@ -0,0 +1,18 @@
+//! Embeds the custom keymap given to `make Keymap=<file>`, which `keyboard.layout=custom`
+//! selects. Without one, the custom keymap is the US layout.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=AILURUS_KEYMAP");
+    let output = Path::new(&env::var("OUT_DIR").unwrap()).join("custom.keymap");
+    match env::var("AILURUS_KEYMAP") {
+        Ok(ref keymap) if !keymap.is_empty() => {
+            println!("cargo:rerun-if-changed={}", keymap);
+            fs::copy(keymap, &output).expect("cannot read the custom keymap");
+        }
+        _ => fs::write(&output, "").unwrap(),
+    }
+}
@@ -0,0 +1,57 @@
+//! Stack unwinding by following the saved frame pointers.
+//!
+//! This relies on the kernel being built with `-C force-frame-pointers=yes`, so that every
+//! function starts with `push rbp; mov rbp, rsp`.
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StackFrame {
+    /// Frame pointer of the frame.
+    pub rbp: u64,
+    /// Address the frame returns to.
+    pub return_address: u64,
+}
+
+pub struct FrameIter {
+    rbp: u64,
+    depth: usize,
+}
+
+impl Iterator for FrameIter {
+    type Item = StackFrame;
+
+    fn next(&mut self) -> Option<StackFrame> {
+        // Stop at the end of the chain or when it leads out of the kernel stacks.
+        if self.rbp == 0 || self.rbp % 8 != 0 || self.rbp < 0xffff_8000_0000_0000 ||
+            self.depth >= MAX_DEPTH {
+            return None;
+        }
+        let (previous_rbp, return_address) = unsafe {
+            let pointer = self.rbp as *const u64;
+            (*pointer, *pointer.offset(1))
+        };
+        if return_address == 0 {
+            return None;
+        }
+        let frame = StackFrame { rbp: self.rbp, return_address };
+
+        // Frames of callers always live at higher addresses.
+        self.rbp = if previous_rbp > self.rbp { previous_rbp } else { 0 };
+        self.depth += 1;
+        Some(frame)
+    }
+}
+
+/// Walk the stack starting from the frame pointer `rbp`.
+pub fn frames_from(rbp: u64) -> FrameIter {
+    FrameIter { rbp, depth: 0 }
+}
+
+/// Walk the stack of the calling function.
+#[inline(always)]
+pub fn frames() -> FrameIter {
+    let rbp: u64;
+    unsafe { asm!("mov %rbp, $0" : "=r"(rbp) ::: "volatile"); }
+    frames_from(rbp)
+}
@@ -0,0 +1,580 @@
+//! GDB remote serial protocol stub on COM2.
+//!
+//! The stub takes control of the CPU on `int3` breakpoints, after single steps and when GDB
+//! interrupts the kernel with Ctrl-C. While it is active, interrupts stay disabled and the
+//! serial port is polled. Run `make gdb` and connect with `target remote :4321`.
+//!
+//! Software breakpoints are written to memory only while the kernel runs, so GDB always reads
+//! the original code. To continue from a breakpoint address, the stub first single-steps the
+//! original instruction and then re-inserts all breakpoints. Hardware breakpoints and
+//! watchpoints use the debug registers through the `watchpoint` module.
+
+use ailurus_core::gdb::{self, BreakpointKind, Command};
+use spin::Mutex;
+use super::super::device::pic::PIC_8259;
+use super::super::device::serial::{SerialPort, COM2};
+use super::super::interrupt::util::InterruptFrame;
+use super::super::memory::{paging, VirtAddr};
+use super::watchpoint::{self, Owner, Watchpoint, WatchpointKind};
+
+const MAX_PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+const COM2_IRQ: u8 = 3;
+
+const RFLAGS_TRAP: u64 = 1 << 8;
+const INT3: u8 = 0xcc;
+/// Sent by GDB to interrupt the running target.
+const INTERRUPT_REQUEST: u8 = 0x03;
+
+/// rax–r15, rip, eflags and the six segment registers, as GDB lays out the `g` packet.
+const REGISTER_COUNT: usize = 24;
+const RIP: usize = 16;
+const EFLAGS: usize = 17;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt = 2,
+    Trap = 5,
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    original: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stepping {
+    No,
+    /// GDB asked for a single step.
+    Requested,
+    /// Stepping over a breakpoint address before continuing.
+    OverBreakpoint,
+}
+
+struct GdbStub {
+    port: SerialPort,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    stepping: Stepping,
+    /// Whether GDB is waiting for a stop reply, i.e. it resumed the kernel.
+    resumed: bool,
+    /// The kind and address of the watchpoint which stopped the kernel, for the stop reply.
+    watch_hit: Option<(&'static str, u64)>,
+    reply: Reply,
+}
+
+struct Reply {
+    data: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < MAX_PACKET_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+    }
+
+    fn push_hex_byte(&mut self, byte: u8) {
+        self.push(gdb::hex_digit(byte >> 4));
+        self.push(gdb::hex_digit(byte));
+    }
+
+    /// Append the `size` low bytes of `value` in target (little-endian) order.
+    fn push_hex_value(&mut self, value: u64, size: usize) {
+        for index in 0..size {
+            self.push_hex_byte((value >> (index * 8)) as u8);
+        }
+    }
+
+    /// Append `value` as a plain big-endian hex number.
+    fn push_hex_number(&mut self, value: u64) {
+        let digits = (64 - value.leading_zeros() as usize + 3) / 4;
+        for index in (0..digits.max(1)).rev() {
+            self.push(gdb::hex_digit((value >> (index * 4)) as u8));
+        }
+    }
+}
+
+static STUB: Mutex<GdbStub> = Mutex::new(GdbStub {
+    port: unsafe { SerialPort::new(COM2) },
+    breakpoints: [None; MAX_BREAKPOINTS],
+    stepping: Stepping::No,
+    resumed: false,
+    watch_hit: None,
+    reply: Reply { data: [0; MAX_PACKET_SIZE], len: 0 },
+});
+
+/// The last packet received from GDB. It is kept apart from `STUB` so that commands, which borrow
+/// from it, can be served while the stub is mutated. Only locked while `STUB` is held.
+static PACKET: Mutex<[u8; MAX_PACKET_SIZE]> = Mutex::new([0; MAX_PACKET_SIZE]);
+
+/// Initialize COM2 and let GDB interrupt the kernel at any time.
+pub fn init() {
+    unsafe {
+        let mut stub = STUB.lock();
+        stub.port.initialize();
+        stub.port.enable_receive_interrupt();
+        PIC_8259.lock().set_masked(COM2_IRQ, false);
+    }
+}
+
+/// Stop in the debugger, waiting for GDB to attach if it has not already.
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { asm!("int3" :::: "volatile"); }
+}
+
+/// Called by the breakpoint exception handler.
+pub fn handle_breakpoint(frame: &mut InterruptFrame) {
+    let mut stub = STUB.lock();
+    // `rip` points after the `int3`. If it was one of ours, restart the original instruction.
+    let address = frame.iret_registers.rip - 1;
+    if stub.breakpoints.iter().flatten().any(|breakpoint| breakpoint.address == address) {
+        frame.iret_registers.rip = address;
+    }
+    stub.run(frame, Signal::Trap, false);
+}
+
+/// Called by the debug exception handler on a single step. Returns false if the stub did not ask
+/// for it.
+pub fn handle_single_step(frame: &mut InterruptFrame) -> bool {
+    let mut stub = STUB.lock();
+    let stepping = stub.stepping;
+    stub.stepping = Stepping::No;
+    frame.iret_registers.rflags &= !RFLAGS_TRAP;
+    match stepping {
+        Stepping::No => false,
+        Stepping::Requested => {
+            stub.run(frame, Signal::Trap, false);
+            true
+        }
+        Stepping::OverBreakpoint => {
+            stub.insert_breakpoints();
+            true
+        }
+    }
+}
+
+/// Called by the debug exception handler when a watchpoint set by GDB triggers.
+pub fn handle_watchpoint(frame: &mut InterruptFrame, watchpoint: &Watchpoint) {
+    let mut stub = STUB.lock();
+    stub.watch_hit = match watchpoint.kind {
+        WatchpointKind::Execute => None,
+        WatchpointKind::Write => Some(("watch", watchpoint.address)),
+        WatchpointKind::ReadWrite => Some(("awatch", watchpoint.address)),
+    };
+    stub.run(frame, Signal::Trap, false);
+}
+
+/// Called by the COM2 interrupt handler.
+pub fn handle_serial_interrupt(frame: &mut InterruptFrame) {
+    let mut stub = STUB.lock();
+    while let Some(byte) = stub.port.try_receive() {
+        match byte {
+            INTERRUPT_REQUEST => {
+                stub.run(frame, Signal::Interrupt, false);
+                return;
+            }
+            // GDB talks to the kernel while it is running, e.g. when attaching.
+            b'$' => {
+                stub.run(frame, Signal::Interrupt, true);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl GdbStub {
+    /// Serve GDB until it resumes the kernel.
+    fn run(&mut self, frame: &mut InterruptFrame, signal: Signal, packet_started: bool) {
+        self.remove_breakpoints();
+        if self.resumed {
+            self.resumed = false;
+            self.reply.clear();
+            self.push_stop_reply(signal);
+            self.send_reply();
+        }
+
+        let mut packet = PACKET.lock();
+        let mut packet_started = packet_started;
+        loop {
+            let len = self.receive_packet(&mut packet[..], packet_started);
+            packet_started = false;
+            self.reply.clear();
+            let command = match gdb::parse_command(&packet[..len]) {
+                Some(command) => command,
+                None => {
+                    self.reply.push_str("E01");
+                    self.send_reply();
+                    continue;
+                }
+            };
+
+            match command {
+                Command::StopReason => self.push_stop_reply(signal),
+                Command::ReadRegisters => {
+                    for index in 0..REGISTER_COUNT {
+                        let (value, size) = read_register(frame, index);
+                        self.reply.push_hex_value(value, size);
+                    }
+                }
+                Command::WriteRegisters(hex) => self.write_registers(frame, hex),
+                Command::ReadRegister(index) if index < REGISTER_COUNT => {
+                    let (value, size) = read_register(frame, index);
+                    self.reply.push_hex_value(value, size);
+                }
+                Command::WriteRegister(index, hex) if index < REGISTER_COUNT => {
+                    match decode_value(hex) {
+                        Some(value) => {
+                            write_register(frame, index, value);
+                            self.reply.push_str("OK");
+                        }
+                        None => self.reply.push_str("E01"),
+                    }
+                }
+                Command::ReadRegister(_) | Command::WriteRegister(..) => self.reply.push_str("E01"),
+                Command::ReadMemory { address, length } => self.read_memory(address, length),
+                Command::WriteMemory { address, data } => self.write_memory(address, data),
+                Command::Continue(address) | Command::Step(address) => {
+                    if let Some(address) = address {
+                        frame.iret_registers.rip = address;
+                    }
+                    if let Command::Step(_) = command {
+                        self.stepping = Stepping::Requested;
+                    } else if self.breakpoint_index(frame.iret_registers.rip).is_some() {
+                        self.stepping = Stepping::OverBreakpoint;
+                    } else {
+                        self.insert_breakpoints();
+                    }
+                    if self.stepping != Stepping::No {
+                        frame.iret_registers.rflags |= RFLAGS_TRAP;
+                    }
+                    self.resumed = true;
+                    self.watch_hit = None;
+                    return;
+                }
+                Command::InsertBreakpoint { kind: BreakpointKind::Software, address, .. } => {
+                    self.add_breakpoint(address)
+                }
+                Command::RemoveBreakpoint { kind: BreakpointKind::Software, address, .. } => {
+                    if let Some(index) = self.breakpoint_index(address) {
+                        self.breakpoints[index] = None;
+                    }
+                    self.reply.push_str("OK");
+                }
+                Command::InsertBreakpoint { kind, address, length } => {
+                    let (kind, length) = hardware_breakpoint(kind, length);
+                    match watchpoint::insert(address, length, kind, Owner::Gdb) {
+                        Ok(_) => self.reply.push_str("OK"),
+                        Err(_) => self.reply.push_str("E28"),
+                    }
+                }
+                Command::RemoveBreakpoint { kind, address, .. } => {
+                    let (kind, _) = hardware_breakpoint(kind, 1);
+                    if let Some(index) = watchpoint::find(address, kind, Owner::Gdb) {
+                        watchpoint::clear_watchpoint(index);
+                    }
+                    self.reply.push_str("OK");
+                }
+                Command::Query(query) => {
+                    if query.starts_with(b"Supported") {
+                        self.reply.push_str("PacketSize=");
+                        self.reply.push_hex_number(MAX_PACKET_SIZE as u64);
+                    } else if query == b"Attached" {
+                        self.reply.push(b'1');
+                    }
+                }
+                Command::SetThread => self.reply.push_str("OK"),
+                Command::Detach | Command::Kill => {
+                    // The kernel cannot be killed, so both just let it run without breakpoints.
+                    self.breakpoints = [None; MAX_BREAKPOINTS];
+                    watchpoint::clear_all(Owner::Gdb);
+                    if command == Command::Detach {
+                        self.reply.push_str("OK");
+                        self.send_reply();
+                    }
+                    return;
+                }
+                _ => {}
+            }
+            self.send_reply();
+        }
+    }
+
+    fn push_stop_reply(&mut self, signal: Signal) {
+        match self.watch_hit {
+            Some((kind, address)) => {
+                self.reply.push(b'T');
+                self.reply.push_hex_byte(signal as u8);
+                self.reply.push_str(kind);
+                self.reply.push(b':');
+                self.reply.push_hex_number(address);
+                self.reply.push(b';');
+            }
+            None => {
+                self.reply.push(b'S');
+                self.reply.push_hex_byte(signal as u8);
+            }
+        }
+    }
+
+    /// Wait for a packet with a valid checksum and store its data in `packet`.
+    fn receive_packet(&mut self, packet: &mut [u8], mut started: bool) -> usize {
+        loop {
+            if !started {
+                while self.port.receive() != b'$' {}
+            }
+            started = false;
+
+            let mut len = 0;
+            let mut overflow = false;
+            loop {
+                let byte = self.port.receive();
+                if byte == b'#' {
+                    break;
+                }
+                if len < packet.len() {
+                    packet[len] = byte;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+            let high = gdb::parse_hex_digit(self.port.receive());
+            let low = gdb::parse_hex_digit(self.port.receive());
+            let valid = match (high, low) {
+                (Some(high), Some(low)) => high << 4 | low == gdb::checksum(&packet[..len]),
+                _ => false,
+            };
+            if valid && !overflow {
+                self.port.send_raw(b'+');
+                return len;
+            }
+            self.port.send_raw(b'-');
+        }
+    }
+
+    fn send_reply(&mut self) {
+        let checksum = gdb::checksum(&self.reply.data[..self.reply.len]);
+        loop {
+            self.port.send_raw(b'$');
+            for index in 0..self.reply.len {
+                self.port.send_raw(self.reply.data[index]);
+            }
+            self.port.send_raw(b'#');
+            self.port.send_raw(gdb::hex_digit(checksum >> 4));
+            self.port.send_raw(gdb::hex_digit(checksum));
+            if self.port.receive() != b'-' {
+                return;
+            }
+        }
+    }
+
+    fn write_registers(&mut self, frame: &mut InterruptFrame, mut hex: &[u8]) {
+        for index in 0..REGISTER_COUNT {
+            let size = read_register(frame, index).1;
+            if hex.len() < size * 2 {
+                break;
+            }
+            match decode_value(&hex[..size * 2]) {
+                Some(value) => write_register(frame, index, value),
+                None => return self.reply.push_str("E01"),
+            }
+            hex = &hex[size * 2..];
+        }
+        self.reply.push_str("OK");
+    }
+
+    fn read_memory(&mut self, address: u64, length: usize) {
+        let length = length.min(MAX_PACKET_SIZE / 2);
+        if !is_accessible(address, length) {
+            return self.reply.push_str("E14");
+        }
+        for offset in 0..length as u64 {
+            let byte = unsafe { *((address + offset) as *const u8) };
+            self.reply.push_hex_byte(byte);
+        }
+    }
+
+    fn write_memory(&mut self, address: u64, hex: &[u8]) {
+        let mut buffer = [0u8; MAX_PACKET_SIZE / 2];
+        let length = match gdb::decode_hex_bytes(hex, &mut buffer) {
+            Some(length) => length,
+            None => return self.reply.push_str("E01"),
+        };
+        if !is_accessible(address, length) {
+            return self.reply.push_str("E14");
+        }
+        for (offset, &byte) in buffer[..length].iter().enumerate() {
+            unsafe { *((address + offset as u64) as *mut u8) = byte; }
+        }
+        self.reply.push_str("OK");
+    }
+
+    fn breakpoint_index(&self, address: u64) -> Option<usize> {
+        self.breakpoints.iter()
+            .position(|breakpoint| breakpoint.map_or(false, |b| b.address == address))
+    }
+
+    fn add_breakpoint(&mut self, address: u64) {
+        if self.breakpoint_index(address).is_some() {
+            return self.reply.push_str("OK");
+        }
+        if !is_accessible(address, 1) {
+            return self.reply.push_str("E14");
+        }
+        match self.breakpoints.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Breakpoint { address, original: 0 });
+                self.reply.push_str("OK");
+            }
+            None => self.reply.push_str("E28"),
+        }
+    }
+
+    fn insert_breakpoints(&mut self) {
+        for breakpoint in self.breakpoints.iter_mut().flatten() {
+            let code = breakpoint.address as *mut u8;
+            unsafe {
+                breakpoint.original = *code;
+                *code = INT3;
+            }
+        }
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for breakpoint in self.breakpoints.iter().flatten() {
+            let code = breakpoint.address as *mut u8;
+            unsafe {
+                if *code == INT3 {
+                    *code = breakpoint.original;
+                }
+            }
+        }
+    }
+}
+
+/// The debug register setup for a `Z1`-`Z4` packet. There are no read-only watchpoints, so
+/// `rwatch` is treated like `awatch`.
+fn hardware_breakpoint(kind: BreakpointKind, length: usize) -> (WatchpointKind, usize) {
+    match kind {
+        BreakpointKind::Software | BreakpointKind::Hardware => (WatchpointKind::Execute, 1),
+        BreakpointKind::Write => (WatchpointKind::Write, length),
+        BreakpointKind::Read | BreakpointKind::Access => (WatchpointKind::ReadWrite, length),
+    }
+}
+
+/// Decode a little-endian register value.
+fn decode_value(hex: &[u8]) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    let len = gdb::decode_hex_bytes(hex, &mut bytes)?;
+    Some(bytes[..len].iter().rev().fold(0u64, |value, &byte| value << 8 | byte as u64))
+}
+
+/// The value and size in bytes of register `index` in GDB's numbering.
+fn read_register(frame: &InterruptFrame, index: usize) -> (u64, usize) {
+    let context = &frame.context_registers;
+    let iret = &frame.iret_registers;
+    match index {
+        0 => (context.rax, 8),
+        1 => (context.rbx, 8),
+        2 => (context.rcx, 8),
+        3 => (context.rdx, 8),
+        4 => (context.rsi, 8),
+        5 => (context.rdi, 8),
+        6 => (context.rbp, 8),
+        7 => (iret.rsp, 8),
+        8 => (context.r8, 8),
+        9 => (context.r9, 8),
+        10 => (context.r10, 8),
+        11 => (context.r11, 8),
+        12 => (context.r12, 8),
+        13 => (context.r13, 8),
+        14 => (context.r14, 8),
+        15 => (context.r15, 8),
+        RIP => (iret.rip, 8),
+        EFLAGS => (iret.rflags, 4),
+        18 => (iret.cs, 4),
+        19 => (iret.ss, 4),
+        // ds, es, fs and gs are not saved; the kernel uses flat segments.
+        _ => (0, 4),
+    }
+}
+
+fn write_register(frame: &mut InterruptFrame, index: usize, value: u64) {
+    let context = &mut frame.context_registers;
+    let iret = &mut frame.iret_registers;
+    match index {
+        0 => context.rax = value,
+        1 => context.rbx = value,
+        2 => context.rcx = value,
+        3 => context.rdx = value,
+        4 => context.rsi = value,
+        5 => context.rdi = value,
+        6 => context.rbp = value,
+        7 => iret.rsp = value,
+        8 => context.r8 = value,
+        9 => context.r9 = value,
+        10 => context.r10 = value,
+        11 => context.r11 = value,
+        12 => context.r12 = value,
+        13 => context.r13 = value,
+        14 => context.r14 = value,
+        15 => context.r15 = value,
+        RIP => iret.rip = value,
+        EFLAGS => iret.rflags = value,
+        // Changing segments from the debugger would only crash the kernel.
+        _ => {}
+    }
+}
+
+/// Whether `length` bytes from `address` are mapped, so that accessing them cannot fault.
+fn is_accessible(address: u64, length: usize) -> bool {
+    let end = match address.checked_add(length as u64) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = address & !0xfff;
+    while page < end {
+        let canonical = page < 0x0000_8000_0000_0000 || page >= 0xffff_8000_0000_0000;
+        if !canonical || paging::page_flags(VirtAddr::new(page)).is_none() {
+            return false;
+        }
+        page += 0x1000;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn register_values_are_little_endian() {
+        let mut reply = Reply { data: [0; MAX_PACKET_SIZE], len: 0 };
+        reply.push_hex_value(0x1122_3344_5566_7788, 8);
+        assert_eq!(&reply.data[..reply.len], &b"8877665544332211"[..]);
+        assert_eq!(decode_value(b"8877665544332211"), Some(0x1122_3344_5566_7788));
+    }
+
+    #[test_case]
+    fn unmapped_memory_is_not_accessible() {
+        assert!(is_accessible(register_values_are_little_endian as u64, 16));
+        assert!(!is_accessible(0xffff_fe00_0000_0000, 1));
+        assert!(!is_accessible(0x0000_8000_0000_0000, 1));
+        assert!(!is_accessible(u64::max_value(), 2));
+    }
+}
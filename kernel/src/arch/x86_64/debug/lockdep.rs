@@ -0,0 +1,206 @@
+//! Lock dependency validation for kernel spinlocks, built with the `lockdep` feature
+//! (`make Lockdep=1`). See `ailurus_core::lockdep` for what is checked.
+//!
+//! Only locks declared as a `debug::lockdep::Mutex` are followed, each one its own class:
+//!
+//! ```ignore
+//! pub static PIC_8259: Mutex<ChainedPics> = Mutex::new("PIC_8259", ChainedPics::new(..));
+//! ```
+//!
+//! Classes are checked before spinning, so a deadlock is reported instead of hanging. A
+//! violation panics with both conflicting chains of locks, and turns the validator off: the
+//! panic handler takes the output locks again. Without the feature, the `Mutex` is a plain
+//! `spin::Mutex` with a name.
+
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::AtomicUsize;
+#[cfg(feature = "lockdep")]
+use ailurus_core::lockdep::{Chain, ClassId, Usage, Validator};
+#[cfg(feature = "lockdep")]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "lockdep")]
+use super::super::interrupt;
+#[cfg(feature = "lockdep")]
+use super::super::platform::instructions;
+#[cfg(feature = "lockdep")]
+use super::super::task::{self, preempt, this_cpu, MAX_CPUS, MAX_TASKS};
+
+/// A lock class, registered with the validator the first time the lock is taken.
+#[cfg_attr(not(feature = "lockdep"), allow(dead_code))]
+struct LockClass {
+    name: &'static str,
+    /// The `ClassId` plus one, or zero while unregistered.
+    id: AtomicUsize,
+}
+
+/// A `spin::Mutex` followed by the validator.
+pub struct Mutex<T> {
+    inner: spin::Mutex<T>,
+    class: LockClass,
+}
+
+impl<T> Mutex<T> {
+    /// `name` appears in the reports, usually the name of the static.
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Mutex {
+            inner: spin::Mutex::new(value),
+            class: LockClass { name, id: AtomicUsize::new(0) },
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        acquire(&self.class, false);
+        MutexGuard { inner: self.inner.lock(), class: &self.class }
+    }
+
+    /// Never deadlocks, so it is only checked for interrupt safety.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let inner = self.inner.try_lock()?;
+        acquire(&self.class, true);
+        Some(MutexGuard { inner, class: &self.class })
+    }
+
+    /// Release the lock whoever holds it. Only for the panic handler, which turns the validator
+    /// off first (see `stop`).
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    inner: spin::MutexGuard<'a, T>,
+    class: &'a LockClass,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        release(self.class);
+    }
+}
+
+#[cfg(feature = "lockdep")]
+struct State {
+    validator: Validator,
+    /// The locks held by every task, by slot, then by the interrupt handlers of every CPU.
+    held: [Chain; MAX_TASKS + MAX_CPUS],
+}
+
+#[cfg(feature = "lockdep")]
+static STATE: spin::Mutex<State> = spin::Mutex::new(State {
+    validator: Validator::new(),
+    held: [Chain::EMPTY; MAX_TASKS + MAX_CPUS],
+});
+/// Cleared once the validator stopped, so that no lock is followed anymore.
+#[cfg(feature = "lockdep")]
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+#[cfg(feature = "lockdep")]
+impl LockClass {
+    fn id(&self, validator: &mut Validator) -> Option<ClassId> {
+        match self.id.load(Ordering::SeqCst) {
+            0 => {
+                let id = validator.register(self.name)?;
+                self.id.store(id + 1, Ordering::SeqCst);
+                Some(id)
+            }
+            id => Some(id - 1),
+        }
+    }
+}
+
+/// The index in `State::held` of the executing code. Takes the scheduler's lock, so it is
+/// called before taking `STATE`, with interrupts disabled.
+#[cfg(feature = "lockdep")]
+fn context() -> usize {
+    if preempt::in_interrupt() { MAX_TASKS + this_cpu() } else { task::current_slot() }
+}
+
+#[cfg(feature = "lockdep")]
+fn acquire(class: &LockClass, try_lock: bool) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    let usage = if preempt::in_interrupt() {
+        Usage::Interrupt
+    } else if instructions::interrupts_enabled() {
+        Usage::InterruptsEnabled
+    } else {
+        Usage::InterruptsDisabled
+    };
+    let report = interrupt::run_without_interrupt(|| {
+        let context = context();
+        let mut state = STATE.lock();
+        let State { validator, held } = &mut *state;
+        let id = class.id(validator)?;
+        let result = validator.acquire(&mut held[context], id, usage, try_lock);
+        if !validator.is_enabled() {
+            ENABLED.store(false, Ordering::SeqCst);
+        }
+        result.err().map(|violation| validator.report(violation))
+    });
+    if let Some(report) = report {
+        panic!("{}", report);
+    }
+}
+
+#[cfg(feature = "lockdep")]
+fn release(class: &LockClass) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    interrupt::run_without_interrupt(|| {
+        let context = context();
+        let id = class.id.load(Ordering::SeqCst);
+        if id != 0 {
+            STATE.lock().held[context].remove(id - 1);
+        }
+    })
+}
+
+/// Stop following locks, before the panic handler releases them by force.
+#[cfg(feature = "lockdep")]
+pub fn stop() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+#[cfg(not(feature = "lockdep"))]
+fn acquire(_class: &LockClass, _try_lock: bool) {}
+
+#[cfg(not(feature = "lockdep"))]
+fn release(_class: &LockClass) {}
+
+#[cfg(not(feature = "lockdep"))]
+pub fn stop() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::interrupt;
+
+    static COUNTER: Mutex<usize> = Mutex::new("COUNTER", 0);
+
+    #[test_case]
+    fn try_lock_fails_while_held() {
+        interrupt::run_without_interrupt(|| {
+            let mut counter = COUNTER.lock();
+            *counter += 1;
+            assert!(COUNTER.try_lock().is_none());
+            drop(counter);
+            assert_eq!(*COUNTER.try_lock().unwrap(), 1);
+        });
+    }
+}
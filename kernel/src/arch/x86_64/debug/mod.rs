@@ -0,0 +1,4 @@
+pub mod backtrace;
+pub mod gdb;
+pub mod lockdep;
+pub mod watchpoint;
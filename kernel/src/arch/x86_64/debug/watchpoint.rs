@@ -0,0 +1,205 @@
+//! Hardware watchpoints through the debug registers.
+//!
+//! A watchpoint traps when the CPU executes, writes or accesses the watched bytes, which is the
+//! quickest way to find out who corrupts a static:
+//!
+//! ```ignore
+//! let address = unsafe { &E820_MAP as *const _ as u64 };
+//! set_watchpoint(address, 8, WatchpointKind::Write).unwrap();
+//! ```
+//!
+//! Every hit is logged with a backtrace. The debug registers belong to the CPU which set them.
+
+use spin::Mutex;
+use super::super::interrupt::{self, util::InterruptFrame};
+use super::super::platform::instructions::{self, BreakpointCondition, BreakpointLength, Dr6};
+use super::{backtrace, gdb};
+use crate::panic::symbols::Symbolized;
+
+const BREAKPOINT_COUNT: usize = 4;
+const RFLAGS_RESUME: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Execute,
+    Write,
+    /// Reads and writes; the CPU cannot trap on reads only.
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointError {
+    /// The length is not 1, 2, 4 or 8, or not 1 for an execute breakpoint.
+    InvalidLength,
+    /// The address is not aligned to the length.
+    Unaligned,
+    /// All four debug registers are in use.
+    NoFreeRegister,
+}
+
+/// Who is told about a hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Kernel,
+    Gdb,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    pub address: u64,
+    pub length: usize,
+    pub kind: WatchpointKind,
+    pub owner: Owner,
+    pub hits: usize,
+}
+
+static WATCHPOINTS: Mutex<[Option<Watchpoint>; BREAKPOINT_COUNT]> =
+    Mutex::new([None; BREAKPOINT_COUNT]);
+
+/// Trap accesses of `kind` to the `length` bytes at `address`, returning the index of the debug
+/// register used.
+pub fn set_watchpoint(address: u64, length: usize, kind: WatchpointKind)
+    -> Result<usize, WatchpointError> {
+    insert(address, length, kind, Owner::Kernel)
+}
+
+pub fn clear_watchpoint(index: usize) {
+    interrupt::run_without_interrupt(|| {
+        WATCHPOINTS.lock()[index] = None;
+        unsafe {
+            let mut dr7 = instructions::read_dr7();
+            dr7.disable(index);
+            instructions::write_dr7(dr7);
+        }
+    })
+}
+
+/// Remove all watchpoints of `owner`.
+pub fn clear_all(owner: Owner) {
+    for index in 0..BREAKPOINT_COUNT {
+        let owned = interrupt::run_without_interrupt(|| {
+            WATCHPOINTS.lock()[index].map_or(false, |watchpoint| watchpoint.owner == owner)
+        });
+        if owned {
+            clear_watchpoint(index);
+        }
+    }
+}
+
+/// How often watchpoint `index` triggered, or `None` if it is not set.
+pub fn hits(index: usize) -> Option<usize> {
+    interrupt::run_without_interrupt(|| WATCHPOINTS.lock()[index].map(|watchpoint| watchpoint.hits))
+}
+
+/// The index of the watchpoint of `owner` with the given address and kind.
+pub fn find(address: u64, kind: WatchpointKind, owner: Owner) -> Option<usize> {
+    interrupt::run_without_interrupt(|| {
+        WATCHPOINTS.lock().iter().position(|watchpoint| watchpoint.map_or(false, |w| {
+            w.address == address && w.kind == kind && w.owner == owner
+        }))
+    })
+}
+
+pub fn insert(address: u64, length: usize, kind: WatchpointKind, owner: Owner)
+    -> Result<usize, WatchpointError> {
+    let encoded_length = BreakpointLength::from_bytes(length)
+        .ok_or(WatchpointError::InvalidLength)?;
+    if kind == WatchpointKind::Execute && length != 1 {
+        return Err(WatchpointError::InvalidLength);
+    }
+    if address % length as u64 != 0 {
+        return Err(WatchpointError::Unaligned);
+    }
+    let condition = match kind {
+        WatchpointKind::Execute => BreakpointCondition::Execute,
+        WatchpointKind::Write => BreakpointCondition::Write,
+        WatchpointKind::ReadWrite => BreakpointCondition::ReadWrite,
+    };
+
+    interrupt::run_without_interrupt(|| {
+        let mut watchpoints = WATCHPOINTS.lock();
+        let index = watchpoints.iter().position(|watchpoint| watchpoint.is_none())
+            .ok_or(WatchpointError::NoFreeRegister)?;
+        watchpoints[index] = Some(Watchpoint { address, length, kind, owner, hits: 0 });
+        unsafe {
+            instructions::write_breakpoint_address(index, address);
+            let mut dr7 = instructions::read_dr7();
+            dr7.enable(index, condition, encoded_length);
+            instructions::write_dr7(dr7);
+        }
+        Ok(index)
+    })
+}
+
+/// Called by the debug exception handler with the content of DR6. Returns false if no
+/// watchpoint triggered.
+pub fn handle_debug_exception(frame: &mut InterruptFrame, status: Dr6) -> bool {
+    let mut handled = false;
+    for index in 0..BREAKPOINT_COUNT {
+        if !status.is_triggered(index) {
+            continue;
+        }
+        let watchpoint = {
+            let mut watchpoints = WATCHPOINTS.lock();
+            match watchpoints[index].as_mut() {
+                Some(watchpoint) => {
+                    watchpoint.hits += 1;
+                    *watchpoint
+                }
+                None => continue,
+            }
+        };
+        handled = true;
+        // Execute breakpoints are faults, so without the resume flag the instruction would
+        // trigger them again.
+        if watchpoint.kind == WatchpointKind::Execute {
+            frame.iret_registers.rflags |= RFLAGS_RESUME;
+        }
+        match watchpoint.owner {
+            Owner::Kernel => report(frame, index, &watchpoint),
+            Owner::Gdb => gdb::handle_watchpoint(frame, &watchpoint),
+        }
+    }
+    handled
+}
+
+fn report(frame: &InterruptFrame, index: usize, watchpoint: &Watchpoint) {
+    // Data breakpoints are traps, so `rip` already points after the accessing instruction.
+    warn!("Watchpoint {} ({:?} of {} bytes at 0x{:x}) hit, continuing at {}", index,
+          watchpoint.kind, watchpoint.length, watchpoint.address,
+          Symbolized(frame.iret_registers.rip));
+    for (depth, stack_frame) in backtrace::frames_from(frame.context_registers.rbp).enumerate() {
+        warn!("  #{:<2} {}", depth, Symbolized(stack_frame.return_address));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    static mut WATCHED: u64 = 0;
+
+    #[test_case]
+    fn write_watchpoint_traps() {
+        let address = unsafe { &WATCHED as *const u64 as u64 };
+        let index = set_watchpoint(address, 8, WatchpointKind::Write).unwrap();
+        unsafe {
+            ptr::write_volatile(&mut WATCHED, 1);
+            let _ = ptr::read_volatile(&WATCHED);
+        }
+        assert_eq!(hits(index), Some(1));
+        clear_watchpoint(index);
+        assert_eq!(hits(index), None);
+    }
+
+    #[test_case]
+    fn invalid_watchpoints_are_rejected() {
+        assert_eq!(set_watchpoint(0x1000, 3, WatchpointKind::Write),
+                   Err(WatchpointError::InvalidLength));
+        assert_eq!(set_watchpoint(0x1000, 4, WatchpointKind::Execute),
+                   Err(WatchpointError::InvalidLength));
+        assert_eq!(set_watchpoint(0x1004, 8, WatchpointKind::ReadWrite),
+                   Err(WatchpointError::Unaligned));
+    }
+}
@@ -0,0 +1,228 @@
+//! Virtual terminals on the VGA text buffer or the framebuffer.
+//!
+//! `init` starts `TERMINAL_COUNT` terminals, each with its own screen content, scrollback buffer
+//! and keyboard input (see `keyboard::try_read_key_from`). Alt+F1 to Alt+F6 switch between them,
+//! and Shift+PageUp and Shift+PageDown scroll the one in the foreground back and forth. `print!`
+//! and the kernel log write to `KERNEL_TERMINAL`.
+
+use ailurus_core::ansi::{Attributes, Cell, Display};
+use ailurus_core::vt::VirtualTerminal;
+use core::fmt;
+use core::mem;
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use super::super::interrupt;
+use super::super::memory::{paging, VirtAddr, CONSOLE_BUFFERS};
+use super::super::memory::page_table::PageTableFlags;
+use super::keyboard::{KeyCode, KeyEvent, KeyState};
+use super::{framebuffer, vga_buffer};
+
+pub const TERMINAL_COUNT: usize = 6;
+/// The terminal of `print!` and the kernel log.
+pub const KERNEL_TERMINAL: usize = 0;
+/// The number of lines each terminal keeps above its screen.
+pub const SCROLLBACK_LINES: usize = 500;
+
+const PAGE_SIZE: usize = 4096;
+const SWITCH_KEYS: [KeyCode; TERMINAL_COUNT] =
+    [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6];
+
+pub struct Consoles {
+    terminals: [Option<VirtualTerminal<'static>>; TERMINAL_COUNT],
+    active: usize,
+}
+
+pub static CONSOLES: Mutex<Consoles> = Mutex::new(Consoles {
+    terminals: [None, None, None, None, None, None],
+    active: KERNEL_TERMINAL,
+});
+/// A copy of `Consoles::active` for the keyboard interrupt handler.
+static ACTIVE: AtomicUsize = AtomicUsize::new(KERNEL_TERMINAL);
+
+/// Allocate the buffers of the terminals and show the first one. Called once the memory
+/// management and, if there is one, the framebuffer console are initialized.
+pub unsafe fn init() {
+    let (columns, rows) = with_display(|display| display.size());
+    let cells = columns * (rows + SCROLLBACK_LINES);
+    let size = TERMINAL_COUNT * cells * mem::size_of::<Cell>();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        paging::map(VirtAddr::new(CONSOLE_BUFFERS + offset as u64), flags);
+    }
+
+    let mut terminals = [None, None, None, None, None, None];
+    for (index, terminal) in terminals.iter_mut().enumerate() {
+        let start = (CONSOLE_BUFFERS as *mut Cell).add(index * cells);
+        for offset in 0..cells {
+            // The fresh pages may hold anything, including invalid characters.
+            ptr::write(start.add(offset), Cell::new(' ', &Attributes::DEFAULT));
+        }
+        let cells = slice::from_raw_parts_mut(start, cells);
+        *terminal = Some(VirtualTerminal::new(cells, columns, rows));
+    }
+    interrupt::run_without_interrupt(|| {
+        let mut consoles = CONSOLES.lock();
+        consoles.terminals = terminals;
+        consoles.active = KERNEL_TERMINAL;
+        ACTIVE.store(KERNEL_TERMINAL, Ordering::SeqCst);
+        let terminal = consoles.terminals[KERNEL_TERMINAL].as_ref().unwrap();
+        with_display(|display| terminal.show(display));
+    });
+    info!("{} virtual terminals of {}x{} characters with {} lines of scrollback",
+          TERMINAL_COUNT, columns, rows, SCROLLBACK_LINES);
+}
+
+pub fn is_enabled() -> bool {
+    interrupt::run_without_interrupt(|| CONSOLES.lock().terminals[KERNEL_TERMINAL].is_some())
+}
+
+/// The terminal in the foreground, which receives the keyboard input.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Bring `terminal` to the foreground.
+pub fn switch_to(terminal: usize) {
+    interrupt::run_without_interrupt(|| {
+        let mut consoles = CONSOLES.lock();
+        if consoles.terminals.get(terminal).map_or(true, Option::is_none) {
+            return;
+        }
+        consoles.active = terminal;
+        ACTIVE.store(terminal, Ordering::SeqCst);
+        let terminal = consoles.terminals[terminal].as_ref().unwrap();
+        with_display(|display| terminal.show(display));
+    })
+}
+
+/// Scroll the terminal in the foreground back by `lines`, or forward if negative.
+pub fn scroll(lines: isize) {
+    interrupt::run_without_interrupt(|| {
+        let mut consoles = CONSOLES.lock();
+        let active = consoles.active;
+        if let Some(terminal) = consoles.terminals[active].as_mut() {
+            with_display(|display| terminal.scroll_view(display, lines));
+        }
+    })
+}
+
+/// Switch or scroll the terminals on Alt+F1..F6 and Shift+PageUp/PageDown, returning whether
+/// `event` was one of these keys. Called by the keyboard interrupt handler.
+pub fn handle_key(event: &KeyEvent) -> bool {
+    if event.state != KeyState::Down || !is_enabled() {
+        return false;
+    }
+    if event.modifiers.alt() {
+        if let Some(terminal) = SWITCH_KEYS.iter().position(|&code| code == event.code) {
+            switch_to(terminal);
+            return true;
+        }
+    }
+    if event.modifiers.shift() {
+        // Half a screen at a time.
+        let lines = with_display(|display| display.size().1 as isize / 2);
+        match event.code {
+            KeyCode::PageUp => scroll(lines),
+            KeyCode::PageDown => scroll(-lines),
+            _ => return false,
+        }
+        return true;
+    }
+    false
+}
+
+/// Run `f` with the screen: the framebuffer if there is one, the VGA text buffer otherwise.
+fn with_display<F: FnOnce(&mut dyn Display) -> R, R>(f: F) -> R {
+    interrupt::run_without_interrupt(|| {
+        if let Some(console) = framebuffer::CONSOLE.lock().as_mut() {
+            return console.with_display(f);
+        }
+        vga_buffer::WRITER.lock().with_display(f)
+    })
+}
+
+struct TerminalWriter<'a> {
+    terminal: &'a mut VirtualTerminal<'static>,
+    shown: bool,
+}
+
+impl<'a> fmt::Write for TerminalWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.shown {
+            let terminal = &mut self.terminal;
+            with_display(|display| terminal.write_str_shown(display, s));
+        } else {
+            self.terminal.write_str(s);
+        }
+        Ok(())
+    }
+}
+
+/// Print to `terminal`. Before `init`, only the kernel terminal exists and prints to the screen
+/// directly.
+pub fn print_to(terminal: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if !is_enabled() {
+        if terminal == KERNEL_TERMINAL {
+            vga_buffer::print(args);
+        }
+        return;
+    }
+    interrupt::run_without_interrupt(|| {
+        let mut consoles = CONSOLES.lock();
+        let shown = consoles.active == terminal;
+        if let Some(terminal) = consoles.terminals.get_mut(terminal).and_then(Option::as_mut) {
+            TerminalWriter { terminal, shown }.write_fmt(args).unwrap();
+        }
+    })
+}
+
+pub fn print(args: fmt::Arguments) {
+    print_to(KERNEL_TERMINAL, args);
+}
+
+/// Log sink writing to the kernel terminal.
+pub struct ConsoleSink;
+
+impl crate::klog::LogSink for ConsoleSink {
+    fn write_str(&self, s: &str) {
+        print(format_args!("{}", s));
+    }
+}
+
+pub static CONSOLE_SINK: ConsoleSink = ConsoleSink;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the first line of `terminal` starts with `text`.
+    fn shows(terminal: usize, text: &str) -> bool {
+        interrupt::run_without_interrupt(|| {
+            let consoles = CONSOLES.lock();
+            let buffer = consoles.terminals[terminal].as_ref().unwrap().buffer();
+            text.chars().enumerate().all(|(column, character)| {
+                buffer.cell(column, 0).character == character
+            })
+        })
+    }
+
+    #[test_case]
+    fn terminals_keep_their_output() {
+        print_to(1, format_args!("\x1b[2J\x1b[Hfirst"));
+        print_to(2, format_args!("\x1b[2J\x1b[Hsecond"));
+        assert!(shows(1, "first "));
+        assert!(shows(2, "second"));
+
+        switch_to(1);
+        assert_eq!(active(), 1);
+        print_to(1, format_args!("!"));
+        assert!(shows(1, "first! "));
+        switch_to(TERMINAL_COUNT);
+        assert_eq!(active(), 1);
+        switch_to(KERNEL_TERMINAL);
+        assert_eq!(active(), KERNEL_TERMINAL);
+    }
+}
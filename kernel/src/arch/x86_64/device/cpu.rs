@@ -11,6 +11,12 @@ pub fn has_apic() -> bool {
     edx & 0b_10_0000_0000 != 0
 }
 
+/// Initial local APIC id of the executing processor, used as the CPU number.
+pub fn cpu_id() -> u8 {
+    let (ebx, _, _) = unsafe { instructions::cpuid(1) };
+    (ebx >> 24) as u8
+}
+
 pub fn get_apic_base_addr()->(u32,u32) {
     unsafe {
         let (eax, edx) = instructions::rdmsr(IA32_APIC_BASE_MSR);
@@ -0,0 +1,267 @@
+//! Text console on the linear framebuffer of a VBE graphics mode.
+//!
+//! The bootloader only sets a graphics mode when asked to (see `Resolution` in the Makefile), and
+//! the VGA text buffer is invisible once it has. `init` then maps the framebuffer and takes over
+//! `print!` and the kernel log.
+//!
+//! The console draws on a double-buffered `Canvas`, which `with_canvas` lends to anything else
+//! that wants to draw, e.g. a boot splash. The console overwrites it when it prints.
+
+use ailurus_core::ansi::{Cell, Display, Terminal};
+use ailurus_core::canvas::Canvas;
+use ailurus_core::framebuffer::{FramebufferInfo, Rgb, Surface};
+use ailurus_core::psf::{self, Font};
+use core::fmt;
+use core::slice;
+use spin::Mutex;
+use super::super::interrupt;
+use super::super::memory::{paging, PhysAddr, VirtAddr, FRAMEBUFFER_BACK_BUFFER};
+use super::super::memory::page_table::PageTableFlags;
+
+/// Height of the cursor in pixels, drawn at the bottom of its cell.
+const CURSOR_HEIGHT: usize = 2;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The canvas as seen by the terminal emulation.
+struct Screen {
+    canvas: Canvas<'static>,
+    font: Font<'static>,
+    columns: usize,
+    rows: usize,
+    /// The cell showing the cursor, if it is drawn.
+    cursor: Option<(usize, usize)>,
+}
+
+impl Screen {
+    /// Draw or erase the cursor at `column` and `row` by inverting the bottom lines of the cell.
+    fn invert_cursor(&mut self, column: usize, row: usize) {
+        let x = column * self.font.width();
+        let y = (row + 1) * self.font.height() - CURSOR_HEIGHT;
+        for y in y..y + CURSOR_HEIGHT {
+            for x in x..x + self.font.width() {
+                let pixel = self.canvas.pixel(x, y);
+                let inverted = Rgb::new(!pixel.red, !pixel.green, !pixel.blue);
+                self.canvas.put_pixel(x as i32, y as i32, inverted);
+            }
+        }
+    }
+}
+
+impl Display for Screen {
+    fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn put(&mut self, column: usize, row: usize, cell: Cell) {
+        if self.cursor == Some((column, row)) {
+            self.cursor = None;
+        }
+        let glyph = self.font.glyph(cell.character);
+        let (x, y) = (column * self.font.width(), row * self.font.height());
+        self.canvas.draw_glyph(x as i32, y as i32, &glyph, cell.foreground.rgb(),
+                               cell.background.rgb());
+    }
+
+    fn scroll_up(&mut self, blank: Cell) {
+        self.canvas.scroll_up(self.font.height(), blank.background.rgb());
+        self.cursor = match self.cursor {
+            Some((column, row)) if row > 0 => Some((column, row - 1)),
+            _ => None,
+        };
+    }
+
+    fn move_cursor(&mut self, column: usize, row: usize) {
+        self.hide_cursor();
+        self.invert_cursor(column, row);
+        self.cursor = Some((column, row));
+    }
+
+    fn hide_cursor(&mut self) {
+        if let Some((column, row)) = self.cursor.take() {
+            self.invert_cursor(column, row);
+        }
+    }
+}
+
+/// Console on the framebuffer, understanding the ANSI escape sequences of `ansi`.
+pub struct FramebufferConsole {
+    terminal: Terminal,
+    screen: Screen,
+}
+
+impl FramebufferConsole {
+    pub fn new(canvas: Canvas<'static>, font: Font<'static>) -> Self {
+        let columns = canvas.width() / font.width();
+        let rows = canvas.height() / font.height();
+        let screen = Screen { canvas, font, columns, rows, cursor: None };
+        let mut console = FramebufferConsole { terminal: Terminal::new(), screen };
+        console.clear_screen();
+        console
+    }
+
+    pub fn canvas(&mut self) -> &mut Canvas<'static> {
+        &mut self.screen.canvas
+    }
+
+    /// The size of the console in characters.
+    pub fn size(&self) -> (usize, usize) {
+        self.screen.size()
+    }
+
+    pub fn write_char(&mut self, character: char) {
+        self.terminal.write_char(&mut self.screen, character);
+        self.screen.canvas.present();
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        self.terminal.write_str(&mut self.screen, s);
+        self.screen.canvas.present();
+    }
+
+    /// Run `f` with the character cells of the console, bypassing its terminal emulation, e.g.
+    /// to show a virtual terminal.
+    pub fn with_display<F: FnOnce(&mut dyn Display) -> R, R>(&mut self, f: F) -> R {
+        let result = f(&mut self.screen);
+        self.screen.canvas.present();
+        result
+    }
+
+    pub fn clear_screen(&mut self) {
+        let background = self.terminal.attributes().colors().1;
+        self.screen.canvas.clear(background.rgb());
+        self.screen.cursor = None;
+        self.terminal.clear(&mut self.screen);
+        self.screen.canvas.present();
+    }
+}
+
+impl fmt::Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+pub static CONSOLE: Mutex<Option<FramebufferConsole>> = Mutex::new(None);
+
+/// Map the framebuffer at `base` write-combining, allocate a back buffer of the same size and
+/// start the console on them.
+pub unsafe fn init(base: PhysAddr, info: FramebufferInfo) {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | paging::WRITE_COMBINING;
+    let virt = paging::map_physical_region(base, info.size() as u64, flags);
+    let front = slice::from_raw_parts_mut(virt.as_u64() as *mut u8, info.size());
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for offset in (0..info.size()).step_by(PAGE_SIZE) {
+        paging::map(VirtAddr::new(FRAMEBUFFER_BACK_BUFFER + offset as u64), flags);
+    }
+    let back = slice::from_raw_parts_mut(FRAMEBUFFER_BACK_BUFFER as *mut u8, info.size());
+
+    let canvas = Canvas::new(Surface::new(back, info), Surface::new(front, info));
+    let console = FramebufferConsole::new(canvas, psf::default_font());
+    let (columns, rows) = console.size();
+    interrupt::run_without_interrupt(|| *CONSOLE.lock() = Some(console));
+    info!("Framebuffer console: {}x{} pixels at {:#x}, {}x{} characters", info.width,
+          info.height, base.as_u64(), columns, rows);
+}
+
+/// Run `f` with the canvas of the console, returning `None` if there is no framebuffer. Call
+/// `Canvas::present` to show the drawing.
+pub fn with_canvas<F: FnOnce(&mut Canvas<'static>) -> R, R>(f: F) -> Option<R> {
+    interrupt::run_without_interrupt(|| {
+        CONSOLE.lock().as_mut().map(|console| f(console.canvas()))
+    })
+}
+
+pub fn is_enabled() -> bool {
+    interrupt::run_without_interrupt(|| CONSOLE.lock().is_some())
+}
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    interrupt::run_without_interrupt(|| {
+        if let Some(console) = CONSOLE.lock().as_mut() {
+            console.write_fmt(args).unwrap();
+        }
+    })
+}
+
+pub struct FramebufferSink;
+
+impl crate::klog::LogSink for FramebufferSink {
+    fn write_str(&self, s: &str) {
+        if let Some(console) = CONSOLE.lock().as_mut() {
+            console.write_string(s);
+        }
+    }
+}
+
+pub static FRAMEBUFFER_SINK: FramebufferSink = FramebufferSink;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ailurus_core::ansi::AnsiColor;
+    use ailurus_core::framebuffer::PixelFormat;
+
+    const WIDTH: usize = 4 * 8;
+    const HEIGHT: usize = 3 * 16;
+    static mut BACK: [u8; WIDTH * HEIGHT * 4] = [0; WIDTH * HEIGHT * 4];
+    static mut FRONT: [u8; WIDTH * HEIGHT * 4] = [0; WIDTH * HEIGHT * 4];
+
+    fn console() -> FramebufferConsole {
+        let info = FramebufferInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            pitch: WIDTH * 4,
+            format: PixelFormat::XRGB8888,
+        };
+        let canvas = unsafe {
+            Canvas::new(Surface::new(&mut BACK, info), Surface::new(&mut FRONT, info))
+        };
+        FramebufferConsole::new(canvas, psf::default_font())
+    }
+
+    /// Whether the cell at `column` and `row` shows `character` in `foreground` on `background`,
+    /// ignoring the cursor.
+    fn shows_in(console: &FramebufferConsole, column: usize, row: usize, character: char,
+                foreground: AnsiColor, background: AnsiColor) -> bool {
+        let glyph = console.screen.font.glyph(character);
+        (0..glyph.height() - CURSOR_HEIGHT).all(|y| (0..glyph.width()).all(|x| {
+            let pixel = console.screen.canvas.pixel(column * 8 + x, row * 16 + y);
+            let color = if glyph.is_set(x, y) { foreground } else { background };
+            pixel == color.rgb()
+        }))
+    }
+
+    fn shows(console: &FramebufferConsole, column: usize, row: usize, character: char) -> bool {
+        shows_in(console, column, row, character, AnsiColor::WHITE, AnsiColor::BLACK)
+    }
+
+    #[test_case]
+    fn console_wraps_and_scrolls() {
+        let mut console = console();
+        assert_eq!(console.size(), (4, 3));
+        console.write_string("abcdé\nx\x08y");
+        assert!(shows(&console, 3, 0, 'd'));
+        assert!(shows(&console, 0, 1, 'é'));
+        assert!(shows(&console, 0, 2, 'y'));
+
+        console.write_string("\nz");
+        assert!(shows(&console, 0, 0, 'é'));
+        assert!(shows(&console, 0, 1, 'y'));
+        assert!(shows(&console, 0, 2, 'z'));
+        assert!(shows(&console, 1, 2, ' '));
+    }
+
+    #[test_case]
+    fn console_understands_escape_sequences() {
+        let mut console = console();
+        console.write_string("ab\x1b[1;34;43mc\x1b[0m\x1b[Hd\x1b[2;2He\x1b[K");
+        assert!(shows(&console, 0, 0, 'd'));
+        assert!(shows_in(&console, 2, 0, 'c', AnsiColor::BLUE.bright(), AnsiColor::YELLOW));
+        assert!(shows(&console, 1, 1, 'e'));
+        assert!(shows(&console, 2, 1, ' '));
+    }
+}
@@ -0,0 +1,253 @@
+//! Intel 8042 PS/2 controller, with the keyboard on the first port and the mouse on the second.
+//!
+//! `init` resets the controller and both devices and enables their interrupts. Afterwards the
+//! interrupt handlers read the data port, so the commands here disable interrupts and poll for
+//! the responses instead.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::super::interrupt;
+use super::super::platform::port::UnsafePort;
+
+const DATA_PORT: u16 = 0x60;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND_PORT: u8 = 0xa7;
+const ENABLE_SECOND_PORT: u8 = 0xa8;
+const TEST_SECOND_PORT: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST_PORT: u8 = 0xab;
+const DISABLE_FIRST_PORT: u8 = 0xad;
+const ENABLE_FIRST_PORT: u8 = 0xae;
+const WRITE_SECOND_PORT: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const CONFIG_FIRST_INTERRUPT: u8 = 1 << 0;
+const CONFIG_SECOND_INTERRUPT: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+/// The controller translates set 2 scancodes to set 1 while this configuration bit is set.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const DEVICE_RESET: u8 = 0xff;
+const DEVICE_RESET_PASSED: u8 = 0xaa;
+pub const ACKNOWLEDGE: u8 = 0xfa;
+
+const POLL_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// The keyboard.
+    First,
+    /// The mouse.
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerError {
+    /// The controller or the device did not respond.
+    Timeout,
+    /// The device answered a command with something else than an acknowledgement.
+    NotAcknowledged(u8),
+    SelfTestFailed(u8),
+    PortTestFailed(Port, u8),
+    /// The port does not exist or failed to initialize.
+    NoDevice(Port),
+}
+
+static FIRST_PORT: AtomicBool = AtomicBool::new(false);
+static SECOND_PORT: AtomicBool = AtomicBool::new(false);
+
+/// Reset the controller and the devices, leaving the keyboard in scancode set 1.
+pub fn init() {
+    let result = interrupt::run_without_interrupt(|| unsafe { initialize() });
+    match result {
+        Ok(()) => info!("PS/2 controller: keyboard {}, mouse {}", is_available(Port::First),
+                        is_available(Port::Second)),
+        Err(error) => warn!("PS/2 controller initialization failed: {:?}", error),
+    }
+}
+
+unsafe fn initialize() -> Result<(), ControllerError> {
+    write_command(DISABLE_FIRST_PORT)?;
+    write_command(DISABLE_SECOND_PORT)?;
+    flush();
+
+    let config = read_config()? & !(CONFIG_FIRST_INTERRUPT | CONFIG_SECOND_INTERRUPT |
+                                     CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    write_command(SELF_TEST)?;
+    match read_data()? {
+        SELF_TEST_PASSED => {}
+        result => return Err(ControllerError::SelfTestFailed(result)),
+    }
+    // The self test may reset the controller.
+    write_config(config)?;
+
+    // Enabling the second port clears its clock bit only on dual-port controllers.
+    let mut dual = false;
+    if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+        write_command(ENABLE_SECOND_PORT)?;
+        dual = read_config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        write_command(DISABLE_SECOND_PORT)?;
+    }
+
+    let mut config = config | CONFIG_TRANSLATION;
+    if test_port(Port::First)? {
+        write_command(ENABLE_FIRST_PORT)?;
+        if reset_device(Port::First) {
+            FIRST_PORT.store(true, Ordering::SeqCst);
+            config |= CONFIG_FIRST_INTERRUPT;
+        }
+    }
+    if dual && test_port(Port::Second)? {
+        write_command(ENABLE_SECOND_PORT)?;
+        if reset_device(Port::Second) {
+            SECOND_PORT.store(true, Ordering::SeqCst);
+            config |= CONFIG_SECOND_INTERRUPT;
+        }
+    }
+    write_config(config)
+}
+
+unsafe fn test_port(port: Port) -> Result<bool, ControllerError> {
+    write_command(match port {
+        Port::First => TEST_FIRST_PORT,
+        Port::Second => TEST_SECOND_PORT,
+    })?;
+    match read_data()? {
+        PORT_TEST_PASSED => Ok(true),
+        result => {
+            warn!("{:?}", ControllerError::PortTestFailed(port, result));
+            Ok(false)
+        }
+    }
+}
+
+unsafe fn reset_device(port: Port) -> bool {
+    let passed = send_unchecked(port, DEVICE_RESET).is_ok() &&
+        read_data() == Ok(DEVICE_RESET_PASSED);
+    // A mouse also sends its ID.
+    flush();
+    passed
+}
+
+/// Whether a device is connected to `port`.
+pub fn is_available(port: Port) -> bool {
+    match port {
+        Port::First => FIRST_PORT.load(Ordering::SeqCst),
+        Port::Second => SECOND_PORT.load(Ordering::SeqCst),
+    }
+}
+
+/// Send `bytes` to the device on `port`, waiting for an acknowledgement of each.
+pub fn send(port: Port, bytes: &[u8]) -> Result<(), ControllerError> {
+    if !is_available(port) {
+        return Err(ControllerError::NoDevice(port));
+    }
+    interrupt::run_without_interrupt(|| unsafe {
+        // Drop pending input, it would be taken as the response.
+        flush();
+        for &byte in bytes {
+            send_unchecked(port, byte)?;
+        }
+        Ok(())
+    })
+}
+
+/// Send a byte to the device on `port` without waiting for the acknowledgement, which arrives
+/// through the interrupt handler. Used from interrupt context.
+pub fn write(port: Port, byte: u8) -> Result<(), ControllerError> {
+    if !is_available(port) {
+        return Err(ControllerError::NoDevice(port));
+    }
+    interrupt::run_without_interrupt(|| unsafe { write_device(port, byte) })
+}
+
+/// Turn the translation of scancode set 2 to set 1 on or off.
+pub fn set_translation(enabled: bool) -> Result<(), ControllerError> {
+    interrupt::run_without_interrupt(|| unsafe {
+        flush();
+        let config = read_config()?;
+        write_config(if enabled {
+            config | CONFIG_TRANSLATION
+        } else {
+            config & !CONFIG_TRANSLATION
+        })
+    })
+}
+
+unsafe fn send_unchecked(port: Port, byte: u8) -> Result<(), ControllerError> {
+    write_device(port, byte)?;
+    match read_data()? {
+        ACKNOWLEDGE => Ok(()),
+        response => Err(ControllerError::NotAcknowledged(response)),
+    }
+}
+
+unsafe fn write_device(port: Port, byte: u8) -> Result<(), ControllerError> {
+    if port == Port::Second {
+        write_command(WRITE_SECOND_PORT)?;
+    }
+    write_port(DATA_PORT, byte)
+}
+
+unsafe fn read_config() -> Result<u8, ControllerError> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+unsafe fn write_config(config: u8) -> Result<(), ControllerError> {
+    write_command(WRITE_CONFIG)?;
+    write_port(DATA_PORT, config)
+}
+
+unsafe fn write_command(command: u8) -> Result<(), ControllerError> {
+    write_port(COMMAND_PORT, command)
+}
+
+unsafe fn read_status() -> u8 {
+    UnsafePort::new(COMMAND_PORT).read()
+}
+
+unsafe fn flush() {
+    while read_status() & STATUS_OUTPUT_FULL != 0 {
+        UnsafePort::<u8>::new(DATA_PORT).read();
+    }
+}
+
+unsafe fn write_port(port: u16, value: u8) -> Result<(), ControllerError> {
+    for _ in 0..POLL_LIMIT {
+        if read_status() & STATUS_INPUT_FULL == 0 {
+            UnsafePort::new(port).write(value);
+            return Ok(());
+        }
+    }
+    Err(ControllerError::Timeout)
+}
+
+unsafe fn read_data() -> Result<u8, ControllerError> {
+    for _ in 0..POLL_LIMIT {
+        if read_status() & STATUS_OUTPUT_FULL != 0 {
+            return Ok(UnsafePort::new(DATA_PORT).read());
+        }
+    }
+    Err(ControllerError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn qemu_has_keyboard_and_mouse() {
+        assert!(is_available(Port::First));
+        assert!(is_available(Port::Second));
+    }
+}
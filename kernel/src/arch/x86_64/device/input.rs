@@ -0,0 +1,183 @@
+//! Input event layer, similar to Linux' evdev.
+//!
+//! Input drivers register a device and publish timestamped events tagged with its `DeviceId`.
+//! Every `Subscriber` receives all events published after it subscribed, so a shell, a GUI and a
+//! test can each consume the input independently:
+//!
+//! ```ignore
+//! let subscriber = input::subscribe().unwrap();
+//! loop {
+//!     match subscriber.read().kind {
+//!         EventKind::MouseMove { dx, dy } => cursor.move_by(dx, dy),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! Events are kept in one ring buffer; a subscriber falling more than `BROADCAST_CAPACITY`
+//! events behind loses the oldest ones.
+
+use ailurus_core::broadcast::{Broadcast, BROADCAST_CAPACITY};
+use ailurus_core::ps2::MouseButtons;
+use spin::Mutex;
+use super::super::interrupt;
+use super::super::platform::instructions;
+use super::keyboard::{KeyCode, KeyEvent, KeyState, Modifiers};
+use super::pit;
+
+pub use ailurus_core::broadcast::MAX_SUBSCRIBERS;
+
+pub const MAX_DEVICES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Keyboard,
+    Mouse,
+    SerialConsole,
+    /// Events injected by software, e.g. by tests.
+    Virtual,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputDevice {
+    pub id: DeviceId,
+    pub kind: DeviceKind,
+    pub name: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Key(KeyEvent),
+    /// Relative mouse movement, with `dy` growing downwards.
+    MouseMove { dx: i16, dy: i16 },
+    MouseButton { button: MouseButtons, state: KeyState },
+    /// A character typed on a terminal, e.g. over the serial console.
+    Character(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Time since boot in microseconds.
+    pub timestamp: u64,
+    pub device: DeviceId,
+    pub kind: EventKind,
+}
+
+impl InputEvent {
+    const EMPTY: InputEvent = InputEvent {
+        timestamp: 0,
+        device: DeviceId(0),
+        kind: EventKind::Key(KeyEvent {
+            code: KeyCode::Escape,
+            state: KeyState::Up,
+            character: None,
+            modifiers: Modifiers::NONE,
+        }),
+    };
+}
+
+static DEVICES: Mutex<[Option<InputDevice>; MAX_DEVICES]> = Mutex::new([None; MAX_DEVICES]);
+static EVENTS: Mutex<Broadcast<InputEvent>> =
+    Mutex::new(Broadcast::new([InputEvent::EMPTY; BROADCAST_CAPACITY]));
+
+/// Register an input device, returning `None` if there are already `MAX_DEVICES`.
+pub fn register_device(kind: DeviceKind, name: &'static str) -> Option<DeviceId> {
+    interrupt::run_without_interrupt(|| {
+        let mut devices = DEVICES.lock();
+        let index = devices.iter().position(|device| device.is_none())?;
+        let id = DeviceId(index as u16);
+        devices[index] = Some(InputDevice { id, kind, name });
+        Some(id)
+    })
+}
+
+pub fn device(id: DeviceId) -> Option<InputDevice> {
+    interrupt::run_without_interrupt(|| {
+        DEVICES.lock().get(id.0 as usize).and_then(|&device| device)
+    })
+}
+
+/// Call `f` for each registered device.
+pub fn for_each_device<F: FnMut(&InputDevice)>(mut f: F) {
+    let devices = interrupt::run_without_interrupt(|| *DEVICES.lock());
+    devices.iter().filter_map(|device| device.as_ref()).for_each(|device| f(device));
+}
+
+/// Deliver an event of `device` to all subscribers. Called by the drivers' interrupt handlers,
+/// and by anything else which wants to inject input.
+pub fn publish(device: DeviceId, kind: EventKind) {
+    let event = InputEvent { timestamp: pit::uptime_micros(), device, kind };
+    interrupt::run_without_interrupt(|| EVENTS.lock().publish(event));
+}
+
+/// Start receiving input events, returning `None` if there are already `MAX_SUBSCRIBERS`.
+pub fn subscribe() -> Option<Subscriber> {
+    interrupt::run_without_interrupt(|| EVENTS.lock().subscribe())
+        .map(|index| Subscriber { index })
+}
+
+/// A consumer of input events. Dropping it unsubscribes.
+pub struct Subscriber {
+    index: usize,
+}
+
+impl Subscriber {
+    /// Take the next event without waiting.
+    pub fn try_read(&self) -> Option<InputEvent> {
+        interrupt::run_without_interrupt(|| EVENTS.lock().pop(self.index))
+    }
+
+    /// Wait for the next event.
+    pub fn read(&self) -> InputEvent {
+        loop {
+            if let Some(event) = self.try_read() {
+                return event;
+            }
+            unsafe { instructions::hlt(); }
+        }
+    }
+
+    /// Number of events lost since the last call because this subscriber fell behind.
+    pub fn take_dropped(&self) -> usize {
+        interrupt::run_without_interrupt(|| EVENTS.lock().take_dropped(self.index))
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        interrupt::run_without_interrupt(|| EVENTS.lock().unsubscribe(self.index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn subscribers_receive_published_events() {
+        let id = register_device(DeviceKind::Virtual, "test").unwrap();
+        assert_eq!(device(id).map(|device| device.kind), Some(DeviceKind::Virtual));
+
+        let first = subscribe().unwrap();
+        publish(id, EventKind::Character('a'));
+        let second = subscribe().unwrap();
+        publish(id, EventKind::Character('b'));
+
+        let kinds = |subscriber: &Subscriber| {
+            let mut kinds = [None; 3];
+            for kind in kinds.iter_mut() {
+                // Ignore real input arriving during the test.
+                *kind = core::iter::repeat_with(|| subscriber.try_read())
+                    .find(|event| event.map_or(true, |event| event.device == id))
+                    .and_then(|event| event.map(|event| event.kind));
+            }
+            kinds
+        };
+        assert_eq!(kinds(&first), [Some(EventKind::Character('a')),
+                                   Some(EventKind::Character('b')), None]);
+        assert_eq!(kinds(&second), [Some(EventKind::Character('b')), None, None]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Keyboard input.
+//!
+//! The keyboard interrupt handler decodes scancodes and queues a `KeyEvent` for every key press
+//! and release, separately for each virtual terminal: keys go to the one in the foreground (see
+//! `console`). Consumers take them with `try_read_key_from`/`read_key_from`, or read whole lines
+//! with `read_line`, which also echoes the input. `try_read_key` and `read_key` read the keys of
+//! the kernel terminal.
+//!
+//! The layout is chosen with `keyboard.layout=us|uk|dvorak|azerty|jis|custom` on the kernel
+//! command line, or at runtime with `set_layout` and `load_keymap`. `keyboard.scancodes=2` or
+//! `set_scancode_set` switches to scancode set 2.
+
+use ailurus_core::keyboard::scancode::ScancodeDecoder;
+use ailurus_core::ps2;
+use ailurus_core::ring_queue::{RingQueue, RING_QUEUE_CAPACITY};
+use core::str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::super::debug::lockdep::Mutex;
+use super::super::interrupt;
+use super::super::platform::instructions;
+use super::console::{self, KERNEL_TERMINAL, TERMINAL_COUNT};
+use super::i8042::{self, ControllerError, Port};
+use super::input::{self, DeviceId, DeviceKind, EventKind};
+
+pub use ailurus_core::keyboard::{KeyCode, KeyState, Modifiers};
+pub use ailurus_core::keyboard::keymap::{Keymap, KeymapError, Layout};
+pub use ailurus_core::keyboard::scancode::ScancodeSet;
+
+/// The keymap selected by `keyboard.layout=custom`, see `build.rs`.
+const CUSTOM_KEYMAP: &str = include_str!(concat!(env!("OUT_DIR"), "/custom.keymap"));
+
+const SET_LEDS: u8 = 0xed;
+const SET_SCANCODE_SET: u8 = 0xf0;
+const SET_TYPEMATIC: u8 = 0xf3;
+const DEFAULT_REPEAT_DELAY: u32 = 500;
+const DEFAULT_REPEAT_RATE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// The character typed by a key press, if any.
+    pub character: Option<char>,
+    /// The modifier state after the event.
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    const EMPTY: KeyEvent = KeyEvent {
+        code: KeyCode::Escape,
+        state: KeyState::Up,
+        character: None,
+        modifiers: Modifiers::NONE,
+    };
+}
+
+/// The key events of each virtual terminal.
+static EVENTS: [RingQueue<KeyEvent>; TERMINAL_COUNT] = [
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+    RingQueue::new([KeyEvent::EMPTY; RING_QUEUE_CAPACITY]),
+];
+static MODIFIERS: AtomicUsize = AtomicUsize::new(0);
+/// The input device ID plus one, or 0 before `init`.
+static DEVICE: AtomicUsize = AtomicUsize::new(0);
+
+struct Keyboard {
+    decoder: ScancodeDecoder,
+    keymap: Keymap,
+    /// LED state to send once the keyboard acknowledges the "set LEDs" command.
+    pending_leds: Option<u8>,
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard> = Mutex::new("KEYBOARD", Keyboard {
+        decoder: ScancodeDecoder::new(ScancodeSet::Set1),
+        keymap: Layout::Us.keymap(),
+        pending_leds: None,
+    });
+}
+
+/// Apply the `keyboard.*` options of the kernel command line: `layout`, `scancodes`, and
+/// `repeat_delay` and `repeat_rate` in milliseconds and characters per second. Called after
+/// `i8042::init`.
+pub fn init() {
+    if !i8042::is_available(Port::First) {
+        return;
+    }
+    if let Some(DeviceId(id)) = input::register_device(DeviceKind::Keyboard, "PS/2 keyboard") {
+        DEVICE.store(id as usize + 1, Ordering::SeqCst);
+    }
+    match crate::cmdline::get("keyboard.layout") {
+        Some("custom") => {
+            if let Err(error) = load_keymap(CUSTOM_KEYMAP) {
+                warn!("Invalid custom keymap: {:?}", error);
+            }
+        }
+        Some(name) => match Layout::from_name(name) {
+            Some(layout) => set_layout(layout),
+            None => warn!("Unknown keyboard layout {}", name),
+        },
+        None => {}
+    }
+    if let Some(number) = crate::cmdline::get("keyboard.scancodes") {
+        match number.parse().ok().and_then(ScancodeSet::from_number) {
+            Some(set) => {
+                if let Err(error) = set_scancode_set(set) {
+                    warn!("Cannot switch to scancode {:?}: {:?}", set, error);
+                }
+            }
+            None => warn!("Unsupported scancode set {}", number),
+        }
+    }
+
+    let option = |key, default| {
+        crate::cmdline::get(key).and_then(|value| value.parse().ok()).unwrap_or(default)
+    };
+    let delay = option("keyboard.repeat_delay", DEFAULT_REPEAT_DELAY);
+    let rate = option("keyboard.repeat_rate", DEFAULT_REPEAT_RATE);
+    if let Err(error) = set_typematic(delay, rate) {
+        warn!("Cannot set the keyboard repeat rate: {:?}", error);
+    }
+    if let Err(error) = i8042::send(Port::First, &[SET_LEDS, modifiers().leds()]) {
+        warn!("Cannot set the keyboard LEDs: {:?}", error);
+    }
+}
+
+/// Switch to a built-in layout.
+pub fn set_layout(layout: Layout) {
+    let keymap = layout.keymap();
+    interrupt::run_without_interrupt(|| KEYBOARD.lock().keymap = keymap);
+}
+
+/// Switch to a keymap in the format described in `ailurus_core::keyboard::keymap`, applied on
+/// top of the US layout. The current keymap is kept if `source` is invalid.
+pub fn load_keymap(source: &str) -> Result<(), KeymapError> {
+    let keymap = Keymap::parse(source)?;
+    interrupt::run_without_interrupt(|| KEYBOARD.lock().keymap = keymap);
+    Ok(())
+}
+
+/// Make the keyboard send scancodes of `set`.
+///
+/// Keyboards use set 2 by default and the controller translates it to set 1, so this only turns
+/// the translation on or off after making sure the keyboard uses set 2.
+pub fn set_scancode_set(set: ScancodeSet) -> Result<(), ControllerError> {
+    interrupt::run_without_interrupt(|| {
+        let mut keyboard = KEYBOARD.lock();
+        i8042::send(Port::First, &[SET_SCANCODE_SET, 2])?;
+        i8042::set_translation(set == ScancodeSet::Set1)?;
+        keyboard.decoder = ScancodeDecoder::new(set);
+        Ok(())
+    })
+}
+
+/// Repeat held keys `rate` times per second after `delay_ms`, rounded to what the keyboard
+/// supports.
+pub fn set_typematic(delay_ms: u32, rate: u32) -> Result<(), ControllerError> {
+    i8042::send(Port::First, &[SET_TYPEMATIC, ps2::typematic_byte(delay_ms, rate)])
+}
+
+/// Decode a scancode and queue the resulting event. Only called by the keyboard interrupt
+/// handler.
+pub fn handle_scancode(scancode: u8) {
+    let keyboard = &mut *KEYBOARD.lock();
+    if scancode == i8042::ACKNOWLEDGE {
+        if let Some(leds) = keyboard.pending_leds.take() {
+            let _ = i8042::write(Port::First, leds);
+        }
+        return;
+    }
+    let (code, state) = match keyboard.decoder.add_byte(scancode) {
+        Some(event) => event,
+        None => return,
+    };
+
+    let mut modifiers = modifiers();
+    if modifiers.update(code, state) {
+        let previous = Modifiers::from_bits_truncate(
+            MODIFIERS.swap(modifiers.bits() as usize, Ordering::SeqCst) as u16);
+        // The keyboard does not turn its LEDs on by itself. Waiting for its acknowledgement
+        // here would race with the scancodes, so the LED state is sent when it arrives.
+        if previous.leds() != modifiers.leds() && i8042::write(Port::First, SET_LEDS).is_ok() {
+            keyboard.pending_leds = Some(modifiers.leds());
+        }
+    }
+    let character = match state {
+        KeyState::Down => keyboard.keymap.character(code, modifiers),
+        KeyState::Up => None,
+    };
+
+    let event = KeyEvent { code, state, character, modifiers };
+    if console::handle_key(&event) {
+        return;
+    }
+    // The interrupt handler is the only producer. Keys typed while the queue is full are lost.
+    let _ = unsafe { EVENTS[console::active()].push(event) };
+    match DEVICE.load(Ordering::SeqCst) {
+        0 => {}
+        id => input::publish(DeviceId(id as u16 - 1), EventKind::Key(event)),
+    }
+}
+
+/// The current modifier state.
+pub fn modifiers() -> Modifiers {
+    Modifiers::from_bits_truncate(MODIFIERS.load(Ordering::SeqCst) as u16)
+}
+
+/// Take the next key event of the kernel terminal without waiting.
+pub fn try_read_key() -> Option<KeyEvent> {
+    try_read_key_from(KERNEL_TERMINAL)
+}
+
+/// Wait for the next key event of the kernel terminal.
+pub fn read_key() -> KeyEvent {
+    read_key_from(KERNEL_TERMINAL)
+}
+
+/// Take the next key event typed while `terminal` was in the foreground, without waiting.
+pub fn try_read_key_from(terminal: usize) -> Option<KeyEvent> {
+    EVENTS[terminal].pop()
+}
+
+/// Wait for the next key event typed while `terminal` was in the foreground.
+pub fn read_key_from(terminal: usize) -> KeyEvent {
+    loop {
+        if let Some(event) = try_read_key_from(terminal) {
+            return event;
+        }
+        // A key arriving right before `hlt` is only noticed on the next timer tick.
+        unsafe { instructions::hlt(); }
+    }
+}
+
+/// Wait for the next character typed on the kernel terminal.
+pub fn read_char() -> char {
+    loop {
+        if let KeyEvent { state: KeyState::Down, character: Some(character), .. } = read_key() {
+            return character;
+        }
+    }
+}
+
+/// Line editing on top of the key events of a terminal: typed characters are echoed and appended
+/// to the buffer, Backspace removes the last one and Enter completes the line. Characters which
+/// do not fit in the buffer are dropped.
+pub struct LineReader<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    complete: bool,
+    terminal: usize,
+}
+
+impl<'a> LineReader<'a> {
+    /// Read a line from the kernel terminal.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        LineReader::with_terminal(buffer, KERNEL_TERMINAL)
+    }
+
+    pub fn with_terminal(buffer: &'a mut [u8], terminal: usize) -> Self {
+        LineReader { buffer, len: 0, complete: false, terminal }
+    }
+
+    /// Process the queued key events, returning true once the line is complete.
+    pub fn poll(&mut self) -> bool {
+        while !self.complete {
+            match try_read_key_from(self.terminal) {
+                Some(event) => self.handle_event(event),
+                None => break,
+            }
+        }
+        self.complete
+    }
+
+    /// The line read so far, without the final newline.
+    pub fn line(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+
+    fn handle_event(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent { state: KeyState::Down, character: Some('\n'), .. } => {
+                self.complete = true;
+                console::print_to(self.terminal, format_args!("\n"));
+            }
+            KeyEvent { state: KeyState::Down, character: Some('\x08'), .. } => {
+                // Remove a whole UTF-8 sequence.
+                if let Some(last) = self.line().chars().next_back() {
+                    self.len -= last.len_utf8();
+                    console::print_to(self.terminal, format_args!("\x08"));
+                }
+            }
+            KeyEvent { state: KeyState::Down, character: Some(character), .. }
+                if !character.is_control() => {
+                let mut encoded = [0; 4];
+                let encoded = character.encode_utf8(&mut encoded);
+                if self.len + encoded.len() <= self.buffer.len() {
+                    self.buffer[self.len..self.len + encoded.len()]
+                        .copy_from_slice(encoded.as_bytes());
+                    self.len += encoded.len();
+                    console::print_to(self.terminal, format_args!("{}", encoded));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Wait for a line of input on `terminal` and return it without the newline.
+pub fn read_line(terminal: usize, buffer: &mut [u8]) -> &str {
+    let len = {
+        let mut reader = LineReader::with_terminal(buffer, terminal);
+        while !reader.poll() {
+            unsafe { instructions::hlt(); }
+        }
+        reader.len
+    };
+    unsafe { str::from_utf8_unchecked(&buffer[..len]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_down(character: char) -> KeyEvent {
+        KeyEvent { code: KeyCode::A, state: KeyState::Down, character: Some(character),
+                   modifiers: Modifiers::empty() }
+    }
+
+    /// The character of the Q key, with interrupts disabled like every user of `KEYBOARD`.
+    fn character_of_q() -> Option<char> {
+        interrupt::run_without_interrupt(|| {
+            KEYBOARD.lock().keymap.character(KeyCode::Q, Modifiers::empty())
+        })
+    }
+
+    #[test_case]
+    fn layouts_can_be_switched() {
+        set_layout(Layout::Azerty);
+        assert_eq!(character_of_q(), Some('a'));
+        assert!(load_keymap("NoSuchKey x").is_err());
+        assert_eq!(character_of_q(), Some('a'));
+        set_layout(Layout::Us);
+        assert_eq!(character_of_q(), Some('q'));
+    }
+
+    #[test_case]
+    fn line_reader_edits_line() {
+        let mut buffer = [0; 4];
+        let mut reader = LineReader::new(&mut buffer);
+        for &character in ['a', 'é', '\x08', 'b', 'c', 'd', 'e'].iter() {
+            reader.handle_event(key_down(character));
+        }
+        assert!(!reader.complete);
+        assert_eq!(reader.line(), "abcd");
+        reader.handle_event(key_down('\n'));
+        assert!(reader.complete);
+    }
+}
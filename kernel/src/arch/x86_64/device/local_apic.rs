@@ -0,0 +1,130 @@
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+use super::cpu;
+use super::super::interrupt;
+use super::super::memory::{PhysAddr, paging};
+use super::super::memory::page_table::PageTableFlags;
+
+const REG_END_OF_INTERRUPT: u32 = 0xb0;
+const REG_SPURIOUS_VECTOR: u32 = 0xf0;
+const REG_ICR_LOW: u32 = 0x300;
+const REG_ICR_HIGH: u32 = 0x310;
+const REG_LVT_LINT0: u32 = 0x350;
+const REG_LVT_LINT1: u32 = 0x360;
+
+const SPURIOUS_APIC_ENABLED: u32 = 1 << 8;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_DELIVERY_EXTINT: u32 = 0b111 << 8;
+
+const ICR_DELIVERY_FIXED: u32 = 0;
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+pub struct LocalApic {
+    base: u64,
+}
+
+impl LocalApic {
+    unsafe fn read(&self, register: u32) -> u32 {
+        read_volatile((self.base + register as u64) as *const u32)
+    }
+
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        write_volatile((self.base + register as u64) as *mut u32, value);
+    }
+
+    fn send_ipi(&mut self, destination: u8, command: u32) {
+        unsafe {
+            self.write(REG_ICR_HIGH, (destination as u32) << 24);
+            self.write(REG_ICR_LOW, command);
+            while self.read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {}
+        }
+    }
+
+    /// Send a non-maskable interrupt to every CPU but this one.
+    pub fn send_nmi_to_others(&mut self) {
+        self.send_ipi(0, ICR_DELIVERY_NMI | ICR_LEVEL_ASSERT | ICR_ALL_EXCLUDING_SELF);
+    }
+
+    /// Send interrupt `vector` to the CPU with local APIC id `destination`. Its handler has to
+    /// call `end_of_interrupt`.
+    pub fn send_interrupt(&mut self, destination: u8, vector: u8) {
+        self.send_ipi(destination, ICR_DELIVERY_FIXED | ICR_LEVEL_ASSERT | vector as u32);
+    }
+
+    /// Accept interrupts on the executing CPU, whose local APIC is the one at these registers.
+    pub fn enable(&mut self) {
+        unsafe {
+            self.write(REG_SPURIOUS_VECTOR, SPURIOUS_APIC_ENABLED | SPURIOUS_VECTOR as u32);
+        }
+    }
+
+    pub fn end_of_interrupt(&mut self) {
+        unsafe { self.write(REG_END_OF_INTERRUPT, 0); }
+    }
+}
+
+/// The vector of the interrupts the local APIC signals for nothing, which need no end of
+/// interrupt.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+pub static LOCAL_APIC: Mutex<Option<LocalApic>> = Mutex::new(None);
+
+/// Map the registers of the local APIC and enable it on the bootstrap processor, for the
+/// interrupts the CPUs send each other. Device interrupts are still delivered through the 8259
+/// PIC, in virtual wire mode.
+pub fn init() {
+    if !cpu::has_apic() {
+        return;
+    }
+    let (eax, edx) = cpu::get_apic_base_addr();
+    let base = ((edx as u64) << 32 | eax as u64) & 0x000f_ffff_ffff_f000;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE;
+    let virt = unsafe { paging::map_physical_region(PhysAddr::new(base), 4096, flags) };
+    let mut apic = LocalApic { base: virt.as_u64() };
+    apic.enable();
+    unsafe {
+        apic.write(REG_LVT_LINT0, LVT_DELIVERY_EXTINT);
+        apic.write(REG_LVT_LINT1, LVT_DELIVERY_NMI);
+    }
+    *LOCAL_APIC.lock() = Some(apic);
+}
+
+/// Enable the local APIC of an application processor, once `init` ran on the bootstrap one.
+pub fn init_application_processor() {
+    interrupt::run_without_interrupt(|| {
+        if let Some(apic) = LOCAL_APIC.lock().as_mut() {
+            apic.enable();
+        }
+    })
+}
+
+/// Send interrupt `vector` to the CPU with local APIC id `cpu`, if there is a local APIC.
+pub fn send_interrupt(cpu: u8, vector: u8) {
+    interrupt::run_without_interrupt(|| {
+        if let Some(apic) = LOCAL_APIC.lock().as_mut() {
+            apic.send_interrupt(cpu, vector);
+        }
+    })
+}
+
+/// Acknowledge an interrupt received through the local APIC.
+pub fn end_of_interrupt() {
+    interrupt::run_without_interrupt(|| {
+        if let Some(apic) = LOCAL_APIC.lock().as_mut() {
+            apic.end_of_interrupt();
+        }
+    })
+}
+
+/// Stop all other CPUs, they halt in the NMI handler once a panic is in progress.
+///
+/// The lock is bypassed since the panicking CPU may hold it.
+pub unsafe fn stop_other_cpus() {
+    LOCAL_APIC.force_unlock();
+    if let Some(apic) = LOCAL_APIC.lock().as_mut() {
+        apic.send_nmi_to_others();
+    }
+}
@@ -1,8 +1,23 @@
 #[macro_use]
 pub mod vga_buffer;
+pub mod framebuffer;
+pub mod console;
+#[macro_use]
+pub mod serial;
 pub mod pic;
+pub mod pit;
+pub mod i8042;
+pub mod keyboard;
+pub mod mouse;
+pub mod input;
 pub mod cpu;
+pub mod local_apic;
+pub mod qemu;
 
 pub fn init_devices() {
-    unsafe { pic::PIC_8259.lock().initialize(); }
-}
\ No newline at end of file
+    unsafe {
+        pic::PIC_8259.lock().initialize();
+        pit::initialize();
+        serial::SERIAL1.lock().initialize();
+    }
+}
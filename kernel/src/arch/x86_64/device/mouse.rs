@@ -0,0 +1,94 @@
+//! PS/2 mouse on the second port of the i8042 controller, reporting through `input`.
+
+use ailurus_core::ps2::{MouseButtons, MouseDecoder};
+use spin::Mutex;
+use super::super::interrupt;
+use super::i8042::{self, Port};
+use super::input::{self, DeviceId, DeviceKind, EventKind};
+use super::keyboard::KeyState;
+use super::pic::PIC_8259;
+
+const MOUSE_IRQ: u8 = 12;
+const CASCADE_IRQ: u8 = 2;
+const SET_DEFAULTS: u8 = 0xf6;
+const ENABLE_REPORTING: u8 = 0xf4;
+
+struct Mouse {
+    decoder: MouseDecoder,
+    buttons: MouseButtons,
+    device: Option<DeviceId>,
+}
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
+    decoder: MouseDecoder::new(),
+    buttons: MouseButtons::NONE,
+    device: None,
+});
+
+/// Start reporting mouse events. Called after `i8042::init`.
+pub fn init() {
+    if !i8042::is_available(Port::Second) {
+        return;
+    }
+    if let Err(error) = i8042::send(Port::Second, &[SET_DEFAULTS, ENABLE_REPORTING]) {
+        warn!("Cannot enable the mouse: {:?}", error);
+        return;
+    }
+    let device = input::register_device(DeviceKind::Mouse, "PS/2 mouse");
+    interrupt::run_without_interrupt(|| MOUSE.lock().device = device);
+    unsafe {
+        let mut pic = PIC_8259.lock();
+        pic.set_masked(CASCADE_IRQ, false);
+        pic.set_masked(MOUSE_IRQ, false);
+    }
+}
+
+/// Decode a byte from the mouse. Only called by the mouse interrupt handler.
+pub fn handle_byte(byte: u8) {
+    let mut mouse = MOUSE.lock();
+    let (packet, device) = match (mouse.decoder.add_byte(byte), mouse.device) {
+        (Some(packet), Some(device)) => (packet, device),
+        _ => return,
+    };
+
+    if packet.dx != 0 || packet.dy != 0 {
+        input::publish(device, EventKind::MouseMove { dx: packet.dx, dy: packet.dy });
+    }
+    let changed = mouse.buttons ^ packet.buttons;
+    for &button in [MouseButtons::LEFT, MouseButtons::RIGHT, MouseButtons::MIDDLE].iter() {
+        if changed.contains(button) {
+            let state = if packet.buttons.contains(button) { KeyState::Down } else { KeyState::Up };
+            input::publish(device, EventKind::MouseButton { button, state });
+        }
+    }
+    mouse.buttons = packet.buttons;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn packets_become_input_events() {
+        let subscriber = input::subscribe().unwrap();
+        let device = interrupt::run_without_interrupt(|| MOUSE.lock().device).unwrap();
+        interrupt::run_without_interrupt(|| {
+            for &byte in [0x29, 2, 0xfe, 0x28, 0, 0].iter() {
+                handle_byte(byte);
+            }
+        });
+        let mut kinds = [None; 4];
+        for kind in kinds.iter_mut() {
+            *kind = subscriber.try_read().map(|event| {
+                assert_eq!(event.device, device);
+                event.kind
+            });
+        }
+        assert_eq!(kinds, [
+            Some(EventKind::MouseMove { dx: 2, dy: 2 }),
+            Some(EventKind::MouseButton { button: MouseButtons::LEFT, state: KeyState::Down }),
+            Some(EventKind::MouseButton { button: MouseButtons::LEFT, state: KeyState::Up }),
+            None,
+        ]);
+    }
+}
@@ -1,4 +1,4 @@
-use spin::Mutex;
+use super::super::debug::lockdep::Mutex;
 use super::super::platform::port::UnsafePort;
 
 const CMD_INIT: u8 = 0x11;
@@ -96,6 +96,17 @@ impl ChainedPics {
         self.pics[1].data_port.write(saved_mask2);
     }
 
+    /// Mask or unmask the interrupt line `irq` (0-15).
+    pub unsafe fn set_masked(&mut self, irq: u8, masked: bool) {
+        let (pic, line) = if irq < 8 {
+            (&mut self.pics[0], irq)
+        } else {
+            (&mut self.pics[1], irq - 8)
+        };
+        let mask = pic.data_port.read();
+        pic.data_port.write(if masked { mask | 1 << line } else { mask & !(1 << line) });
+    }
+
     pub fn handles_interrupt(&self, interrupt_id: u8) -> bool {
         self.pics.iter().any(|p| p.handles_interrupt(interrupt_id))
     }
@@ -111,7 +122,7 @@ impl ChainedPics {
 }
 
 
-pub static PIC_8259: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe {
+pub static PIC_8259: Mutex<ChainedPics> =
+    Mutex::new("PIC_8259", unsafe {
         ChainedPics::new(PIC1_INTERRUPT_OFFSET, PIC2_INTERRUPT_OFFSET)
     });
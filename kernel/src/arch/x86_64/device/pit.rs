@@ -0,0 +1,43 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::super::platform::port::UnsafePort;
+
+const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+const CMD_CHANNEL0_SQUARE_WAVE: u8 = 0x36;
+
+/// Frequency of the timer interrupt after `initialize`.
+pub const TIMER_FREQUENCY: u32 = 100;
+
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Program channel 0 of the 8253/8254 PIT to fire IRQ0 `TIMER_FREQUENCY` times per second.
+pub unsafe fn initialize() {
+    let divisor = PIT_BASE_FREQUENCY / TIMER_FREQUENCY;
+    let mut command: UnsafePort<u8> = UnsafePort::new(0x43);
+    let mut channel0: UnsafePort<u8> = UnsafePort::new(0x40);
+
+    command.write(CMD_CHANNEL0_SQUARE_WAVE);
+    channel0.write(divisor as u8);
+    channel0.write((divisor >> 8) as u8);
+}
+
+/// Called from the timer interrupt handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time since boot in microseconds, with the resolution of a tick.
+pub fn uptime_micros() -> u64 {
+    ticks() as u64 * (1_000_000 / TIMER_FREQUENCY as u64)
+}
+
+/// Time since boot as (seconds, microseconds).
+pub fn uptime() -> (usize, usize) {
+    let ticks = ticks();
+    let frequency = TIMER_FREQUENCY as usize;
+    (ticks / frequency, (ticks % frequency) * (1_000_000 / frequency))
+}
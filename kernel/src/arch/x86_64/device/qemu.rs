@@ -0,0 +1,21 @@
+use super::super::platform::port::UnsafePort;
+
+/// Port of the `isa-debug-exit` device, see the `test` target in the Makefile.
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// QEMU exits with status `(code << 1) | 1`, so neither can be confused with QEMU's own errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe {
+        let mut port: UnsafePort<u32> = UnsafePort::new(ISA_DEBUG_EXIT_PORT);
+        port.write(code as u32);
+    }
+    // Only reached when the device is missing.
+    crate::panic::halt()
+}
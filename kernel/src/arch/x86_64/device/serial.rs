@@ -0,0 +1,175 @@
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use super::super::platform::port::UnsafePort;
+use super::input::{self, DeviceId, DeviceKind, EventKind};
+use super::pic::PIC_8259;
+
+pub const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
+const COM1_IRQ: u8 = 4;
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// A 16550 compatible UART.
+pub struct SerialPort {
+    data: UnsafePort<u8>,
+    interrupt_enable: UnsafePort<u8>,
+    fifo_control: UnsafePort<u8>,
+    line_control: UnsafePort<u8>,
+    modem_control: UnsafePort<u8>,
+    line_status: UnsafePort<u8>,
+}
+
+impl SerialPort {
+    pub const unsafe fn new(base: u16) -> SerialPort {
+        SerialPort {
+            data: UnsafePort::new(base),
+            interrupt_enable: UnsafePort::new(base + 1),
+            fifo_control: UnsafePort::new(base + 2),
+            line_control: UnsafePort::new(base + 3),
+            modem_control: UnsafePort::new(base + 4),
+            line_status: UnsafePort::new(base + 5),
+        }
+    }
+
+    /// Set up the port for 38400 baud, 8 data bits, no parity and one stop bit.
+    pub unsafe fn initialize(&mut self) {
+        self.interrupt_enable.write(0x00);
+
+        // Set DLAB so that the first two registers hold the baud rate divisor.
+        self.line_control.write(0x80);
+        self.data.write(0x03);
+        self.interrupt_enable.write(0x00);
+
+        // 8N1, clear DLAB
+        self.line_control.write(0x03);
+        // Enable and clear FIFO with a 14-byte threshold
+        self.fifo_control.write(0xC7);
+        // DTR, RTS and OUT2
+        self.modem_control.write(0x0B);
+    }
+
+    /// Raise an interrupt whenever a byte is received.
+    pub unsafe fn enable_receive_interrupt(&mut self) {
+        self.interrupt_enable.write(0x01);
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.send_raw(b'\r');
+                self.send_raw(b'\n');
+            }
+            _ => self.send_raw(byte),
+        }
+    }
+
+    pub fn send_raw(&mut self, byte: u8) {
+        while self.line_status() & LINE_STATUS_TRANSMIT_EMPTY == 0 {}
+        unsafe { self.data.write(byte); }
+    }
+
+    /// Return a received byte if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.line_status() & LINE_STATUS_DATA_READY != 0 {
+            Some(unsafe { self.data.read() })
+        } else {
+            None
+        }
+    }
+
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+pub static SERIAL1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(COM1) });
+
+/// Log sink writing to the first serial port.
+pub struct SerialSink;
+
+impl crate::klog::LogSink for SerialSink {
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        let _ = SERIAL1.lock().write_str(s);
+    }
+}
+
+pub static SERIAL_SINK: SerialSink = SerialSink;
+
+/// The input device ID of the serial console plus one, or 0 before `init_console_input`.
+static CONSOLE_DEVICE: AtomicUsize = AtomicUsize::new(0);
+
+/// Publish the characters received on the first serial port as input events.
+pub fn init_console_input() {
+    let id = match input::register_device(DeviceKind::SerialConsole, "COM1") {
+        Some(DeviceId(id)) => id,
+        None => return,
+    };
+    CONSOLE_DEVICE.store(id as usize + 1, Ordering::SeqCst);
+    unsafe {
+        SERIAL1.lock().enable_receive_interrupt();
+        PIC_8259.lock().set_masked(COM1_IRQ, false);
+    }
+}
+
+/// Only called by the interrupt handler of the first serial port.
+pub fn handle_console_interrupt() {
+    // Only this handler reads from COM1, and the receiver registers are independent of the
+    // transmitter which `SERIAL1` guards, so taking the lock is unnecessary and could deadlock.
+    let mut port = unsafe { SerialPort::new(COM1) };
+    let device = match CONSOLE_DEVICE.load(Ordering::SeqCst) {
+        0 => None,
+        id => Some(DeviceId(id as u16 - 1)),
+    };
+    while let Some(byte) = port.try_receive() {
+        // Terminals send CR for Enter and DEL for Backspace.
+        let character = match byte {
+            b'\r' => '\n',
+            0x7f => '\x08',
+            0x00...0x7f => byte as char,
+            // Non-ASCII input is not decoded.
+            _ => continue,
+        };
+        if let Some(device) = device {
+            input::publish(device, EventKind::Character(character));
+        }
+    }
+}
+
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::arch::x86_64::device::serial::print(format_args!($($arg)*)));
+}
+
+macro_rules! serial_println {
+    () => (serial_print!("\n"));
+    ($fmt:expr) => (serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    use super::super::interrupt;
+    interrupt::run_without_interrupt(|| {
+        SERIAL1.lock().write_fmt(args).unwrap();
+    })
+}
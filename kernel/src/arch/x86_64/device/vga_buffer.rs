@@ -1,6 +1,9 @@
 #[allow(dead_code)]
 
-use spin::Mutex;
+use ailurus_core::ansi::{AnsiColor, Attributes, Cell, Display, Terminal};
+use ailurus_core::cp437;
+use ailurus_core::utf8::Utf8Decoder;
+use super::super::debug::lockdep::Mutex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -23,13 +26,54 @@ pub enum Color {
     White = 15,
 }
 
+/// The VGA colors in the ANSI order, which swaps red and blue, and cyan and yellow.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown, Color::Blue, Color::Magenta,
+    Color::Cyan, Color::LightGray, Color::DarkGray, Color::LightRed, Color::LightGreen,
+    Color::Yellow, Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+impl Color {
+    /// The color with the attribute bits `index`, modulo 16.
+    pub fn from_index(index: u8) -> Color {
+        const COLORS: [Color; 16] = [
+            Color::Black, Color::Blue, Color::Green, Color::Cyan, Color::Red, Color::Magenta,
+            Color::Brown, Color::LightGray, Color::DarkGray, Color::LightBlue, Color::LightGreen,
+            Color::LightCyan, Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+        ];
+        COLORS[usize::from(index & 0xf)]
+    }
+}
+
+impl From<AnsiColor> for Color {
+    fn from(color: AnsiColor) -> Color {
+        ANSI_COLORS[usize::from(color.index())]
+    }
+}
+
+impl From<Color> for AnsiColor {
+    fn from(color: Color) -> AnsiColor {
+        let index = ANSI_COLORS.iter().position(|&ansi| ansi == color).unwrap();
+        AnsiColor::new(index as u8)
+    }
+}
+
+/// A foreground and a background color, as stored in the text buffer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    pub fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    pub fn foreground(self) -> Color {
+        Color::from_index(self.0)
+    }
+
+    pub fn background(self) -> Color {
+        Color::from_index(self.0 >> 4)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,114 +90,110 @@ struct Buffer {
     chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// The text buffer as seen by the terminal emulation.
+struct Screen<'a>(&'a mut Buffer);
+
+impl<'a> Display for Screen<'a> {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn put(&mut self, column: usize, row: usize, cell: Cell) {
+        let ascii_character = cp437::encode(cell.character).unwrap_or(cp437::REPLACEMENT);
+        let color_code = ColorCode::new(cell.foreground.into(), cell.background.into());
+        self.0.chars[row][column] = ScreenChar { ascii_character, color_code };
+    }
+
+    fn scroll_up(&mut self, blank: Cell) {
+        for row in 1..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let character = self.0.chars[row][col];
+                self.0.chars[row - 1][col] = character;
+            }
+        }
+        for col in 0..BUFFER_WIDTH {
+            self.put(col, BUFFER_HEIGHT - 1, blank);
+        }
+    }
+
+    fn move_cursor(&mut self, column: usize, row: usize) {
+        set_cursor_index(row as u16 * BUFFER_WIDTH as u16 + column as u16);
+    }
+
+    fn hide_cursor(&mut self) {
+        // A position beyond the end of the screen.
+        set_cursor_index((BUFFER_WIDTH * BUFFER_HEIGHT) as u16);
+    }
+}
+
+fn set_cursor_index(index: u16) {
+    unsafe {
+        asm!("push rax\n\
+              push rbx\n\
+              push rdx\n
+              mov dx,0x03d4\n
+              mov al,0x0e\n
+              out dx,al\n
+              inc dx\n
+              mov al,bh\n
+              out dx,al\n
+              dec dx\n
+              mov al,0xf\n
+              out dx,al\n
+              inc dx\n
+              mov al,bl\n
+              out dx,al\n
+              pop rdx\n
+              pop rbx\n
+              pop rax\n"
+             : :"{bx}"(index): : "intel")
+    }
+}
+
+/// Console on the VGA text buffer, understanding the ANSI escape sequences of `ansi`. Output is
+/// UTF-8 and shown in code page 437, the character set of the VGA font.
 pub struct Writer {
-    row_position: usize,
-    column_position: usize,
-    color_code: ColorCode,
+    terminal: Terminal,
+    decoder: Utf8Decoder,
     buffer: &'static mut Buffer,
 }
 
 impl Writer {
+    /// Write one byte of UTF-8 encoded output.
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            _ => {
-                let row = self.row_position;
-                let col = self.column_position;
-                self.buffer.chars[row][col] = ScreenChar{
-                    ascii_character: byte,
-                    color_code: self.color_code,
-                };
-                self.column_position += 1;
-
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line()
-                }
-            }
-        }
-        self.refresh_cursor();
+        let (terminal, buffer) = (&mut self.terminal, &mut *self.buffer);
+        self.decoder.push(byte, |character| {
+            terminal.write_char(&mut Screen(&mut *buffer), character)
+        });
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20...0x7f | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
-            }
-        }
+        self.terminal.write_str(&mut Screen(&mut *self.buffer), s);
     }
 
     pub fn clear_screen(&mut self) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                self.buffer.chars[row][col] = blank;
-            }
-        }
-        self.column_position = 0;
-        self.row_position = 0;
-        self.refresh_cursor();
-    }
-
-    fn new_line(&mut self) {
-        self.row_position += 1;
-        if self.row_position >= BUFFER_HEIGHT {
-            self.scroll_screen();
-            self.row_position = BUFFER_HEIGHT - 1;
-            self.clear_line();
-        }
-        else {
-            self.clear_line();
-        }
-        self.column_position = 0;
+        self.terminal.clear(&mut Screen(&mut *self.buffer));
     }
 
-    fn scroll_screen(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col];
-                self.buffer.chars[row - 1][col] = character;
-            }
-        }
+    /// Run `f` with the text buffer, bypassing the terminal emulation of the writer, e.g. to show
+    /// a virtual terminal.
+    pub fn with_display<F: FnOnce(&mut dyn Display) -> R, R>(&mut self, f: F) -> R {
+        f(&mut Screen(&mut *self.buffer))
     }
 
-    fn clear_line(&mut self) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[self.row_position][col] = blank;
-        }
+    /// The colors of the following output.
+    pub fn color_code(&self) -> ColorCode {
+        let (foreground, background) = self.terminal.attributes().colors();
+        ColorCode::new(foreground.into(), background.into())
     }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    fn refresh_cursor(&self) {
-        let index : u16 = self.row_position as u16 * BUFFER_WIDTH as u16 + self.column_position as u16;
-        unsafe {
-            asm!("push rax\n\
-                  push rbx\n\
-                  push rdx\n
-                  mov dx,0x03d4\n
-                  mov al,0x0e\n
-                  out dx,al\n
-                  inc dx\n
-                  mov al,bh\n
-                  out dx,al\n
-                  dec dx\n
-                  mov al,0xf\n
-                  out dx,al\n
-                  inc dx\n
-                  mov al,bl\n
-                  out dx,al\n
-                  pop rdx\n
-                  pop rbx\n
-                  pop rax\n"
-                 : :"{bx}"(index): : "intel")
-        }
+    /// Write the following output in `color_code`. The same as the SGR escape sequences.
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.terminal.set_attributes(Attributes {
+            foreground: color_code.foreground().into(),
+            background: color_code.background().into(),
+            ..Attributes::DEFAULT
+        });
     }
 }
 
@@ -167,14 +207,24 @@ impl fmt::Write for Writer {
 
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        row_position: 0,
-        column_position: 0,
-        color_code: ColorCode::new(Color::LightGray, Color::Black),
+    pub static ref WRITER: Mutex<Writer> = Mutex::new("WRITER", Writer {
+        terminal: Terminal::new(),
+        decoder: Utf8Decoder::new(),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
     });
 }
 
+/// Log sink writing to the VGA text buffer.
+pub struct VgaSink;
+
+impl crate::klog::LogSink for VgaSink {
+    fn write_str(&self, s: &str) {
+        WRITER.lock().write_string(s);
+    }
+}
+
+pub static VGA_SINK: VgaSink = VgaSink;
+
 macro_rules! print {
     ($($arg:tt)*) => ($crate::arch::x86_64::device::vga_buffer::print(format_args!($($arg)*)));
 }
@@ -188,7 +238,108 @@ macro_rules! println {
 pub fn print(args: fmt::Arguments) {
     use core::fmt::Write;
     use super::super::interrupt;
+    if super::console::is_enabled() {
+        return super::console::print(args);
+    }
+    // The text buffer is invisible in graphics modes.
+    if super::framebuffer::is_enabled() {
+        return super::framebuffer::print(args);
+    }
     interrupt::run_without_interrupt(|| {
         WRITER.lock().write_fmt(args).unwrap();
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::interrupt;
+
+    #[test_case]
+    fn write_string_stores_characters() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string("\n");
+            let row = writer.terminal.cursor().1;
+            writer.write_string("Ailurus");
+            for (col, &byte) in b"Ailurus".iter().enumerate() {
+                assert_eq!(writer.buffer.chars[row][col].ascii_character, byte);
+            }
+            assert_eq!(writer.terminal.cursor().0, 7);
+        });
+    }
+
+    #[test_case]
+    fn non_ascii_is_mapped_to_code_page_437() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string("\n");
+            let row = writer.terminal.cursor().1;
+            writer.write_string("é╬€");
+            for &byte in "ü".as_bytes() {
+                writer.write_byte(byte);
+            }
+            let chars = &writer.buffer.chars[row];
+            assert_eq!(chars[0].ascii_character, 0x82);
+            assert_eq!(chars[1].ascii_character, 0xce);
+            assert_eq!(chars[2].ascii_character, 0xfe);
+            assert_eq!(chars[3].ascii_character, 0x81);
+            assert_eq!(writer.terminal.cursor().0, 4);
+        });
+    }
+
+    #[test_case]
+    fn color_code_can_be_changed() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            let original = writer.color_code();
+            assert_eq!(original, ColorCode::new(Color::LightGray, Color::Black));
+            let color_code = ColorCode::new(Color::Yellow, Color::Blue);
+            writer.set_color_code(color_code);
+            writer.write_string("\n");
+            let row = writer.terminal.cursor().1;
+            writer.write_string("x");
+            assert_eq!(writer.buffer.chars[row][0].color_code, color_code);
+            assert_eq!(writer.color_code(), color_code);
+            writer.set_color_code(original);
+        });
+    }
+
+    #[test_case]
+    fn escape_sequences_set_colors() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string("\n");
+            let row = writer.terminal.cursor().1;
+            writer.write_string("\x1b[31;44mx\x1b[0my");
+            assert_eq!(writer.terminal.cursor().0, 2);
+            let chars = &writer.buffer.chars[row];
+            assert_eq!(chars[0].color_code, ColorCode::new(Color::Red, Color::Blue));
+            assert_eq!(chars[1].color_code, ColorCode::new(Color::LightGray, Color::Black));
+        });
+    }
+
+    #[test_case]
+    fn long_lines_wrap() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string("\n");
+            for _ in 0..BUFFER_WIDTH + 1 {
+                writer.write_byte(b'x');
+            }
+            assert_eq!(writer.terminal.cursor().0, 1);
+        });
+    }
+
+    #[test_case]
+    fn backspace_erases_previous_character() {
+        interrupt::run_without_interrupt(|| {
+            let mut writer = WRITER.lock();
+            writer.write_string("\n");
+            let row = writer.terminal.cursor().1;
+            writer.write_string("ab\x08");
+            assert_eq!(writer.terminal.cursor().0, 1);
+            assert_eq!(writer.buffer.chars[row][1].ascii_character, b' ');
+        });
+    }
+}
@@ -1,37 +1,122 @@
+use super::super::memory::user_access;
+use super::super::task::user;
 impl_handler!(divide_by_zero, frame, {
+    if frame.iret_registers.from_user_mode() {
+        user::end_faulting_task("DIVIDE BY ZERO", frame.iret_registers.rip);
+    }
     dump_interrupt_info!("DIVIDE BY ZERO", frame);
     loop{}
 });
 
+impl_handler!(debug, frame, {
+    use super::super::debug::{gdb, watchpoint};
+    use super::super::platform::instructions::{self, Dr6};
+
+    let status = instructions::read_dr6();
+    instructions::write_dr6(Dr6::empty());
+
+    let mut handled = false;
+    if status.contains(Dr6::SINGLE_STEP) {
+        handled |= gdb::handle_single_step(frame);
+    }
+    if status.intersects(Dr6::TRAPS) {
+        handled |= watchpoint::handle_debug_exception(frame, status);
+    }
+    if !handled {
+        warn!("Unexpected debug exception at 0x{:0>16X} (DR6: {:?})",
+              frame.iret_registers.rip, status);
+    }
+});
+
+impl_paranoid_handler!(non_maskable_interrupt, frame, {
+    // Sent by a panicking CPU to stop the others.
+    if crate::panic::is_panicking() {
+        crate::panic::halt();
+    }
+    warn!("NON-MASKABLE INTERRUPT at 0x{:0>16X}", frame.iret_registers.rip);
+});
+
+impl_handler!(breakpoint, frame, {
+    super::super::debug::gdb::handle_breakpoint(frame);
+});
+
+impl_handler!(invalid_opcode, frame, {
+    if frame.iret_registers.from_user_mode() {
+        user::end_faulting_task("INVALID OPCODE", frame.iret_registers.rip);
+    }
+    dump_interrupt_info!("INVALID OPCODE", frame);
+    loop{}
+});
+
+// On its own stack, as the stack in use may be the cause.
+impl_paranoid_handler_with_error_code!(double_fault, frame, {
+    dump_interrupt_info_with_error_code!("DOUBLE FAULT", frame);
+    loop{}
+});
+
+impl_handler_with_error_code!(general_protection_fault, frame, {
+    if frame.iret_registers.from_user_mode() {
+        user::end_faulting_task("GENERAL PROTECTION FAULT", frame.iret_registers.rip);
+    }
+    dump_interrupt_info_with_error_code!("GENERAL PROTECTION FAULT", frame);
+    loop{}
+});
+
+impl_handler_with_error_code!(page_fault, frame, {
+    let address = super::super::platform::instructions::read_cr2();
+    if frame.iret_registers.from_user_mode() {
+        user::end_faulting_task("PAGE FAULT", frame.iret_registers.rip);
+    }
+    // A copy from or to user memory, which fails with EFAULT instead.
+    if let Some(continuation) = user_access::fixup(frame.iret_registers.rip, address) {
+        frame.iret_registers.rip = continuation;
+        return;
+    }
+    dump_interrupt_info_with_error_code!("PAGE FAULT", frame);
+    println!("ACCESSED ADDRESS: 0x{:0>16X}", address);
+    loop{}
+});
+
 use super::super::device::pic::PIC_8259;
+use super::super::device::pit;
 use super::super::platform::port::UnsafePort;
 impl_handler!(timer, frame, {
-//    print!(".");
+    pit::tick();
     PIC_8259.lock().notify_end_of_interrupt(32);
+    // May switch to another task, so the end of interrupt has to come first.
+    super::super::task::tick();
+});
+
+use super::super::device::local_apic;
+impl_handler!(reschedule, frame, {
+    local_apic::end_of_interrupt();
+    // May switch to another task, so the end of interrupt has to come first.
+    super::super::task::handle_reschedule_interrupt();
 });
 
-use pc_keyboard::{Keyboard, ScancodeSet1, DecodedKey, layouts};
-use spin::Mutex;
-lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-        Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1));
-}
+// Needs no end of interrupt.
+impl_handler!(spurious, frame, {});
 
+use super::super::device::keyboard;
 impl_handler!(keyboard, frame, {
-    unsafe {
-        let mut port = UnsafePort::new(0x60);
-        let scancode: u8 = port.read();
-
-        let mut keyboard = KEYBOARD.lock();
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
-            }
-        }
-    }
+    let scancode: u8 = UnsafePort::new(0x60).read();
+    keyboard::handle_scancode(scancode);
     PIC_8259.lock().notify_end_of_interrupt(33);
 });
 
+use super::super::device::mouse;
+impl_handler!(mouse, frame, {
+    let byte: u8 = UnsafePort::new(0x60).read();
+    mouse::handle_byte(byte);
+    PIC_8259.lock().notify_end_of_interrupt(44);
+});
+
+impl_handler!(serial1, frame, {
+    super::super::device::serial::handle_console_interrupt();
+    PIC_8259.lock().notify_end_of_interrupt(36);
+});
+
+impl_handler!(serial2, frame, {
+    super::super::debug::gdb::handle_serial_interrupt(frame);
+    PIC_8259.lock().notify_end_of_interrupt(35);
+});
@@ -1,5 +1,6 @@
 use super::super::platform::segmentation::{SegmentSelector, PrivilegeLevel, get_cs};
 use super::handler;
+pub use ailurus_core::idt::IdtEntryOption;
 
 pub const INT_COUNT: usize = 256;
 
@@ -57,46 +58,37 @@ impl IdtEntry {
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct IdtEntryOption(u16);
-
-impl IdtEntryOption {
-    const fn minimal() -> Self {
-        IdtEntryOption(1 << 9 | 1 << 10 | 1 << 11)
-    }
-
-    fn new() -> Self {
-        let mut options = Self::minimal();
-        options.set_present(true).enable_interrupt(false);
-        options
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::platform::gdt;
+    use super::super::super::syscall;
 
-    pub fn set_present(&mut self, present: bool) -> &mut Self {
-        if present {
-            self.0 |= 1 << 15;
-        } else {
-            self.0 &= 0x7fff;
-        }
-        self
+    fn handler_address(entry: &IdtEntry) -> u64 {
+        entry.pointer_low as u64 | (entry.pointer_middle as u64) << 16 |
+            (entry.pointer_high as u64) << 32
     }
 
-    pub fn enable_interrupt(&mut self, enable: bool) -> &mut Self {
-        if enable {
-            self.0 |= 1 << 8;
-        } else {
-            self.0 &= 0xfeff;
+    #[test_case]
+    fn handlers_are_installed() {
+        unsafe {
+            assert_eq!(handler_address(&IDT[0]), handler::divide_by_zero as u64);
+            assert_eq!(handler_address(&IDT[32]), handler::timer as u64);
+            assert_eq!(handler_address(&IDT[33]), handler::keyboard as u64);
+            assert_eq!(handler_address(&IDT[14]), handler::page_fault as u64);
+            assert_eq!(handler_address(&IDT[0xf0]), handler::reschedule as u64);
+            assert_eq!(handler_address(&IDT[0x80]), syscall::interrupt_entry as u64);
+            assert_eq!(IDT[0x80].options.bits() >> 13 & 0b11, 3);
+            for &vector in [0, 2, 6, 8, 13, 14, 32, 33].iter() {
+                assert!(IDT[vector].options.is_present());
+            }
+            assert_eq!(IDT[2].options.bits() & 0b111, gdt::NMI_STACK_INDEX);
+            assert_eq!(IDT[8].options.bits() & 0b111, gdt::DOUBLE_FAULT_STACK_INDEX);
         }
-        self
     }
 
-    pub fn set_privilege_level(&mut self, dpl: u16) -> &mut Self {
-        self.0 = (self.0 & 0x9fff) | dpl;
-        self
-    }
-
-    pub fn set_stack_index(&mut self, stack_index: u16) -> &mut Self {
-        self.0 = (self.0 & 0xfff8) | stack_index;
-        self
+    #[test_case]
+    fn missing_entries_are_not_present() {
+        unsafe { assert!(!IDT[254].options.is_present()); }
     }
 }
-
@@ -3,15 +3,45 @@ pub mod util;
 pub mod handler;
 pub mod idt;
 
+/// Fill the IDT and load it on the bootstrap processor.
 pub fn init_idt() {
     unsafe {
-        use self::idt::{lidt, DescriptorTablePointer, Idt, IDT};
-        use core::mem::size_of;
+        use self::idt::IDT;
+        use super::platform::gdt;
         IDT[0].set_handler_fn(handler::divide_by_zero);
+        IDT[1].set_handler_fn(handler::debug);
+        IDT[2].set_handler_fn(handler::non_maskable_interrupt)
+            .set_stack_index(gdt::NMI_STACK_INDEX);
+        IDT[3].set_handler_fn(handler::breakpoint);
+        IDT[6].set_handler_fn(handler::invalid_opcode);
+        IDT[8].set_handler_fn(handler::double_fault)
+            .set_stack_index(gdt::DOUBLE_FAULT_STACK_INDEX);
+        IDT[13].set_handler_fn(handler::general_protection_fault);
+        IDT[14].set_handler_fn(handler::page_fault);
 
         IDT[32].set_handler_fn(handler::timer);
         IDT[33].set_handler_fn(handler::keyboard);
+        IDT[35].set_handler_fn(handler::serial2);
+        IDT[36].set_handler_fn(handler::serial1);
+        IDT[44].set_handler_fn(handler::mouse);
 
+        use super::syscall;
+        IDT[syscall::INT_VECTOR as usize].set_handler_fn(syscall::interrupt_entry)
+            .set_privilege_level(3);
+
+        use super::device::local_apic::SPURIOUS_VECTOR;
+        use super::task::RESCHEDULE_VECTOR;
+        IDT[RESCHEDULE_VECTOR as usize].set_handler_fn(handler::reschedule);
+        IDT[SPURIOUS_VECTOR as usize].set_handler_fn(handler::spurious);
+    }
+    load_idt();
+}
+
+/// Load the IDT filled by `init_idt` on the executing CPU. Application processors share it.
+pub fn load_idt() {
+    use self::idt::{lidt, DescriptorTablePointer, Idt, IDT};
+    use core::mem::size_of;
+    unsafe {
         let ptr = DescriptorTablePointer {
             base: &IDT as *const _ as u64,
             limit: (size_of::<Idt>() - 1) as u16,
@@ -21,11 +51,43 @@ pub fn init_idt() {
     }
 }
 
-pub fn run_without_interrupt<F>(p: F) where F: Fn() {
+/// Run `p` with interrupts disabled, restoring the previous interrupt flag afterwards so that
+/// it can be nested and called from interrupt handlers.
+pub fn run_without_interrupt<F, R>(p: F) -> R where F: FnOnce() -> R {
     use super::platform::instructions;
     unsafe {
+        let enabled = instructions::interrupts_enabled();
         instructions::cli();
-        p();
-        instructions::sti();
+        let result = p();
+        if enabled {
+            instructions::sti();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::device::pit;
+    use super::super::platform::instructions;
+
+    #[test_case]
+    fn timer_interrupts_arrive() {
+        let start = pit::ticks();
+        while pit::ticks() < start + 2 {
+            unsafe { instructions::hlt(); }
+        }
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn run_without_interrupt_restores_flag() {
+        assert!(instructions::interrupts_enabled());
+        run_without_interrupt(|| {
+            assert!(!instructions::interrupts_enabled());
+            run_without_interrupt(|| {});
+            assert!(!instructions::interrupts_enabled());
+        });
+        assert!(instructions::interrupts_enabled());
+    }
+}
@@ -55,6 +55,54 @@ macro_rules! error_code_pop {
     )};
 }
 
+// The kernel keeps its GS base, which points at the `CpuLocal` of the CPU (see
+// `task::cpu_local`), while it runs. User mode runs with the GS base the kernel swapped out into
+// IA32_KERNEL_GS_BASE, so the handlers swap it back in on entry from user mode and out again on
+// return, depending on the CS the CPU pushed.
+macro_rules! swapgs_if_from_user {
+    () => (asm!(
+        "test qword ptr [rsp + 8], 3
+         jz 2f
+         swapgs
+         2:"
+        : : : : "intel", "volatile"
+    ));
+    (error_code) => (asm!(
+        "test qword ptr [rsp + 16], 3
+         jz 2f
+         swapgs
+         2:"
+        : : : : "intel", "volatile"
+    ));
+}
+
+// A non-maskable interrupt can come between `swapgs` and `iretq`, where CS does not tell which
+// GS base is loaded, so its handler reads the GS base itself: kernel addresses have the top bit
+// set. `rbx` remembers the swap across `inner`, which preserves it.
+macro_rules! paranoid_swapgs {
+    () => (asm!(
+        "mov ecx, 0xc0000101
+         rdmsr
+         xor ebx, ebx
+         test edx, edx
+         js 2f
+         swapgs
+         mov ebx, 1
+         2:"
+        : : : "rax", "rbx", "rcx", "rdx" : "intel", "volatile"
+    ));
+}
+
+macro_rules! paranoid_swapgs_back {
+    () => (asm!(
+        "test ebx, ebx
+         jz 2f
+         swapgs
+         2:"
+        : : : : "intel", "volatile"
+    ));
+}
+
 
 macro_rules! fs_push {
     () => (asm!(
@@ -72,6 +120,7 @@ macro_rules! fs_pop {
     ));
 }
 
+#[derive(Default)]
 #[repr(packed)]
 pub struct ContextRegisters {
     pub rax: u64,
@@ -100,6 +149,13 @@ pub struct IretRegisters {
     pub ss: u64,
 }
 
+impl IretRegisters {
+    /// Whether the interrupt or exception came from user mode.
+    pub fn from_user_mode(&self) -> bool {
+        self.cs & 0b11 == 3
+    }
+}
+
 #[repr(packed)]
 pub struct InterruptFrame {
     pub context_registers: ContextRegisters,
@@ -162,9 +218,11 @@ macro_rules! impl_handler {
         pub unsafe extern fn $name () {
             #[inline(never)]
             unsafe fn inner($stack: &mut $crate::arch::x86_64::interrupt::util::InterruptFrame) {
+                let _interrupt = $crate::arch::x86_64::task::preempt::InterruptGuard::enter();
                 $func
             }
 
+            swapgs_if_from_user!();
             context_push!();
 
             let rsp: u64;
@@ -174,30 +232,91 @@ macro_rules! impl_handler {
 
             context_pop!();
 
+            swapgs_if_from_user!();
             iret!();
         }
     };
 }
 
-macro_rules! impl_handler_with_error_code {
+/// Like `impl_handler!`, for the handlers which can interrupt the kernel anywhere, even where
+/// it swaps GS bases (see `paranoid_swapgs!`).
+macro_rules! impl_paranoid_handler {
+    ($name:ident, $stack: ident, $func:block) => {
+        #[naked]
+        pub unsafe extern fn $name () {
+            #[inline(never)]
+            unsafe fn inner($stack: &mut $crate::arch::x86_64::interrupt::util::InterruptFrame) {
+                let _interrupt = $crate::arch::x86_64::task::preempt::InterruptGuard::enter();
+                $func
+            }
+
+            context_push!();
+            paranoid_swapgs!();
+
+            let rsp: u64;
+            asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
+
+            inner(&mut *(rsp as *mut $crate::arch::x86_64::interrupt::util::InterruptFrame));
+
+            paranoid_swapgs_back!();
+            context_pop!();
+
+            iret!();
+        }
+    };
+}
+
+/// Like `impl_handler_with_error_code!`, for the exceptions which can come where the kernel
+/// swaps GS bases (see `paranoid_swapgs!`).
+macro_rules! impl_paranoid_handler_with_error_code {
     ($name:ident, $stack:ident, $func:block) => {
         #[naked]
         pub unsafe extern fn $name () {
             #[inline(never)]
             unsafe fn inner($stack: &$crate::arch::x86_64::interrupt::util::InterruptFrameWithErrorCode) {
+                let _interrupt = $crate::arch::x86_64::task::preempt::InterruptGuard::enter();
                 $func
             }
 
             context_push!();
+            paranoid_swapgs!();
 
             let rsp: u64;
             asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
 
             inner(&*(rsp as *const $crate::arch::x86_64::interrupt::util::InterruptFrameWithErrorCode));
 
+            paranoid_swapgs_back!();
+            context_pop!();
+
+            error_code_pop!();
+            iret!();
+        }
+    };
+}
+
+macro_rules! impl_handler_with_error_code {
+    ($name:ident, $stack:ident, $func:block) => {
+        #[naked]
+        pub unsafe extern fn $name () {
+            #[inline(never)]
+            unsafe fn inner($stack: &mut $crate::arch::x86_64::interrupt::util::InterruptFrameWithErrorCode) {
+                let _interrupt = $crate::arch::x86_64::task::preempt::InterruptGuard::enter();
+                $func
+            }
+
+            swapgs_if_from_user!(error_code);
+            context_push!();
+
+            let rsp: u64;
+            asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
+
+            inner(&mut *(rsp as *mut $crate::arch::x86_64::interrupt::util::InterruptFrameWithErrorCode));
+
             context_pop!();
 
             error_code_pop!();
+            swapgs_if_from_user!();
             iret!();
         }
     };
@@ -0,0 +1,155 @@
+//! Address spaces: every program runs in one of its own, see `task::exec`.
+//!
+//! An address space is a PML4 with its own user part (see `user`) and the entries of the kernel
+//! PML4 for the rest: the bootloader's identity mapping of low memory and the kernel half. `init`
+//! gives each entry of the kernel half a level 3 table up front, so that the kernel mappings made
+//! later show up in every address space. As in the kernel PML4, the last entry links the PML4 to
+//! itself, for `paging`, which works on the active address space.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use super::page_table::{PageTable, PageTableFlags};
+use super::paging::{self, RECURSIVE_INDEX};
+use super::user::{USER_END, USER_START};
+use super::{free_frame, try_alloc_frame, PhysAddr};
+use super::super::platform::instructions;
+
+const PAGE_SIZE: u64 = 4096;
+/// The bits of CR3 holding the address of the PML4.
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The PML4 the kernel boots on, recorded by `init`.
+static KERNEL_PML4: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpace {
+    pml4: PhysAddr,
+}
+
+/// Record the active PML4 as the kernel's, and allocate the tables shared by all the address
+/// spaces. Has to be done once the frame allocator works, before any other address space.
+pub fn init() {
+    KERNEL_PML4.store(instructions::read_cr3() & ADDRESS_MASK, Ordering::SeqCst);
+    unsafe { paging::init_kernel_tables(); }
+}
+
+/// The page table at `frame`, mapped in the physical memory map.
+unsafe fn table(frame: PhysAddr) -> &'static mut PageTable {
+    let address = paging::map_physical_region(frame, PAGE_SIZE, PageTableFlags::WRITABLE |
+                                              PageTableFlags::NO_EXECUTE);
+    &mut *(address.as_u64() as *mut PageTable)
+}
+
+impl AddressSpace {
+    /// The address space of the kernel, which kernel threads run in. Its user part is the one
+    /// the tests map their programs in.
+    pub fn kernel() -> Self {
+        AddressSpace { pml4: PhysAddr::new(KERNEL_PML4.load(Ordering::SeqCst)) }
+    }
+
+    /// A new address space with nothing mapped in its user part, or `None` if there is no memory
+    /// left for its PML4.
+    pub fn new() -> Option<Self> {
+        let pml4 = try_alloc_frame()?;
+        unsafe {
+            let kernel = table(Self::kernel().pml4);
+            let entries = table(pml4);
+            entries.zero();
+            entries[0] = kernel[0];
+            for index in 256..RECURSIVE_INDEX as usize {
+                entries[index] = kernel[index];
+            }
+            entries[RECURSIVE_INDEX as usize].set_address(pml4, PageTableFlags::PRESENT |
+                                                          PageTableFlags::WRITABLE);
+        }
+        Some(AddressSpace { pml4 })
+    }
+
+    /// Whether it is the address space of the executing CPU.
+    pub fn is_active(&self) -> bool {
+        instructions::read_cr3() & ADDRESS_MASK == self.pml4.as_u64()
+    }
+
+    /// Make it the address space of the executing CPU.
+    pub unsafe fn activate(&self) {
+        if !self.is_active() {
+            instructions::write_cr3(self.pml4.as_u64());
+        }
+    }
+
+    /// Free the pages of the user part, the tables mapping them and the PML4. The address space
+    /// must not be the kernel's nor active on any CPU.
+    pub unsafe fn destroy(self) {
+        assert!(self != Self::kernel(), "The kernel address space cannot be destroyed");
+        assert!(!self.is_active(), "An active address space cannot be destroyed");
+        let first = (USER_START >> 39) as usize;
+        let end = (USER_END >> 39) as usize;
+        free_tables(self.pml4, 4, first, end);
+    }
+}
+
+/// Free the frames mapped by the entries `first..end` of the table of `level` at `frame`, the
+/// tables below included, then the table itself.
+unsafe fn free_tables(frame: PhysAddr, level: usize, first: usize, end: usize) {
+    for index in first..end {
+        let entry = table(frame)[index];
+        if entry.is_unused() {
+            continue;
+        }
+        if level == 1 {
+            free_frame(entry.address());
+        } else {
+            free_tables(entry.address(), level - 1, 0, 512);
+        }
+    }
+    free_frame(frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+    use super::super::user;
+    use super::super::VirtAddr;
+    use super::super::super::task::preempt::PreemptGuard;
+
+    #[test_case]
+    fn new_address_spaces_share_the_kernel_part() {
+        let space = AddressSpace::new().unwrap();
+        assert_ne!(space, AddressSpace::kernel());
+        assert!(!space.is_active());
+        unsafe {
+            let kernel = table(AddressSpace::kernel().pml4);
+            let entries = table(space.pml4);
+            for index in 0..RECURSIVE_INDEX as usize {
+                let shared = index == 0 || index >= 256;
+                assert_eq!(entries[index].is_unused(), !shared || kernel[index].is_unused());
+                if shared {
+                    assert_eq!(entries[index].address(), kernel[index].address());
+                }
+            }
+            assert_eq!(entries[RECURSIVE_INDEX as usize].address(), space.pml4);
+            space.destroy();
+        }
+    }
+
+    #[test_case]
+    fn user_pages_are_private() {
+        const PAGE: u64 = USER_START + 0x40000;
+        let space = AddressSpace::new().unwrap();
+        let kernel = AddressSpace::kernel();
+        // Switching tasks would activate the kernel address space again.
+        let guard = PreemptGuard::new();
+        unsafe {
+            space.activate();
+            user::map(VirtAddr::new(PAGE), PAGE_SIZE, PageTableFlags::WRITABLE);
+            ptr::write_volatile(PAGE as *mut u8, 0x2a);
+            kernel.activate();
+            assert!(user::is_unmapped(VirtAddr::new(PAGE), PAGE_SIZE));
+            space.activate();
+            assert_eq!(ptr::read_volatile(PAGE as *const u8), 0x2a);
+            kernel.activate();
+            drop(guard);
+            space.destroy();
+        }
+    }
+}
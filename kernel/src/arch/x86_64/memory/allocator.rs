@@ -1,30 +1,58 @@
-use super::PhysAddr;
+use super::{phys_to_virt, PhysAddr};
 use super::layout::{all_memory_area, E820Type, E820Tag, MemoryAreaIter};
 
 const PT_SIZE: usize = 4096;
+/// Memory below 1MiB holds the E820 map, the bootloader, its page tables and the boot stack.
+const LOW_MEMORY_END: usize = 0x10_0000;
 
-struct PtAllocator {
+pub struct PtAllocator {
     number: usize,
     kstart_num: usize,
     kend_num: usize,
     current_area: Option<E820Tag>,
-    iterator: MemoryAreaIter
+    iterator: MemoryAreaIter,
+    /// The last freed page, which holds the address of the one freed before, or zero. Freed
+    /// pages are in the physical memory map (see `free_page`).
+    free_list: u64,
 }
 
 impl PtAllocator {
     pub fn new(kernel_start: PhysAddr, kernel_end: PhysAddr) -> Self {
         let mut allocator = PtAllocator {
-            number: 0,
+            number: LOW_MEMORY_END / PT_SIZE,
             kstart_num: kernel_start.as_u64() as usize / PT_SIZE,
             kend_num: kernel_end.as_u64() as usize / PT_SIZE,
             current_area: None,
             iterator: all_memory_area(),
+            free_list: 0,
         };
         allocator.choose_next_area();
         allocator
     }
 
     pub fn alloc_page(&mut self) -> PhysAddr {
+        self.try_alloc_page().expect("No memory to allocate page table")
+    }
+
+    /// Allocate a page, or `None` if physical memory is exhausted. Freed pages come first.
+    pub fn try_alloc_page(&mut self) -> Option<PhysAddr> {
+        if self.free_list != 0 {
+            let page = PhysAddr::new(self.free_list);
+            self.free_list = unsafe { *(phys_to_virt(page).as_u64() as *const u64) };
+            return Some(page);
+        }
+        self.next_page()
+    }
+
+    /// Give back `page`, which has to be mapped at its address in the physical memory map, as
+    /// `paging::map_physical_region` does.
+    pub unsafe fn free_page(&mut self, page: PhysAddr) {
+        *(phys_to_virt(page).as_u64() as *mut u64) = self.free_list;
+        self.free_list = page.as_u64();
+    }
+
+    /// The next page never allocated.
+    fn next_page(&mut self) -> Option<PhysAddr> {
         if let Some(area) = self.current_area {
             let area_end = (area.base_address.as_u64() as usize + area.size) / PT_SIZE;
             if self.number >= area_end {
@@ -36,23 +64,26 @@ impl PtAllocator {
             else {
                 let addr =  PhysAddr::new((self.number * PT_SIZE) as u64);
                 self.number += 1;
-                return addr;
+                return Some(addr);
             }
-            self.alloc_page()
+            self.next_page()
         }
         else {
-            panic!("No memory to allocate page table")
+            None
         }
     }
 
     fn choose_next_area(&mut self) {
         self.current_area = self.iterator.clone().filter(|area| {
             let num = (area.base_address.as_u64() as usize + area.size) / PT_SIZE;
-            num > self.number
+            area.is_free() && num > self.number
         }).min_by_key(|area| area.base_address.as_u64());
 
         if let Some(area) = self.current_area {
-            self.number = area.base_address.as_u64() as usize / PT_SIZE;
+            let start = (area.base_address.as_u64() as usize + PT_SIZE - 1) / PT_SIZE;
+            if start > self.number {
+                self.number = start;
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,71 +1,36 @@
 use core::mem::size_of;
 use super::PhysAddr;
+pub use ailurus_core::e820::{E820Tag, E820Type, RawE820Tag};
+use ailurus_core::e820;
 
-const E820_MAX: usize = 20;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum E820Type {
-    None,
-    Free,
-    Reserved,
-    Unknown,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub struct E820Tag {
-    pub base_address: PhysAddr,
-    pub size: usize,
-    pub mem_type: E820Type,
-}
-
-impl E820Tag {
-    const fn missing() -> Self {
-        E820Tag {
-            base_address: unsafe{ PhysAddr::new_unchecked(0) },
-            size: 0,
-            mem_type: E820Type::None
-        }
-    }
-
-    pub fn is_free(&self) -> bool {
-        self.mem_type == E820Type::Free
-    }
-}
+const E820_MAX: usize = 32;
 
 static mut MEMORY_AREA_NUM: usize = 0;
 static mut MEMORY_SIZE: usize = 0;
-static mut E820_MAP: [E820Tag; E820_MAX] = [E820Tag::missing(); E820_MAX];
+/// The sanitised map. Overlapping areas can split each other, into up to `2 * E820_MAX - 1`.
+static mut E820_MAP: [E820Tag; 2 * E820_MAX] = [E820Tag::missing(); 2 * E820_MAX];
 
+/// Read the memory map left by the bootloader at `address` and sanitise it.
 pub unsafe fn read_e820_map(address: PhysAddr) {
-    #[repr(C)]
-    struct RawE820Tag {
-        base_address: u64,
-        size: u64,
-        mem_type: u32,
-        _reversed: u32,
-    }
+    let mut raw_tags = [E820Tag::missing(); E820_MAX];
+    let mut raw_num = 0;
     let mut last_tag_addr = address.as_u64();
     loop {
-        let tag = &mut *(last_tag_addr as *mut RawE820Tag);
+        let tag = &*(last_tag_addr as *const RawE820Tag);
         last_tag_addr += size_of::<RawE820Tag>() as u64;
         if tag.mem_type == 0 {
             break
-        } else {
-            MEMORY_SIZE += tag.size as usize;
-            E820_MAP[MEMORY_AREA_NUM] = E820Tag {
-                base_address: PhysAddr::new(tag.base_address),
-                size: tag.size as usize,
-                mem_type: match tag.mem_type {
-                    0 => E820Type::None,
-                    1 => E820Type::Free,
-                    2 => E820Type::Reserved,
-                    _ => E820Type::Unknown
-                }
-            };
-            MEMORY_AREA_NUM += 1;
         }
-        assert_ne!(MEMORY_AREA_NUM, E820_MAX, "Incorrect E820 map")
+        assert_ne!(raw_num, E820_MAX, "Incorrect E820 map");
+        if let Some(tag) = E820Tag::from_raw(tag) {
+            raw_tags[raw_num] = tag;
+            raw_num += 1;
+        }
     }
+
+    MEMORY_AREA_NUM = e820::sanitize(&raw_tags[..raw_num], &mut E820_MAP)
+        .expect("Incorrect E820 map");
+    MEMORY_SIZE = E820_MAP[..MEMORY_AREA_NUM].iter().map(|tag| tag.size).sum();
 }
 
 pub fn physical_memory_size() -> usize {
@@ -101,3 +66,22 @@ impl Iterator for MemoryAreaIter {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn e820_map_is_read() {
+        assert!(memory_area_num() > 0);
+        let total: usize = all_memory_area().map(|area| area.size).sum();
+        assert_eq!(total, physical_memory_size());
+    }
+
+    #[test_case]
+    fn e820_map_has_free_memory_above_1mib() {
+        assert!(all_memory_area().any(|area| {
+            area.is_free() && area.base_address.as_u64() + area.size as u64 > 0x10_0000
+        }));
+    }
+}
@@ -1,11 +1,61 @@
-pub mod address;
 pub mod layout;
-pub mod page_table;
 pub mod allocator;
+pub mod address_space;
+pub mod paging;
+pub mod user;
+pub mod user_access;
+pub use ailurus_core::{address, page_table};
 pub use self::address::{PhysAddr, VirtAddr};
 
-pub fn init_memory() {
-    unsafe { layout::read_e820_map(PhysAddr::new(0x500)); }
+use spin::Mutex;
+use self::allocator::PtAllocator;
+use self::page_table::PageTableFlags;
+
+/// The bootloader maps the first 10MiB of physical memory at this offset, and regions mapped
+/// with `paging::map_physical_region` follow the same scheme.
+pub const PHYS_MAP_OFFSET: u64 = 0xffff_ff00_0000_0000;
+/// The back buffer of the framebuffer console.
+pub const FRAMEBUFFER_BACK_BUFFER: u64 = 0xffff_fd00_0000_0000;
+/// The screens and scrollback buffers of the virtual terminals.
+pub const CONSOLE_BUFFERS: u64 = 0xffff_fc00_0000_0000;
+/// The stacks of the kernel threads, see `task::stack`.
+pub const KERNEL_STACKS: u64 = 0xffff_fb00_0000_0000;
+
+pub static FRAME_ALLOCATOR: Mutex<Option<PtAllocator>> = Mutex::new(None);
+
+pub fn init_memory(kernel_start: PhysAddr, kernel_size: usize) {
+    unsafe {
+        layout::read_e820_map(PhysAddr::new(0x500));
+        paging::init_pat();
+    }
+    let kernel_end = PhysAddr::new(kernel_start.as_u64() + kernel_size as u64);
+    *FRAME_ALLOCATOR.lock() = Some(PtAllocator::new(kernel_start, kernel_end));
+}
+
+/// Allocate a physical frame of 4KiB.
+pub fn alloc_frame() -> PhysAddr {
+    FRAME_ALLOCATOR.lock().as_mut().expect("Frame allocator is not initialized").alloc_page()
+}
+
+/// Allocate a physical frame of 4KiB, or `None` if physical memory is exhausted.
+pub fn try_alloc_frame() -> Option<PhysAddr> {
+    FRAME_ALLOCATOR.lock().as_mut().expect("Frame allocator is not initialized").try_alloc_page()
+}
+
+/// Give back `frame`, allocated by `alloc_frame`, once nothing maps or uses it any more. Its
+/// first bytes link it to the other free frames.
+pub fn free_frame(frame: PhysAddr) {
+    unsafe {
+        // Outside the lock, as it may allocate page tables.
+        paging::map_physical_region(frame, 4096, PageTableFlags::WRITABLE |
+                                    PageTableFlags::NO_EXECUTE);
+        FRAME_ALLOCATOR.lock().as_mut().expect("Frame allocator is not initialized")
+            .free_page(frame);
+    }
+}
+
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(address.as_u64() + PHYS_MAP_OFFSET)
 }
 
 use core::marker::PhantomData;
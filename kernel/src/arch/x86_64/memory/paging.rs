@@ -0,0 +1,233 @@
+use super::{PhysAddr, VirtAddr, PHYS_MAP_OFFSET, alloc_frame, try_alloc_frame};
+use super::page_table::{PageTable, PageTableEntry, PageTableFlags};
+use super::super::platform::instructions;
+
+// The bootloader links the last entry of the PML4 to the PML4 itself, so every page table of
+// the active address space can be reached through these recursive addresses.
+pub const RECURSIVE_INDEX: u64 = 0o777;
+const PAGE_SIZE: u64 = 4096;
+
+const IA32_PAT: u32 = 0x277;
+const PAT_WRITE_COMBINING: u32 = 0x01;
+
+/// In level 1 entries the bit of `HUGE_PAGE` selects the upper half of the page attribute table
+/// instead, and `init_pat` makes its first entry write-combining.
+pub const WRITE_COMBINING: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+fn sign_extend(address: u64) -> u64 {
+    if address & (1 << 47) != 0 {
+        address | 0xffff_0000_0000_0000
+    } else {
+        address
+    }
+}
+
+fn table_address(p4: u64, p3: u64, p2: u64, p1: u64) -> *mut PageTable {
+    sign_extend(p4 << 39 | p3 << 30 | p2 << 21 | p1 << 12) as *mut PageTable
+}
+
+unsafe fn p4_table() -> &'static mut PageTable {
+    &mut *table_address(RECURSIVE_INDEX, RECURSIVE_INDEX, RECURSIVE_INDEX, RECURSIVE_INDEX)
+}
+
+unsafe fn p3_table(page: VirtAddr) -> &'static mut PageTable {
+    &mut *table_address(RECURSIVE_INDEX, RECURSIVE_INDEX, RECURSIVE_INDEX,
+                        page.p4_index() as u64)
+}
+
+unsafe fn p2_table(page: VirtAddr) -> &'static mut PageTable {
+    &mut *table_address(RECURSIVE_INDEX, RECURSIVE_INDEX, page.p4_index() as u64,
+                        page.p3_index() as u64)
+}
+
+unsafe fn p1_table(page: VirtAddr) -> &'static mut PageTable {
+    &mut *table_address(RECURSIVE_INDEX, page.p4_index() as u64, page.p3_index() as u64,
+                        page.p2_index() as u64)
+}
+
+/// Program the page attribute table so that `WRITE_COMBINING` selects write-combining. The
+/// entries selected by `WRITE_THROUGH` and `NO_CACHE` alone keep their power-on memory types.
+pub unsafe fn init_pat() {
+    let (eax, edx) = instructions::rdmsr(IA32_PAT);
+    instructions::wrmsr(eax, (edx & !0xff) | PAT_WRITE_COMBINING, IA32_PAT);
+}
+
+/// Make sure `table[index]` points to a next level table, allocating a zeroed one if necessary.
+/// Returns false if there is no memory left for it.
+unsafe fn ensure_next_table(table: &mut PageTable, index: usize, next: *mut PageTable,
+                            user: bool) -> bool {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if user {
+        flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    if table[index].is_unused() {
+        let frame = match try_alloc_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        table[index].set_address(frame, flags);
+        instructions::invlpg(next as u64);
+        (*next).zero();
+    } else {
+        assert!(!table[index].flags().contains(PageTableFlags::HUGE_PAGE),
+                "Huge pages are not supported");
+        let current = table[index].flags();
+        table[index].set_flags(current | flags);
+    }
+    true
+}
+
+/// Give every entry of the kernel half of the PML4 a level 3 table, so that the address spaces
+/// which copy these entries (see `address_space`) share the kernel mappings made afterwards.
+pub unsafe fn init_kernel_tables() {
+    for index in 256..RECURSIVE_INDEX as usize {
+        let page = VirtAddr::new(sign_extend((index as u64) << 39));
+        assert!(ensure_next_table(p4_table(), index, p3_table(page), false),
+                "No memory for the kernel page tables");
+    }
+}
+
+/// Map the 4KiB page starting at `page` to `frame` in the active address space.
+pub unsafe fn map_to(page: VirtAddr, frame: PhysAddr, flags: PageTableFlags) {
+    assert!(try_map_to(page, frame, flags), "No memory to allocate page table");
+}
+
+/// `map_to`, returning false if there is no memory left for the page tables.
+pub unsafe fn try_map_to(page: VirtAddr, frame: PhysAddr, flags: PageTableFlags) -> bool {
+    assert!(page.is_aligned(PAGE_SIZE), "Page address is not aligned");
+    let user = flags.contains(PageTableFlags::USER_ACCESSIBLE);
+
+    let tables = ensure_next_table(p4_table(), page.p4_index(), p3_table(page), user)
+        && ensure_next_table(p3_table(page), page.p3_index(), p2_table(page), user)
+        && ensure_next_table(p2_table(page), page.p2_index(), p1_table(page), user);
+    if !tables {
+        return false;
+    }
+
+    let p1 = p1_table(page);
+    assert!(p1[page.p1_index()].is_unused(), "Page 0x{:x} is already mapped", page.as_u64());
+    p1[page.p1_index()].set_address(frame, flags | PageTableFlags::PRESENT);
+    instructions::invlpg(page.as_u64());
+    true
+}
+
+/// Allocate a frame and map `page` to it.
+pub unsafe fn map(page: VirtAddr, flags: PageTableFlags) -> PhysAddr {
+    let frame = alloc_frame();
+    map_to(page, frame, flags);
+    frame
+}
+
+/// `map`, or `None` if there is no memory left for the frame or the page tables.
+pub unsafe fn try_map(page: VirtAddr, flags: PageTableFlags) -> Option<PhysAddr> {
+    let frame = try_alloc_frame()?;
+    if try_map_to(page, frame, flags) { Some(frame) } else { None }
+}
+
+/// Replace the flags of the mapped `page`, returning false if it is not mapped.
+pub unsafe fn update_flags(page: VirtAddr, flags: PageTableFlags) -> bool {
+    if mapped_entry(page).is_none() {
+        return false;
+    }
+    let entry = &mut p1_table(page)[page.p1_index()];
+    entry.set_flags(flags | PageTableFlags::PRESENT);
+    instructions::invlpg(page.as_u64());
+    true
+}
+
+/// Remove the mapping of `page`, returning the frame it was mapped to.
+pub unsafe fn unmap(page: VirtAddr) -> Option<PhysAddr> {
+    if !is_table_present(page, 3) {
+        return None;
+    }
+    let p1 = p1_table(page);
+    let entry = &mut p1[page.p1_index()];
+    if entry.is_unused() {
+        return None;
+    }
+    let frame = entry.address();
+    entry.set_unused();
+    instructions::invlpg(page.as_u64());
+    Some(frame)
+}
+
+/// Whether the tables down to `level` (1 = P3, 2 = P2, 3 = P1) exist for `page`.
+fn is_table_present(page: VirtAddr, level: usize) -> bool {
+    unsafe {
+        let present = |entry: &PageTableEntry|
+            entry.flags().contains(PageTableFlags::PRESENT) &&
+            !entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        (level < 1 || present(&p4_table()[page.p4_index()])) &&
+            (level < 2 || present(&p3_table(page)[page.p3_index()])) &&
+            (level < 3 || present(&p2_table(page)[page.p2_index()]))
+    }
+}
+
+/// The level 1 entry mapping `address`, if the page is present.
+fn mapped_entry(address: VirtAddr) -> Option<PageTableEntry> {
+    if !is_table_present(address, 3) {
+        return None;
+    }
+    let entry = unsafe { p1_table(address)[address.p1_index()] };
+    if entry.flags().contains(PageTableFlags::PRESENT) {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+/// Flags of the page containing `address`, if it is mapped.
+pub fn page_flags(address: VirtAddr) -> Option<PageTableFlags> {
+    mapped_entry(address).map(|entry| entry.flags())
+}
+
+/// Translate a virtual address of the active address space to its physical address.
+pub fn translate(address: VirtAddr) -> Option<PhysAddr> {
+    mapped_entry(address)
+        .map(|entry| PhysAddr::new(entry.address().as_u64() + address.page_offset() as u64))
+}
+
+/// Map `size` bytes of physical memory starting at `start` to `PHYS_MAP_OFFSET + start`,
+/// skipping pages which are already mapped, and return the virtual address of `start`.
+pub unsafe fn map_physical_region(start: PhysAddr, size: u64, flags: PageTableFlags) -> VirtAddr {
+    let first = start.align_down(PAGE_SIZE).as_u64();
+    let mut frame = first;
+    while frame < start.as_u64() + size {
+        let page = VirtAddr::new(frame + PHYS_MAP_OFFSET);
+        if translate(page).is_none() {
+            map_to(page, PhysAddr::new(frame), flags);
+        }
+        frame += PAGE_SIZE;
+    }
+    VirtAddr::new(start.as_u64() + PHYS_MAP_OFFSET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn map_translate_unmap() {
+        let page = VirtAddr::new(0xffff_fe00_0000_0000);
+        unsafe {
+            let frame = map(page, PageTableFlags::WRITABLE);
+            assert!(frame.is_aligned(PAGE_SIZE));
+            assert_eq!(translate(VirtAddr::new(page.as_u64() + 0x10)),
+                       Some(PhysAddr::new(frame.as_u64() + 0x10)));
+
+            let pointer = page.as_u64() as *mut u64;
+            pointer.write_volatile(0xdead_beef);
+            assert_eq!(pointer.read_volatile(), 0xdead_beef);
+
+            assert_eq!(unmap(page), Some(frame));
+        }
+        assert_eq!(translate(page), None);
+    }
+
+    #[test_case]
+    fn kernel_is_mapped() {
+        let address = VirtAddr::new(map_translate_unmap as fn() as u64);
+        assert!(translate(address).is_some());
+        assert!(page_flags(address).unwrap().contains(PageTableFlags::PRESENT));
+    }
+}
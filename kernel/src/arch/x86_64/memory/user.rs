@@ -0,0 +1,108 @@
+//! The part of the address space user mode may access, from `USER_START` to `USER_END`: below
+//! lies the bootloader's identity mapping of low memory, above the kernel. Each address space
+//! has its own (see `address_space`), and these functions work on the active one.
+
+use core::ptr;
+use super::page_table::PageTableFlags;
+use super::{free_frame, paging, VirtAddr};
+
+pub const USER_START: u64 = 0x0000_0080_0000_0000;
+/// The end of the lower half of the address space.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+/// Where a task starts placing the mappings `mmap` chooses the address of.
+pub const MMAP_START: u64 = 0x0000_2000_0000_0000;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Whether the `size` bytes at `start` lie in the user part of the address space.
+pub fn contains(start: u64, size: u64) -> bool {
+    start >= USER_START && start <= USER_END && size <= USER_END - start
+}
+
+fn pages(start: VirtAddr, size: u64) -> impl Iterator<Item = VirtAddr> {
+    assert!(start.is_aligned(PAGE_SIZE), "User pages are not aligned");
+    assert!(contains(start.as_u64(), size), "0x{:x} is not a user address", start.as_u64());
+    (start.as_u64()..start.as_u64() + size).step_by(PAGE_SIZE as usize).map(VirtAddr::new)
+}
+
+/// The first mapped page over the `size` bytes from the page-aligned `start`.
+fn first_mapped(start: VirtAddr, size: u64) -> Option<VirtAddr> {
+    pages(start, size).find(|&page| paging::page_flags(page).is_some())
+}
+
+/// Whether none of the pages over the `size` bytes from the page-aligned `start` are mapped.
+pub fn is_unmapped(start: VirtAddr, size: u64) -> bool {
+    first_mapped(start, size).is_none()
+}
+
+/// The first address from the page-aligned `start` with `size` unmapped bytes of user space
+/// there.
+pub fn find_unmapped(start: u64, size: u64) -> Option<u64> {
+    let mut start = start;
+    while contains(start, size) {
+        match first_mapped(VirtAddr::new(start), size) {
+            None => return Some(start),
+            Some(page) => start = page.as_u64() + PAGE_SIZE,
+        }
+    }
+    None
+}
+
+/// Map zeroed pages over the `size` bytes from the page-aligned `start`, accessible from user
+/// mode with `flags`: `WRITABLE` and `NO_EXECUTE` are the ones that make sense.
+pub unsafe fn map(start: VirtAddr, size: u64, flags: PageTableFlags) {
+    assert!(try_map(start, size, flags), "No memory for user pages");
+}
+
+/// `map`, returning false with nothing mapped if physical memory runs out.
+pub unsafe fn try_map(start: VirtAddr, size: u64, flags: PageTableFlags) -> bool {
+    for page in pages(start, size) {
+        // Accessible from user mode right away, so that the page tables above are too.
+        let mapped = paging::try_map(page, PageTableFlags::USER_ACCESSIBLE |
+                                     PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+        if mapped.is_none() {
+            unmap(start, page.as_u64() - start.as_u64());
+            return false;
+        }
+        ptr::write_bytes(page.as_u64() as *mut u8, 0, PAGE_SIZE as usize);
+    }
+    protect(start, size, flags);
+    true
+}
+
+/// Change the flags of the mapped user pages over the `size` bytes from `start`, e.g. to make
+/// them read-only once their contents are written.
+pub unsafe fn protect(start: VirtAddr, size: u64, flags: PageTableFlags) {
+    for page in pages(start, size) {
+        let updated = paging::update_flags(page, flags | PageTableFlags::USER_ACCESSIBLE);
+        assert!(updated, "User page 0x{:x} is not mapped", page.as_u64());
+    }
+}
+
+/// Remove the user pages over the `size` bytes from `start` and free their frames, skipping
+/// those not mapped.
+pub unsafe fn unmap(start: VirtAddr, size: u64) {
+    for page in pages(start, size) {
+        if let Some(frame) = paging::unmap(page) {
+            free_frame(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = USER_START + 0x50000;
+
+    #[test_case]
+    fn find_unmapped_skips_mapped_pages() {
+        unsafe { map(VirtAddr::new(START + PAGE_SIZE), PAGE_SIZE, PageTableFlags::empty()); }
+        assert!(is_unmapped(VirtAddr::new(START), PAGE_SIZE));
+        assert!(!is_unmapped(VirtAddr::new(START), 2 * PAGE_SIZE));
+        assert_eq!(find_unmapped(START, PAGE_SIZE), Some(START));
+        assert_eq!(find_unmapped(START, 2 * PAGE_SIZE), Some(START + 2 * PAGE_SIZE));
+        assert_eq!(find_unmapped(USER_END - PAGE_SIZE, 2 * PAGE_SIZE), None);
+        unsafe { unmap(VirtAddr::new(START + PAGE_SIZE), PAGE_SIZE); }
+    }
+}
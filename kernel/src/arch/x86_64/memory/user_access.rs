@@ -0,0 +1,226 @@
+//! Access to user memory from system calls, which fails with `EFAULT` instead of crashing the
+//! kernel when user mode passes a bad pointer.
+//!
+//! ```ignore
+//! let path = UserSlice::new(arguments[0], arguments[1])?;
+//! path.read(&mut buffer[..path.len()])?;
+//! UserPtr::<u64>::new(arguments[2])?.write(&size)?;
+//! ```
+//!
+//! Addresses outside the user part of the address space are refused up front. Within it, the
+//! copy itself finds out whether the pages are mapped and allow the access: a page fault stops
+//! it, and the handler continues after the faulting instruction, as the fix-up table of the
+//! kernel says (see `fixup`). The table is the `.fixup_table` section, which the linker script
+//! puts between `__fixup_table_start` and `__fixup_table_end`.
+
+use ailurus_core::syscall::Error;
+use core::marker::PhantomData;
+use core::{mem, slice};
+use super::user;
+
+/// Where to continue when `instruction` faults.
+#[repr(C)]
+struct Fixup {
+    instruction: u64,
+    continuation: u64,
+}
+
+extern "C" {
+    static __fixup_table_start: Fixup;
+    static __fixup_table_end: Fixup;
+}
+
+fn fixup_table() -> &'static [Fixup] {
+    unsafe {
+        let start = &__fixup_table_start as *const Fixup;
+        let end = &__fixup_table_end as *const Fixup;
+        slice::from_raw_parts(start, (end as usize - start as usize) / mem::size_of::<Fixup>())
+    }
+}
+
+/// Where to continue after a page fault in kernel mode at `rip` on `address`, if the kernel
+/// expects the instruction to fault on user addresses. Called by the page fault handler.
+pub fn fixup(rip: u64, address: u64) -> Option<u64> {
+    if !user::contains(address, 1) {
+        return None;
+    }
+    fixup_table().iter().find(|fixup| fixup.instruction == rip).map(|fixup| fixup.continuation)
+}
+
+/// Copy `len` bytes from `source` to `destination`, one of them in user memory. Returns the
+/// number of bytes left when a page fault stopped the copy, zero if none did.
+#[inline(never)]
+unsafe fn copy(destination: *mut u8, source: *const u8, len: usize) -> usize {
+    let left: usize;
+    let _destination_end: usize;
+    let _source_end: usize;
+    // A fault leaves the count of the bytes left in `rcx`.
+    asm!(".Lcopy_user:
+          rep movsb
+          .Lcopy_user_end:
+          .pushsection .fixup_table, \"a\"
+          .quad .Lcopy_user, .Lcopy_user_end
+          .popsection"
+         : "={rcx}"(left), "={rdi}"(_destination_end), "={rsi}"(_source_end)
+         : "{rdi}"(destination), "{rsi}"(source), "{rcx}"(len)
+         : "memory" : "intel", "volatile");
+    left
+}
+
+fn check_range(address: u64, len: usize) -> Result<(), Error> {
+    if user::contains(address, len as u64) { Ok(()) } else { Err(Error::Fault) }
+}
+
+/// Copy the user memory at `source` into `destination`.
+pub fn copy_from_user(destination: &mut [u8], source: u64) -> Result<(), Error> {
+    check_range(source, destination.len())?;
+    match unsafe { copy(destination.as_mut_ptr(), source as *const u8, destination.len()) } {
+        0 => Ok(()),
+        _ => Err(Error::Fault),
+    }
+}
+
+/// Copy `source` to the user memory at `destination`.
+pub fn copy_to_user(destination: u64, source: &[u8]) -> Result<(), Error> {
+    check_range(destination, source.len())?;
+    match unsafe { copy(destination as *mut u8, source.as_ptr(), source.len()) } {
+        0 => Ok(()),
+        _ => Err(Error::Fault),
+    }
+}
+
+/// Types for which any bytes are a valid value, as user memory may hold anything.
+pub unsafe trait Plain: Copy {}
+
+macro_rules! plain {
+    ($($type: ty),*) => ($(unsafe impl Plain for $type {})*);
+}
+
+plain!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// A pointer to a `T` in user memory, as passed to a system call.
+pub struct UserPtr<T> {
+    address: u64,
+    _type: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        UserPtr { address: self.address, _type: PhantomData }
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Plain> UserPtr<T> {
+    /// Fails if the `T` at `address` does not lie in the user part of the address space.
+    pub fn new(address: u64) -> Result<Self, Error> {
+        check_range(address, mem::size_of::<T>())?;
+        Ok(UserPtr { address, _type: PhantomData })
+    }
+
+    pub fn address(self) -> u64 {
+        self.address
+    }
+
+    pub fn read(self) -> Result<T, Error> {
+        unsafe {
+            let mut value: T = mem::zeroed();
+            let bytes = slice::from_raw_parts_mut(&mut value as *mut T as *mut u8,
+                                                  mem::size_of::<T>());
+            copy_from_user(bytes, self.address)?;
+            Ok(value)
+        }
+    }
+
+    pub fn write(self, value: &T) -> Result<(), Error> {
+        let bytes = unsafe {
+            slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+        };
+        copy_to_user(self.address, bytes)
+    }
+}
+
+/// Bytes of user memory, as passed to a system call.
+#[derive(Debug, Clone, Copy)]
+pub struct UserSlice {
+    address: u64,
+    len: usize,
+}
+
+impl UserSlice {
+    /// Fails if the `len` bytes at `address` do not lie in the user part of the address space.
+    pub fn new(address: u64, len: u64) -> Result<Self, Error> {
+        check_range(address, len as usize)?;
+        Ok(UserSlice { address, len: len as usize })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The `len` bytes from `offset`, which have to be within this slice.
+    pub fn subslice(&self, offset: usize, len: usize) -> UserSlice {
+        assert!(offset <= self.len && len <= self.len - offset, "User subslice out of range");
+        UserSlice { address: self.address + offset as u64, len }
+    }
+
+    /// Copy the bytes into `buffer`, of the same length.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        assert_eq!(buffer.len(), self.len, "Reading a user slice into a buffer of another size");
+        copy_from_user(buffer, self.address)
+    }
+
+    /// Copy `bytes`, of the same length, to the slice.
+    pub fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        assert_eq!(bytes.len(), self.len, "Writing a buffer of another size to a user slice");
+        copy_to_user(self.address, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::page_table::PageTableFlags;
+    use super::super::user::USER_START;
+    use super::super::VirtAddr;
+
+    const PAGE_SIZE: u64 = 4096;
+    /// A page no other test maps, followed by an unmapped one.
+    const PAGE: u64 = USER_START + 0x30000;
+
+    #[test_case]
+    fn kernel_addresses_are_refused() {
+        let mut buffer = [0; 8];
+        let kernel = &buffer as *const _ as u64;
+        assert_eq!(copy_from_user(&mut buffer, kernel).err(), Some(Error::Fault));
+        assert!(UserSlice::new(USER_START, u64::max_value()).is_err());
+        assert!(UserPtr::<u64>::new(user::USER_END - 4).is_err());
+    }
+
+    #[test_case]
+    fn unmapped_user_memory_faults() {
+        unsafe { user::map(VirtAddr::new(PAGE), PAGE_SIZE, PageTableFlags::WRITABLE); }
+        let across = UserSlice::new(PAGE + PAGE_SIZE - 4, 8).unwrap();
+        assert_eq!(across.write(&[1; 8]).err(), Some(Error::Fault));
+        assert_eq!(UserPtr::<u32>::new(PAGE + PAGE_SIZE).unwrap().read().err(),
+                   Some(Error::Fault));
+
+        let pointer = UserPtr::<u32>::new(PAGE + PAGE_SIZE - 4).unwrap();
+        pointer.write(&0x1234_5678).unwrap();
+        assert_eq!(pointer.read(), Ok(0x1234_5678));
+
+        unsafe { user::protect(VirtAddr::new(PAGE), PAGE_SIZE, PageTableFlags::empty()); }
+        assert_eq!(pointer.write(&0).err(), Some(Error::Fault));
+        assert_eq!(pointer.read(), Ok(0x1234_5678));
+        unsafe { user::unmap(VirtAddr::new(PAGE), PAGE_SIZE); }
+    }
+}
@@ -2,8 +2,16 @@
 pub mod device;
 
 pub mod memory;
+#[macro_use]
 pub mod interrupt;
 pub mod platform;
+pub mod debug;
+pub mod task;
+pub mod syscall;
+
+use ailurus_core::framebuffer::{ColorField, FramebufferInfo, PixelFormat};
+use core::slice;
+use self::memory::PhysAddr;
 
 #[repr(packed)]
 pub struct KernelArgs {
@@ -13,20 +21,87 @@ pub struct KernelArgs {
     stack_size: u64,
     env_base: u64,
     env_size: u64,
+    /// The VBE linear framebuffer, or zero if the bootloader kept the text mode.
+    fb_base: u64,
+    fb_pitch: u32,
+    fb_width: u32,
+    fb_height: u32,
+    fb_bits_per_pixel: u8,
+    fb_red_position: u8,
+    fb_red_size: u8,
+    fb_green_position: u8,
+    fb_green_size: u8,
+    fb_blue_position: u8,
+    fb_blue_size: u8,
+}
+
+impl KernelArgs {
+    fn framebuffer(&self) -> Option<(PhysAddr, FramebufferInfo)> {
+        if self.fb_base == 0 {
+            return None;
+        }
+        let info = FramebufferInfo {
+            width: self.fb_width as usize,
+            height: self.fb_height as usize,
+            pitch: self.fb_pitch as usize,
+            format: PixelFormat {
+                bits_per_pixel: self.fb_bits_per_pixel,
+                red: ColorField { position: self.fb_red_position, size: self.fb_red_size },
+                green: ColorField { position: self.fb_green_position, size: self.fb_green_size },
+                blue: ColorField { position: self.fb_blue_position, size: self.fb_blue_size },
+            },
+        };
+        Some((PhysAddr::new(self.fb_base), info))
+    }
 }
 
 
 #[no_mangle]
 pub extern fn kstart(kernel_args: &KernelArgs) {
-    device::init_devices(); 
+    task::cpu_local::init();
+    device::init_devices();
+    device::vga_buffer::WRITER.lock().clear_screen();
+
+    crate::klog::init();
+    crate::klog::add_sink(&device::vga_buffer::VGA_SINK);
+    crate::klog::add_sink(&device::serial::SERIAL_SINK);
+    unsafe {
+        let env = slice::from_raw_parts(kernel_args.env_base as *const u8,
+                                        kernel_args.env_size as usize);
+        crate::cmdline::init(env);
+    }
+
+    platform::gdt::init();
+    syscall::init();
     interrupt::init_idt();
-    memory::init_memory();
+    debug::gdb::init();
+
+    let kernel_base = PhysAddr::new(kernel_args.kernel_base);
+    let kernel_size = kernel_args.kernel_size as usize;
+    memory::init_memory(kernel_base, kernel_size);
+    memory::address_space::init();
+    unsafe {
+        let image = memory::phys_to_virt(kernel_base).as_u64() as *const u8;
+        crate::panic::symbols::init(slice::from_raw_parts(image, kernel_size));
+    }
+    if let Some((base, info)) = kernel_args.framebuffer() {
+        unsafe { device::framebuffer::init(base, info); }
+    }
+    unsafe { device::console::init(); }
+    crate::klog::remove_sink(&device::vga_buffer::VGA_SINK);
+    crate::klog::dmesg(&device::console::CONSOLE_SINK);
+    crate::klog::add_sink(&device::console::CONSOLE_SINK);
+    device::local_apic::init();
+    device::i8042::init();
+    device::keyboard::init();
+    device::mouse::init();
+    device::serial::init_console_input();
+    task::init("kmain");
+
     unsafe { platform::instructions::sti();}
-    
-    device::vga_buffer::WRITER.lock().clear_screen();
-    
-    println!("APIC support: {}", device::cpu::has_apic());
-    println!("Vendor: {}", device::cpu::get_vendor_info().as_string());
+
+    info!("APIC support: {}", device::cpu::has_apic());
+    info!("Vendor: {}", device::cpu::get_vendor_info().as_string());
 
     super::super::kmain();
 }
\ No newline at end of file
@@ -0,0 +1,137 @@
+//! The global descriptor table, which replaces the bootloader's, and a task state segment for
+//! every CPU.
+//!
+//! ```text
+//! 0x00  null
+//! 0x08  kernel code
+//! 0x10  kernel data
+//! 0x18  user data   (selector 0x1b)
+//! 0x20  user code   (selector 0x23)
+//! 0x28  TSS of CPU 0, two entries each
+//! ```
+//!
+//! The kernel segments stay where the bootloader put them. The user data segment comes right
+//! before the user code segment, as `sysret` expects.
+//!
+//! The TSS also holds the interrupt stacks of the CPU, which the IDT entries of non-maskable
+//! interrupts and double faults select: both can happen on a stack the kernel cannot use, such
+//! as the user stack in the first and last instructions of the `syscall` entry, or an overflowed
+//! kernel stack.
+
+use ailurus_core::gdt::{Descriptor, TaskStateSegment};
+use core::mem::size_of;
+use super::super::interrupt::idt::DescriptorTablePointer;
+use super::super::task::{this_cpu, MAX_CPUS};
+use super::segmentation::{self, PrivilegeLevel, SegmentSelector};
+
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
+
+/// The entries of the interrupt stack table used by the IDT.
+pub const NMI_STACK_INDEX: u16 = 1;
+pub const DOUBLE_FAULT_STACK_INDEX: u16 = 2;
+
+const INTERRUPT_STACK_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct InterruptStack([u8; INTERRUPT_STACK_SIZE]);
+
+impl InterruptStack {
+    fn top(&self) -> u64 {
+        self as *const InterruptStack as u64 + INTERRUPT_STACK_SIZE as u64
+    }
+}
+
+#[repr(C)]
+struct Gdt {
+    segments: [Descriptor; 5],
+    tss: [[Descriptor; 2]; MAX_CPUS],
+}
+
+static mut GDT: Gdt = Gdt {
+    segments: [
+        Descriptor::NULL,
+        Descriptor::KERNEL_CODE,
+        Descriptor::KERNEL_DATA,
+        Descriptor::USER_DATA,
+        Descriptor::USER_CODE,
+    ],
+    tss: [[Descriptor::NULL; 2]; MAX_CPUS],
+};
+
+static mut TSS: [TaskStateSegment; MAX_CPUS] = [TaskStateSegment::new(); MAX_CPUS];
+
+static mut NMI_STACKS: [InterruptStack; MAX_CPUS] =
+    [InterruptStack([0; INTERRUPT_STACK_SIZE]); MAX_CPUS];
+static mut DOUBLE_FAULT_STACKS: [InterruptStack; MAX_CPUS] =
+    [InterruptStack([0; INTERRUPT_STACK_SIZE]); MAX_CPUS];
+
+fn tss_selector(cpu: usize) -> SegmentSelector {
+    SegmentSelector::new(5 + 2 * cpu as u16, PrivilegeLevel::Ring0)
+}
+
+unsafe fn lgdt(gdt: &DescriptorTablePointer) {
+    asm!("lgdt ($0)" :: "r" (gdt) : "memory");
+}
+
+unsafe fn ltr(selector: SegmentSelector) {
+    asm!("ltr $0" :: "r" (selector.0) : "memory");
+}
+
+/// Load the GDT and the TSS of the executing CPU, and reload the segment registers. Every CPU
+/// calls it once, before it takes an interrupt from user mode.
+pub fn init() {
+    let cpu = this_cpu();
+    assert!(cpu < MAX_CPUS, "CPU {} is beyond MAX_CPUS", cpu);
+    unsafe {
+        TSS[cpu].set_interrupt_stack(NMI_STACK_INDEX as usize, NMI_STACKS[cpu].top());
+        TSS[cpu].set_interrupt_stack(DOUBLE_FAULT_STACK_INDEX as usize,
+                                     DOUBLE_FAULT_STACKS[cpu].top());
+        GDT.tss[cpu] = Descriptor::tss(&TSS[cpu] as *const _ as u64);
+        lgdt(&DescriptorTablePointer {
+            base: &GDT as *const _ as u64,
+            limit: (size_of::<Gdt>() - 1) as u16,
+        });
+        segmentation::load_cs(KERNEL_CODE_SELECTOR);
+        segmentation::load_ss(KERNEL_DATA_SELECTOR);
+        segmentation::load_ds(KERNEL_DATA_SELECTOR);
+        segmentation::load_es(KERNEL_DATA_SELECTOR);
+        ltr(tss_selector(cpu));
+    }
+}
+
+/// Make interrupts from user mode on `cpu` switch to the stack ending at `stack_top`. Only
+/// called by the scheduler, for the task it switches to.
+pub fn set_kernel_stack(cpu: usize, stack_top: u64) {
+    unsafe { TSS[cpu].set_kernel_stack(stack_top); }
+}
+
+/// The stack interrupts from user mode on `cpu` switch to.
+pub fn kernel_stack(cpu: usize) -> u64 {
+    unsafe { TSS[cpu].kernel_stack() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn kernel_segments_are_loaded() {
+        assert_eq!(segmentation::get_cs().0, KERNEL_CODE_SELECTOR.0);
+        assert_eq!(USER_CODE_SELECTOR.0, 0x23);
+        assert_eq!(USER_DATA_SELECTOR.0, 0x1b);
+    }
+
+    #[test_case]
+    fn interrupt_stacks_are_installed() {
+        let cpu = this_cpu();
+        unsafe {
+            assert_eq!(TSS[cpu].interrupt_stack(NMI_STACK_INDEX as usize), NMI_STACKS[cpu].top());
+            assert_eq!(TSS[cpu].interrupt_stack(DOUBLE_FAULT_STACK_INDEX as usize),
+                       DOUBLE_FAULT_STACKS[cpu].top());
+        }
+    }
+}
@@ -8,10 +8,58 @@ pub unsafe fn cli() {
     asm!("cli"::::"volatile");
 }
 
+pub fn rflags() -> u64 {
+    let rflags: u64;
+    unsafe { asm!("pushfq; pop $0" : "=r"(rflags) : : "memory" : "volatile"); }
+    rflags
+}
+
+pub fn interrupts_enabled() -> bool {
+    rflags() & (1 << 9) != 0
+}
+
 pub unsafe fn hlt() {
     asm!("hlt"::::"volatile");
 }
 
+/// Enable interrupts and halt until the next one. `sti` only takes effect after the following
+/// instruction, so no interrupt can be handled before `hlt`.
+pub unsafe fn enable_interrupts_and_hlt() {
+    asm!("sti
+          hlt"::::"volatile");
+}
+
+pub unsafe fn invlpg(address: u64) {
+    asm!("invlpg ($0)" :: "r"(address) : "memory" : "volatile");
+}
+
+/// The address whose access caused the last page fault.
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov %cr2, $0" : "=r"(value) ::: "volatile"); }
+    value
+}
+
+pub fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov %cr3, $0" : "=r"(value) ::: "volatile"); }
+    value
+}
+
+pub unsafe fn write_cr3(value: u64) {
+    asm!("mov $0, %cr3" :: "r"(value) : "memory" : "volatile");
+}
+
+/// Save the x87 and SSE registers to the 512 bytes at `area`, 16-byte aligned.
+pub unsafe fn fxsave(area: *mut u8) {
+    asm!("fxsave64 ($0)" :: "r"(area) : "memory" : "volatile");
+}
+
+/// Load the x87 and SSE registers from the 512 bytes at `area`, as saved by `fxsave`.
+pub unsafe fn fxrstor(area: *const u8) {
+    asm!("fxrstor64 ($0)" :: "r"(area) : "memory" : "volatile");
+}
+
 // It will fail to execute when CPU does not support `cpuid`, so this function is unsafe.
 pub unsafe fn cpuid(eax: u32) -> (u32, u32, u32) {
     let ebx: u32;
@@ -69,5 +117,219 @@ pub unsafe fn inl(port: u16) -> u32 {
 }
 
 pub unsafe fn outl(port: u16, value: u32) {
-    asm!("outw %eax, %dx" :: "{dx}"(port), "{eax}"(value) :: "volatile");
-}
\ No newline at end of file
+    asm!("outl %eax, %dx" :: "{dx}"(port), "{eax}"(value) :: "volatile");
+}
+
+// Debug registers
+bitflags! {
+    /// Debug status register, telling which condition caused a debug exception.
+    pub struct Dr6: u64 {
+        /// The condition of breakpoint 0 was met.
+        const TRAP0 = 1 << 0;
+        const TRAP1 = 1 << 1;
+        const TRAP2 = 1 << 2;
+        const TRAP3 = 1 << 3;
+        const TRAPS = 0xf;
+        /// The next instruction accesses a debug register while DR7.GD is set.
+        const DEBUG_REGISTER_ACCESS = 1 << 13;
+        /// Single step through the trap flag.
+        const SINGLE_STEP = 1 << 14;
+        /// Task switch to a task with the T flag set.
+        const TASK_SWITCH = 1 << 15;
+    }
+}
+
+impl Dr6 {
+    /// Whether the condition of breakpoint `index` (0-3) was met.
+    pub fn is_triggered(&self, index: usize) -> bool {
+        assert!(index < 4, "Invalid breakpoint index: {}", index);
+        self.bits() & 1 << index != 0
+    }
+}
+
+/// When a hardware breakpoint triggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointCondition {
+    Execute = 0b00,
+    Write = 0b01,
+    Io = 0b10,
+    ReadWrite = 0b11,
+}
+
+/// Number of bytes covered by a hardware breakpoint. The address must be aligned to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointLength {
+    One = 0b00,
+    Two = 0b01,
+    Eight = 0b10,
+    Four = 0b11,
+}
+
+impl BreakpointLength {
+    pub fn from_bytes(bytes: usize) -> Option<Self> {
+        match bytes {
+            1 => Some(BreakpointLength::One),
+            2 => Some(BreakpointLength::Two),
+            4 => Some(BreakpointLength::Four),
+            8 => Some(BreakpointLength::Eight),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            BreakpointLength::One => 1,
+            BreakpointLength::Two => 2,
+            BreakpointLength::Four => 4,
+            BreakpointLength::Eight => 8,
+        }
+    }
+}
+
+/// Debug control register, enabling breakpoints 0-3 and setting their conditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dr7(u64);
+
+impl Dr7 {
+    /// Bit 10 is reserved and always set.
+    pub const fn new() -> Self {
+        Dr7(1 << 10)
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Dr7(bits | 1 << 10)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        assert!(index < 4, "Invalid breakpoint index: {}", index);
+        self.0 & 0b11 << (index * 2) != 0
+    }
+
+    /// Globally enable breakpoint `index`, which does not get cleared on task switches.
+    pub fn enable(&mut self, index: usize, condition: BreakpointCondition,
+                  length: BreakpointLength) -> &mut Self {
+        assert!(index < 4, "Invalid breakpoint index: {}", index);
+        let control_shift = 16 + index * 4;
+        self.0 &= !(0b1111 << control_shift);
+        self.0 |= (condition as u64 | (length as u64) << 2) << control_shift;
+        self.0 |= 0b10 << (index * 2);
+        self
+    }
+
+    pub fn disable(&mut self, index: usize) -> &mut Self {
+        assert!(index < 4, "Invalid breakpoint index: {}", index);
+        self.0 &= !(0b11 << (index * 2) | 0b1111 << (16 + index * 4));
+        self
+    }
+
+    pub fn condition(&self, index: usize) -> BreakpointCondition {
+        match (self.0 >> (16 + index * 4)) & 0b11 {
+            0b00 => BreakpointCondition::Execute,
+            0b01 => BreakpointCondition::Write,
+            0b10 => BreakpointCondition::Io,
+            _ => BreakpointCondition::ReadWrite,
+        }
+    }
+
+    pub fn length(&self, index: usize) -> BreakpointLength {
+        match (self.0 >> (18 + index * 4)) & 0b11 {
+            0b00 => BreakpointLength::One,
+            0b01 => BreakpointLength::Two,
+            0b10 => BreakpointLength::Eight,
+            _ => BreakpointLength::Four,
+        }
+    }
+}
+
+macro_rules! debug_address_register {
+    ($read:ident, $write:ident, $read_template:tt, $write_template:tt) => {
+        /// Read the linear address of a hardware breakpoint.
+        pub fn $read() -> u64 {
+            let value: u64;
+            unsafe { asm!($read_template : "=r"(value) ::: "volatile"); }
+            value
+        }
+
+        /// Set the linear address of a hardware breakpoint.
+        pub unsafe fn $write(address: u64) {
+            asm!($write_template :: "r"(address) :: "volatile");
+        }
+    };
+}
+
+debug_address_register!(read_dr0, write_dr0, "mov %dr0, $0", "mov $0, %dr0");
+debug_address_register!(read_dr1, write_dr1, "mov %dr1, $0", "mov $0, %dr1");
+debug_address_register!(read_dr2, write_dr2, "mov %dr2, $0", "mov $0, %dr2");
+debug_address_register!(read_dr3, write_dr3, "mov %dr3, $0", "mov $0, %dr3");
+
+/// Read the address of breakpoint `index` (0-3).
+pub fn read_breakpoint_address(index: usize) -> u64 {
+    match index {
+        0 => read_dr0(),
+        1 => read_dr1(),
+        2 => read_dr2(),
+        3 => read_dr3(),
+        _ => panic!("Invalid breakpoint index: {}", index),
+    }
+}
+
+/// Set the address of breakpoint `index` (0-3).
+pub unsafe fn write_breakpoint_address(index: usize, address: u64) {
+    match index {
+        0 => write_dr0(address),
+        1 => write_dr1(address),
+        2 => write_dr2(address),
+        3 => write_dr3(address),
+        _ => panic!("Invalid breakpoint index: {}", index),
+    }
+}
+
+pub fn read_dr6() -> Dr6 {
+    let value: u64;
+    unsafe { asm!("mov %dr6, $0" : "=r"(value) ::: "volatile"); }
+    Dr6::from_bits_truncate(value)
+}
+
+/// The processor never clears DR6, so the handler has to after each debug exception.
+pub unsafe fn write_dr6(value: Dr6) {
+    // Bits 4-11 and 16-31 are reserved and read as ones.
+    asm!("mov $0, %dr6" :: "r"(value.bits() | 0xffff_0ff0) :: "volatile");
+}
+
+pub fn read_dr7() -> Dr7 {
+    let value: u64;
+    unsafe { asm!("mov %dr7, $0" : "=r"(value) ::: "volatile"); }
+    Dr7::from_bits(value)
+}
+
+pub unsafe fn write_dr7(value: Dr7) {
+    asm!("mov $0, %dr7" :: "r"(value.bits()) :: "volatile");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn dr7_encodes_conditions() {
+        let mut dr7 = Dr7::new();
+        dr7.enable(1, BreakpointCondition::Write, BreakpointLength::Eight);
+        assert_eq!(dr7.bits(), 1 << 10 | 0b10 << 2 | 0b1001 << 20);
+        assert!(dr7.is_enabled(1) && !dr7.is_enabled(0));
+        assert_eq!(dr7.condition(1), BreakpointCondition::Write);
+        assert_eq!(dr7.length(1), BreakpointLength::Eight);
+        dr7.disable(1);
+        assert_eq!(dr7, Dr7::new());
+    }
+
+    #[test_case]
+    fn dr6_reports_triggered_breakpoints() {
+        let dr6 = Dr6::from_bits_truncate(0xffff_4ff2);
+        assert!(dr6.is_triggered(1) && !dr6.is_triggered(0));
+        assert!(dr6.contains(Dr6::SINGLE_STEP));
+    }
+}
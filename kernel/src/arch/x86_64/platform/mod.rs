@@ -1,3 +1,4 @@
+pub mod gdt;
 pub mod port;
 pub mod instructions;
 pub mod segmentation;
\ No newline at end of file
@@ -42,6 +42,19 @@ pub fn get_cs() -> SegmentSelector {
     SegmentSelector(selector)
 }
 
+/// Reload CS with a far return to the next instruction.
+#[inline(never)]
+pub unsafe fn load_cs(selector: SegmentSelector) {
+    asm!(
+        "push rax
+         lea rax, [rip + .Lreloaded_cs]
+         push rax
+         retfq
+        .Lreloaded_cs:"
+        : :"{rax}"(selector.0 as u64) :"rax", "memory" :"intel", "volatile"
+    )
+}
+
 pub unsafe fn load_ds(selector: SegmentSelector) {
     asm!(
         "mov ds, rax"
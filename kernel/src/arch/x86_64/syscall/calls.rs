@@ -0,0 +1,155 @@
+//! The system calls, in the order of their numbers.
+
+use ailurus_core::syscall::{self, Arguments, Error};
+use core::{cmp, str};
+use super::super::device::console::{self, KERNEL_TERMINAL};
+use super::super::device::keyboard;
+use super::super::memory::page_table::PageTableFlags;
+use super::super::memory::user_access::UserSlice;
+use super::super::memory::{user, VirtAddr};
+use super::super::task::{self, sync::Mutex};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// How many bytes `write` copies from user memory at once.
+const CHUNK_SIZE: usize = 256;
+
+/// The longest line `read` returns at once, newline included.
+const LINE_SIZE: usize = 256;
+
+/// Held by `mmap` from checking that pages are unmapped to mapping them, since tasks may share
+/// an address space.
+static MMAP_LOCK: Mutex<()> = Mutex::new(());
+
+/// Print `bytes` to the kernel terminal, with a replacement character for every invalid UTF-8
+/// sequence. Returns how many were printed: all but an incomplete sequence at the end, which
+/// the next bytes may complete, unless `last`.
+fn print_bytes(bytes: &[u8], last: bool) -> usize {
+    let mut rest = bytes;
+    loop {
+        match str::from_utf8(rest) {
+            Ok(text) => {
+                console::print(format_args!("{}", text));
+                return bytes.len();
+            }
+            Err(error) => {
+                let (valid, after) = rest.split_at(error.valid_up_to());
+                let valid = unsafe { str::from_utf8_unchecked(valid) };
+                match error.error_len() {
+                    Some(invalid) => {
+                        console::print(format_args!("{}\u{fffd}", valid));
+                        rest = &after[invalid..];
+                    }
+                    None if last => {
+                        console::print(format_args!("{}\u{fffd}", valid));
+                        return bytes.len();
+                    }
+                    None => {
+                        console::print(format_args!("{}", valid));
+                        return bytes.len() - after.len();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `write(fd, buffer, len)`: standard output and standard error go to the kernel terminal.
+pub fn write(arguments: &Arguments) -> Result<u64, Error> {
+    let (fd, address, len) = (arguments[0], arguments[1], arguments[2]);
+    if fd != syscall::STDOUT && fd != syscall::STDERR {
+        return Err(Error::BadFileDescriptor);
+    }
+    let bytes = UserSlice::new(address, len)?;
+    let mut buffer = [0; CHUNK_SIZE];
+    // The start of a UTF-8 sequence cut by the end of the previous chunk.
+    let mut kept = 0;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let count = cmp::min(CHUNK_SIZE - kept, bytes.len() - offset);
+        bytes.subslice(offset, count).read(&mut buffer[kept..kept + count])?;
+        offset += count;
+        let end = kept + count;
+        let printed = print_bytes(&buffer[..end], offset == bytes.len());
+        buffer[..end].rotate_left(printed);
+        kept = end - printed;
+    }
+    Ok(len)
+}
+
+/// `read(fd, buffer, len)`: standard input is the next line typed on the kernel terminal, with
+/// its newline, cut to `len` bytes.
+pub fn read(arguments: &Arguments) -> Result<u64, Error> {
+    let (fd, address, len) = (arguments[0], arguments[1], arguments[2]);
+    if fd != syscall::STDIN {
+        return Err(Error::BadFileDescriptor);
+    }
+    let buffer = UserSlice::new(address, len)?;
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    let mut line = [0; LINE_SIZE];
+    let line_len = keyboard::read_line(KERNEL_TERMINAL, &mut line[..LINE_SIZE - 1]).len();
+    line[line_len] = b'\n';
+    let count = cmp::min(line_len + 1, buffer.len());
+    buffer.subslice(0, count).write(&line[..count])?;
+    Ok(count as u64)
+}
+
+/// `exit(status)`
+pub fn exit(arguments: &Arguments) -> Result<u64, Error> {
+    let name = task::current().map_or("?", |task| task.name);
+    let status = arguments[0] as i64;
+    info!("Task {} exited with status {}", name, status);
+    task::exit_with(status)
+}
+
+/// `yield()`
+pub fn yield_now(_arguments: &Arguments) -> Result<u64, Error> {
+    task::yield_now();
+    Ok(0)
+}
+
+/// `sleep(milliseconds)`
+pub fn sleep(arguments: &Arguments) -> Result<u64, Error> {
+    task::sleep(arguments[0]);
+    Ok(0)
+}
+
+/// `mmap(address, len, protection)`: zeroed pages at `address`, which has to be page-aligned and
+/// not mapped yet. If `address` is zero, in the first unmapped room from the end of the previous
+/// mapping the kernel placed for the task, or from `user::MMAP_START`. At most
+/// `syscall::MAX_MAPPING_SIZE` bytes at once, and `OutOfMemory` if physical memory runs out.
+pub fn mmap(arguments: &Arguments) -> Result<u64, Error> {
+    let (address, len, protection) = (arguments[0], arguments[1], arguments[2]);
+    let size = syscall::mapping_size(len)?;
+    syscall::check_protection(protection)?;
+    let _lock = MMAP_LOCK.lock();
+    let start = if address == 0 {
+        user::find_unmapped(task::next_mapping(), size).ok_or(Error::OutOfMemory)?
+    } else if address % PAGE_SIZE == 0 && user::contains(address, size)
+        && user::is_unmapped(VirtAddr::new(address), size) {
+        address
+    } else {
+        return Err(Error::InvalidArgument);
+    };
+    let mut flags = PageTableFlags::empty();
+    if protection & syscall::PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if protection & syscall::PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if !unsafe { user::try_map(VirtAddr::new(start), size, flags) } {
+        return Err(Error::OutOfMemory);
+    }
+    if address == 0 {
+        task::set_next_mapping(start + size);
+    }
+    Ok(start)
+}
+
+/// `getpid()`
+pub fn getpid(_arguments: &Arguments) -> Result<u64, Error> {
+    Ok(task::current().map_or(0, |task| task.id.0))
+}
@@ -0,0 +1,220 @@
+//! System calls from user mode, with `syscall` or `int 0x80` (see `ailurus_core::syscall` for
+//! the ABI).
+//!
+//! Both entries save the registers of the task on its kernel stack in an `InterruptFrame`, the
+//! same as an interrupt from user mode, and look the call up in `TABLE`. Unlike an interrupt,
+//! `syscall` does not switch stacks: its entry swaps in the kernel GS base with `swapgs` to find
+//! the `CpuLocal` of the CPU (see `task::cpu_local`), which holds the kernel stack of the running
+//! task, and swaps the user GS base back in right before `sysret`. Until the switch, and again
+//! from the return to the user stack to `sysret`, the kernel runs on the user stack with
+//! interrupts masked: only a non-maskable interrupt can come, and it switches to a stack of its
+//! own (see `platform::gdt`).
+//!
+//! Calls run in task context with interrupts enabled: they may sleep, and be preempted. They
+//! access user memory through `memory::user_access`.
+
+mod calls;
+
+use ailurus_core::syscall::{self, Arguments, Error};
+use super::interrupt::util::InterruptFrame;
+use super::memory::user::USER_END;
+use super::platform::gdt::{KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR};
+use super::platform::instructions;
+use super::task;
+
+/// The vector of the `int 0x80` fallback.
+pub const INT_VECTOR: u8 = 0x80;
+
+const IA32_EFER: u32 = 0xc000_0080;
+const IA32_STAR: u32 = 0xc000_0081;
+const IA32_LSTAR: u32 = 0xc000_0082;
+const IA32_FMASK: u32 = 0xc000_0084;
+
+const EFER_SYSCALL_ENABLE: u32 = 1;
+/// The flags `syscall` clears: trap, interrupt, direction and alignment check.
+const SYSCALL_FLAGS_MASK: u32 = 0x4_0700;
+
+type Call = fn(&Arguments) -> Result<u64, Error>;
+
+/// The calls, indexed by number.
+static TABLE: [Call; syscall::COUNT] = [
+    calls::write,
+    calls::read,
+    calls::exit,
+    calls::yield_now,
+    calls::sleep,
+    calls::mmap,
+    calls::getpid,
+];
+
+/// Enable `syscall` on the executing CPU. Every CPU calls it once, after `gdt::init`.
+pub fn init() {
+    unsafe {
+        let (efer, efer_high) = instructions::rdmsr(IA32_EFER);
+        instructions::wrmsr(efer | EFER_SYSCALL_ENABLE, efer_high, IA32_EFER);
+        // `syscall` loads CS from bits 32-47 and SS from the next entry. `sysret` loads SS from
+        // the entry after the one in bits 48-63 and CS from the one after that, user data then
+        // user code.
+        let star = (KERNEL_DATA_SELECTOR.0 as u32) << 16 | KERNEL_CODE_SELECTOR.0 as u32;
+        instructions::wrmsr(0, star, IA32_STAR);
+        let entry = syscall_entry as u64;
+        instructions::wrmsr(entry as u32, (entry >> 32) as u32, IA32_LSTAR);
+        instructions::wrmsr(SYSCALL_FLAGS_MASK, 0, IA32_FMASK);
+    }
+}
+
+/// The target of `syscall`, with the user `rip` in `rcx` and `rflags` in `r11`, interrupts
+/// disabled and the user stack.
+#[naked]
+unsafe extern fn syscall_entry() {
+    // The frame of an interrupt from user mode, with the selectors of the GDT.
+    asm!("swapgs
+          mov gs:[16], rsp
+          mov rsp, gs:[8]
+          push 0x1b
+          push qword ptr gs:[16]
+          push r11
+          push 0x23
+          push rcx"
+         : : : : "intel", "volatile");
+    context_push!();
+
+    let rsp: u64;
+    asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
+
+    dispatch(&mut *(rsp as *mut InterruptFrame));
+
+    context_pop!();
+    // Interrupts stay disabled on the user stack until `sysret` restores the flags.
+    asm!("swapgs
+          pop rcx
+          add rsp, 8
+          pop r11
+          pop rsp
+          sysretq"
+         : : : : "intel", "volatile");
+}
+
+/// The handler of `int 0x80`, an interrupt gate user mode may use.
+#[naked]
+pub unsafe extern fn interrupt_entry() {
+    swapgs_if_from_user!();
+    // Unlike `syscall`, the gate leaves the direction flag as user mode set it.
+    asm!("cld" : : : : "intel", "volatile");
+    context_push!();
+
+    let rsp: u64;
+    asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
+
+    dispatch(&mut *(rsp as *mut InterruptFrame));
+
+    context_pop!();
+    swapgs_if_from_user!();
+    iret!();
+}
+
+/// Run the call the registers in `frame` ask for, and put its result in `rax`.
+#[inline(never)]
+unsafe fn dispatch(frame: &mut InterruptFrame) {
+    let rip = frame.iret_registers.rip;
+    if rip >= USER_END {
+        // Right after the last byte of user space: `sysret` would fault in kernel mode.
+        task::user::end_faulting_task("SYSTEM CALL AT THE END OF USER SPACE", rip);
+    }
+    instructions::sti();
+    let number = frame.context_registers.rax;
+    let arguments = [
+        frame.context_registers.rdi, frame.context_registers.rsi, frame.context_registers.rdx,
+        frame.context_registers.r10, frame.context_registers.r8, frame.context_registers.r9,
+    ];
+    let result = if number < syscall::COUNT as u64 {
+        TABLE[number as usize](&arguments)
+    } else {
+        Err(Error::NoSuchCall)
+    };
+    frame.context_registers.rax = syscall::encode(result);
+    instructions::cli();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::memory::user;
+    use super::super::memory::VirtAddr;
+    use super::super::task::user::tests::run_user_program;
+
+    /// `mmap(0, 4096, PROT_READ | PROT_WRITE)`, then push the address, write 0x2a there and
+    /// `exit(0)`, making the calls with the two bytes of instruction given.
+    macro_rules! program {
+        ($first: expr, $second: expr) => {
+            [0xb8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5
+             0x31, 0xff,                         // xor edi, edi
+             0xbe, 0x00, 0x10, 0x00, 0x00,       // mov esi, 0x1000
+             0xba, 0x03, 0x00, 0x00, 0x00,       // mov edx, 3
+             $first, $second,
+             0x50,                               // push rax
+             0xc6, 0x00, 0x2a,                   // mov byte ptr [rax], 0x2a
+             0xb8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2
+             0x31, 0xff,                         // xor edi, edi
+             $first, $second]
+        };
+    }
+
+    static SYSCALL: [u8; 32] = program!(0x0f, 0x05);
+    static INT_0X80: [u8; 32] = program!(0xcd, 0x80);
+
+    /// `mmap(0, 1 << 40, PROT_READ)`, then push the result and `exit(0)`.
+    static MMAP_TOO_LARGE: [u8; 32] = [
+        0xb8, 0x05, 0x00, 0x00, 0x00,                               // mov eax, 5
+        0x31, 0xff,                                                 // xor edi, edi
+        0x48, 0xbe, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // movabs rsi, 1 << 40
+        0xba, 0x01, 0x00, 0x00, 0x00,                               // mov edx, 1
+        0x0f, 0x05,                                                 // syscall
+        0x50,                                                       // push rax
+        0xb8, 0x02, 0x00, 0x00, 0x00,                               // mov eax, 2
+        0x0f, 0x05,                                                 // syscall
+    ];
+
+    /// `write(1, USER_START + 0x40000, 4)` on an unmapped page, then push the result and
+    /// `exit(1)`.
+    static WRITE_UNMAPPED: [u8; 35] = [
+        0xb8, 0x00, 0x00, 0x00, 0x00,                               // mov eax, 0
+        0xbf, 0x01, 0x00, 0x00, 0x00,                               // mov edi, 1
+        0x48, 0xbe, 0x00, 0x00, 0x04, 0x00, 0x80, 0x00, 0x00, 0x00, // movabs rsi, ...
+        0xba, 0x04, 0x00, 0x00, 0x00,                               // mov edx, 4
+        0x0f, 0x05,                                                 // syscall
+        0x50,                                                       // push rax
+        0xb8, 0x02, 0x00, 0x00, 0x00,                               // mov eax, 2
+        0x0f, 0x05,                                                 // syscall
+    ];
+
+    fn check_mapping(address: u64) {
+        assert!(address >= user::MMAP_START);
+        unsafe {
+            assert_eq!(*(address as *const u8), 0x2a);
+            user::unmap(VirtAddr::new(address), 4096);
+        }
+    }
+
+    #[test_case]
+    fn syscall_returns_to_user_mode() {
+        check_mapping(run_user_program(&SYSCALL));
+    }
+
+    #[test_case]
+    fn int_0x80_returns_to_user_mode() {
+        check_mapping(run_user_program(&INT_0X80));
+    }
+
+    #[test_case]
+    fn oversized_mappings_fail_with_enomem() {
+        let result = run_user_program(&MMAP_TOO_LARGE);
+        assert_eq!(syscall::error_code(result), Some(Error::OutOfMemory.code()));
+    }
+
+    #[test_case]
+    fn bad_pointers_fail_with_efault() {
+        let result = run_user_program(&WRITE_UNMAPPED);
+        assert_eq!(syscall::error_code(result), Some(Error::Fault.code()));
+    }
+}
@@ -0,0 +1,108 @@
+//! Switching between the stacks of kernel threads.
+//!
+//! A task which is not running keeps its registers on its own stack, in the layout of the
+//! interrupt handlers' `ContextRegisters` followed by the flags and the return address, and only
+//! its stack pointer in its `Task`:
+//!
+//! ```text
+//! stack pointer -> rax, rbx, ..., r15   (ContextRegisters)
+//!                  rflags
+//!                  return address of switch_to
+//! ```
+//!
+//! The scheduler clears the saved stack pointer of a task before it switches away, since
+//! another CPU may pick the task before its registers are saved: `switch_to` waits for the stack
+//! pointer of the next task to be set.
+//!
+//! The kernel is built without floating point, so only user mode uses the x87 and SSE
+//! registers. The scheduler saves them in the `FpuState` of the previous task and loads those of
+//! the next one before switching stacks, with its lock held.
+
+use core::mem;
+use core::ptr;
+use super::super::interrupt::util::ContextRegisters;
+use super::super::platform::instructions;
+
+/// The x87 and SSE registers of a task, in the layout of `fxsave`.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct FpuState {
+    control_word: u16,
+    status: [u8; 22],
+    mxcsr: u32,
+    registers: [u8; 484],
+}
+
+impl FpuState {
+    /// The registers as after `fninit`, with every SSE exception masked.
+    pub const INITIAL: FpuState = FpuState {
+        control_word: 0x037f,
+        status: [0; 22],
+        mxcsr: 0x1f80,
+        registers: [0; 484],
+    };
+
+    /// Store the registers of the executing CPU.
+    pub unsafe fn save(&mut self) {
+        instructions::fxsave(self as *mut FpuState as *mut u8);
+    }
+
+    /// Load the registers into the executing CPU.
+    pub unsafe fn restore(&self) {
+        instructions::fxrstor(self as *const FpuState as *const u8);
+    }
+}
+
+/// The stack of a task which has not run yet, as `switch_to` expects it.
+#[repr(packed)]
+struct InitialFrame {
+    registers: ContextRegisters,
+    rflags: u64,
+    /// Where `switch_to` returns to.
+    rip: u64,
+    /// Never used, but aligns the stack as if `rip` had been called.
+    return_address: u64,
+}
+
+/// Interrupts enabled, plus the reserved bit 1.
+const INITIAL_RFLAGS: u64 = 0x202;
+
+/// Prepare the stack ending at `stack_top` so that switching to it calls `entry(argument)` with
+/// interrupts enabled, and return the stack pointer to switch to.
+pub unsafe fn init_stack(stack_top: u64, entry: extern "C" fn(u64) -> !, argument: u64) -> u64 {
+    let frame = (stack_top - mem::size_of::<InitialFrame>() as u64) as *mut InitialFrame;
+    ptr::write(frame, InitialFrame {
+        registers: ContextRegisters { rdi: argument, ..ContextRegisters::default() },
+        rflags: INITIAL_RFLAGS,
+        rip: entry as u64,
+        return_address: 0,
+    });
+    frame as u64
+}
+
+/// Save the registers of the running task on its stack and its stack pointer in `*previous`,
+/// then continue the task whose stack pointer is `*next`, once it is not zero. Returns when
+/// another task switches back to `*previous`.
+pub unsafe fn switch_to(previous: *mut u64, next: *const u64) {
+    asm!("call switch_stacks"
+         : : "{rdi}"(previous), "{rsi}"(next) : "memory" : "intel", "volatile");
+}
+
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn switch_stacks() {
+    asm!("pushfq" : : : : "intel", "volatile");
+    context_push!();
+    asm!("mov [rdi], rsp
+        .Lwait_for_stack_pointer:
+          mov rax, [rsi]
+          pause
+          test rax, rax
+          jz .Lwait_for_stack_pointer
+          mov rsp, rax"
+         : : : "memory" : "intel", "volatile");
+    context_pop!();
+    asm!("popfq
+          ret"
+         : : : : "intel", "volatile");
+}
@@ -0,0 +1,80 @@
+//! Per-CPU data, found through the GS base.
+//!
+//! Every CPU gets a dense index below `MAX_CPUS` as it starts, the bootstrap processor 0, and
+//! points its GS base at its `CpuLocal`. `this_cpu` is then a single load relative to GS,
+//! unlike `cpuid`, which serializes the CPU, traps to the hypervisor under virtualization and
+//! returns sparse local APIC ids.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::super::device::cpu;
+use super::super::platform::instructions;
+use super::MAX_CPUS;
+
+const IA32_GS_BASE: u32 = 0xc000_0101;
+const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CpuLocal {
+    /// The index of the CPU, at `gs:[0]`.
+    index: usize,
+    /// The top of the kernel stack of the running task, for the `syscall` entry, at `gs:[8]`.
+    kernel_stack: u64,
+    /// The user stack pointer while the `syscall` entry switches stacks, at `gs:[16]`.
+    user_stack: u64,
+    /// The local APIC id, to send interrupts to the CPU.
+    apic_id: u8,
+}
+
+static mut CPU_LOCALS: [CpuLocal; MAX_CPUS] =
+    [CpuLocal { index: 0, kernel_stack: 0, user_stack: 0, apic_id: 0 }; MAX_CPUS];
+
+/// The number of CPUs which called `init`.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Give the executing CPU the next index and point its GS base at its `CpuLocal`. Every CPU
+/// calls it once, before anything uses `this_cpu`: the bootstrap processor first thing in
+/// `kstart`, the application processors in `task::start_cpu`.
+pub fn init() -> usize {
+    let index = CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+    assert!(index < MAX_CPUS, "CPU {} is beyond MAX_CPUS", index);
+    unsafe {
+        CPU_LOCALS[index].index = index;
+        CPU_LOCALS[index].apic_id = cpu::cpu_id();
+        let local = &CPU_LOCALS[index] as *const CpuLocal as u64;
+        instructions::wrmsr(local as u32, (local >> 32) as u32, IA32_GS_BASE);
+        // The GS base of user mode, which `swapgs` swaps in on the way there.
+        instructions::wrmsr(0, 0, IA32_KERNEL_GS_BASE);
+    }
+    index
+}
+
+/// The index of the executing CPU.
+pub fn this_cpu() -> usize {
+    let index: usize;
+    unsafe { asm!("mov $0, qword ptr gs:[0]" : "=r"(index) : : : "intel", "volatile"); }
+    index
+}
+
+/// Make `syscall` on `cpu` switch to the kernel stack whose top is `top`. Called by the
+/// scheduler with the next task's stack, together with `gdt::set_kernel_stack`.
+pub fn set_kernel_stack(cpu: usize, top: u64) {
+    unsafe { CPU_LOCALS[cpu].kernel_stack = top; }
+}
+
+/// The local APIC id of the CPU with index `cpu`.
+pub fn apic_id(cpu: usize) -> u8 {
+    assert!(cpu < CPU_COUNT.load(Ordering::SeqCst), "CPU {} is not online", cpu);
+    unsafe { CPU_LOCALS[cpu].apic_id }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn this_cpu_is_a_dense_index() {
+        assert!(this_cpu() < CPU_COUNT.load(Ordering::SeqCst));
+        assert_eq!(apic_id(this_cpu()), cpu::cpu_id());
+    }
+}
@@ -0,0 +1,295 @@
+//! Running ELF executables in user mode.
+//!
+//! ```ignore
+//! // In a spawned task:
+//! let error = task::exec::exec(INIT, &[b"init"], &[b"TERM=ailurus"]);
+//! error!("Cannot run init: {:?}", error);
+//! ```
+//!
+//! `load` checks the file (see `ailurus_core::elf`), copies its loadable segments to the user
+//! part of the active address space, each page with the permissions of the segments on it, and
+//! builds the initial stack below `STACK_TOP` with the arguments, the environment and the
+//! auxiliary vector the System V ABI expects. Static executables go at their link address,
+//! which has to be in the user part; position-independent ones at `PIE_BASE`, after applying
+//! their relocations. Dynamically linked executables are refused, as there is no dynamic linker.
+//!
+//! `exec` loads the program in a fresh address space (see `memory::address_space`), so that
+//! programs running in different tasks do not see each other. It replaces the one the task had,
+//! which is destroyed, and is itself destroyed with the pages of the program when the task exits.
+//! `mmap` starts placing the mappings of the program at `user::MMAP_START` again.
+
+use ailurus_core::elf::stack::{self, AT_BASE, AT_ENTRY, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM};
+use ailurus_core::elf::{Elf, ElfError, SegmentFlags, PROGRAM_HEADER_SIZE};
+use core::{ptr, slice};
+use super::super::device::pit;
+use super::super::memory::address_space::AddressSpace;
+use super::super::memory::page_table::PageTableFlags;
+use super::super::memory::user::{self, MMAP_START, USER_END};
+use super::super::memory::VirtAddr;
+use super::{replace_address_space, set_next_mapping};
+use super::user::enter_user_mode;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Where position-independent executables are loaded.
+pub const PIE_BASE: u64 = 0x0000_1000_0000_0000;
+
+/// The top of the user stack, one page below the end of the user part.
+pub const STACK_TOP: u64 = USER_END - PAGE_SIZE;
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    Elf(ElfError),
+    /// The segments do not lie in the user part of the address space.
+    OutOfRange,
+    /// Something is already mapped where the segments or the stack go.
+    AddressInUse,
+    /// The arguments and the environment do not fit on the stack.
+    ArgumentsTooLong,
+    /// No memory left for a new address space.
+    OutOfMemory,
+}
+
+impl From<ElfError> for ExecError {
+    fn from(error: ElfError) -> Self {
+        ExecError::Elf(error)
+    }
+}
+
+/// A program loaded in the user part of the address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    /// Added to the addresses of the file, zero for static executables.
+    pub base: u64,
+    pub entry: u64,
+    pub stack_pointer: u64,
+    start: u64,
+    end: u64,
+}
+
+impl Image {
+    /// Unmap the segments and the stack of the program, once no task runs it anymore.
+    pub unsafe fn unload(&self) {
+        user::unmap(VirtAddr::new(self.start), self.end - self.start);
+        user::unmap(VirtAddr::new(STACK_TOP - STACK_SIZE), STACK_SIZE);
+    }
+}
+
+/// The flags of the user page at `page`, relative to the load base: writable or executable if
+/// any segment on it is.
+fn page_flags(elf: &Elf, page: u64) -> PageTableFlags {
+    let mut flags = PageTableFlags::NO_EXECUTE;
+    for segment in elf.segments() {
+        let (start, end) = segment.pages();
+        if page < start || page >= end {
+            continue;
+        }
+        if segment.flags.contains(SegmentFlags::WRITE) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags.contains(SegmentFlags::EXECUTE) {
+            flags.remove(PageTableFlags::NO_EXECUTE);
+        }
+    }
+    flags
+}
+
+/// The bytes `AT_RANDOM` points to. There is no entropy source yet, so they only depend on the
+/// uptime.
+fn random_bytes() -> [u8; 16] {
+    let mut state = pit::uptime_micros() | 1;
+    let mut bytes = [0; 16];
+    for byte in bytes.iter_mut() {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *byte = state as u8;
+    }
+    bytes
+}
+
+/// Load the ELF executable `executable` with the arguments `argv` and the environment `envp`
+/// in the active address space. Nothing is mapped if it fails.
+pub unsafe fn load(executable: &[u8], argv: &[&[u8]], envp: &[&[u8]])
+                   -> Result<Image, ExecError> {
+    let elf = Elf::parse(executable)?;
+    let base = if elf.is_position_independent() { PIE_BASE } else { 0 };
+    let (start, end) = elf.extent();
+    let (start, end) = match (base.checked_add(start), base.checked_add(end)) {
+        (Some(start), Some(end)) if user::contains(start, end - start) => (start, end),
+        _ => return Err(ExecError::OutOfRange),
+    };
+    let stack_bottom = STACK_TOP - STACK_SIZE;
+    if end > stack_bottom {
+        return Err(ExecError::OutOfRange);
+    }
+    if !user::is_unmapped(VirtAddr::new(start), end - start)
+        || !user::is_unmapped(VirtAddr::new(stack_bottom), STACK_SIZE) {
+        return Err(ExecError::AddressInUse);
+    }
+    let auxv = [
+        (AT_PHDR, elf.program_headers_address().map_or(0, |address| base + address)),
+        (AT_PHENT, PROGRAM_HEADER_SIZE as u64),
+        (AT_PHNUM, elf.program_header_count() as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_ENTRY, base + elf.entry()),
+    ];
+    if stack::size(argv, envp, &auxv) > STACK_SIZE as usize {
+        return Err(ExecError::ArgumentsTooLong);
+    }
+
+    // Writable until the contents are in place. Segments may share a page.
+    for segment in elf.segments() {
+        let (first, last) = segment.pages();
+        for page in (base + first..base + last).step_by(PAGE_SIZE as usize) {
+            let page = VirtAddr::new(page);
+            if user::is_unmapped(page, PAGE_SIZE) {
+                user::map(page, PAGE_SIZE, PageTableFlags::WRITABLE);
+            }
+        }
+        let bytes = elf.file_bytes(&segment);
+        ptr::copy_nonoverlapping(bytes.as_ptr(), (base + segment.address) as *mut u8,
+                                 bytes.len());
+    }
+    for relocation in elf.relocations() {
+        ptr::write_unaligned((base + relocation.offset) as *mut u64, base + relocation.addend);
+    }
+    for page in (start..end).step_by(PAGE_SIZE as usize) {
+        user::protect(VirtAddr::new(page), PAGE_SIZE, page_flags(&elf, page - base));
+    }
+
+    user::map(VirtAddr::new(stack_bottom), STACK_SIZE,
+              PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+    let stack = slice::from_raw_parts_mut(stack_bottom as *mut u8, STACK_SIZE as usize);
+    let stack_pointer = stack::build(stack, STACK_TOP, argv, envp, &auxv, random_bytes())
+        .expect("The size of the initial stack was checked");
+    Ok(Image { base, entry: base + elf.entry(), stack_pointer, start, end })
+}
+
+/// Continue the current task in user mode with the program `executable`, loaded in a fresh
+/// address space, see `load`. Returns only if it cannot be loaded, the task keeping its address
+/// space.
+///
+/// The task must have been started by `spawn` and hold no lock, as for `user::enter_user_mode`.
+/// `executable`, `argv` and `envp` have to be in kernel memory, as the user part changes.
+pub fn exec(executable: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> ExecError {
+    let space = match AddressSpace::new() {
+        Some(space) => space,
+        None => return ExecError::OutOfMemory,
+    };
+    let previous = replace_address_space(space);
+    match unsafe { load(executable, argv, envp) } {
+        Ok(image) => unsafe {
+            if previous != AddressSpace::kernel() {
+                previous.destroy();
+            }
+            // The mappings of the previous program are gone with its address space.
+            set_next_mapping(MMAP_START);
+            enter_user_mode(image.entry, image.stack_pointer)
+        },
+        Err(error) => {
+            replace_address_space(previous);
+            unsafe { space.destroy(); }
+            error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::memory::paging;
+    use super::super::{exit_status, spawn, state, yield_now, TaskState};
+    use spin::Mutex;
+
+    /// Built from `ailurus-core/programs/args.s` by `make programs`. They exit with `argc` and
+    /// the first byte of `argv[0]` shifted left by 8.
+    static STATIC: &[u8] = include_bytes!("../../../../../ailurus-core/programs/args-static");
+    static PIE: &[u8] = include_bytes!("../../../../../ailurus-core/programs/args-pie");
+    /// The exit status of both for the arguments of `run_executable`.
+    const STATUS: i64 = 2 | (b'a' as i64) << 8;
+    /// Where the static executable keeps its data.
+    const STATIC_DATA: u64 = 0x0000_0080_0100_2000;
+
+    static TOO_LONG: [u8; STACK_SIZE as usize] = [b'a'; STACK_SIZE as usize];
+
+    static EXECUTABLE: Mutex<&'static [u8]> = Mutex::new(&[]);
+    static ERROR: Mutex<Option<ExecError>> = Mutex::new(None);
+
+    fn run_executable() {
+        let executable = *EXECUTABLE.lock();
+        let error = exec(executable, &[b"args", b"-v"], &[b"TERM=ailurus"]);
+        *ERROR.lock() = Some(error);
+    }
+
+    /// Run `executable` in `count` new tasks at once until they end, and return their exit
+    /// statuses.
+    fn run(executable: &'static [u8], count: usize) -> [Option<i64>; 2] {
+        *EXECUTABLE.lock() = executable;
+        *ERROR.lock() = None;
+        let mut ids = [None; 2];
+        for id in ids.iter_mut().take(count) {
+            *id = Some(spawn("exec", run_executable).unwrap());
+        }
+        let mut statuses = [None; 2];
+        for (id, status) in ids.iter().zip(statuses.iter_mut()) {
+            if let Some(id) = *id {
+                while state(id) != Some(TaskState::Zombie) {
+                    yield_now();
+                }
+                *status = exit_status(id);
+            }
+        }
+        statuses
+    }
+
+    #[test_case]
+    fn static_executables_run() {
+        assert_eq!(run(STATIC, 1), [Some(STATUS), None]);
+        // In an address space of their own, destroyed when they exit.
+        assert!(user::is_unmapped(VirtAddr::new(STATIC_DATA), PAGE_SIZE));
+    }
+
+    #[test_case]
+    fn position_independent_executables_run() {
+        assert_eq!(run(PIE, 1), [Some(STATUS), None]);
+    }
+
+    #[test_case]
+    fn programs_linked_at_the_same_address_run_side_by_side() {
+        assert_eq!(run(STATIC, 2), [Some(STATUS), Some(STATUS)]);
+        assert_eq!(*ERROR.lock(), None);
+    }
+
+    #[test_case]
+    fn failed_exec_returns_the_error() {
+        assert_eq!(run(b"#!/bin/sh\n", 1), [Some(0), None]);
+        assert_eq!(*ERROR.lock(), Some(ExecError::Elf(ElfError::InvalidMagic)));
+    }
+
+    #[test_case]
+    fn segments_get_their_permissions() {
+        let image = unsafe { load(PIE, &[], &[]) }.unwrap();
+        assert_eq!(unsafe { load(PIE, &[], &[]) }.err(), Some(ExecError::AddressInUse));
+        let flags = |address| paging::page_flags(VirtAddr::new(address)).unwrap();
+        let code = flags(image.entry);
+        assert!(!code.contains(PageTableFlags::WRITABLE));
+        assert!(!code.contains(PageTableFlags::NO_EXECUTE));
+        let result = image.base + Elf::parse(PIE).unwrap().relocations().next().unwrap().addend;
+        assert!(flags(result).contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+        assert!(flags(image.stack_pointer).contains(PageTableFlags::NO_EXECUTE));
+        unsafe { image.unload(); }
+        assert!(paging::page_flags(VirtAddr::new(image.entry)).is_none());
+    }
+
+    #[test_case]
+    fn bad_executables_are_refused() {
+        assert_eq!(unsafe { load(b"#!/bin/sh\n", &[], &[]) }.err(),
+                   Some(ExecError::Elf(ElfError::InvalidMagic)));
+        assert_eq!(unsafe { load(PIE, &[&TOO_LONG[..]], &[]) }.err(),
+                   Some(ExecError::ArgumentsTooLong));
+    }
+}
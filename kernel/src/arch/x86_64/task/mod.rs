@@ -0,0 +1,988 @@
+//! Kernel threads.
+//!
+//! Each task runs on its own guard-paged kernel stack. Ready tasks wait in the queue of the
+//! scheduling class of their `Policy` (see `ailurus_core::sched`): real-time tasks first, then
+//! the fair ones sharing the CPU by their nice value, then the idle ones. The running task keeps
+//! the CPU until it yields, blocks or exits, or until the timer interrupt or the wake-up of a
+//! more urgent task preempts it (unless preemption is disabled, see `preempt`).
+//!
+//! ```ignore
+//! task::spawn("worker", || loop {
+//!     do_some_work();
+//! });
+//! task::spawn_with_policy("backup", backup, Policy::Fair { nice: 10 });
+//! ```
+//!
+//! The flow of control which calls `init`, i.e. `kstart` and `kmain`, becomes the first task.
+//! The quantum, the time slice of the round-robin policies and the period over which the fair
+//! tasks share the CPU, can be set on the kernel command line with `sched.quantum`, in
+//! milliseconds.
+//!
+//! Every CPU has its own queues, and an idle task which runs when they are empty. New and woken
+//! tasks go to the least loaded CPU their affinity allows, and the CPUs take tasks from the
+//! busiest one every `BALANCE_INTERVAL` ticks and whenever they become idle (see
+//! `ailurus_core::sched::smp`). A CPU which queues a task for another one that should run it
+//! right away sends it a reschedule interrupt. One lock protects the queues of all the CPUs.
+//!
+//! Tasks sleep until something happens with the wait queues of `wait`, or the locks of `sync`
+//! built on them, or for a time with `sleep`. Spawned tasks can continue in user mode, see
+//! `user`, and run ELF executables there, each in an address space of its own, see `exec`.
+//!
+//! Application processors join with `start_cpu`. The CPUs are numbered in the order in which
+//! they start, see `cpu_local`. Nothing starts the application processors yet, and only the
+//! bootstrap processor has a timer: the others only switch tasks when one yields, blocks or
+//! exits, or when they receive a reschedule interrupt.
+
+pub mod context;
+pub mod cpu_local;
+pub mod exec;
+pub mod preempt;
+pub mod stack;
+pub mod sync;
+pub mod user;
+pub mod wait;
+
+use ailurus_core::sched::smp::{busiest, least_loaded};
+use ailurus_core::sched::{Classes, Enqueue};
+use core::mem;
+use spin::Mutex;
+use super::device::{local_apic, pit};
+use super::interrupt;
+use super::memory::address_space::AddressSpace;
+use super::memory::paging;
+use super::memory::user::MMAP_START;
+use super::platform::{gdt, instructions};
+use super::syscall;
+use self::context::FpuState;
+use self::wait::WaitQueue;
+
+pub use ailurus_core::sched::smp::CpuSet;
+pub use self::cpu_local::this_cpu;
+pub use ailurus_core::sched::{Policy, MAX_TASKS};
+
+/// The quantum without `sched.quantum`, in milliseconds.
+pub const DEFAULT_QUANTUM_MS: u32 = 50;
+
+/// The number of CPUs the scheduler can run tasks on.
+pub const MAX_CPUS: usize = 8;
+
+/// The period of load balancing, in timer ticks.
+pub const BALANCE_INTERVAL: u32 = 10;
+
+/// The vector of the interrupt which makes a CPU pick its next task again.
+pub const RESCHEDULE_VECTOR: u8 = 0xf0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    /// Waiting in the queue of its scheduling class.
+    Ready,
+    /// Waiting for `wake`.
+    Blocked,
+    /// Finished, waiting for its slot to be reused.
+    Zombie,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Task {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: TaskState,
+    /// The CPU it runs or waits on, or last ran on.
+    pub cpu: usize,
+    /// The CPUs it may run on.
+    affinity: CpuSet,
+    /// The saved stack pointer while not running, zero while running or switching away, see
+    /// `context`.
+    stack_pointer: u64,
+    /// The saved preemption-disable count while not running.
+    preempt_count: usize,
+    /// Whether `unpark` was called since `park` last returned.
+    unparked: bool,
+    /// Where `mmap` starts looking for room for the mappings it places, see `next_mapping`.
+    next_mapping: u64,
+    /// The address space it runs in, the kernel's unless it runs a program, see `exec`.
+    pub address_space: AddressSpace,
+    /// The status it exited with, once a zombie.
+    exit_status: i64,
+}
+
+struct Cpu {
+    /// The queues, and the scheduling state of the tasks of this CPU.
+    classes: Classes,
+    /// The slot of the running task.
+    current: usize,
+    /// The slot of the idle task, never queued.
+    idle: usize,
+    ticks_since_balance: u32,
+}
+
+impl Cpu {
+    const fn new() -> Self {
+        Cpu { classes: Classes::new(1), current: 0, idle: 0, ticks_since_balance: 0 }
+    }
+}
+
+struct Scheduler {
+    tasks: [Option<Task>; MAX_TASKS],
+    /// Whether the stack of each slot has been mapped. The first task and the idle tasks of the
+    /// application processors keep their boot stacks.
+    stacks_mapped: [bool; MAX_TASKS],
+    /// The x87 and SSE registers of each slot while its task does not run, see `context`.
+    fpu_states: [FpuState; MAX_TASKS],
+    cpus: [Cpu; MAX_CPUS],
+    online: CpuSet,
+    /// The CPUs to send a reschedule interrupt to once the lock is released.
+    kicks: CpuSet,
+    next_id: u64,
+}
+
+impl Scheduler {
+    fn find(&self, id: TaskId) -> Option<usize> {
+        self.tasks.iter().position(|task| task.map_or(false, |task| task.id == id))
+    }
+
+    fn task(&mut self, slot: usize) -> &mut Task {
+        self.tasks[slot].as_mut().unwrap()
+    }
+
+    fn is_idle(&self, slot: usize) -> bool {
+        self.online.iter().any(|cpu| self.cpus[cpu].idle == slot)
+    }
+
+    fn free_slot(&self) -> Option<usize> {
+        // The slot of a zombie is free once it has switched away and saved its stack pointer.
+        (1..MAX_TASKS).find(|&slot| self.tasks[slot].map_or(true, |task| {
+            task.state == TaskState::Zombie && task.stack_pointer != 0
+        }))
+    }
+
+    /// Set up a task running `entry` in a free slot, with its scheduling state on `cpu`, but
+    /// do not queue it.
+    fn create(&mut self, name: &'static str, entry: fn(), policy: Policy, cpu: usize,
+              affinity: CpuSet) -> Option<usize> {
+        let slot = self.free_slot()?;
+        if !self.stacks_mapped[slot] {
+            unsafe { stack::map(slot); }
+            self.stacks_mapped[slot] = true;
+        }
+        let stack_pointer = unsafe {
+            context::init_stack(stack::top(slot), task_entry, entry as usize as u64)
+        };
+        self.fpu_states[slot] = FpuState::INITIAL;
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks[slot] = Some(Task {
+            id,
+            name,
+            state: TaskState::Ready,
+            cpu,
+            affinity,
+            stack_pointer,
+            preempt_count: 0,
+            unparked: false,
+            next_mapping: MMAP_START,
+            address_space: AddressSpace::kernel(),
+            exit_status: 0,
+        });
+        self.cpus[cpu].classes.add(slot, policy);
+        Some(slot)
+    }
+
+    /// The number of tasks ready to run on each CPU, the running one included.
+    fn loads(&self) -> [usize; MAX_CPUS] {
+        let mut loads = [0; MAX_CPUS];
+        for cpu in self.online.iter() {
+            let busy = self.cpus[cpu].current != self.cpus[cpu].idle;
+            loads[cpu] = self.cpus[cpu].classes.len() + busy as usize;
+        }
+        loads
+    }
+
+    /// The CPU to queue the task in `slot` on: the least loaded one it may run on, preferably
+    /// the one it last ran on.
+    fn select_cpu(&self, slot: usize) -> usize {
+        let task = self.tasks[slot].unwrap();
+        let loads = self.loads();
+        least_loaded(&loads, self.online.intersection(task.affinity), task.cpu)
+            .or_else(|| least_loaded(&loads, self.online, task.cpu))
+            .unwrap_or(task.cpu)
+    }
+
+    /// Make `cpu` pick its next task again, with an interrupt once the lock is released if it
+    /// is another one.
+    fn kick(&mut self, cpu: usize) {
+        if cpu == this_cpu() {
+            preempt::request_reschedule();
+        } else {
+            self.kicks.insert(cpu);
+        }
+    }
+
+    /// Kick `cpu` if the task queued there in `slot` should preempt its running one.
+    fn kick_if_urgent(&mut self, slot: usize, cpu: usize) {
+        let current = self.cpus[cpu].current;
+        if current == self.cpus[cpu].idle || self.cpus[cpu].classes.preempts(slot, current) {
+            self.kick(cpu);
+        }
+    }
+
+    /// Queue the task in `slot`, which is not queued, on `cpu` for `reason`, moving its
+    /// scheduling state from the CPU it was on.
+    fn enqueue(&mut self, slot: usize, cpu: usize, reason: Enqueue) {
+        let from = self.tasks[slot].unwrap().cpu;
+        if from == cpu {
+            self.cpus[cpu].classes.enqueue(slot, reason);
+        } else {
+            let entity = self.cpus[from].classes.detach(slot);
+            self.cpus[cpu].classes.attach(slot, entity, reason);
+            self.task(slot).cpu = cpu;
+        }
+        self.kick_if_urgent(slot, cpu);
+    }
+
+    /// Move a queued task which may run on `this` CPU from the busiest CPU, if that evens out
+    /// their loads. Returns its slot.
+    fn pull(&mut self, this: usize) -> Option<usize> {
+        let from = busiest(&self.loads(), self.online, this)?;
+        let tasks = &self.tasks;
+        let (slot, entity) = self.cpus[from].classes.steal(&|slot| {
+            tasks[slot].map_or(false, |task| task.affinity.contains(this))
+        })?;
+        self.cpus[this].classes.attach(slot, entity, Enqueue::Preempted);
+        self.task(slot).cpu = this;
+        Some(slot)
+    }
+
+    /// The periodic load balancing of `cpu`. The idle CPUs have no timer, so it also wakes up
+    /// those which could take a task.
+    fn balance(&mut self, cpu: usize) {
+        if let Some(slot) = self.pull(cpu) {
+            self.kick_if_urgent(slot, cpu);
+        }
+        let loads = self.loads();
+        for other in self.online.iter() {
+            let idle = self.cpus[other].current == self.cpus[other].idle;
+            if other != cpu && idle && busiest(&loads, self.online, other).is_some() {
+                self.kick(other);
+            }
+        }
+    }
+
+    /// Make the blocked task in `slot` ready again.
+    fn wake(&mut self, slot: usize) {
+        self.task(slot).state = TaskState::Ready;
+        let cpu = self.select_cpu(slot);
+        self.enqueue(slot, cpu, Enqueue::Woken);
+    }
+
+    fn unpark(&mut self, slot: usize) {
+        match self.task(slot).state {
+            TaskState::Blocked => self.wake(slot),
+            TaskState::Zombie => {}
+            TaskState::Running | TaskState::Ready => self.task(slot).unparked = true,
+        }
+    }
+
+    /// Queue the current task of `cpu` for `reason` if it is still running, make the next task
+    /// the current one and return the locations of the stack pointers to switch between.
+    /// `None` if the current task goes on.
+    fn switch_next(&mut self, cpu: usize, reason: Enqueue) -> Option<(*mut u64, *const u64)> {
+        let previous = self.cpus[cpu].current;
+        let idle = self.cpus[cpu].idle;
+        let task = self.tasks[previous].unwrap();
+        if previous != idle && task.state == TaskState::Running {
+            self.task(previous).state = TaskState::Ready;
+            if task.affinity.contains(cpu) {
+                self.cpus[cpu].classes.enqueue(previous, reason);
+            } else {
+                // Its affinity changed while it ran.
+                let target = self.select_cpu(previous);
+                self.enqueue(previous, target, reason);
+            }
+        }
+        let next = self.cpus[cpu].classes.pick_next().unwrap_or(idle);
+        self.task(next).state = TaskState::Running;
+        preempt::clear_reschedule();
+        if next == previous {
+            // Still the most urgent, or the idle task with nothing else to run.
+            return None;
+        }
+        let task = self.task(previous);
+        task.preempt_count = preempt::count();
+        task.stack_pointer = 0;
+        let previous_stack_pointer = &mut task.stack_pointer as *mut u64;
+
+        unsafe {
+            self.fpu_states[previous].save();
+            self.fpu_states[next].restore();
+            self.task(next).address_space.activate();
+        }
+        if self.stacks_mapped[next] {
+            // Where interrupts and system calls from user mode push their frames.
+            gdt::set_kernel_stack(cpu, stack::top(next));
+            cpu_local::set_kernel_stack(cpu, stack::top(next));
+        }
+        let task = self.tasks[next].as_ref().unwrap();
+        preempt::set_count(task.preempt_count);
+        self.cpus[cpu].current = next;
+        Some((previous_stack_pointer, &task.stack_pointer as *const u64))
+    }
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+    tasks: [None; MAX_TASKS],
+    stacks_mapped: [false; MAX_TASKS],
+    fpu_states: [FpuState::INITIAL; MAX_TASKS],
+    cpus: [
+        Cpu::new(), Cpu::new(), Cpu::new(), Cpu::new(),
+        Cpu::new(), Cpu::new(), Cpu::new(), Cpu::new(),
+    ],
+    online: CpuSet::EMPTY,
+    kicks: CpuSet::EMPTY,
+    next_id: 1,
+});
+
+/// Run `f` with the scheduler locked, then send the reschedule interrupts it asked for.
+/// Interrupts have to be disabled.
+fn locked<R, F: FnOnce(&mut Scheduler) -> R>(f: F) -> R {
+    let (result, kicks) = {
+        let mut scheduler = SCHEDULER.lock();
+        let result = f(&mut scheduler);
+        (result, mem::replace(&mut scheduler.kicks, CpuSet::EMPTY))
+    };
+    for cpu in kicks.iter() {
+        local_apic::send_interrupt(cpu_local::apic_id(cpu), RESCHEDULE_VECTOR);
+    }
+    result
+}
+
+fn with_scheduler<R, F: FnOnce(&mut Scheduler) -> R>(f: F) -> R {
+    interrupt::run_without_interrupt(|| locked(f))
+}
+
+/// Turn the running flow of control into the first task, named `name`, and start scheduling on
+/// the bootstrap processor.
+pub fn init(name: &'static str) {
+    let cpu = this_cpu();
+    let quantum_ms = crate::cmdline::get("sched.quantum")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_QUANTUM_MS);
+    set_quantum(quantum_ms * pit::TIMER_FREQUENCY / 1000);
+    with_scheduler(|scheduler| {
+        scheduler.tasks[0] = Some(Task {
+            id: TaskId(0),
+            name,
+            state: TaskState::Running,
+            cpu,
+            affinity: CpuSet::ALL,
+            stack_pointer: 0,
+            preempt_count: 0,
+            unparked: false,
+            next_mapping: MMAP_START,
+            address_space: AddressSpace::kernel(),
+            exit_status: 0,
+        });
+        scheduler.cpus[cpu].classes.add(0, Policy::default());
+        scheduler.cpus[cpu].current = 0;
+        let idle = scheduler.create("idle", || idle(), Policy::Idle, cpu, CpuSet::single(cpu))
+            .expect("No slot for the idle task");
+        scheduler.cpus[cpu].idle = idle;
+        scheduler.online.insert(cpu);
+    });
+    info!("Scheduler: quantum of {} ticks", quantum());
+}
+
+/// Let the executing application processor run tasks, its boot flow becoming its idle task.
+/// It has to run on the kernel page tables, and `init` has to be done on the bootstrap
+/// processor.
+pub fn start_cpu() -> ! {
+    interrupt::load_idt();
+    unsafe { paging::init_pat(); }
+    let cpu = cpu_local::init();
+    gdt::init();
+    syscall::init();
+    local_apic::init_application_processor();
+    with_scheduler(|scheduler| {
+        let slot = scheduler.free_slot().expect("No slot for the idle task");
+        let id = TaskId(scheduler.next_id);
+        scheduler.next_id += 1;
+        scheduler.tasks[slot] = Some(Task {
+            id,
+            name: "idle",
+            state: TaskState::Running,
+            cpu,
+            affinity: CpuSet::single(cpu),
+            stack_pointer: 0,
+            preempt_count: 0,
+            unparked: false,
+            next_mapping: MMAP_START,
+            address_space: AddressSpace::kernel(),
+            exit_status: 0,
+        });
+        scheduler.cpus[cpu].classes.add(slot, Policy::Idle);
+        scheduler.cpus[cpu].current = slot;
+        scheduler.cpus[cpu].idle = slot;
+        scheduler.online.insert(cpu);
+    });
+    info!("Scheduler: CPU {} online", cpu);
+    idle()
+}
+
+/// The idle task of every CPU: takes tasks from the busiest CPU, or waits for an interrupt.
+fn idle() -> ! {
+    loop {
+        unsafe { instructions::cli(); }
+        let ready = locked(|scheduler| {
+            let cpu = this_cpu();
+            scheduler.pull(cpu);
+            !scheduler.cpus[cpu].classes.is_empty()
+        });
+        if ready {
+            unsafe { instructions::sti(); }
+            reschedule(Enqueue::Preempted);
+        } else {
+            // No interrupt can queue a task unnoticed before `hlt`.
+            unsafe { instructions::enable_interrupts_and_hlt(); }
+        }
+    }
+}
+
+/// The CPUs running tasks.
+pub fn online_cpus() -> CpuSet {
+    interrupt::run_without_interrupt(|| SCHEDULER.lock().online)
+}
+
+/// The quantum in timer ticks.
+pub fn quantum() -> u32 {
+    with_scheduler(|scheduler| scheduler.cpus[this_cpu()].classes.quantum())
+}
+
+/// Change the quantum to `ticks`, at least one.
+pub fn set_quantum(ticks: u32) {
+    with_scheduler(|scheduler| {
+        for cpu in scheduler.cpus.iter_mut() {
+            cpu.classes.set_quantum(ticks);
+        }
+    })
+}
+
+/// Start a fair task of nice value 0 running `entry`, returning `None` if there are already
+/// `MAX_TASKS`.
+pub fn spawn(name: &'static str, entry: fn()) -> Option<TaskId> {
+    spawn_with_policy(name, entry, Policy::default())
+}
+
+/// Start a task of `policy` running `entry`, returning `None` if there are already `MAX_TASKS`
+/// or if `policy` is invalid.
+pub fn spawn_with_policy(name: &'static str, entry: fn(), policy: Policy) -> Option<TaskId> {
+    if !policy.is_valid() {
+        return None;
+    }
+    with_scheduler(|scheduler| {
+        let slot = scheduler.create(name, entry, policy, this_cpu(), CpuSet::ALL)?;
+        let cpu = scheduler.select_cpu(slot);
+        scheduler.enqueue(slot, cpu, Enqueue::Spawned);
+        Some(scheduler.task(slot).id)
+    })
+}
+
+/// The policy of task `id`, or `None` if there is no such task.
+pub fn policy(id: TaskId) -> Option<Policy> {
+    with_scheduler(|scheduler| {
+        let slot = scheduler.find(id)?;
+        let cpu = scheduler.task(slot).cpu;
+        Some(scheduler.cpus[cpu].classes.entity(slot).policy())
+    })
+}
+
+/// Change the policy of task `id`, returning false if there is no such task, if it is an idle
+/// task or if `policy` is invalid.
+pub fn set_policy(id: TaskId, policy: Policy) -> bool {
+    with_scheduler(|scheduler| {
+        let slot = match scheduler.find(id) {
+            Some(slot) if !scheduler.is_idle(slot) => slot,
+            _ => return false,
+        };
+        let task = *scheduler.task(slot);
+        if !scheduler.cpus[task.cpu].classes.set_policy(slot, policy) {
+            return false;
+        }
+        if task.state == TaskState::Ready {
+            scheduler.kick_if_urgent(slot, task.cpu);
+        }
+        true
+    })
+}
+
+/// The CPUs task `id` may run on, or `None` if there is no such task.
+pub fn affinity(id: TaskId) -> Option<CpuSet> {
+    with_scheduler(|scheduler| scheduler.find(id).map(|slot| scheduler.task(slot).affinity))
+}
+
+/// Let task `id` only run on `cpus`, moving it if it is on another CPU. Returns false if there
+/// is no such task, if it is an idle task or if no CPU of `cpus` is online.
+pub fn set_affinity(id: TaskId, cpus: CpuSet) -> bool {
+    let moved = with_scheduler(|scheduler| {
+        let slot = match scheduler.find(id) {
+            Some(slot) if !scheduler.is_idle(slot) => slot,
+            _ => return false,
+        };
+        if cpus.intersection(scheduler.online).is_empty() {
+            return false;
+        }
+        scheduler.task(slot).affinity = cpus;
+        let task = *scheduler.task(slot);
+        if !cpus.contains(task.cpu) {
+            match task.state {
+                TaskState::Ready => {
+                    scheduler.cpus[task.cpu].classes.dequeue(slot);
+                    let cpu = scheduler.select_cpu(slot);
+                    scheduler.enqueue(slot, cpu, Enqueue::Preempted);
+                }
+                // Moved when it switches away.
+                TaskState::Running => scheduler.kick(task.cpu),
+                // Placed when it is woken up.
+                TaskState::Blocked | TaskState::Zombie => {}
+            }
+        }
+        true
+    });
+    // The running task leaves right away if it is not allowed here any more.
+    preempt_if_requested();
+    moved
+}
+
+/// The first function of every task except the first one.
+extern "C" fn task_entry(entry: u64) -> ! {
+    let entry: fn() = unsafe { mem::transmute(entry as usize) };
+    entry();
+    exit()
+}
+
+/// Switch to the next task, queueing the running one for `reason`.
+fn reschedule(reason: Enqueue) {
+    interrupt::run_without_interrupt(|| {
+        let switch = locked(|scheduler| scheduler.switch_next(this_cpu(), reason));
+        if let Some((previous, next)) = switch {
+            unsafe { context::switch_to(previous, next); }
+        }
+    })
+}
+
+/// Preempt the running task if that was requested and preemption is enabled.
+fn preempt_if_requested() {
+    if preempt::is_enabled() && preempt::take_reschedule() {
+        // From an interrupt handler, continues in the handler, which returns to the
+        // interrupted code, once the task is scheduled again.
+        reschedule(Enqueue::Preempted);
+    }
+}
+
+/// The tasks in `sleep`, which check their deadline on every timer tick.
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// Sleep for at least `ms` milliseconds, counted in timer ticks.
+pub fn sleep(ms: u64) {
+    let frequency = pit::TIMER_FREQUENCY as u64;
+    let deadline = (pit::ticks() as u64).saturating_add(ms.saturating_mul(frequency) / 1000 + 1);
+    SLEEPERS.wait_until(|| pit::ticks() as u64 >= deadline);
+}
+
+/// Let the other ready tasks of the same class run first. Returns immediately if there is none.
+pub fn yield_now() {
+    reschedule(Enqueue::Yielded);
+}
+
+/// Account a timer tick to the running task, and preempt it if it used up its time or a more
+/// urgent task was woken up. Balances the load every `BALANCE_INTERVAL` ticks. Called by the
+/// timer interrupt handler after the end of interrupt.
+pub fn tick() {
+    let expired = with_scheduler(|scheduler| {
+        let cpu = this_cpu();
+        let current = scheduler.cpus[cpu].current;
+        let expired = if current == scheduler.cpus[cpu].idle {
+            !scheduler.cpus[cpu].classes.is_empty()
+        } else {
+            scheduler.cpus[cpu].classes.tick(current)
+        };
+        scheduler.cpus[cpu].ticks_since_balance += 1;
+        if scheduler.cpus[cpu].ticks_since_balance >= BALANCE_INTERVAL {
+            scheduler.cpus[cpu].ticks_since_balance = 0;
+            scheduler.balance(cpu);
+        }
+        expired
+    });
+    if expired {
+        preempt::request_reschedule();
+    }
+    if !SLEEPERS.is_empty() {
+        SLEEPERS.wake_all();
+    }
+    preempt_if_requested();
+}
+
+/// Handle the interrupt by which another CPU asks this one to pick its next task again.
+/// Called by the interrupt handler after the end of interrupt.
+pub fn handle_reschedule_interrupt() {
+    preempt::request_reschedule();
+    preempt_if_requested();
+}
+
+/// Stop running the current task until `wake` is called with its id.
+pub fn block() {
+    block_current(false);
+}
+
+/// Stop running the current task until `unpark` or `wake` is called with its id, unless
+/// `unpark` was called since `park` last returned. May also return for no reason: the caller
+/// checks in a loop whether what it waits for happened, e.g. with a `wait::WaitQueue`.
+pub fn park() {
+    block_current(true);
+}
+
+fn block_current(unless_unparked: bool) {
+    interrupt::run_without_interrupt(|| {
+        let switch = locked(|scheduler| {
+            let cpu = this_cpu();
+            let current = scheduler.cpus[cpu].current;
+            assert!(current != scheduler.cpus[cpu].idle, "An idle task cannot block");
+            let task = scheduler.task(current);
+            if unless_unparked && mem::replace(&mut task.unparked, false) {
+                return None;
+            }
+            task.state = TaskState::Blocked;
+            // The idle task runs if nothing else is ready.
+            Some(scheduler.switch_next(cpu, Enqueue::Preempted).expect("A blocked task was picked"))
+        });
+        if let Some((previous, next)) = switch {
+            unsafe { context::switch_to(previous, next); }
+        }
+    })
+}
+
+/// Make the blocked task `id` ready again, returning false if it is not blocked.
+pub fn wake(id: TaskId) -> bool {
+    with_scheduler(|scheduler| {
+        let slot = match scheduler.find(id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if scheduler.task(slot).state != TaskState::Blocked {
+            return false;
+        }
+        scheduler.wake(slot);
+        true
+    })
+}
+
+/// Wake task `id` if it is blocked, else make its next `park` return right away. Returns false
+/// if there is no such task.
+pub fn unpark(id: TaskId) -> bool {
+    with_scheduler(|scheduler| match scheduler.find(id) {
+        Some(slot) => {
+            scheduler.unpark(slot);
+            true
+        }
+        None => false,
+    })
+}
+
+/// `unpark` for the task in `slot`, as kept by wait queues.
+pub(super) fn unpark_slot(slot: usize) {
+    with_scheduler(|scheduler| scheduler.unpark(slot))
+}
+
+/// The slot of the current task, as kept by wait queues and `debug::lockdep`.
+pub(crate) fn current_slot() -> usize {
+    with_scheduler(|scheduler| scheduler.cpus[this_cpu()].current)
+}
+
+/// Whether the current task was started by `spawn`, rather than being the first task or an
+/// idle task, which run on boot stacks.
+pub(super) fn is_spawned() -> bool {
+    with_scheduler(|scheduler| {
+        let current = scheduler.cpus[this_cpu()].current;
+        current != 0 && !scheduler.is_idle(current)
+    })
+}
+
+/// Check in debug builds that the current task may sleep: that it does not run an interrupt
+/// handler, and neither holds a `preempt::SpinLock` nor disabled interrupts.
+pub fn might_sleep() {
+    debug_assert!(instructions::interrupts_enabled() && preempt::is_enabled()
+                  && !preempt::in_interrupt(),
+                  "Sleeping in interrupt context, or with interrupts or preemption disabled");
+}
+
+/// Finish the running task.
+pub fn exit() -> ! {
+    exit_with(0)
+}
+
+/// Finish the running task with `status`, destroying its address space if it has its own.
+pub fn exit_with(status: i64) -> ! {
+    let space = replace_address_space(AddressSpace::kernel());
+    if space != AddressSpace::kernel() {
+        unsafe { space.destroy(); }
+    }
+    interrupt::run_without_interrupt(|| {
+        let switch = locked(|scheduler| {
+            let cpu = this_cpu();
+            let current = scheduler.cpus[cpu].current;
+            assert!(current != 0, "The first task cannot exit");
+            assert!(current != scheduler.cpus[cpu].idle, "An idle task cannot exit");
+            let task = scheduler.task(current);
+            task.state = TaskState::Zombie;
+            task.exit_status = status;
+            scheduler.switch_next(cpu, Enqueue::Preempted)
+        });
+        let (previous, next) = switch.expect("A zombie task was picked");
+        unsafe { context::switch_to(previous, next); }
+    });
+    unreachable!("A zombie task was continued")
+}
+
+/// The task running on this CPU, or `None` before `init`.
+pub fn current() -> Option<Task> {
+    with_scheduler(|scheduler| scheduler.tasks[scheduler.cpus[this_cpu()].current])
+}
+
+/// Where `mmap` of the running task starts looking for room: right after the last mapping it
+/// placed, or `MMAP_START`.
+pub fn next_mapping() -> u64 {
+    with_scheduler(|scheduler| {
+        let current = scheduler.cpus[this_cpu()].current;
+        scheduler.task(current).next_mapping
+    })
+}
+
+/// Make `mmap` of the running task look for room from `address` next.
+pub fn set_next_mapping(address: u64) {
+    with_scheduler(|scheduler| {
+        let current = scheduler.cpus[this_cpu()].current;
+        scheduler.task(current).next_mapping = address;
+    })
+}
+
+/// The state of task `id`, or `None` if there is no such task.
+pub fn state(id: TaskId) -> Option<TaskState> {
+    with_scheduler(|scheduler| scheduler.find(id).map(|slot| scheduler.task(slot).state))
+}
+
+/// The status task `id` exited with, or `None` if there is no such task or it has not exited.
+pub fn exit_status(id: TaskId) -> Option<i64> {
+    with_scheduler(|scheduler| {
+        let task = *scheduler.task(scheduler.find(id)?);
+        if task.state == TaskState::Zombie { Some(task.exit_status) } else { None }
+    })
+}
+
+/// Make `space` the address space of the running task and activate it, returning the one it
+/// had.
+pub(super) fn replace_address_space(space: AddressSpace) -> AddressSpace {
+    with_scheduler(|scheduler| {
+        let current = scheduler.cpus[this_cpu()].current;
+        unsafe { space.activate(); }
+        mem::replace(&mut scheduler.task(current).address_space, space)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn worker() {
+        for _ in 0..3 {
+            STEPS.fetch_add(1, Ordering::SeqCst);
+            yield_now();
+        }
+    }
+
+    #[test_case]
+    fn tasks_take_turns() {
+        STEPS.store(0, Ordering::SeqCst);
+        let first = spawn("worker", worker).unwrap();
+        let second = spawn("worker", worker).unwrap();
+        assert_ne!(first, second);
+
+        while STEPS.load(Ordering::SeqCst) < 6 {
+            yield_now();
+        }
+        assert_eq!(current().map(|task| task.id), Some(TaskId(0)));
+    }
+
+    #[test_case]
+    fn zombie_slots_are_reused() {
+        fn nothing() {}
+        for _ in 0..2 * MAX_TASKS {
+            let id = spawn("nothing", nothing).unwrap();
+            while state(id) != Some(TaskState::Zombie) {
+                yield_now();
+            }
+        }
+    }
+
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    fn busy() {
+        STARTED.store(true, Ordering::SeqCst);
+        while !STOP.load(Ordering::SeqCst) {}
+    }
+
+    #[test_case]
+    fn busy_tasks_are_preempted() {
+        STARTED.store(false, Ordering::SeqCst);
+        STOP.store(false, Ordering::SeqCst);
+        spawn("busy", busy).unwrap();
+        // Neither task yields, so each only runs because the other one is preempted.
+        while !STARTED.load(Ordering::SeqCst) {}
+        STOP.store(true, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn disabled_preemption_keeps_the_task_running() {
+        STARTED.store(false, Ordering::SeqCst);
+        STOP.store(false, Ordering::SeqCst);
+        let _guard = preempt::PreemptGuard::new();
+        spawn("busy", busy).unwrap();
+        let start = pit::ticks();
+        while pit::ticks() < start + 2 * quantum() as usize {
+            unsafe { instructions::hlt(); }
+        }
+        assert!(!STARTED.load(Ordering::SeqCst));
+        STOP.store(true, Ordering::SeqCst);
+    }
+
+    fn waiter() {
+        block();
+        STEPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn blocked_tasks_run_once_woken() {
+        STEPS.store(0, Ordering::SeqCst);
+        let id = spawn("waiter", waiter).unwrap();
+        while state(id) != Some(TaskState::Blocked) {
+            yield_now();
+        }
+        yield_now();
+        assert_eq!(STEPS.load(Ordering::SeqCst), 0);
+
+        assert!(wake(id));
+        assert!(!wake(id));
+        while state(id) != Some(TaskState::Zombie) {
+            yield_now();
+        }
+        assert_eq!(STEPS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test_case]
+    fn realtime_tasks_preempt_fair_ones() {
+        STARTED.store(false, Ordering::SeqCst);
+        STOP.store(true, Ordering::SeqCst);
+        let id = spawn_with_policy("busy", busy, Policy::Fifo { priority: 10 }).unwrap();
+        assert_eq!(policy(id), Some(Policy::Fifo { priority: 10 }));
+        // Preempted at the next tick at the latest.
+        while !STARTED.load(Ordering::SeqCst) {}
+        assert!(spawn_with_policy("busy", busy, Policy::Fifo { priority: 0 }).is_none());
+    }
+
+    static WAITING: AtomicU64 = AtomicU64::new(0);
+
+    fn wake_waiting() {
+        STARTED.store(true, Ordering::SeqCst);
+        wake(TaskId(WAITING.load(Ordering::SeqCst)));
+    }
+
+    #[test_case]
+    fn idle_tasks_only_run_when_nothing_else_is_ready() {
+        STARTED.store(false, Ordering::SeqCst);
+        WAITING.store(current().unwrap().id.0, Ordering::SeqCst);
+        spawn_with_policy("idle", wake_waiting, Policy::Idle).unwrap();
+        let start = pit::ticks();
+        while pit::ticks() < start + 2 * quantum() as usize {
+            unsafe { instructions::hlt(); }
+        }
+        assert!(!STARTED.load(Ordering::SeqCst));
+
+        block();
+        assert!(STARTED.load(Ordering::SeqCst));
+    }
+
+    #[test_case]
+    fn affinity_needs_an_online_cpu() {
+        let id = current().unwrap().id;
+        let online = online_cpus();
+        assert!(online.contains(this_cpu()));
+        assert!(!set_affinity(id, CpuSet::EMPTY));
+        assert!(!set_affinity(id, CpuSet::from_bits(!online.bits())));
+        assert!(!set_affinity(TaskId(u64::max_value()), online));
+        assert_eq!(affinity(id), Some(CpuSet::ALL));
+    }
+
+    static RAN_ON: AtomicUsize = AtomicUsize::new(MAX_CPUS);
+
+    fn record_cpu() {
+        RAN_ON.store(current().unwrap().cpu, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn tasks_stay_on_their_cpus() {
+        let cpu = this_cpu();
+        let this = current().unwrap().id;
+        assert!(set_affinity(this, CpuSet::single(cpu)));
+        assert_eq!(current().map(|task| (task.id, task.cpu)), Some((this, cpu)));
+
+        RAN_ON.store(MAX_CPUS, Ordering::SeqCst);
+        let id = spawn("pinned", record_cpu).unwrap();
+        assert!(set_affinity(id, CpuSet::single(cpu)));
+        assert_eq!(affinity(id), Some(CpuSet::single(cpu)));
+        while state(id) != Some(TaskState::Zombie) {
+            yield_now();
+        }
+        assert_eq!(RAN_ON.load(Ordering::SeqCst), cpu);
+        assert!(set_affinity(this, CpuSet::ALL));
+    }
+
+    fn set_xmm0(value: u64) {
+        unsafe { asm!("movq xmm0, $0" : : "r"(value) : : "intel", "volatile"); }
+    }
+
+    fn xmm0() -> u64 {
+        let value: u64;
+        unsafe { asm!("movq $0, xmm0" : "=r"(value) : : : "intel", "volatile"); }
+        value
+    }
+
+    fn clobber_xmm0() {
+        for value in 0..3 {
+            set_xmm0(value);
+            yield_now();
+        }
+    }
+
+    #[test_case]
+    fn tasks_keep_their_sse_registers() {
+        set_xmm0(0x1234_5678);
+        let id = spawn("clobber", clobber_xmm0).unwrap();
+        while state(id) != Some(TaskState::Zombie) {
+            yield_now();
+        }
+        assert_eq!(xmm0(), 0x1234_5678);
+    }
+
+    #[test_case]
+    fn sleep_lasts_at_least_its_time() {
+        let start = pit::ticks();
+        sleep(30);
+        assert!(pit::ticks() >= start + 3);
+    }
+}
@@ -0,0 +1,168 @@
+//! Preemption control.
+//!
+//! The running task is only preempted while its preemption-disable count is zero. The count
+//! belongs to the task: the scheduler saves it on every switch. A reschedule requested while
+//! preemption is disabled, because the time of the task ran out or a more urgent task woke up,
+//! is remembered, and happens as soon as the count drops back to zero. Both are kept per CPU.
+//!
+//! The count also records the interrupt handlers the task is running, in its upper bits (see
+//! `InterruptGuard`), so that a task switched away from inside a handler is still known to run
+//! it when it comes back.
+//!
+//! Interrupts enabled with a `spin::Mutex` held allow a switch to a task spinning on the same
+//! lock; `SpinLock` keeps preemption disabled for as long as it is held instead.
+
+use ailurus_core::sched::Enqueue;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use super::super::interrupt;
+use super::super::platform::instructions;
+use super::{this_cpu, MAX_CPUS};
+
+static COUNTS: [AtomicUsize; MAX_CPUS] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+static NEED_RESCHEDULE: [AtomicBool; MAX_CPUS] = [
+    AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+    AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+    AtomicBool::new(false), AtomicBool::new(false),
+];
+
+/// What entering an interrupt handler adds to the count.
+const HARDIRQ_OFFSET: usize = 1 << 16;
+/// The part of the count which disables preemption.
+const PREEMPT_MASK: usize = HARDIRQ_OFFSET - 1;
+
+/// The count of the executing CPU. Interrupts have to be disabled, or preemption for anything
+/// but `disable`, so that the task stays on the CPU.
+fn local_count() -> &'static AtomicUsize {
+    &COUNTS[this_cpu()]
+}
+
+/// Keep the running task on the CPU until the matching `enable`. Can be nested.
+pub fn disable() {
+    interrupt::run_without_interrupt(|| local_count().fetch_add(1, Ordering::SeqCst));
+}
+
+/// Undo one `disable`, and reschedule if it was requested in between.
+pub fn enable() {
+    let enabled = instructions::interrupts_enabled();
+    let reschedule = interrupt::run_without_interrupt(|| {
+        let previous = local_count().fetch_sub(1, Ordering::SeqCst);
+        assert!(previous & PREEMPT_MASK > 0, "preempt::enable without disable");
+        previous == 1 && enabled && take_reschedule()
+    });
+    if reschedule {
+        super::reschedule(Enqueue::Preempted);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    interrupt::run_without_interrupt(|| local_count().load(Ordering::SeqCst) & PREEMPT_MASK == 0)
+}
+
+/// Whether the executing code is an interrupt handler, or runs on its behalf.
+pub fn in_interrupt() -> bool {
+    interrupt::run_without_interrupt(|| local_count().load(Ordering::SeqCst) >= HARDIRQ_OFFSET)
+}
+
+/// The count of the running task, saved by the scheduler.
+pub(super) fn count() -> usize {
+    local_count().load(Ordering::SeqCst)
+}
+
+/// Install the count of the task the scheduler switches to.
+pub(super) fn set_count(count: usize) {
+    local_count().store(count, Ordering::SeqCst);
+}
+
+/// Ask for the running task to be preempted as soon as preemption is enabled.
+pub(super) fn request_reschedule() {
+    NEED_RESCHEDULE[this_cpu()].store(true, Ordering::SeqCst);
+}
+
+/// Whether a reschedule was requested, withdrawing the request.
+pub(super) fn take_reschedule() -> bool {
+    NEED_RESCHEDULE[this_cpu()].swap(false, Ordering::SeqCst)
+}
+
+/// The reschedule request is void once the scheduler picked the next task.
+pub(super) fn clear_reschedule() {
+    NEED_RESCHEDULE[this_cpu()].store(false, Ordering::SeqCst);
+}
+
+/// Disables preemption until dropped.
+pub struct PreemptGuard(());
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        disable();
+        PreemptGuard(())
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        enable();
+    }
+}
+
+/// Marks the executing code as an interrupt handler until dropped. Every handler enters one
+/// (see `impl_handler!`), with interrupts disabled.
+pub struct InterruptGuard(());
+
+impl InterruptGuard {
+    pub fn enter() -> Self {
+        local_count().fetch_add(HARDIRQ_OFFSET, Ordering::SeqCst);
+        InterruptGuard(())
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        local_count().fetch_sub(HARDIRQ_OFFSET, Ordering::SeqCst);
+    }
+}
+
+/// A spinlock whose holder is not preempted.
+pub struct SpinLock<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        SpinLock { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        let preempt = PreemptGuard::new();
+        SpinLockGuard { inner: self.inner.lock(), _preempt: preempt }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        let preempt = PreemptGuard::new();
+        self.inner.try_lock().map(|inner| SpinLockGuard { inner, _preempt: preempt })
+    }
+}
+
+/// Fields drop in order: the lock is released before preemption is enabled again.
+pub struct SpinLockGuard<'a, T> {
+    inner: MutexGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
@@ -0,0 +1,31 @@
+//! Kernel stacks, each with an unmapped guard page below it so that an overflow faults instead
+//! of silently overwriting whatever lies below.
+//!
+//! Stack `index` occupies slot `index` of the `KERNEL_STACKS` region: its guard page, followed by
+//! `STACK_SIZE` bytes of stack.
+
+use super::super::memory::{paging, VirtAddr, KERNEL_STACKS};
+use super::super::memory::page_table::PageTableFlags;
+
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+const SLOT_SIZE: u64 = PAGE_SIZE + STACK_SIZE;
+
+/// The address of the guard page of stack `index`.
+pub fn guard_page(index: usize) -> u64 {
+    KERNEL_STACKS + index as u64 * SLOT_SIZE
+}
+
+/// The initial stack pointer of stack `index`, right above its end.
+pub fn top(index: usize) -> u64 {
+    guard_page(index) + SLOT_SIZE
+}
+
+/// Map the pages of stack `index`, leaving its guard page unmapped.
+pub unsafe fn map(index: usize) {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for address in (guard_page(index) + PAGE_SIZE..top(index)).step_by(PAGE_SIZE as usize) {
+        paging::map(VirtAddr::new(address), flags);
+    }
+}
@@ -0,0 +1,379 @@
+//! Locks whose waiters sleep instead of spinning, for task context only: taking them, or even
+//! trying to, in an interrupt handler fails a debug check, as does taking them with interrupts
+//! or preemption disabled (see `task::might_sleep`). Releasing them never sleeps, so a
+//! `Semaphore` can be released and a `Condvar` notified from an interrupt handler.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::preempt;
+use super::wait::WaitQueue;
+
+/// A mutual exclusion lock.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Sleep until the lock is free, and take it.
+    pub fn lock(&self) -> MutexGuard<T> {
+        super::might_sleep();
+        if !self.acquire() {
+            self.waiters.wait_until(|| self.acquire());
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        debug_assert!(!preempt::in_interrupt(), "Trying to take a Mutex in interrupt context");
+        if self.acquire() { Some(MutexGuard { mutex: self }) } else { None }
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+}
+
+/// Releases the lock when dropped, waking a waiter.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.wake_one();
+    }
+}
+
+/// The reader count of a `RwLock` while a writer holds it.
+const WRITER: usize = !0;
+
+/// A lock held by any number of readers or by one writer. Readers are preferred: a writer
+/// waits for as long as readers keep holding the lock.
+pub struct RwLock<T> {
+    /// The number of readers, or `WRITER`.
+    state: AtomicUsize,
+    readers: WaitQueue,
+    writers: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Sleep until no writer holds the lock, and take it for reading.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        super::might_sleep();
+        if !self.acquire_read() {
+            self.readers.wait_until(|| self.acquire_read());
+        }
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        debug_assert!(!preempt::in_interrupt(), "Trying to take a RwLock in interrupt context");
+        if self.acquire_read() { Some(RwLockReadGuard { lock: self }) } else { None }
+    }
+
+    /// Sleep until nobody holds the lock, and take it for writing.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        super::might_sleep();
+        if !self.acquire_write() {
+            self.writers.wait_until(|| self.acquire_write());
+        }
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        debug_assert!(!preempt::in_interrupt(), "Trying to take a RwLock in interrupt context");
+        if self.acquire_write() { Some(RwLockWriteGuard { lock: self }) } else { None }
+    }
+
+    fn acquire_read(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state < WRITER - 1 {
+            match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire,
+                                                   Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+        false
+    }
+
+    fn acquire_write(&self) -> bool {
+        self.state.compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.writers.wake_one();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.readers.wake_all();
+        self.lock.writers.wake_one();
+    }
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore { permits: AtomicUsize::new(permits), waiters: WaitQueue::new() }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::SeqCst)
+    }
+
+    /// Sleep until a permit is available, and take it.
+    pub fn acquire(&self) {
+        super::might_sleep();
+        if !self.take_permit() {
+            self.waiters.wait_until(|| self.take_permit());
+        }
+    }
+
+    /// Take a permit if one is available.
+    pub fn try_acquire(&self) -> bool {
+        debug_assert!(!preempt::in_interrupt(),
+                      "Trying to acquire a Semaphore in interrupt context");
+        self.take_permit()
+    }
+
+    fn take_permit(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(permits, permits - 1, Ordering::Acquire,
+                                                     Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Give a permit back, waking a waiter.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+/// A condition variable, for waiting on the state behind a `Mutex`.
+///
+/// As with any condition variable, `wait` may return without a notification, so the state is
+/// checked again in a loop, which `wait_while` does.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    /// Release the lock of `guard`, sleep until notified and take the lock again.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        super::might_sleep();
+        let mutex = guard.mutex;
+        let slot = super::current_slot();
+        // A notification from now on makes `park` return.
+        self.waiters.enqueue(slot);
+        drop(guard);
+        super::park();
+        self.waiters.dequeue(slot);
+        mutex.lock()
+    }
+
+    /// Wait for as long as `condition` holds for the state behind the lock of `guard`.
+    pub fn wait_while<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F)
+                                -> MutexGuard<'a, T> where F: FnMut(&mut T) -> bool {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake the task which has waited the longest, returning false if none waits.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    /// Wake all the waiting tasks, returning how many there were.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{spawn, state, yield_now, TaskId, TaskState};
+
+    /// Let the other tasks run until task `id` is in `wanted`.
+    fn wait_for(id: TaskId, wanted: TaskState) {
+        while state(id) != Some(wanted) {
+            yield_now();
+        }
+    }
+
+    static COUNTER: Mutex<usize> = Mutex::new(0);
+
+    fn increment() {
+        *COUNTER.lock() += 1;
+    }
+
+    #[test_case]
+    fn mutex_waiters_sleep_until_it_is_unlocked() {
+        let mut counter = COUNTER.lock();
+        *counter = 0;
+        let id = spawn("increment", increment).unwrap();
+        wait_for(id, TaskState::Blocked);
+        assert!(COUNTER.try_lock().is_none());
+        *counter += 1;
+        drop(counter);
+        wait_for(id, TaskState::Zombie);
+        assert_eq!(*COUNTER.lock(), 2);
+    }
+
+    static TABLE: RwLock<usize> = RwLock::new(0);
+
+    fn read_table() {
+        assert_eq!(*TABLE.read(), 0);
+    }
+
+    fn write_table() {
+        *TABLE.write() += 1;
+    }
+
+    #[test_case]
+    fn readers_share_the_rwlock() {
+        let reader = TABLE.read();
+        let other = spawn("reader", read_table).unwrap();
+        wait_for(other, TaskState::Zombie);
+        let writer = spawn("writer", write_table).unwrap();
+        wait_for(writer, TaskState::Blocked);
+        assert!(TABLE.try_write().is_none());
+        drop(reader);
+        wait_for(writer, TaskState::Zombie);
+        assert_eq!(*TABLE.read(), 1);
+        *TABLE.try_write().unwrap() = 0;
+    }
+
+    static PERMITS: Semaphore = Semaphore::new(1);
+
+    fn take_permit() {
+        PERMITS.acquire();
+        PERMITS.release();
+    }
+
+    #[test_case]
+    fn semaphore_waiters_sleep_until_a_permit_is_released() {
+        PERMITS.acquire();
+        assert!(!PERMITS.try_acquire());
+        let id = spawn("permit", take_permit).unwrap();
+        wait_for(id, TaskState::Blocked);
+        PERMITS.release();
+        wait_for(id, TaskState::Zombie);
+        assert_eq!(PERMITS.available_permits(), 1);
+    }
+
+    static READY: Mutex<bool> = Mutex::new(false);
+    static READY_CHANGED: Condvar = Condvar::new();
+
+    fn wait_until_ready() {
+        let ready = READY_CHANGED.wait_while(READY.lock(), |ready| !*ready);
+        assert!(*ready);
+    }
+
+    #[test_case]
+    fn condvar_waiters_sleep_until_notified() {
+        *READY.lock() = false;
+        let id = spawn("waiter", wait_until_ready).unwrap();
+        wait_for(id, TaskState::Blocked);
+        *READY.lock() = true;
+        assert!(READY_CHANGED.notify_one());
+        wait_for(id, TaskState::Zombie);
+        assert!(!READY_CHANGED.notify_one());
+    }
+}
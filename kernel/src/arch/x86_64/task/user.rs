@@ -0,0 +1,191 @@
+//! Tasks running in user mode (ring 3).
+//!
+//! A task enters user mode for good with `enter_user_mode`, and comes back to the kernel only
+//! through interrupts, exceptions and system calls (see `syscall`): the CPU switches to the
+//! kernel stack of the task, which the scheduler installs in the TSS (see `platform::gdt`), and
+//! the handlers return to user mode with `iretq`. An exception raised by user code ends the task.
+//!
+//! ```ignore
+//! // In a spawned task:
+//! memory::user::map(VirtAddr::new(CODE), PAGE_SIZE, PageTableFlags::WRITABLE);
+//! // Copy the code, make it read-only with `memory::user::protect`, map a stack, then:
+//! task::user::enter_user_mode(CODE, STACK_TOP)
+//! ```
+//!
+//! User pages live in the user part of the address space shared by all tasks (see
+//! `memory::user`).
+
+use super::super::platform::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use super::preempt;
+
+/// Interrupts enabled, plus the reserved bit 1. IOPL 0 keeps user mode away from the ports and
+/// the interrupt flag.
+const USER_RFLAGS: u64 = 0x202;
+
+/// Continue the current task in user mode at `entry`, with the stack pointer `stack_top`. The
+/// kernel stack of the task is abandoned: interrupts from user mode start again from its top.
+///
+/// The task must have been started by `spawn`, which gives it a kernel stack of its own, and
+/// hold no lock.
+pub unsafe fn enter_user_mode(entry: u64, stack_top: u64) -> ! {
+    assert!(super::is_spawned(), "Only spawned tasks can enter user mode");
+    assert!(preempt::is_enabled() && !preempt::in_interrupt(),
+            "Entering user mode with preemption disabled or from an interrupt handler");
+    // No register keeps a kernel value. Interrupts stay disabled from `swapgs`, which swaps the
+    // kernel GS base out (see `interrupt::util`), until `iretq` loads the user flags.
+    asm!("push rax
+          push rsi
+          push rdx
+          push rcx
+          push rdi
+          mov ds, ax
+          mov es, ax
+          xor eax, eax
+          xor ebx, ebx
+          xor ecx, ecx
+          xor edx, edx
+          xor esi, esi
+          xor edi, edi
+          xor ebp, ebp
+          xor r8d, r8d
+          xor r9d, r9d
+          xor r10d, r10d
+          xor r11d, r11d
+          xor r12d, r12d
+          xor r13d, r13d
+          xor r14d, r14d
+          xor r15d, r15d
+          cli
+          swapgs
+          iretq"
+         : : "{rax}"(USER_DATA_SELECTOR.0 as u64), "{rsi}"(stack_top),
+             "{rdx}"(USER_RFLAGS), "{rcx}"(USER_CODE_SELECTOR.0 as u64), "{rdi}"(entry)
+         : "memory" : "intel", "volatile");
+    unreachable!()
+}
+
+/// End the current task, whose user code raised `exception` at `rip`. Only called by the
+/// exception handlers, for exceptions from user mode.
+pub fn end_faulting_task(exception: &str, rip: u64) -> ! {
+    let name = super::current().map_or("?", |task| task.name);
+    warn!("Task {}: {} at 0x{:x} in user mode, ending it", name, exception, rip);
+    super::exit()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use super::super::super::memory::page_table::PageTableFlags;
+    use super::super::super::memory::user::{self, USER_START};
+    use super::super::super::memory::VirtAddr;
+    use super::super::{spawn, state, yield_now, TaskState};
+    use core::ptr;
+    use spin::Mutex;
+
+    const PAGE_SIZE: u64 = 4096;
+    const CODE: u64 = USER_START;
+    const STACK: u64 = USER_START + 0x10000;
+
+    /// `cli` faults in user mode, and hangs the test in kernel mode.
+    static CLI_THEN_LOOP: [u8; 3] = [0xfa, 0xeb, 0xfe];
+    /// `mov al, [rbx]` with `rbx` at `PHYS_MAP_OFFSET`, mapped for the kernel only.
+    static READ_KERNEL: [u8; 12] = [0x48, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff,
+                                    0x8a, 0x03];
+
+    /// `getpid()` into `xmm0`, `yield()` ten times, then push the difference between `xmm0`
+    /// and the pid and `exit(0)`.
+    static KEEP_XMM0: [u8; 51] = [
+        0xb8, 0x06, 0x00, 0x00, 0x00,         // mov eax, 6
+        0x0f, 0x05,                           // syscall
+        0x66, 0x48, 0x0f, 0x6e, 0xc0,         // movq xmm0, rax
+        0x48, 0x89, 0xc3,                     // mov rbx, rax
+        0x41, 0xbc, 0x0a, 0x00, 0x00, 0x00,   // mov r12d, 10
+        0xb8, 0x03, 0x00, 0x00, 0x00,         // mov eax, 3
+        0x0f, 0x05,                           // syscall
+        0x41, 0xff, 0xcc,                     // dec r12d
+        0x75, 0xf4,                           // jnz -12
+        0x66, 0x48, 0x0f, 0x7e, 0xc0,         // movq rax, xmm0
+        0x48, 0x29, 0xd8,                     // sub rax, rbx
+        0x50,                                 // push rax
+        0xb8, 0x02, 0x00, 0x00, 0x00,         // mov eax, 2
+        0x31, 0xff,                           // xor edi, edi
+        0x0f, 0x05,                           // syscall
+    ];
+
+    static PROGRAM: Mutex<&'static [u8]> = Mutex::new(&[]);
+
+    fn run_program() {
+        let program = *PROGRAM.lock();
+        unsafe {
+            user::map(VirtAddr::new(CODE), PAGE_SIZE, PageTableFlags::WRITABLE);
+            ptr::copy_nonoverlapping(program.as_ptr(), CODE as *mut u8, program.len());
+            user::protect(VirtAddr::new(CODE), PAGE_SIZE, PageTableFlags::empty());
+            user::map(VirtAddr::new(STACK), PAGE_SIZE,
+                      PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+            enter_user_mode(CODE, STACK + PAGE_SIZE)
+        }
+    }
+
+    /// Run `program` in user mode in a new task until the task ends, and return the last word
+    /// the program left on its stack.
+    pub(crate) fn run_user_program(program: &'static [u8]) -> u64 {
+        *PROGRAM.lock() = program;
+        let id = spawn("user", run_program).unwrap();
+        while state(id) != Some(TaskState::Zombie) {
+            yield_now();
+        }
+        unsafe {
+            let last_word = *((STACK + PAGE_SIZE - 8) as *const u64);
+            user::unmap(VirtAddr::new(CODE), PAGE_SIZE);
+            user::unmap(VirtAddr::new(STACK), PAGE_SIZE);
+            last_word
+        }
+    }
+
+    #[test_case]
+    fn user_code_runs_in_ring_3() {
+        run_user_program(&CLI_THEN_LOOP);
+    }
+
+    #[test_case]
+    fn user_code_cannot_read_the_kernel() {
+        run_user_program(&READ_KERNEL);
+    }
+
+    /// A second stack, for another task running the code at `CODE`.
+    const OTHER_STACK: u64 = USER_START + 0x20000;
+
+    fn run_on_stack() {
+        unsafe { enter_user_mode(CODE, STACK + PAGE_SIZE) }
+    }
+
+    fn run_on_other_stack() {
+        unsafe { enter_user_mode(CODE, OTHER_STACK + PAGE_SIZE) }
+    }
+
+    #[test_case]
+    fn user_tasks_keep_their_sse_registers() {
+        let stack_flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        unsafe {
+            user::map(VirtAddr::new(CODE), PAGE_SIZE, PageTableFlags::WRITABLE);
+            ptr::copy_nonoverlapping(KEEP_XMM0.as_ptr(), CODE as *mut u8, KEEP_XMM0.len());
+            user::protect(VirtAddr::new(CODE), PAGE_SIZE, PageTableFlags::empty());
+            user::map(VirtAddr::new(STACK), PAGE_SIZE, stack_flags);
+            user::map(VirtAddr::new(OTHER_STACK), PAGE_SIZE, stack_flags);
+        }
+        let ids = [spawn("user", run_on_stack).unwrap(),
+                   spawn("user", run_on_other_stack).unwrap()];
+        for &id in ids.iter() {
+            while state(id) != Some(TaskState::Zombie) {
+                yield_now();
+            }
+        }
+        unsafe {
+            for &stack in [STACK, OTHER_STACK].iter() {
+                assert_eq!(*((stack + PAGE_SIZE - 8) as *const u64), 0);
+                user::unmap(VirtAddr::new(stack), PAGE_SIZE);
+            }
+            user::unmap(VirtAddr::new(CODE), PAGE_SIZE);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Wait queues: tasks sleeping until a condition holds.
+//!
+//! ```ignore
+//! static DONE: AtomicBool = AtomicBool::new(false);
+//! static QUEUE: WaitQueue = WaitQueue::new();
+//!
+//! // In a task:
+//! QUEUE.wait_until(|| DONE.load(Ordering::SeqCst));
+//! // Anywhere, interrupt handlers included:
+//! DONE.store(true, Ordering::SeqCst);
+//! QUEUE.wake_all();
+//! ```
+//!
+//! A waiter queues itself before checking the condition and parks (see `task::park`) after,
+//! so a wake-up in between is not lost.
+
+use ailurus_core::sched::RunQueue;
+use core::mem;
+use spin::Mutex;
+use super::super::interrupt;
+
+pub struct WaitQueue {
+    /// The slots of the waiting tasks.
+    tasks: Mutex<RunQueue>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { tasks: Mutex::new(RunQueue::new()) }
+    }
+
+    pub fn len(&self) -> usize {
+        interrupt::run_without_interrupt(|| self.tasks.lock().len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sleep until `condition` holds, checking it again after every wake-up. Only in task
+    /// context.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) {
+        super::might_sleep();
+        let slot = super::current_slot();
+        loop {
+            self.enqueue(slot);
+            if condition() {
+                self.dequeue(slot);
+                return;
+            }
+            super::park();
+        }
+    }
+
+    /// Queue the task in `slot`, unless it already is.
+    pub(super) fn enqueue(&self, slot: usize) {
+        interrupt::run_without_interrupt(|| self.tasks.lock().push_back(slot));
+    }
+
+    /// Take the task in `slot` out of the queue, returning false if it was not in it.
+    pub(super) fn dequeue(&self, slot: usize) -> bool {
+        interrupt::run_without_interrupt(|| self.tasks.lock().remove(slot))
+    }
+
+    /// Wake the task which has waited the longest, returning false if none waits.
+    pub fn wake_one(&self) -> bool {
+        let slot = interrupt::run_without_interrupt(|| self.tasks.lock().pop_front());
+        match slot {
+            Some(slot) => {
+                super::unpark_slot(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake all the waiting tasks, returning how many there were.
+    pub fn wake_all(&self) -> usize {
+        // Tasks woken on other CPUs may queue themselves again right away.
+        let tasks = interrupt::run_without_interrupt(|| {
+            mem::replace(&mut *self.tasks.lock(), RunQueue::new())
+        });
+        for slot in tasks.iter() {
+            super::unpark_slot(slot);
+        }
+        tasks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{spawn, state, yield_now, TaskState};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static QUEUE: WaitQueue = WaitQueue::new();
+
+    fn waiter() {
+        QUEUE.wait_until(|| DONE.load(Ordering::SeqCst));
+    }
+
+    #[test_case]
+    fn waiters_sleep_until_the_condition_holds() {
+        DONE.store(false, Ordering::SeqCst);
+        let id = spawn("waiter", waiter).unwrap();
+        while state(id) != Some(TaskState::Blocked) {
+            yield_now();
+        }
+        assert_eq!(QUEUE.len(), 1);
+        // Checks the condition again and goes back to sleep.
+        assert_eq!(QUEUE.wake_all(), 1);
+        while QUEUE.is_empty() || state(id) != Some(TaskState::Blocked) {
+            yield_now();
+        }
+
+        DONE.store(true, Ordering::SeqCst);
+        assert!(QUEUE.wake_one());
+        while state(id) != Some(TaskState::Zombie) {
+            yield_now();
+        }
+        assert!(QUEUE.is_empty());
+        assert!(!QUEUE.wake_one());
+    }
+}
@@ -0,0 +1,25 @@
+//! Kernel command line.
+//!
+//! The bootloader passes the command line given to `make KernelCmdline=...` as the environment,
+//! copied to the top of the boot stack, which lives as long as the kernel.
+
+use ailurus_core::cmdline::CommandLine;
+use core::str;
+
+static mut COMMAND_LINE: &'static str = "";
+
+pub unsafe fn init(env: &'static [u8]) {
+    match str::from_utf8(env) {
+        Ok(command_line) => COMMAND_LINE = command_line,
+        Err(_) => warn!("Ignoring kernel command line, it is not UTF-8"),
+    }
+}
+
+pub fn command_line() -> CommandLine<'static> {
+    CommandLine::new(unsafe { COMMAND_LINE })
+}
+
+/// The value of the `key=value` option.
+pub fn get(key: &str) -> Option<&'static str> {
+    command_line().get(key)
+}
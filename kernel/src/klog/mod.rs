@@ -0,0 +1,181 @@
+//! Kernel logger for the `log` facade.
+//!
+//! Every record is stamped with the uptime and the CPU id, appended to an in-memory ring buffer
+//! (the `dmesg` buffer) and written to all registered sinks. Records can be filtered by level,
+//! globally and per module.
+
+pub mod ring_buffer;
+
+use core::fmt::{self, Write};
+use core::str;
+use log::{LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use crate::arch::device::{cpu, pit};
+use crate::arch::interrupt;
+use self::ring_buffer::RingBuffer;
+
+const MAX_SINKS: usize = 4;
+const MAX_FILTERS: usize = 16;
+
+/// A destination of formatted log output, e.g. a console or a serial port.
+pub trait LogSink: Sync {
+    fn write_str(&self, s: &str);
+}
+
+struct LoggerState {
+    default_level: LevelFilter,
+    filters: [Option<(&'static str, LevelFilter)>; MAX_FILTERS],
+    sinks: [Option<&'static dyn LogSink>; MAX_SINKS],
+    ring_buffer: RingBuffer,
+}
+
+impl LoggerState {
+    /// The level of the longest module filter matching `target`, or the default level.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let mut matched: Option<(&str, LevelFilter)> = None;
+        for &(module, level) in self.filters.iter().filter_map(|filter| filter.as_ref()) {
+            let matches = target.starts_with(module) &&
+                (target.len() == module.len() || target[module.len()..].starts_with("::"));
+            if matches && matched.map_or(true, |(best, _)| module.len() > best.len()) {
+                matched = Some((module, level));
+            }
+        }
+        matched.map_or(self.default_level, |(_, level)| level)
+    }
+}
+
+/// Writes formatted output to the ring buffer and every sink.
+struct RecordWriter<'a> {
+    state: &'a mut LoggerState,
+}
+
+impl<'a> fmt::Write for RecordWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.state.ring_buffer.write(s.as_bytes());
+        for sink in self.state.sinks.iter().filter_map(|sink| *sink) {
+            sink.write_str(s);
+        }
+        Ok(())
+    }
+}
+
+static STATE: Mutex<LoggerState> = Mutex::new(LoggerState {
+    default_level: LevelFilter::Info,
+    filters: [None; MAX_FILTERS],
+    sinks: [None; MAX_SINKS],
+    ring_buffer: RingBuffer::new(),
+});
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        interrupt::run_without_interrupt(|| {
+            metadata.level() <= STATE.lock().level_for(metadata.target())
+        })
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let (seconds, micros) = pit::uptime();
+        interrupt::run_without_interrupt(|| {
+            let mut state = STATE.lock();
+            let _ = write!(RecordWriter { state: &mut state },
+                           "[{:>5}.{:06}] cpu{} {:<5} {}: {}\n",
+                           seconds, micros, cpu::cpu_id(), record.level(), record.target(),
+                           record.args());
+        })
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the kernel logger. Records are only kept in the ring buffer until a sink is added.
+pub fn init() {
+    log::set_logger(&LOGGER).expect("Logger has already been set");
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Register a sink, returning false if there is no room for another one.
+pub fn add_sink(sink: &'static dyn LogSink) -> bool {
+    interrupt::run_without_interrupt(|| {
+        let mut state = STATE.lock();
+        match state.sinks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(sink);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+pub fn remove_sink(sink: &'static dyn LogSink) {
+    let address = sink as *const dyn LogSink as *const u8;
+    interrupt::run_without_interrupt(|| {
+        for slot in STATE.lock().sinks.iter_mut() {
+            if slot.map_or(false, |s| s as *const dyn LogSink as *const u8 == address) {
+                *slot = None;
+            }
+        }
+    })
+}
+
+/// Set the level used for modules without a filter of their own.
+pub fn set_default_level(level: LevelFilter) {
+    interrupt::run_without_interrupt(|| STATE.lock().default_level = level)
+}
+
+/// Set the level of `module` and its submodules, e.g. `kernel::arch::x86_64::interrupt`.
+pub fn set_module_level(module: &'static str, level: LevelFilter) {
+    interrupt::run_without_interrupt(|| {
+        let mut state = STATE.lock();
+        let slot = state.filters.iter().position(|filter| filter.map_or(false, |(m, _)| m == module))
+            .or_else(|| state.filters.iter().position(|filter| filter.is_none()));
+        match slot {
+            Some(index) => state.filters[index] = Some((module, level)),
+            None => panic!("Too many log filters"),
+        }
+    })
+}
+
+/// Write the content of the ring buffer to `sink`.
+pub fn dmesg(sink: &dyn LogSink) {
+    interrupt::run_without_interrupt(|| {
+        let state = STATE.lock();
+        let (older, newer) = state.ring_buffer.as_slices();
+        write_bytes(sink, older);
+        write_bytes(sink, newer);
+    })
+}
+
+/// Release the logger lock, for the panic handler only.
+pub unsafe fn force_unlock() {
+    STATE.force_unlock();
+}
+
+pub fn clear_dmesg() {
+    interrupt::run_without_interrupt(|| STATE.lock().ring_buffer.clear())
+}
+
+/// Write `bytes` as text, skipping sequences which are not valid UTF-8 (e.g. a character split
+/// by the ring buffer boundary).
+fn write_bytes(sink: &dyn LogSink, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match str::from_utf8(bytes) {
+            Ok(s) => {
+                sink.write_str(s);
+                return;
+            }
+            Err(error) => {
+                let valid = error.valid_up_to();
+                sink.write_str(unsafe { str::from_utf8_unchecked(&bytes[..valid]) });
+                bytes = &bytes[valid + error.error_len().unwrap_or(bytes.len() - valid)..];
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+/// Fixed-size byte ring that keeps the most recent log output, overwriting the oldest bytes when
+/// it is full.
+pub struct RingBuffer {
+    data: [u8; RING_BUFFER_SIZE],
+    /// Index of the next byte to be written.
+    head: usize,
+    /// Number of valid bytes, at most `RING_BUFFER_SIZE`.
+    len: usize,
+}
+
+pub const RING_BUFFER_SIZE: usize = 16 * 1024;
+
+impl RingBuffer {
+    pub const fn new() -> Self {
+        RingBuffer {
+            data: [0; RING_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.data[self.head] = byte;
+            self.head = (self.head + 1) % RING_BUFFER_SIZE;
+            if self.len < RING_BUFFER_SIZE {
+                self.len += 1;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The buffered bytes in chronological order as two slices.
+    ///
+    /// Once the buffer has wrapped, the oldest line is most likely truncated, so the output
+    /// starts after its end.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.len < RING_BUFFER_SIZE {
+            return (&self.data[..self.len], &[]);
+        }
+        let (older, newer) = (&self.data[self.head..], &self.data[..self.head]);
+        match older.iter().position(|&byte| byte == b'\n') {
+            Some(index) => (&older[index + 1..], newer),
+            None => {
+                let index = newer.iter().position(|&byte| byte == b'\n')
+                    .map_or(newer.len(), |index| index + 1);
+                (&newer[index..], &[])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn keeps_everything_until_full() {
+        let mut buffer = RingBuffer::new();
+        buffer.write(b"first\nsecond\n");
+        assert_eq!(buffer.len(), 13);
+        assert_eq!(buffer.as_slices(), (&b"first\nsecond\n"[..], &b""[..]));
+    }
+
+    #[test_case]
+    fn drops_truncated_line_after_wrapping() {
+        let mut buffer = RingBuffer::new();
+        for _ in 0..RING_BUFFER_SIZE / 8 {
+            buffer.write(b"0123456\n");
+        }
+        buffer.write(b"abc\n");
+        assert_eq!(buffer.len(), RING_BUFFER_SIZE);
+        let (older, newer) = buffer.as_slices();
+        assert!(older.starts_with(b"0123456\n"));
+        assert_eq!(newer, &b"abc\n"[..]);
+    }
+}
@@ -3,32 +3,39 @@
 #![feature(min_const_fn)]
 #![feature(const_fn)]
 #![feature(naked_functions)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![cfg_attr(test, no_main)]
 
 
 #[macro_use]
 extern crate lazy_static;
 
 extern crate spin;
+extern crate ailurus_core;
 
 #[macro_use]
 extern crate bitflags;
 
-use core::panic::PanicInfo;
+#[macro_use]
+extern crate log;
 
 #[macro_use]
 mod arch;
+mod cmdline;
+mod klog;
+mod panic;
+#[cfg(test)]
+mod testing;
 
 pub use self::arch::kstart;
 
-/// This function is called on panic.
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop{}
-}
-
 pub fn kmain() -> ! {
-    println!("Started Ailurus-OS successfully!");
+    info!("Started Ailurus-OS successfully!");
+
+    #[cfg(test)]
+    test_main();
 
     print_memory_map();
 
@@ -45,13 +52,13 @@ fn divide_by_zero() {
 }
 
 fn print_memory_map() {
-    println!("Memory map:");
-    println!("{:<20}{:<20}{:<20}", "Start Address", "Size", "Memory Type");
+    info!("Memory map:");
+    info!("{:<20}{:<20}{:<20}", "Start Address", "Size", "Memory Type");
     for tag in arch::memory::layout::all_memory_area() {
-        println!("0x{:0>16X}  0x{:0>16X}  {:?}",
-                 tag.base_address.as_u64(), tag.size, tag.mem_type)
+        info!("0x{:0>16X}  0x{:0>16X}  {:?}",
+              tag.base_address.as_u64(), tag.size, tag.mem_type)
     }
 
-    let mut total_mem_size = arch::memory::layout::physical_memory_size();
-    println!("Total memory size: {}MB", total_mem_size / 1024 / 1024)
+    let total_mem_size = arch::memory::layout::physical_memory_size();
+    info!("Total memory size: {}MB", total_mem_size / 1024 / 1024)
 }
\ No newline at end of file
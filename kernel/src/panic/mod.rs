@@ -0,0 +1,75 @@
+//! Panic handling: stop the machine, report where it happened and keep the log.
+
+pub mod symbols;
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::debug::{backtrace, lockdep};
+use crate::arch::device::{console, framebuffer, local_apic, serial, vga_buffer};
+use crate::arch::platform::instructions;
+use crate::klog;
+
+static PANIC_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn is_panicking() -> bool {
+    PANIC_COUNT.load(Ordering::SeqCst) != 0
+}
+
+/// This function is called on panic.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    unsafe { instructions::cli(); }
+
+    #[cfg(test)]
+    crate::testing::handle_panic(info);
+
+    if PANIC_COUNT.fetch_add(1, Ordering::SeqCst) != 0 {
+        // Either the panic handler itself panicked or another CPU is already panicking, so only
+        // use the simplest output path.
+        unsafe { serial::SERIAL1.force_unlock(); }
+        serial_println!("\nPANIC WHILE PANICKING: {}", info);
+        halt();
+    }
+
+    unsafe {
+        local_apic::stop_other_cpus();
+        release_output_locks();
+    }
+    console::switch_to(console::KERNEL_TERMINAL);
+
+    error!("KERNEL PANIC: {}", info);
+    print_backtrace();
+
+    serial_println!("{:-^78}", " DMESG ");
+    klog::dmesg(&serial::SERIAL_SINK);
+    serial_println!("{:-^78}", " END OF DMESG ");
+
+    halt()
+}
+
+/// Unlock the consoles and the logger, which this CPU may have held when it panicked.
+pub unsafe fn release_output_locks() {
+    lockdep::stop();
+    vga_buffer::WRITER.force_unlock();
+    framebuffer::CONSOLE.force_unlock();
+    console::CONSOLES.force_unlock();
+    serial::SERIAL1.force_unlock();
+    klog::force_unlock();
+}
+
+fn print_backtrace() {
+    error!("Backtrace:");
+    for (index, frame) in backtrace::frames().enumerate() {
+        error!("  #{:<2} {}", index, symbols::Symbolized(frame.return_address));
+    }
+}
+
+/// Halt this CPU forever.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            instructions::cli();
+            instructions::hlt();
+        }
+    }
+}
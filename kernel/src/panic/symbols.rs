@@ -0,0 +1,97 @@
+//! Symbol table for backtraces.
+//!
+//! The Makefile lists the function symbols of `build/kernel.sym` with `nm -n -C` and adds the
+//! result to the kernel image as the `.ksyms` section. Each line has the form
+//! `ffffff0000101230 T kernel::kmain`, sorted by address.
+
+use core::fmt;
+use core::ptr::read_unaligned;
+use core::str;
+
+const SYMBOL_SECTION: &str = ".ksyms";
+
+static mut SYMBOLS: &'static str = "";
+
+fn read_u16(image: &[u8], offset: usize) -> u16 {
+    assert!(offset + 2 <= image.len());
+    unsafe { read_unaligned(image.as_ptr().add(offset) as *const u16) }
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    assert!(offset + 4 <= image.len());
+    unsafe { read_unaligned(image.as_ptr().add(offset) as *const u32) }
+}
+
+fn read_u64(image: &[u8], offset: usize) -> u64 {
+    assert!(offset + 8 <= image.len());
+    unsafe { read_unaligned(image.as_ptr().add(offset) as *const u64) }
+}
+
+/// Find the content of the section called `name` in an ELF64 image.
+fn find_section<'a>(image: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    if image.len() < 64 || &image[..4] != b"\x7fELF" {
+        return None;
+    }
+    let section_offset = read_u64(image, 0x28) as usize;
+    let section_size = read_u16(image, 0x3A) as usize;
+    let section_num = read_u16(image, 0x3C) as usize;
+    let names_index = read_u16(image, 0x3E) as usize;
+    if section_offset + section_size * section_num > image.len() || names_index >= section_num {
+        return None;
+    }
+
+    let header = |index: usize| section_offset + index * section_size;
+    let names_offset = read_u64(image, header(names_index) + 0x18) as usize;
+
+    (0..section_num).map(header).find(|&header| {
+        let start = names_offset + read_u32(image, header) as usize;
+        image.get(start..start + name.len() + 1)
+            .map_or(false, |bytes| &bytes[..name.len()] == name.as_bytes() && bytes[name.len()] == 0)
+    }).and_then(|header| {
+        let offset = read_u64(image, header + 0x18) as usize;
+        let size = read_u64(image, header + 0x20) as usize;
+        image.get(offset..offset + size)
+    })
+}
+
+/// Load the symbol table from the kernel image loaded by the bootloader.
+pub unsafe fn init(kernel_image: &'static [u8]) {
+    if let Some(section) = find_section(kernel_image, SYMBOL_SECTION) {
+        if let Ok(symbols) = str::from_utf8(section) {
+            SYMBOLS = symbols;
+        }
+    }
+    if SYMBOLS.is_empty() {
+        warn!("No symbol table found, backtraces will not be symbolized");
+    }
+}
+
+/// Find the function containing `address`, returning its name and the offset into it.
+pub fn resolve(address: u64) -> Option<(&'static str, u64)> {
+    let mut found = None;
+    for line in unsafe { SYMBOLS }.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (start, name) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(start), Some(_), Some(name)) => (start, name),
+            _ => continue,
+        };
+        match u64::from_str_radix(start, 16) {
+            Ok(start) if start <= address => found = Some((name, address - start)),
+            Ok(_) => break,
+            Err(_) => continue,
+        }
+    }
+    found
+}
+
+/// Formats an address together with the symbol it belongs to.
+pub struct Symbolized(pub u64);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match resolve(self.0) {
+            Some((name, offset)) => write!(f, "0x{:0>16X} {}+0x{:x}", self.0, name, offset),
+            None => write!(f, "0x{:0>16X} <unknown>", self.0),
+        }
+    }
+}
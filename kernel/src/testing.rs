@@ -0,0 +1,109 @@
+//! In-kernel test framework.
+//!
+//! `make test` builds the kernel with `--test` and boots it in QEMU. `kmain` calls `test_main`,
+//! which runs every `#[test_case]` and reports the results over the serial port. The outcome is
+//! passed back to QEMU through the `isa-debug-exit` device.
+
+use core::mem;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::device::qemu::{exit_qemu, QemuExitCode};
+use crate::arch::platform::instructions;
+
+pub trait Testable {
+    fn run(&self);
+
+    fn should_panic(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{} ... ", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// A test which passes only if it panics:
+///
+/// ```ignore
+/// #[test_case]
+/// const INVALID_ADDRESS: ShouldPanic = ShouldPanic("invalid_address", || { ... });
+/// ```
+pub struct ShouldPanic(pub &'static str, pub fn());
+
+impl Testable for ShouldPanic {
+    fn run(&self) {
+        serial_print!("{} (should panic) ... ", self.0);
+        (self.1)();
+        serial_println!("[failed]");
+        serial_println!("The test did not panic");
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    fn should_panic(&self) -> bool {
+        true
+    }
+}
+
+static mut TESTS: &'static [&'static dyn Testable] = &[];
+/// Index of the next test to run.
+static NEXT_TEST: AtomicUsize = AtomicUsize::new(0);
+/// Stack pointer to continue from after an expected panic.
+static mut RESUME_STACK: u64 = 0;
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    unsafe {
+        // The test list generated by the harness is a constant.
+        TESTS = mem::transmute(tests);
+        let rsp: u64;
+        asm!("mov %rsp, $0" : "=r"(rsp) ::: "volatile");
+        // Leave some room below the current frame and keep the ABI stack alignment.
+        RESUME_STACK = (rsp - 512) & !0xf;
+    }
+    run_tests();
+}
+
+fn run_tests() -> ! {
+    let tests = unsafe { TESTS };
+    loop {
+        let index = NEXT_TEST.fetch_add(1, Ordering::SeqCst);
+        if index >= tests.len() {
+            break;
+        }
+        tests[index].run();
+    }
+    serial_println!("All {} tests passed", tests.len());
+    exit_qemu(QemuExitCode::Success)
+}
+
+extern "C" fn resume_tests() -> ! {
+    unsafe { instructions::sti(); }
+    run_tests()
+}
+
+/// Called by the panic handler. Reports the running test as failed unless it was expected to
+/// panic, in which case the remaining tests are run on a fresh stack.
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    unsafe { crate::panic::release_output_locks(); }
+
+    let tests = unsafe { TESTS };
+    let index = NEXT_TEST.load(Ordering::SeqCst).wrapping_sub(1);
+    if index < tests.len() && tests[index].should_panic() {
+        serial_println!("[ok]");
+        unsafe {
+            asm!("mov $0, %rsp
+                  call *$1"
+                 :: "r"(RESUME_STACK), "r"(resume_tests as extern "C" fn() -> !)
+                 : "memory" : "volatile");
+        }
+        unreachable!();
+    }
+
+    serial_println!("[failed]");
+    serial_println!("{}", info);
+    exit_qemu(QemuExitCode::Failed)
+}